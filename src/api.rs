@@ -4,15 +4,17 @@ use crate::openai::pipelines::llm_engine::LLMEngine;
 use crate::openai::pipelines::pipeline::DefaultLoader;
 use crate::openai::requests::Messages;
 use crate::openai::resolve_tools_for_request;
-use crate::openai::sampling_params::{GenerationConfig, SamplingParams};
+use crate::openai::sampling_params::{GenerationConfig, Priority, SamplingParams};
 use crate::openai::PipelineConfig;
 use crate::scheduler::cache_engine::{CacheConfig, CacheEngine};
 use crate::scheduler::prefix_cache::PrefixCacheConfig;
 use crate::scheduler::SchedulerConfig;
 use candle_core::{DType, Result};
 use parking_lot::RwLock;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::pin::Pin;
 use std::sync::Arc;
+use std::task::{Context, Poll};
 use tokio::sync::Notify;
 
 const REQUEST_ADMISSION_DECODE_BUDGET_TOKENS: usize = 4096;
@@ -54,6 +56,7 @@ pub struct EngineBuilder {
     presence_penalty: Option<f32>,
     prefill_chunk_size: Option<usize>,
     yarn_scaling_factor: Option<f64>,
+    priority_limits: HashMap<Priority, usize>,
 }
 
 impl EngineBuilder {
@@ -78,6 +81,7 @@ impl EngineBuilder {
             frequency_penalty: None,
             presence_penalty: None,
             prefill_chunk_size: None,
+            priority_limits: HashMap::new(),
             yarn_scaling_factor: None,
         }
     }
@@ -122,8 +126,10 @@ impl EngineBuilder {
         self
     }
 
+    /// Use a fixed KV cache budget (MB) instead of sizing it by `kv_fraction`.
     pub fn with_kvcache_mem_gpu(mut self, kvcache_mem_gpu: usize) -> Self {
         self.kvcache_mem_gpu = kvcache_mem_gpu;
+        self.kv_fraction = None;
         self
     }
 
@@ -176,6 +182,12 @@ impl EngineBuilder {
         self
     }
 
+    /// Cap the number of running requests of one priority class.
+    pub fn with_priority_limit(mut self, priority: Priority, max_running: usize) -> Self {
+        self.priority_limits.insert(priority, max_running);
+        self
+    }
+
     pub async fn build_async(self) -> Result<Engine> {
         let (model_id, weight_path, weight_file) = match self.repo {
             ModelRepo::ModelID((model_id, filename)) => (
@@ -378,6 +390,10 @@ impl EngineBuilder {
             max_num_batched_tokens,
             prefix_cache: PrefixCacheConfig::default(),
             mamba_cache_capacity: mamba_active_slot_capacity,
+            priority_limits: self.priority_limits.clone(),
+            max_waiting_requests: None,
+            max_waiting_tokens: None,
+            queue_timeout: None,
         };
 
         let notify = Arc::new(Notify::new());
@@ -436,6 +452,60 @@ impl EngineBuilder {
     }
 }
 
+/// A model served next to the main one, parsed from `--extra-model`:
+/// comma-separated `key=value` pairs such as
+/// `name=embed,m=Qwen/Qwen3-Embedding-0.6B,kv-fraction=0.2`.
+///
+/// Keys: `name` (served name, defaults to the model's own), `m` (model id),
+/// `w` (local weight path), `f` (GGUF file), `isq`, `d` (device id),
+/// `kv-fraction`, `mem` (fixed KV cache MB) and `max-num-seqs`.
+#[derive(Clone, Debug)]
+pub struct ExtraModelSpec {
+    pub name: Option<String>,
+    pub builder: EngineBuilder,
+}
+
+impl std::str::FromStr for ExtraModelSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> std::result::Result<Self, String> {
+        let mut fields = HashMap::new();
+        for field in spec.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+            let (key, value) = field
+                .split_once('=')
+                .ok_or_else(|| format!("expected `key=value`, got `{field}`"))?;
+            if fields.insert(key.trim(), value.trim()).is_some() {
+                return Err(format!("`{key}` given twice"));
+            }
+        }
+        // `ModelRepo` borrows for 'static; specs are parsed once at startup.
+        let leak = |value: &str| -> &'static str { Box::leak(value.to_string().into_boxed_str()) };
+        let repo = match (fields.remove("m"), fields.remove("w"), fields.remove("f")) {
+            (Some(id), None, file) => ModelRepo::ModelID((leak(id), file.map(leak))),
+            (None, Some(path), None) => ModelRepo::ModelPath(leak(path)),
+            (None, None, Some(file)) => ModelRepo::ModelFile(vec![leak(file)]),
+            _ => return Err("give either `m` (with optional `f`), `w` or `f`".to_string()),
+        };
+        if fields.contains_key("mem") && fields.contains_key("kv-fraction") {
+            return Err("give either `mem` or `kv-fraction`, not both".to_string());
+        }
+        let mut builder = EngineBuilder::new(repo);
+        let name = fields.remove("name").map(str::to_string);
+        for (key, value) in fields {
+            let invalid = || format!("invalid `{key}` value `{value}`");
+            builder = match key {
+                "isq" => builder.with_isq(value),
+                "d" => builder.with_device_ids(vec![value.parse().map_err(|_| invalid())?]),
+                "kv-fraction" => builder.with_kv_fraction(value.parse().map_err(|_| invalid())?),
+                "mem" => builder.with_kvcache_mem_gpu(value.parse().map_err(|_| invalid())?),
+                "max-num-seqs" => builder.with_max_num_seqs(value.parse().map_err(|_| invalid())?),
+                _ => return Err(format!("unknown key `{key}`")),
+            };
+        }
+        Ok(Self { name, builder })
+    }
+}
+
 /// The main inference engine context.
 ///
 /// This struct holds the initialized `LLMEngine` and allows performing
@@ -450,11 +520,201 @@ pub struct Engine {
 }
 
 use crate::openai::requests::{ChatCompletionRequest, EmbeddingRequest};
-use crate::openai::responses::{ChatCompletionResponse, EmbeddingResponse};
+use crate::openai::responses::{
+    ChatCompletionChunk, ChatCompletionResponse, ChatCompletionUsageResponse, EmbeddingResponse,
+};
 use crate::openai::streaming::ChatResponse;
+use crate::tools::ToolCall;
 use tracing::{info, warn};
 
+/// A submitted generation request.
+///
+/// Await the result with `response`, or stop it early with `cancel`; a
+/// cancelled request still completes, with finish reason `cancelled`.
+pub struct RequestHandle {
+    engine: Arc<RwLock<LLMEngine>>,
+    notify: Arc<Notify>,
+    req_notify: Arc<Notify>,
+    request_id: String,
+    model: Option<String>,
+}
+
+impl RequestHandle {
+    pub fn id(&self) -> &str {
+        &self.request_id
+    }
+
+    /// Ask the engine to stop this request. Returns false if it already
+    /// finished.
+    pub fn cancel(&self) -> bool {
+        cancel_request(&self.engine, &self.notify, &self.request_id)
+    }
+
+    /// Wait for the request to finish.
+    pub async fn response(self) -> Result<ChatCompletionResponse> {
+        loop {
+            let e = self.engine.read();
+            if e.completion_records.contains_key(&self.request_id)
+                || e.rejected_requests.contains_key(&self.request_id)
+            {
+                break;
+            }
+            drop(e);
+            self.req_notify.notified().await;
+        }
+        if let Some(error) = self
+            .engine
+            .write()
+            .rejected_requests
+            .remove(&self.request_id)
+        {
+            return Err(candle_core::Error::msg(error.message().to_string()));
+        }
+
+        let e = self.engine.read();
+        let response_model = if e.model_name().is_empty() {
+            self.model.unwrap_or("default".to_string())
+        } else {
+            e.model_name().to_string()
+        };
+        if let Some(record) = e.completion_records.get(&self.request_id) {
+            Ok(ChatCompletionResponse {
+                id: self.request_id,
+                choices: record.0.clone(),
+                created: record.1.created,
+                model: response_model,
+                object: "chat.completion",
+                system_fingerprint: Some(e.system_fingerprint().to_string()),
+                usage: record.1.clone(),
+            })
+        } else {
+            Err(candle_core::Error::msg("Failed to get response"))
+        }
+    }
+}
+
+fn cancel_request(engine: &RwLock<LLMEngine>, notify: &Notify, request_id: &str) -> bool {
+    let cancelled = engine.write().cancel_request(request_id);
+    if cancelled {
+        notify.notify_one();
+    }
+    cancelled
+}
+
+/// One piece of a streamed generation. `index` is the choice index.
+#[derive(Debug, Clone)]
+pub enum StreamDelta {
+    Content {
+        index: usize,
+        text: String,
+    },
+    Reasoning {
+        index: usize,
+        text: String,
+    },
+    ToolCalls {
+        index: usize,
+        tool_calls: Vec<ToolCall>,
+    },
+    Finish {
+        index: usize,
+        reason: String,
+    },
+    /// Token usage, sent once after every choice has finished.
+    Usage(ChatCompletionUsageResponse),
+}
+
+impl StreamDelta {
+    fn from_chunk(chunk: ChatCompletionChunk) -> Vec<StreamDelta> {
+        let mut deltas = Vec::new();
+        for choice in chunk.choices {
+            let index = choice.index;
+            if let Some(text) = choice.delta.reasoning_content.filter(|t| !t.is_empty()) {
+                deltas.push(StreamDelta::Reasoning { index, text });
+            }
+            if let Some(text) = choice.delta.content.filter(|t| !t.is_empty()) {
+                deltas.push(StreamDelta::Content { index, text });
+            }
+            if let Some(tool_calls) = choice.delta.tool_calls.filter(|c| !c.is_empty()) {
+                deltas.push(StreamDelta::ToolCalls { index, tool_calls });
+            }
+            if let Some(reason) = choice.finish_reason {
+                deltas.push(StreamDelta::Finish { index, reason });
+            }
+        }
+        if let Some(usage) = chunk.usage {
+            deltas.push(StreamDelta::Usage(usage));
+        }
+        deltas
+    }
+}
+
+/// Output of `Engine::generate_stream`. Ends after the usage delta, or after
+/// the first error.
+pub struct ChatStream {
+    engine: Arc<RwLock<LLMEngine>>,
+    notify: Arc<Notify>,
+    request_id: String,
+    rx: tokio::sync::mpsc::Receiver<ChatResponse>,
+    pending: VecDeque<StreamDelta>,
+    done: bool,
+}
+
+impl ChatStream {
+    pub fn id(&self) -> &str {
+        &self.request_id
+    }
+
+    /// Ask the engine to stop this request; the stream then finishes with
+    /// reason `cancelled`. Returns false if it already finished.
+    pub fn cancel(&self) -> bool {
+        cancel_request(&self.engine, &self.notify, &self.request_id)
+    }
+}
+
+impl futures::Stream for ChatStream {
+    type Item = Result<StreamDelta>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(delta) = self.pending.pop_front() {
+                return Poll::Ready(Some(Ok(delta)));
+            }
+            if self.done {
+                return Poll::Ready(None);
+            }
+            match self.rx.poll_recv(cx) {
+                Poll::Ready(Some(ChatResponse::Chunk(chunk))) => {
+                    self.pending.extend(StreamDelta::from_chunk(chunk));
+                }
+                Poll::Ready(Some(
+                    ChatResponse::ModelError(e)
+                    | ChatResponse::InternalError(e)
+                    | ChatResponse::ValidationError(e),
+                )) => {
+                    self.done = true;
+                    return Poll::Ready(Some(Err(candle_core::Error::msg(
+                        e.message().to_string(),
+                    ))));
+                }
+                Poll::Ready(Some(ChatResponse::Done) | None) => self.done = true,
+                Poll::Ready(Some(_)) => {}
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
 impl Engine {
+    /// The underlying engine, e.g. to serve it over HTTP.
+    pub fn llm_engine(&self) -> Arc<RwLock<LLMEngine>> {
+        self.engine.clone()
+    }
+
+    pub fn pipeline_config(&self) -> &PipelineConfig {
+        &self.pipeline_config
+    }
+
     /// Validates prompt length against model limits.
     fn validate_prompt(&self, token_ids: &[u32], request_type: &str) -> Result<()> {
         let prompt_len = token_ids.len();
@@ -490,14 +750,80 @@ impl Engine {
         &self,
         messages: Vec<ChatCompletionRequest>,
     ) -> Result<ChatCompletionResponse> {
+        if messages.len() != 1 {
+            return Err(candle_core::Error::msg(format!(
+                "generate_async takes exactly one request, got {}; use generate_many for batches",
+                messages.len()
+            )));
+        }
         self.generate_request(messages.into_iter().next().unwrap())
+            .await?
+            .response()
             .await
     }
 
-    pub async fn generate_request(
+    /// Submit a request and return a handle to await or cancel it.
+    pub async fn generate_request(&self, request: ChatCompletionRequest) -> Result<RequestHandle> {
+        let model = request.model.clone();
+        let req_notify = Arc::new(Notify::new());
+        let request_id = self
+            .submit_request(request, Some(req_notify.clone()), None)
+            .await?;
+        Ok(RequestHandle {
+            engine: self.engine.clone(),
+            notify: self.notify.clone(),
+            req_notify,
+            request_id,
+            model,
+        })
+    }
+
+    /// Submit all requests to the scheduler at once, so they are batched
+    /// together, and wait for every response.
+    pub async fn generate_many(
+        &self,
+        requests: Vec<ChatCompletionRequest>,
+    ) -> Result<Vec<ChatCompletionResponse>> {
+        let mut handles = Vec::with_capacity(requests.len());
+        for request in requests {
+            match self.generate_request(request).await {
+                Ok(handle) => handles.push(handle),
+                Err(e) => {
+                    for handle in &handles {
+                        handle.cancel();
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        futures::future::try_join_all(handles.into_iter().map(RequestHandle::response)).await
+    }
+
+    /// Submit a request and stream its output as it is generated. Dropping
+    /// the stream aborts the request.
+    pub async fn generate_stream(&self, request: ChatCompletionRequest) -> Result<ChatStream> {
+        let (tx, rx) = tokio::sync::mpsc::channel(1024);
+        let request_id = self
+            .submit_request(request, None, Some(Arc::new(tx)))
+            .await?;
+        Ok(ChatStream {
+            engine: self.engine.clone(),
+            notify: self.notify.clone(),
+            request_id,
+            rx,
+            pending: VecDeque::new(),
+            done: false,
+        })
+    }
+
+    /// Validate a chat request, render its prompt and add it to the engine.
+    /// The result is announced through `sync_notify`, or streamed to `sender`.
+    async fn submit_request(
         &self,
         mut request: ChatCompletionRequest,
-    ) -> Result<ChatCompletionResponse> {
+        sync_notify: Option<Arc<Notify>>,
+        sender: Option<Arc<tokio::sync::mpsc::Sender<ChatResponse>>>,
+    ) -> Result<String> {
         if let Messages::Chat(messages) = &mut request.messages {
             crate::openai::requests::normalize_empty_openai_tool_results(messages);
             crate::openai::requests::validate_openai_tool_messages(messages)
@@ -691,10 +1017,6 @@ impl Engine {
             );
         }
 
-        // Let's create a local notify for this request.
-        let req_notify = std::sync::Arc::new(tokio::sync::Notify::new());
-
-        // Re-add request with req_notify
         let prefilled_reasoning_end =
             crate::tools::stream_parser::detect_prefilled_reasoning_end_marker(&prompt);
 
@@ -712,8 +1034,10 @@ impl Engine {
                 request.min_p,
                 request.top_k,
                 request.use_beam_search.unwrap_or(false),
-                1.0,
-                crate::openai::sampling_params::EarlyStoppingCondition::UnlikelyBetterCandidates,
+                request.length_penalty.unwrap_or(1.0),
+                request.early_stopping.clone().unwrap_or(
+                    crate::openai::sampling_params::EarlyStoppingCondition::UnlikelyBetterCandidates,
+                ),
                 request.stop.clone(),
                 request.stop_token_ids.clone().unwrap_or_default(),
                 request.ignore_eos.unwrap_or(false),
@@ -725,6 +1049,52 @@ impl Engine {
             )
             .map_err(candle_core::Error::msg)?;
             sampling_params.mcp_mode = if has_tools { Some(true) } else { None };
+            sampling_params.seed = request.seed;
+            sampling_params.priority = request.priority.unwrap_or_default();
+            sampling_params.user = request.user.clone();
+            sampling_params
+                .set_timeout(request.timeout)
+                .map_err(candle_core::Error::msg)?;
+            if sampling_params.best_of > 1 && !e.supports_parallel_sampling() {
+                return Err(candle_core::Error::msg(
+                    "`n` and `best_of` greater than 1 are not supported for hybrid models.",
+                ));
+            }
+            // Candidates are only ranked once they all finish, and beams are
+            // reordered until the search ends, so neither can be streamed.
+            if sender.is_some() && sampling_params.best_of > sampling_params.n {
+                return Err(candle_core::Error::msg(
+                    "`best_of` greater than `n` cannot be used with streaming.",
+                ));
+            }
+            if sampling_params.use_beam_search && sender.is_some() {
+                return Err(candle_core::Error::msg(
+                    "`use_beam_search` cannot be used with streaming.",
+                ));
+            }
+            if sampling_params.use_beam_search && has_tools {
+                return Err(candle_core::Error::msg(
+                    "`use_beam_search` cannot be used with tools.",
+                ));
+            }
+            let vocab_size = e.tokenizer().get_vocab_size(true);
+            sampling_params
+                .set_logit_bias(request.logit_bias.as_ref(), vocab_size)
+                .map_err(candle_core::Error::msg)?;
+            sampling_params
+                .set_response_format(request.response_format.as_ref())
+                .map_err(candle_core::Error::msg)?;
+            sampling_params
+                .set_tool_choice(
+                    &resolved_tools,
+                    &resolved_tool_choice,
+                    e.tool_call_envelope(),
+                )
+                .map_err(candle_core::Error::msg)?;
+            // Check the queue under the same lock as the enqueue so concurrent
+            // requests cannot all pass the limits.
+            e.check_admission(token_ids.len())
+                .map_err(candle_core::Error::msg)?;
             e.add_request(
                 token_ids,
                 request_id.clone(),
@@ -737,42 +1107,15 @@ impl Engine {
                 resolved_tools.clone(),
                 resolved_tool_choice,
                 image_data,
-                None, // streamer
-                Some(req_notify.clone()),
-                false,
+                sender.clone(),
+                sync_notify,
+                sender.is_some(), // include_usage
                 prefilled_reasoning_end,
             );
             self.notify.notify_one();
         }
 
-        // The loop below handles the wait.
-        loop {
-            let e = self.engine.read();
-            if e.completion_records.contains_key(&request_id) {
-                break;
-            }
-            drop(e);
-            req_notify.notified().await;
-        }
-
-        let e = self.engine.read();
-        let response_model = if e.model_name().is_empty() {
-            request.model.clone().unwrap_or("default".to_string())
-        } else {
-            e.model_name().to_string()
-        };
-        if let Some(record) = e.completion_records.get(&request_id) {
-            Ok(ChatCompletionResponse {
-                id: request_id,
-                choices: record.0.clone(),
-                created: record.1.created,
-                model: response_model,
-                object: "chat.completion",
-                usage: record.1.clone(),
-            })
-        } else {
-            Err(candle_core::Error::msg("Failed to get response"))
-        }
+        Ok(request_id)
     }
 
     pub fn embed(&self, request: EmbeddingRequest) -> Result<EmbeddingResponse> {
@@ -784,68 +1127,75 @@ impl Engine {
     }
 
     pub async fn embed_async(&self, request: EmbeddingRequest) -> Result<EmbeddingResponse> {
-        let prompt_tokens = {
+        let items = request.input.clone().into_items();
+        if items.is_empty() {
+            return Err(candle_core::Error::msg("Empty input"));
+        }
+        let prompts = {
             let e = self.engine.read();
-
-            let prompt_str = match &request.input {
-                crate::openai::requests::EmbeddingInput::String(s) => s.clone(),
-                crate::openai::requests::EmbeddingInput::MultiString(vec) => {
-                    // Just take the first one for now, or join?
-                    // The logic in openai_server handle list
-                    if vec.is_empty() {
-                        return Err(candle_core::Error::msg("Empty input"));
+            items
+                .into_iter()
+                .map(|item| match item {
+                    crate::openai::requests::CompletionPromptItem::Text(text) => Ok(e
+                        .tokenizer()
+                        .encode(text, false)
+                        .map_err(candle_core::Error::msg)?
+                        .get_ids()
+                        .to_vec()),
+                    crate::openai::requests::CompletionPromptItem::Tokens(token_ids) => {
+                        crate::openai::utils::check_prompt_token_ids(e.tokenizer(), &token_ids)?;
+                        Ok(token_ids)
                     }
-                    vec[0].clone()
-                }
-                _ => {
-                    return Err(candle_core::Error::msg(
-                        "Unsupported input type for internal API",
-                    ))
-                }
-            };
-
-            e.tokenizer()
-                .encode(prompt_str, false)
-                .map_err(candle_core::Error::msg)?
-                .get_ids()
-                .to_vec()
+                })
+                .collect::<Result<Vec<_>>>()?
         };
 
+        for prompt_tokens in &prompts {
+            if prompt_tokens.is_empty() {
+                return Err(candle_core::Error::msg(
+                    "Embedding input must contain at least one token",
+                ));
+            }
+            // Validate prompt length
+            self.validate_prompt(prompt_tokens, "embed_async")?;
+        }
         info!(
-            "[embed_async] Processing embedding request with {} tokens",
-            prompt_tokens.len()
+            "[embed_async] Processing embedding request with {} input(s), {} tokens",
+            prompts.len(),
+            prompts.iter().map(Vec::len).sum::<usize>()
         );
 
-        // Validate prompt length
-        self.validate_prompt(&prompt_tokens, "embed_async")?;
-
         let request_id = format!("embd-{}", uuid::Uuid::new_v4());
 
-        let (tx, mut rx) = tokio::sync::mpsc::channel(1024);
-
+        // Each input is embedded as its own engine request.
+        let mut receivers = Vec::with_capacity(prompts.len());
         {
             let mut e = self.engine.write();
-            e.add_request(
-                prompt_tokens,
-                request_id.clone(),
-                 std::time::SystemTime::now(),
-                 SamplingParams::new(
-                    1, None, 0.0, 0.0, None, None, None, None, None, false, 1.0,
-                    crate::openai::sampling_params::EarlyStoppingCondition::UnlikelyBetterCandidates,
-                    None, Vec::new(), false, 1, None, None, true, None
-                ).map_err(candle_core::Error::msg).unwrap(),
-                false,
-                true, // is_embedding
-                request.encoding_format.clone(),
-                request.embedding_type.clone(),
-                Vec::new(),
-                crate::openai::ToolChoiceKind::Auto,
-                None,
-                Some(std::sync::Arc::new(tx)),
-                None,
-                false,
-                None,
-            );
+            for (index, prompt_tokens) in prompts.into_iter().enumerate() {
+                let (tx, rx) = tokio::sync::mpsc::channel(1024);
+                receivers.push(rx);
+                e.add_request(
+                    prompt_tokens,
+                    format!("{request_id}-{index}"),
+                    std::time::SystemTime::now(),
+                    SamplingParams::new(
+                        1, None, 0.0, 0.0, None, None, None, None, None, false, 1.0,
+                        crate::openai::sampling_params::EarlyStoppingCondition::UnlikelyBetterCandidates,
+                        None, Vec::new(), false, 1, None, None, true, None
+                    ).map_err(candle_core::Error::msg)?,
+                    false,
+                    true, // is_embedding
+                    request.encoding_format.clone(),
+                    request.embedding_type.clone(),
+                    Vec::new(),
+                    crate::openai::ToolChoiceKind::Auto,
+                    None,
+                    Some(std::sync::Arc::new(tx)),
+                    None,
+                    false,
+                    None,
+                );
+            }
             self.notify.notify_one();
         }
 
@@ -854,33 +1204,37 @@ impl Engine {
             request_id
         );
 
-        match rx.recv().await {
-            Some(ChatResponse::Embedding(resp)) => {
-                info!(
-                    "[embed_async] Request {} completed successfully",
-                    request_id
-                );
-                Ok(resp)
-            }
-            Some(ChatResponse::ModelError(e)) => {
-                warn!(
-                    "[embed_async] Request {} failed with model error: {}",
-                    request_id, e
-                );
-                Err(candle_core::Error::msg(e.to_string()))
-            }
-            Some(_) => {
-                warn!(
-                    "[embed_async] Request {} received unexpected response type",
-                    request_id
-                );
-                Err(candle_core::Error::msg("Unexpected response type"))
-            }
-            None => {
-                warn!("[embed_async] Request {} channel closed", request_id);
-                Err(candle_core::Error::msg("Channel closed"))
-            }
+        let mut parts = Vec::with_capacity(receivers.len());
+        for mut rx in receivers {
+            let part = match rx.recv().await {
+                Some(ChatResponse::Embedding(resp)) => resp,
+                Some(ChatResponse::ModelError(e)) => {
+                    warn!(
+                        "[embed_async] Request {} failed with model error: {}",
+                        request_id, e
+                    );
+                    return Err(candle_core::Error::msg(e.message().to_string()));
+                }
+                Some(_) => {
+                    warn!(
+                        "[embed_async] Request {} received unexpected response type",
+                        request_id
+                    );
+                    return Err(candle_core::Error::msg("Unexpected response type"));
+                }
+                None => {
+                    warn!("[embed_async] Request {} channel closed", request_id);
+                    return Err(candle_core::Error::msg("Channel closed"));
+                }
+            };
+            parts.push(part);
         }
+        info!(
+            "[embed_async] Request {} completed successfully",
+            request_id
+        );
+        EmbeddingResponse::concat(parts)
+            .ok_or_else(|| candle_core::Error::msg("No embedding produced"))
     }
 
     pub fn shutdown(&self) {
@@ -890,3 +1244,65 @@ impl Engine {
         self.notify.notify_waiters();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::openai::responses::{Choice, ChoiceData};
+
+    #[test]
+    fn stream_delta_splits_chunk_fields() {
+        let chunk = ChatCompletionChunk {
+            id: "cmpl-1".to_string(),
+            choices: vec![Choice {
+                delta: ChoiceData {
+                    content: Some("Hi".to_string()),
+                    reasoning_content: Some("think".to_string()),
+                    role: Some("assistant".to_string()),
+                    tool_calls: None,
+                },
+                finish_reason: Some("stop".to_string()),
+                index: 1,
+                logprobs: None,
+            }],
+            created: 0,
+            model: "m".to_string(),
+            object: "chat.completion.chunk",
+            system_fingerprint: None,
+            usage: None,
+        };
+        let deltas = StreamDelta::from_chunk(chunk);
+        assert!(matches!(
+            deltas.as_slice(),
+            [
+                StreamDelta::Reasoning { index: 1, text: reasoning },
+                StreamDelta::Content { index: 1, text: content },
+                StreamDelta::Finish { index: 1, reason },
+            ] if reasoning == "think" && content == "Hi" && reason == "stop"
+        ));
+    }
+
+    #[test]
+    fn extra_model_specs_parse_repo_and_budget() {
+        let spec: ExtraModelSpec = "name=embed, m=Qwen/Qwen3-Embedding-0.6B, mem=2048, d=1"
+            .parse()
+            .unwrap();
+        assert_eq!(spec.name.as_deref(), Some("embed"));
+        assert!(matches!(
+            spec.builder.repo,
+            ModelRepo::ModelID(("Qwen/Qwen3-Embedding-0.6B", None))
+        ));
+        assert_eq!(spec.builder.kvcache_mem_gpu, 2048);
+        assert_eq!(spec.builder.kv_fraction, None);
+        assert_eq!(spec.builder.device_ids, Some(vec![1]));
+
+        let spec: ExtraModelSpec = "f=/models/q4.gguf".parse().unwrap();
+        assert!(spec.name.is_none());
+        assert!(matches!(spec.builder.repo, ModelRepo::ModelFile(_)));
+
+        assert!("name=x".parse::<ExtraModelSpec>().is_err());
+        assert!("m=a,w=b".parse::<ExtraModelSpec>().is_err());
+        assert!("m=a,kv-fraction=lots".parse::<ExtraModelSpec>().is_err());
+        assert!("m=a,port=1".parse::<ExtraModelSpec>().is_err());
+    }
+}
@@ -1,7 +1,53 @@
 use crate::openai::communicator::DaemonManager;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::{process, thread, time};
 use tracing::{debug, info, warn};
+
+static NUM_PEERS: AtomicUsize = AtomicUsize::new(0);
+static LAST_HEARTBEAT_MS: AtomicU64 = AtomicU64::new(0);
+static HEARTBEAT_FAILURES: AtomicUsize = AtomicUsize::new(0);
+
+/// State of the heartbeat between the main process and its daemon processes.
+#[derive(Debug, Clone)]
+pub struct HeartbeatStatus {
+    /// Daemon processes paired with this process (the main process, for a daemon).
+    pub num_peers: usize,
+    /// Time since the last successful heartbeat, if any.
+    pub since_last_heartbeat: Option<time::Duration>,
+    /// Heartbeats that failed since the last successful one.
+    pub consecutive_failures: usize,
+}
+
+fn now_ms() -> u64 {
+    time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Heartbeat state of this process, or `None` if no heartbeat worker runs.
+pub fn heartbeat_status() -> Option<HeartbeatStatus> {
+    let num_peers = NUM_PEERS.load(Ordering::Relaxed);
+    if num_peers == 0 {
+        return None;
+    }
+    let last = LAST_HEARTBEAT_MS.load(Ordering::Relaxed);
+    Some(HeartbeatStatus {
+        num_peers,
+        since_last_heartbeat: (last > 0)
+            .then(|| time::Duration::from_millis(now_ms().saturating_sub(last))),
+        consecutive_failures: HEARTBEAT_FAILURES.load(Ordering::Relaxed),
+    })
+}
+
 pub async fn heartbeat_worker(num_subprocess: Option<usize>) {
+    NUM_PEERS.store(
+        if DaemonManager::is_daemon() {
+            1
+        } else {
+            num_subprocess.unwrap_or(0)
+        },
+        Ordering::Relaxed,
+    );
     let _ = thread::spawn(move || {
         let mut connect_retry_count = 0;
         let mut command_manager = if DaemonManager::is_daemon() {
@@ -41,8 +87,11 @@ pub async fn heartbeat_worker(num_subprocess: Option<usize>) {
                     process::abort();
                 }
                 heartbeat_error_count += 1;
+                HEARTBEAT_FAILURES.fetch_add(1, Ordering::Relaxed);
             } else {
                 debug!("paired processes still alive!");
+                LAST_HEARTBEAT_MS.store(now_ms(), Ordering::Relaxed);
+                HEARTBEAT_FAILURES.store(0, Ordering::Relaxed);
             }
             let _ = thread::sleep(time::Duration::from_millis(1000 as u64));
         }
@@ -1,16 +1,24 @@
 use axum::{
     extract::State,
     http::{self, Method},
-    routing::{get, post},
+    middleware,
+    routing::{delete, get, post},
     Json, Router,
 };
 use candle_core::{DType, Device, Result};
+use candle_vllm::api::ExtraModelSpec;
 #[cfg(feature = "nccl")]
 use candle_vllm::backend::heartbeat;
+use candle_vllm::openai::auth::{require_api_key, ApiKeys};
 use candle_vllm::openai::models::Config;
-use candle_vllm::openai::openai_server::{chat_completions, create_embeddings};
+use candle_vllm::openai::openai_server::{
+    cancel_request, chat_completions, completions, create_embeddings, create_message,
+    create_response, delete_response, detokenize, health, key_usage, metrics, ready,
+    retrieve_response, status, tokenize,
+};
 use candle_vllm::openai::pipelines::llm_engine::LLMEngine;
 use candle_vllm::openai::pipelines::pipeline::DefaultLoader;
+use candle_vllm::openai::responses_api::ResponseStore;
 use candle_vllm::openai::sampling_params::GenerationConfig;
 use candle_vllm::openai::utils::{
     bind_addr_for_rank, bind_api_listener, ensure_server_bindings_or_exit,
@@ -18,6 +26,7 @@ use candle_vllm::openai::utils::{
 };
 use candle_vllm::openai::{kv_cache_capacity_tokens, OpenAIServerData};
 use candle_vllm::scheduler::cache_engine::{CacheConfig, CacheEngine};
+use candle_vllm::scheduler::fairness::parse_priority_limits;
 use candle_vllm::scheduler::prefix_cache::PrefixCacheConfig;
 use candle_vllm::scheduler::SchedulerConfig;
 use clap::Parser;
@@ -84,6 +93,24 @@ struct Args {
     #[arg(long)]
     mtp: Option<usize>,
 
+    /// Small model (Hugging Face id or local directory) sharing the target's tokenizer,
+    /// used for speculative decoding on any architecture (single-rank mode only)
+    #[arg(long)]
+    draft_model: Option<String>,
+
+    /// Tokens the draft model proposes per decode step
+    #[arg(long, default_value_t = 4)]
+    draft_tokens: usize,
+
+    /// Speculate up to this many tokens per decode step by prompt lookup: the tokens
+    /// that followed an earlier occurrence of the sequence's trailing n-gram
+    #[arg(long)]
+    prompt_lookup: Option<usize>,
+
+    /// Longest trailing n-gram matched by `--prompt-lookup`
+    #[arg(long, default_value_t = 3)]
+    prompt_lookup_ngram: usize,
+
     #[arg(long, default_value_t = false)]
     cpu: bool,
 
@@ -144,6 +171,32 @@ struct Args {
     #[arg(long)]
     prefill_chunk_size: Option<usize>,
 
+    /// Most running requests per priority class (interactive, normal, batch), e.g. `batch=4`
+    #[arg(long, value_delimiter = ',')]
+    priority_limits: Vec<String>,
+
+    /// Most requests waiting for prefill; further requests get 429 (default: unbounded)
+    #[arg(long)]
+    max_waiting_requests: Option<usize>,
+
+    /// Most prompt tokens waiting for prefill; further requests get 429 (default: unbounded)
+    #[arg(long)]
+    max_waiting_tokens: Option<usize>,
+
+    /// Seconds a request may wait before its first prefill before it is rejected
+    #[arg(long)]
+    queue_timeout: Option<f64>,
+
+    /// File of accepted API keys (`key [name=..] [rpm=..] [admin]` per line); falls back to
+    /// the comma-separated CANDLE_VLLM_API_KEYS. Requires `Authorization: Bearer` on /v1/*
+    #[arg(long)]
+    api_keys: Option<String>,
+
+    /// Another model to serve, routed by the request's `model` field; repeatable, e.g.
+    /// `name=embed,m=Qwen/Qwen3-Embedding-0.6B,kv-fraction=0.5` (single-process mode only)
+    #[arg(long)]
+    extra_model: Vec<ExtraModelSpec>,
+
     /// KV cache dtype: auto (default), fp8, turbo8, turbo4, turbo3
     #[arg(long)]
     kvcache_dtype: Option<String>,
@@ -254,7 +307,8 @@ async fn main() -> Result<()> {
         .with_mtp(args.mtp),
     );
 
-    let (paths, gguf) = loader.prepare_model_weights(args.hf_token, args.hf_token_path)?;
+    let (paths, gguf) =
+        loader.prepare_model_weights(args.hf_token.clone(), args.hf_token_path.clone())?;
 
     let dtype = candle_vllm::get_dtype(args.dtype);
     let kvcache_dtype_enum = if let Some(ref s) = args.kvcache_dtype {
@@ -294,6 +348,23 @@ async fn main() -> Result<()> {
         args.prefill_chunk_size.is_none() || args.prefill_chunk_size.unwrap() % 1024 == 0,
         "Error: prefill_chunk_size must be divisible by 1024!"
     );
+    let priority_limits =
+        parse_priority_limits(&args.priority_limits).map_err(candle_core::Error::msg)?;
+    let queue_timeout = args
+        .queue_timeout
+        .map(|secs| {
+            if secs.is_finite() && secs > 0.0 {
+                Ok(std::time::Duration::from_secs_f64(secs))
+            } else {
+                Err(candle_core::Error::msg(format!(
+                    "--queue-timeout must be a positive number of seconds, got {secs}"
+                )))
+            }
+        })
+        .transpose()?;
+    let api_keys = ApiKeys::from_file_or_env(args.api_keys.as_deref())
+        .map_err(candle_core::Error::msg)?
+        .map(Arc::new);
 
     // Multi-node validation
     let is_multi_node = args.num_nodes > 1;
@@ -356,6 +427,23 @@ async fn main() -> Result<()> {
     } else {
         !args.multithread
     };
+    // Without nccl, `multi_process` still runs every rank on engine threads.
+    let multiprocess_runner = multi_process && cfg!(feature = "nccl");
+    if multiprocess_runner && !args.extra_model.is_empty() {
+        candle_core::bail!("--extra-model is only supported in single-process mode");
+    }
+    if args.draft_model.is_some() && (multiprocess_runner || num_shards > 1) {
+        candle_core::bail!("--draft-model is only supported on a single device");
+    }
+    if args.prompt_lookup.is_some() && (args.draft_model.is_some() || args.mtp.is_some()) {
+        candle_core::bail!("use only one of --mtp, --draft-model and --prompt-lookup");
+    }
+    if args.prompt_lookup.is_some() && multiprocess_runner {
+        candle_core::bail!(
+            "--prompt-lookup is not supported in multi-process mode, use --multithread"
+        );
+    }
+    let draft_device_ids = device_ids.clone();
 
     #[cfg(all(feature = "cuda", feature = "graph"))]
     {
@@ -474,6 +562,44 @@ async fn main() -> Result<()> {
         Err(e) => panic!("{e:?}"),
         Ok((p, c)) => (p, c),
     };
+
+    // Load the draft before sizing the KV cache so its weights are accounted for.
+    let draft_pipeline = match &args.draft_model {
+        Some(draft_model) => {
+            let is_local = std::path::Path::new(draft_model).is_dir();
+            let loader = DefaultLoader::new(
+                (!is_local).then(|| draft_model.clone()),
+                is_local.then(|| draft_model.clone()),
+                None,
+                None,
+                None,
+            );
+            let (paths, gguf) =
+                loader.prepare_model_weights(args.hf_token.clone(), args.hf_token_path.clone())?;
+            let (mut pipelines, _) = loader
+                .load_model(
+                    paths,
+                    dtype,
+                    kv_cache_dtype,
+                    gguf,
+                    None,
+                    block_size,
+                    args.max_num_seqs,
+                    draft_device_ids,
+                    #[cfg(feature = "nccl")]
+                    None,
+                    None,
+                    None,
+                    #[cfg(feature = "nccl")]
+                    None,
+                    #[cfg(feature = "nccl")]
+                    None,
+                )
+                .await?;
+            Some(pipelines.remove(0))
+        }
+        None => None,
+    };
     let mut config: Option<Config> = None;
     let mut cache_config: Option<CacheConfig> = None;
     let devices: Vec<_> = default_pipelines
@@ -581,6 +707,36 @@ async fn main() -> Result<()> {
             Err(err) => return Err(err),
         };
 
+    // The draft cache is addressed through the target's block tables, so both
+    // get the same number of blocks; split the budget by their block sizes.
+    let kvcache_mem_gpu = match &draft_pipeline {
+        Some(draft) => {
+            let blocks_for = |cfg: &Config| {
+                candle_vllm::get_cache_config(
+                    kvcache_mem_gpu,
+                    args.kvcache_mem_cpu,
+                    block_size,
+                    cfg,
+                    kv_cache_dtype,
+                    num_shards,
+                    kvcache_dtype_enum,
+                )
+                .num_gpu_blocks
+                .unwrap_or(0)
+            };
+            let target_blocks = blocks_for(&first_config);
+            let draft_blocks = blocks_for(&draft.get_model_config());
+            let target_mem = kvcache_mem_gpu * draft_blocks / (target_blocks + draft_blocks).max(1);
+            info!(
+                "Draft model KV cache takes {} MB of the {} MB budget",
+                kvcache_mem_gpu - target_mem,
+                kvcache_mem_gpu
+            );
+            target_mem
+        }
+        None => kvcache_mem_gpu,
+    };
+
     let pipelines: std::collections::HashMap<usize, _> = default_pipelines
         .into_iter()
         .map(|pipeline| {
@@ -676,6 +832,10 @@ async fn main() -> Result<()> {
             max_num_batched_tokens,
             prefix_cache: prefix_cache_config,
             mamba_cache_capacity: mamba_active_slot_capacity,
+            priority_limits,
+            max_waiting_requests: args.max_waiting_requests,
+            max_waiting_tokens: args.max_waiting_tokens,
+            queue_timeout,
         },
         &cache_config,
         &config,
@@ -689,6 +849,27 @@ async fn main() -> Result<()> {
         args.disable_cuda_graph,
     )?;
 
+    if let Some(draft) = draft_pipeline {
+        // Same GPU block count as the target; draft KV is never swapped to CPU.
+        let mut draft_cache_config = cache_config.clone();
+        draft_cache_config.num_cpu_blocks = Some(0);
+        let cache_engine = CacheEngine::new(
+            &draft.get_model_config(),
+            &draft_cache_config,
+            draft_cache_config.dtype,
+            draft.device(),
+            num_shards,
+        )?;
+        llm_engine
+            .write()
+            .set_draft_model(draft, cache_engine, args.draft_tokens)?;
+    }
+    if let Some(num_speculative) = args.prompt_lookup {
+        llm_engine
+            .write()
+            .set_prompt_lookup(num_speculative, args.prompt_lookup_ngram)?;
+    }
+
     if args.temperature.is_some() || pipeline_config.generation_cfg.is_none() {
         //overwrite the generation config when temperature (and others) specified in arguments
         //disable multinomial sampling (generation randomness) by setting `temperature` as 0
@@ -753,12 +934,47 @@ async fn main() -> Result<()> {
         None
     };
 
+    // Extra models load after the main one has taken its KV cache, so each
+    // `kv-fraction` applies to the memory left at that point.
+    let response_store = Arc::new(ResponseStore::default());
+    let served_name = llm_engine.read().model_name().to_string();
+    let mut extra_models: Vec<Arc<OpenAIServerData>> = Vec::new();
+    for spec in args.extra_model {
+        let engine = spec
+            .builder
+            .with_kvcache_dtype(kvcache_dtype_enum)
+            .build_async()
+            .await?;
+        let name = spec
+            .name
+            .unwrap_or_else(|| engine.llm_engine().read().model_name().to_string());
+        if name == served_name || extra_models.iter().any(|m| m.served_name == name) {
+            candle_core::bail!("--extra-model name `{name}` is already served");
+        }
+        info!("Serving extra model `{name}`.");
+        extra_models.push(Arc::new(OpenAIServerData {
+            pipeline_config: engine.pipeline_config().clone(),
+            model: engine.llm_engine(),
+            record_conversation: args.record_conversation,
+            device: Device::Cpu,
+            mcp_manager: mcp_manager.clone(),
+            response_store: response_store.clone(),
+            api_keys: api_keys.clone(),
+            served_name: name,
+            extra_models: Vec::new(),
+        }));
+    }
+
     let server_data = OpenAIServerData {
         pipeline_config,
         model: llm_engine,
         record_conversation: args.record_conversation,
         device: Device::Cpu,
         mcp_manager: mcp_manager.clone(),
+        response_store,
+        api_keys: api_keys.clone(),
+        served_name,
+        extra_models,
     };
 
     if let Some(manager) = &mcp_manager {
@@ -788,25 +1004,22 @@ async fn main() -> Result<()> {
         .allow_methods(Any)
         .allow_headers(Any);
 
-    let app = Router::new()
+    let v1_routes: Router<Arc<OpenAIServerData>> = Router::new()
         .route(
             "/v1/models",
             get(|State(data): State<Arc<OpenAIServerData>>| async move {
-                let (model_name, modalities) = {
-                    let engine = data.model.read();
-                    let (pipeline, _) = engine.get_pipeline(0).unwrap();
-                    let modalities = if pipeline.image_config.is_some() {
-                        vec!["text", "image"]
-                    } else {
-                        vec!["text", "embedding"]
-                    };
-                    (pipeline.name().to_string(), modalities)
-                };
-                Json(json!({
-                    "object": "list",
-                    "data": [
-                        {
-                            "id": model_name,
+                let models = data
+                    .served_models()
+                    .map(|served| {
+                        let engine = served.model.read();
+                        let (pipeline, _) = engine.get_pipeline(0).unwrap();
+                        let modalities = if pipeline.image_config.is_some() {
+                            vec!["text", "image"]
+                        } else {
+                            vec!["text", "embedding"]
+                        };
+                        json!({
+                            "id": served.served_name,
                             "object": "model",
                             "created": std::time::SystemTime::now()
                                 .duration_since(std::time::UNIX_EPOCH)
@@ -815,14 +1028,50 @@ async fn main() -> Result<()> {
                             "owned_by": "candle-vllm",
                             "permission": [],
                             "modalities": modalities,
-                            "max_model_len": data.pipeline_config.max_model_len,
-                        }
-                    ]
+                            "max_model_len": served.pipeline_config.max_model_len,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                Json(json!({
+                    "object": "list",
+                    "data": models,
                 }))
             }),
         )
         .route("/v1/chat/completions", post(chat_completions))
+        .route("/v1/completions", post(completions))
         .route("/v1/embeddings", post(create_embeddings))
+        .route("/v1/messages", post(create_message))
+        .route("/v1/responses", post(create_response))
+        .route(
+            "/v1/responses/{response_id}",
+            get(retrieve_response).delete(delete_response),
+        )
+        .route("/v1/requests/{request_id}", delete(cancel_request))
+        // Tokenizing reports prefix-cache hits, which reveal other tenants' prompts.
+        .route("/tokenize", post(tokenize))
+        .route("/detokenize", post(detokenize));
+    let v1_routes = match &api_keys {
+        Some(keys) => {
+            info!(
+                "API key authentication enabled for /v1, /tokenize and /detokenize routes ({} keys).",
+                keys.len()
+            );
+            v1_routes.route_layer(middleware::from_fn_with_state(
+                keys.clone(),
+                require_api_key,
+            ))
+        }
+        None => v1_routes,
+    };
+
+    let app = Router::new()
+        .merge(v1_routes)
+        .route("/metrics", get(metrics))
+        .route("/health", get(health))
+        .route("/ready", get(ready))
+        .route("/status", get(status))
+        .route("/admin/usage", get(key_usage))
         .layer(cors_layer)
         .with_state(Arc::new(server_data));
 
@@ -0,0 +1,777 @@
+//! Anthropic Messages API (`/v1/messages`). Content blocks are mapped onto
+//! chat messages and served through the chat completion path; streamed chunks
+//! are re-emitted as Anthropic message events.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use super::requests::{
+    ChatCompletionRequest, ChatMessage, ImageUrlContent, MessageContent, MessageContentType,
+    Messages, StopTokens, StreamOptions,
+};
+use super::responses::{
+    APIError, ChatChoiceData, ChatCompletionChunk, ChatCompletionUsageResponse,
+};
+use crate::tools::{Function, Tool, ToolCall, ToolChoice, ToolChoiceFunction, ToolChoiceMode};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SystemPrompt {
+    Text(String),
+    Blocks(Vec<TextBlock>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TextBlock {
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum AnthropicContent {
+    Text(String),
+    Blocks(Vec<ContentBlock>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ImageSource {
+    Base64 { media_type: String, data: String },
+    Url { url: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentBlock {
+    Text {
+        text: String,
+    },
+    Image {
+        source: ImageSource,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: Value,
+    },
+    ToolResult {
+        tool_use_id: String,
+        #[serde(default)]
+        content: Option<AnthropicContent>,
+        #[serde(default)]
+        is_error: Option<bool>,
+    },
+    Thinking {
+        thinking: String,
+        #[serde(default)]
+        signature: Option<String>,
+    },
+    RedactedThinking {
+        data: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnthropicMessage {
+    pub role: String,
+    pub content: AnthropicContent,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnthropicTool {
+    /// Set for server tools (`web_search_20250305`, ...), absent for custom tools.
+    #[serde(default, rename = "type")]
+    pub tool_type: Option<String>,
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub input_schema: Option<Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AnthropicToolChoice {
+    Auto,
+    Any,
+    Tool { name: String },
+    None,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ThinkingConfig {
+    Enabled {
+        #[serde(default)]
+        budget_tokens: Option<usize>,
+    },
+    Disabled,
+}
+
+/// Request body of `/v1/messages`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessagesRequest {
+    pub model: Option<String>,
+    pub messages: Vec<AnthropicMessage>,
+    #[serde(default)]
+    pub system: Option<SystemPrompt>,
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+    #[serde(default)]
+    pub stop_sequences: Option<Vec<String>>,
+    #[serde(default)]
+    pub stream: Option<bool>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub top_p: Option<f32>,
+    #[serde(default)]
+    pub top_k: Option<isize>,
+    #[serde(default)]
+    pub tools: Option<Vec<AnthropicTool>>,
+    #[serde(default)]
+    pub tool_choice: Option<AnthropicToolChoice>,
+    #[serde(default)]
+    pub thinking: Option<ThinkingConfig>,
+    #[serde(default)]
+    pub metadata: Option<Value>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OutputBlock {
+    Thinking {
+        thinking: String,
+        signature: String,
+    },
+    Text {
+        text: String,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: Value,
+    },
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AnthropicUsage {
+    pub input_tokens: usize,
+    pub output_tokens: usize,
+    pub cache_read_input_tokens: usize,
+}
+
+impl From<&ChatCompletionUsageResponse> for AnthropicUsage {
+    fn from(usage: &ChatCompletionUsageResponse) -> Self {
+        Self {
+            input_tokens: usage.prompt_tokens,
+            output_tokens: usage.completion_tokens,
+            cache_read_input_tokens: usage
+                .prompt_tokens_details
+                .as_ref()
+                .map_or(0, |details| details.cached_tokens),
+        }
+    }
+}
+
+/// A `message` object, as returned by `/v1/messages`.
+#[derive(Debug, Clone, Serialize)]
+pub struct MessagesResponse {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub object: &'static str,
+    pub role: &'static str,
+    pub model: String,
+    pub content: Vec<OutputBlock>,
+    pub stop_reason: Option<&'static str>,
+    pub stop_sequence: Option<String>,
+    pub usage: AnthropicUsage,
+}
+
+impl MessagesResponse {
+    pub fn new(model: String) -> Self {
+        Self {
+            id: format!("msg_{}", Uuid::new_v4().simple()),
+            object: "message",
+            role: "assistant",
+            model,
+            content: Vec::new(),
+            stop_reason: None,
+            stop_sequence: None,
+            usage: AnthropicUsage::default(),
+        }
+    }
+
+    /// Complete the message with its content blocks.
+    pub fn finish(
+        &mut self,
+        content: Vec<OutputBlock>,
+        finish_reason: Option<&str>,
+        usage: Option<&ChatCompletionUsageResponse>,
+    ) {
+        self.content = content;
+        self.stop_reason = Some(stop_reason(finish_reason));
+        if let Some(usage) = usage {
+            self.usage = AnthropicUsage::from(usage);
+        }
+    }
+}
+
+fn stop_reason(finish_reason: Option<&str>) -> &'static str {
+    match finish_reason {
+        Some("length") => "max_tokens",
+        Some("tool_calls") => "tool_use",
+        _ => "end_turn",
+    }
+}
+
+fn chat_message(role: &str, content: Option<MessageContentType>) -> ChatMessage {
+    ChatMessage {
+        role: role.to_string(),
+        content,
+        tool_calls: None,
+        tool_call_id: None,
+        reasoning_content: None,
+    }
+}
+
+fn image_content(source: ImageSource) -> MessageContent {
+    match source {
+        ImageSource::Base64 { media_type, data } => MessageContent::ImageBase64 {
+            image_base64: format!("data:{media_type};base64,{data}"),
+        },
+        ImageSource::Url { url } => MessageContent::ImageUrl {
+            image_url: ImageUrlContent::Url(url),
+        },
+    }
+}
+
+fn tool_result_text(content: Option<AnthropicContent>) -> Result<String, APIError> {
+    let blocks = match content {
+        None => return Ok(String::new()),
+        Some(AnthropicContent::Text(text)) => return Ok(text),
+        Some(AnthropicContent::Blocks(blocks)) => blocks,
+    };
+    blocks
+        .into_iter()
+        .map(|block| match block {
+            ContentBlock::Text { text } => Ok(text),
+            _ => Err(APIError::new_str(
+                "Only `text` blocks are supported in `tool_result` content.",
+            )),
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(|texts| texts.join("\n"))
+}
+
+/// Convert one Anthropic message into chat messages. Tool results of a user
+/// turn become `tool` messages ahead of its remaining content; thinking and
+/// tool use blocks of an assistant turn join its message.
+fn convert_message(message: AnthropicMessage) -> Result<Vec<ChatMessage>, APIError> {
+    let role = match message.role.as_str() {
+        "user" | "assistant" => message.role.as_str(),
+        other => {
+            return Err(APIError::new(format!(
+                "Unsupported message role '{other}'."
+            )))
+        }
+    };
+    let blocks = match message.content {
+        AnthropicContent::Text(text) => {
+            return Ok(vec![chat_message(
+                role,
+                Some(MessageContentType::PureText(text)),
+            )])
+        }
+        AnthropicContent::Blocks(blocks) => blocks,
+    };
+    let mut messages = Vec::new();
+    let mut parts = Vec::new();
+    let mut reasoning: Option<String> = None;
+    let mut tool_calls = Vec::new();
+    for block in blocks {
+        match block {
+            ContentBlock::Text { text } => parts.push(MessageContent::Text { text }),
+            ContentBlock::Image { source } => parts.push(image_content(source)),
+            ContentBlock::ToolUse { id, name, input } if role == "assistant" => {
+                tool_calls.push(ToolCall::new(id, name, input.to_string()));
+            }
+            ContentBlock::ToolResult {
+                tool_use_id,
+                content,
+                is_error,
+            } if role == "user" => {
+                let mut text = tool_result_text(content)?;
+                if is_error == Some(true) {
+                    text = format!("Error: {text}");
+                }
+                let mut tool = chat_message("tool", Some(MessageContentType::PureText(text)));
+                tool.tool_call_id = Some(tool_use_id);
+                messages.push(tool);
+            }
+            ContentBlock::Thinking { thinking, .. } if role == "assistant" => {
+                reasoning = Some(match reasoning {
+                    Some(previous) => format!("{previous}\n{thinking}"),
+                    None => thinking,
+                });
+            }
+            ContentBlock::RedactedThinking { .. } => {}
+            _ => {
+                return Err(APIError::new(format!(
+                    "Unsupported content block in a '{role}' message."
+                )))
+            }
+        }
+    }
+    if role == "user" && parts.is_empty() && !messages.is_empty() {
+        return Ok(messages);
+    }
+    let mut chat = chat_message(role, None);
+    let all_text = parts
+        .iter()
+        .all(|part| matches!(part, MessageContent::Text { .. }));
+    chat.content = Some(if all_text {
+        MessageContentType::PureText(
+            parts
+                .into_iter()
+                .filter_map(|part| match part {
+                    MessageContent::Text { text } => Some(text),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        )
+    } else {
+        MessageContentType::Multi(parts)
+    });
+    chat.reasoning_content = reasoning;
+    chat.tool_calls = (!tool_calls.is_empty()).then_some(tool_calls);
+    messages.push(chat);
+    Ok(messages)
+}
+
+fn chat_tools(tools: &[AnthropicTool]) -> Result<Vec<Tool>, APIError> {
+    tools
+        .iter()
+        .map(|tool| {
+            if let Some(tool_type) = tool.tool_type.as_deref().filter(|t| *t != "custom") {
+                return Err(APIError::new(format!(
+                    "Unsupported tool type '{tool_type}'; only custom tools are supported."
+                )));
+            }
+            Ok(Tool {
+                tool_type: "function".to_string(),
+                function: Function {
+                    name: tool.name.clone(),
+                    description: tool.description.clone(),
+                    parameters: tool
+                        .input_schema
+                        .clone()
+                        .unwrap_or(json!({"type": "object", "properties": {}})),
+                    strict: None,
+                },
+            })
+        })
+        .collect()
+}
+
+/// Build the chat request for a messages request.
+pub fn chat_request(request: &MessagesRequest) -> Result<ChatCompletionRequest, APIError> {
+    let mut messages = Vec::with_capacity(request.messages.len() + 1);
+    if let Some(system) = &request.system {
+        let system = match system {
+            SystemPrompt::Text(text) => text.clone(),
+            SystemPrompt::Blocks(blocks) => blocks
+                .iter()
+                .map(|block| block.text.as_str())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        };
+        messages.push(chat_message(
+            "system",
+            Some(MessageContentType::PureText(system)),
+        ));
+    }
+    for message in &request.messages {
+        messages.extend(convert_message(message.clone())?);
+    }
+    let tools = chat_tools(request.tools.as_deref().unwrap_or_default())?;
+    let tool_choice = request.tool_choice.as_ref().map(|choice| match choice {
+        AnthropicToolChoice::Auto => ToolChoice::Mode(ToolChoiceMode::Auto),
+        AnthropicToolChoice::Any => ToolChoice::Mode(ToolChoiceMode::Required),
+        AnthropicToolChoice::None => ToolChoice::Mode(ToolChoiceMode::None),
+        AnthropicToolChoice::Tool { name } => ToolChoice::Function {
+            choice_type: crate::tools::ToolChoiceType::Function,
+            function: ToolChoiceFunction { name: name.clone() },
+        },
+    });
+    Ok(ChatCompletionRequest {
+        model: request.model.clone(),
+        messages: Messages::Chat(messages),
+        temperature: request.temperature,
+        top_p: request.top_p,
+        top_k: request.top_k,
+        max_tokens: request.max_tokens,
+        stop: request.stop_sequences.clone().map(StopTokens::Multi),
+        stream: request.stream,
+        stream_options: Some(StreamOptions {
+            include_usage: true,
+        }),
+        thinking: request
+            .thinking
+            .as_ref()
+            .map(|thinking| matches!(thinking, ThinkingConfig::Enabled { .. })),
+        user: request
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.get("user_id"))
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        tools: (!tools.is_empty()).then_some(tools),
+        tool_choice,
+        ..Default::default()
+    })
+}
+
+fn tool_use_block(call: &ToolCall) -> OutputBlock {
+    let arguments = call.function.arguments.as_deref().unwrap_or_default();
+    OutputBlock::ToolUse {
+        id: call.id.clone(),
+        name: call.function.name.clone(),
+        input: serde_json::from_str(arguments).unwrap_or(json!({})),
+    }
+}
+
+/// Content blocks of a finished chat choice.
+pub fn output_blocks(message: &ChatChoiceData) -> Vec<OutputBlock> {
+    let mut blocks = Vec::new();
+    if let Some(reasoning) = message.reasoning_content.as_ref().filter(|r| !r.is_empty()) {
+        blocks.push(OutputBlock::Thinking {
+            thinking: reasoning.clone(),
+            signature: String::new(),
+        });
+    }
+    if let Some(content) = message.content.as_ref().filter(|c| !c.is_empty()) {
+        blocks.push(OutputBlock::Text {
+            text: content.clone(),
+        });
+    }
+    for call in message.tool_calls.iter().flatten() {
+        blocks.push(tool_use_block(call));
+    }
+    blocks
+}
+
+/// A server-sent event of a streamed message.
+pub struct MessageStreamEvent {
+    pub event: &'static str,
+    pub data: Value,
+}
+
+fn stream_event(event: &'static str, mut data: Value) -> MessageStreamEvent {
+    data["type"] = json!(event);
+    MessageStreamEvent { event, data }
+}
+
+#[derive(PartialEq)]
+enum OpenBlock {
+    Thinking,
+    Text,
+}
+
+/// Turns chat completion chunks into Anthropic message streaming events.
+pub struct MessageStreamState {
+    message: MessagesResponse,
+    /// Index of the next content block.
+    index: usize,
+    open: Option<OpenBlock>,
+    finish_reason: Option<String>,
+    usage: Option<ChatCompletionUsageResponse>,
+}
+
+impl MessageStreamState {
+    pub fn new(message: MessagesResponse) -> Self {
+        Self {
+            message,
+            index: 0,
+            open: None,
+            finish_reason: None,
+            usage: None,
+        }
+    }
+
+    /// `message_start` and an initial `ping`.
+    pub fn start(&self) -> Vec<MessageStreamEvent> {
+        vec![
+            stream_event("message_start", json!({ "message": self.message })),
+            stream_event("ping", json!({})),
+        ]
+    }
+
+    fn close_block(&mut self, events: &mut Vec<MessageStreamEvent>) {
+        if self.open.take().is_some() {
+            events.push(stream_event(
+                "content_block_stop",
+                json!({ "index": self.index }),
+            ));
+            self.index += 1;
+        }
+    }
+
+    fn push_delta(&mut self, kind: OpenBlock, delta: &str, events: &mut Vec<MessageStreamEvent>) {
+        if self.open.as_ref() != Some(&kind) {
+            self.close_block(events);
+            let block = match kind {
+                OpenBlock::Thinking => {
+                    json!({ "type": "thinking", "thinking": "", "signature": "" })
+                }
+                OpenBlock::Text => json!({ "type": "text", "text": "" }),
+            };
+            events.push(stream_event(
+                "content_block_start",
+                json!({ "index": self.index, "content_block": block }),
+            ));
+            self.open = Some(kind);
+        }
+        let delta = match self.open {
+            Some(OpenBlock::Thinking) => json!({ "type": "thinking_delta", "thinking": delta }),
+            _ => json!({ "type": "text_delta", "text": delta }),
+        };
+        events.push(stream_event(
+            "content_block_delta",
+            json!({ "index": self.index, "delta": delta }),
+        ));
+    }
+
+    /// Events for one chat completion chunk.
+    pub fn on_chunk(&mut self, chunk: ChatCompletionChunk) -> Vec<MessageStreamEvent> {
+        let mut events = Vec::new();
+        if let Some(usage) = chunk.usage {
+            self.usage = Some(usage);
+        }
+        for choice in chunk.choices {
+            if let Some(reasoning) = choice.delta.reasoning_content.filter(|r| !r.is_empty()) {
+                self.push_delta(OpenBlock::Thinking, &reasoning, &mut events);
+            }
+            if let Some(content) = choice.delta.content.filter(|c| !c.is_empty()) {
+                self.push_delta(OpenBlock::Text, &content, &mut events);
+            }
+            for call in choice.delta.tool_calls.iter().flatten() {
+                self.close_block(&mut events);
+                events.push(stream_event(
+                    "content_block_start",
+                    json!({
+                        "index": self.index,
+                        "content_block": { "type": "tool_use", "id": call.id, "name": call.function.name, "input": {} },
+                    }),
+                ));
+                events.push(stream_event(
+                    "content_block_delta",
+                    json!({
+                        "index": self.index,
+                        "delta": { "type": "input_json_delta", "partial_json": call.function.arguments.as_deref().unwrap_or_default() },
+                    }),
+                ));
+                events.push(stream_event(
+                    "content_block_stop",
+                    json!({ "index": self.index }),
+                ));
+                self.index += 1;
+            }
+            if choice.finish_reason.is_some() {
+                self.finish_reason = choice.finish_reason;
+            }
+        }
+        events
+    }
+
+    /// Close the open block, then `message_delta` with the stop reason and
+    /// usage, and `message_stop`.
+    pub fn finish(&mut self) -> Vec<MessageStreamEvent> {
+        let mut events = Vec::new();
+        self.close_block(&mut events);
+        let usage = self
+            .usage
+            .as_ref()
+            .map(AnthropicUsage::from)
+            .unwrap_or_default();
+        events.push(stream_event(
+            "message_delta",
+            json!({
+                "delta": { "stop_reason": stop_reason(self.finish_reason.as_deref()), "stop_sequence": null },
+                "usage": usage,
+            }),
+        ));
+        events.push(stream_event("message_stop", json!({})));
+        events
+    }
+
+    /// An `error` event, for failures after the stream has started.
+    pub fn error(message: String) -> MessageStreamEvent {
+        stream_event(
+            "error",
+            json!({ "error": { "type": "api_error", "message": message } }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::openai::responses::{Choice, ChoiceData};
+
+    fn request(body: Value) -> MessagesRequest {
+        serde_json::from_value(body).unwrap()
+    }
+
+    #[test]
+    fn content_blocks_map_to_chat_messages() {
+        let request = request(json!({
+            "model": "m",
+            "max_tokens": 128,
+            "system": [{"type": "text", "text": "Be brief."}],
+            "stop_sequences": ["\n\n"],
+            "thinking": {"type": "enabled", "budget_tokens": 1024},
+            "tools": [{"name": "weather", "input_schema": {"type": "object"}}],
+            "tool_choice": {"type": "any"},
+            "messages": [
+                {"role": "user", "content": [
+                    {"type": "text", "text": "Weather?"},
+                    {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "AAAA"}}
+                ]},
+                {"role": "assistant", "content": [
+                    {"type": "thinking", "thinking": "Need a tool.", "signature": "sig"},
+                    {"type": "tool_use", "id": "toolu_1", "name": "weather", "input": {"city": "Paris"}}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "toolu_1", "content": [{"type": "text", "text": "Sunny"}]}
+                ]}
+            ]
+        }));
+        let chat = chat_request(&request).unwrap();
+        let Messages::Chat(messages) = &chat.messages else {
+            panic!("expected chat messages");
+        };
+        let roles = messages.iter().map(|m| m.role.as_str()).collect::<Vec<_>>();
+        assert_eq!(roles, vec!["system", "user", "assistant", "tool"]);
+        assert!(matches!(
+            &messages[1].content,
+            Some(MessageContentType::Multi(parts)) if matches!(
+                &parts[1],
+                MessageContent::ImageBase64 { image_base64 } if image_base64.starts_with("data:image/png;base64,")
+            )
+        ));
+        assert_eq!(
+            messages[2].reasoning_content.as_deref(),
+            Some("Need a tool.")
+        );
+        let call = &messages[2].tool_calls.as_ref().unwrap()[0];
+        assert_eq!(
+            call.function.arguments.as_deref(),
+            Some("{\"city\":\"Paris\"}")
+        );
+        assert_eq!(messages[3].tool_call_id.as_deref(), Some("toolu_1"));
+        assert!(matches!(
+            &messages[3].content,
+            Some(MessageContentType::PureText(text)) if text == "Sunny"
+        ));
+        assert_eq!(chat.thinking, Some(true));
+        assert!(matches!(chat.stop, Some(StopTokens::Multi(_))));
+        assert!(matches!(
+            chat.tool_choice,
+            Some(ToolChoice::Mode(ToolChoiceMode::Required))
+        ));
+
+        let request = self::request(json!({
+            "messages": [{"role": "user", "content": [{"type": "tool_use", "id": "t", "name": "n", "input": {}}]}]
+        }));
+        assert!(chat_request(&request).is_err());
+    }
+
+    #[test]
+    fn output_blocks_parse_tool_arguments() {
+        let message = ChatChoiceData {
+            content: Some("Calling.".to_string()),
+            reasoning_content: None,
+            role: "assistant".to_string(),
+            tool_calls: Some(vec![ToolCall::new(
+                "call_1",
+                "weather",
+                "{\"city\":\"Paris\"}",
+            )]),
+        };
+        let blocks = json!(output_blocks(&message));
+        assert_eq!(blocks[0]["type"], "text");
+        assert_eq!(blocks[1]["type"], "tool_use");
+        assert_eq!(blocks[1]["input"]["city"], "Paris");
+    }
+
+    fn chunk(
+        reasoning: Option<&str>,
+        content: Option<&str>,
+        tool_call: Option<ToolCall>,
+        finish_reason: Option<&str>,
+    ) -> ChatCompletionChunk {
+        ChatCompletionChunk {
+            id: "cmpl-1".to_string(),
+            choices: vec![Choice {
+                delta: ChoiceData {
+                    content: content.map(str::to_string),
+                    reasoning_content: reasoning.map(str::to_string),
+                    role: None,
+                    tool_calls: tool_call.map(|call| vec![call]),
+                },
+                finish_reason: finish_reason.map(str::to_string),
+                index: 0,
+                logprobs: None,
+            }],
+            created: 0,
+            model: "m".to_string(),
+            object: "chat.completion.chunk",
+            system_fingerprint: None,
+            usage: None,
+        }
+    }
+
+    #[test]
+    fn stream_events_follow_content_blocks() {
+        let mut state = MessageStreamState::new(MessagesResponse::new("m".to_string()));
+        let mut events = state.start();
+        events.extend(state.on_chunk(chunk(Some("Hm"), None, None, None)));
+        events.extend(state.on_chunk(chunk(None, Some("Let me"), None, None)));
+        events.extend(state.on_chunk(chunk(None, Some(" check."), None, None)));
+        events.extend(state.on_chunk(chunk(
+            None,
+            None,
+            Some(ToolCall::new("call_1", "weather", "{}")),
+            Some("tool_calls"),
+        )));
+        events.extend(state.finish());
+        let names = events.iter().map(|e| e.event).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "message_start",
+                "ping",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_delta",
+                "content_block_stop",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "message_delta",
+                "message_stop",
+            ]
+        );
+        assert_eq!(events[3].data["delta"]["type"], "thinking_delta");
+        assert_eq!(events[10].data["index"], 2);
+        assert_eq!(events[10].data["delta"]["type"], "input_json_delta");
+        assert_eq!(events[12].data["delta"]["stop_reason"], "tool_use");
+        assert!(events.iter().all(|e| e.data["type"] == e.event));
+    }
+}
@@ -0,0 +1,274 @@
+//! Optional API-key authentication for the `/v1/*` routes.
+//!
+//! Keys come from the `--api-keys` file, or from `CANDLE_VLLM_API_KEYS` when
+//! no file is given. Each entry is a key followed by optional settings:
+//!
+//! ```text
+//! # key                name=<label>  rpm=<requests per minute>  admin
+//! sk-team-a-5f2c9d     name=team-a   rpm=120
+//! sk-ops-91be04        name=ops      admin
+//! ```
+//!
+//! The file holds one entry per line; the environment variable separates
+//! entries with commas. Token usage is accounted per key name by the engine.
+
+use super::responses::{APIError, ChatResponder};
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderMap};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Environment variable read when `--api-keys` is not given.
+pub const API_KEYS_ENV: &str = "CANDLE_VLLM_API_KEYS";
+
+/// An accepted key and its settings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiKey {
+    /// Label that usage is accounted under; never the secret itself.
+    pub name: String,
+    /// Requests allowed per minute, unlimited when unset.
+    pub requests_per_minute: Option<u32>,
+    /// Whether the key may read `/admin/usage`.
+    pub admin: bool,
+}
+
+/// Name of the key that authenticated a request, stored as a request extension.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedKey(pub String);
+
+/// Token bucket refilled at `requests_per_minute / 60` per second.
+#[derive(Debug)]
+struct RateBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateBucket {
+    /// Take one request from the bucket, or return the seconds until one is available.
+    fn acquire(&mut self, requests_per_minute: u32, now: Instant) -> Result<(), u64> {
+        let capacity = requests_per_minute as f64;
+        let per_second = capacity / 60.0;
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * per_second).min(capacity);
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - self.tokens) / per_second).ceil().max(1.0) as u64)
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ApiKeys {
+    keys: HashMap<String, ApiKey>,
+    buckets: Mutex<HashMap<String, RateBucket>>,
+}
+
+impl ApiKeys {
+    /// Load keys from `path`, falling back to `CANDLE_VLLM_API_KEYS`.
+    /// Returns `None` when neither is set, leaving the API open.
+    pub fn from_file_or_env(path: Option<&str>) -> Result<Option<Self>, String> {
+        let keys = match path {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .map_err(|e| format!("unable to read API keys file {path}: {e}"))?;
+                Self::parse(text.lines())?
+            }
+            None => match std::env::var(API_KEYS_ENV) {
+                Ok(value) if !value.trim().is_empty() => Self::parse(value.split(','))?,
+                _ => return Ok(None),
+            },
+        };
+        if keys.keys.is_empty() {
+            return Err("no API keys configured".to_string());
+        }
+        Ok(Some(keys))
+    }
+
+    /// Parse key entries, skipping blank lines and `#` comments.
+    pub fn parse<'a>(entries: impl IntoIterator<Item = &'a str>) -> Result<Self, String> {
+        let mut keys = HashMap::new();
+        for entry in entries {
+            let entry = entry.trim();
+            if entry.is_empty() || entry.starts_with('#') {
+                continue;
+            }
+            let mut fields = entry.split_whitespace();
+            let secret = fields.next().unwrap_or_default();
+            let mut key = ApiKey {
+                name: format!("key-{}", keys.len() + 1),
+                requests_per_minute: None,
+                admin: false,
+            };
+            for field in fields {
+                match field.split_once('=') {
+                    Some(("name", name)) if !name.is_empty() => key.name = name.to_string(),
+                    Some(("rpm", rpm)) => {
+                        key.requests_per_minute = match rpm.parse::<u32>() {
+                            Ok(rpm) if rpm > 0 => Some(rpm),
+                            _ => return Err(format!("invalid rpm `{rpm}` for key {}", key.name)),
+                        }
+                    }
+                    None if field == "admin" => key.admin = true,
+                    _ => return Err(format!("unknown API key option `{field}`")),
+                }
+            }
+            if keys.values().any(|other: &ApiKey| other.name == key.name) {
+                return Err(format!("duplicate API key name `{}`", key.name));
+            }
+            if keys.insert(secret.to_string(), key).is_some() {
+                return Err("duplicate API key".to_string());
+            }
+        }
+        Ok(Self {
+            keys,
+            buckets: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.keys.values().map(|key| key.name.as_str())
+    }
+
+    /// Find the key sent as `Authorization: Bearer <key>` or `x-api-key`.
+    pub fn authenticate(&self, headers: &HeaderMap) -> Option<&ApiKey> {
+        let bearer = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        let secret = bearer.or_else(|| {
+            headers
+                .get("x-api-key")
+                .and_then(|value| value.to_str().ok())
+        })?;
+        self.keys.get(secret.trim())
+    }
+
+    /// Count a request against the key's rate limit, returning the seconds
+    /// to wait when the limit is reached.
+    pub fn check_rate(&self, key: &ApiKey, now: Instant) -> Result<(), u64> {
+        let Some(requests_per_minute) = key.requests_per_minute else {
+            return Ok(());
+        };
+        self.buckets
+            .lock()
+            .entry(key.name.clone())
+            .or_insert_with(|| RateBucket {
+                tokens: requests_per_minute as f64,
+                updated: now,
+            })
+            .acquire(requests_per_minute, now)
+    }
+}
+
+fn unauthorized() -> ChatResponder {
+    ChatResponder::Unauthorized(
+        APIError::new_str("Missing or invalid API key.").with_code("invalid_api_key"),
+    )
+}
+
+/// Reject requests without a valid key and apply the key's rate limit.
+pub async fn require_api_key(
+    State(keys): State<Arc<ApiKeys>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(key) = keys.authenticate(request.headers()) else {
+        return unauthorized().into_response();
+    };
+    if let Err(retry_after) = keys.check_rate(key, Instant::now()) {
+        return ChatResponder::TooManyRequests(
+            APIError::new(format!(
+                "Rate limit of {} requests per minute reached for API key {}.",
+                key.requests_per_minute.unwrap_or_default(),
+                key.name
+            ))
+            .with_code("rate_limit_exceeded"),
+            retry_after,
+        )
+        .into_response();
+    }
+    request
+        .extensions_mut()
+        .insert(AuthenticatedKey(key.name.clone()));
+    next.run(request).await
+}
+
+/// Allow only admin keys through; used for the `/admin/*` routes.
+pub fn require_admin(keys: &ApiKeys, headers: &HeaderMap) -> Result<(), ChatResponder> {
+    match keys.authenticate(headers) {
+        Some(key) if key.admin => Ok(()),
+        Some(key) => Err(ChatResponder::Forbidden(APIError::new(format!(
+            "API key {} is not an admin key.",
+            key.name
+        )))),
+        None => Err(unauthorized()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use std::time::Duration;
+
+    #[test]
+    fn entries_parse_names_limits_and_admin() {
+        let keys = ApiKeys::parse(
+            "# comment\nsk-a name=team-a rpm=60\n\nsk-b admin\n"
+                .lines()
+                .chain(["sk-c"]),
+        )
+        .unwrap();
+        assert_eq!(keys.len(), 3);
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer sk-a"),
+        );
+        let key = keys.authenticate(&headers).unwrap();
+        assert_eq!(key.name, "team-a");
+        assert_eq!(key.requests_per_minute, Some(60));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", HeaderValue::from_static("sk-b"));
+        let key = keys.authenticate(&headers).unwrap();
+        assert_eq!(key.name, "key-2");
+        assert!(key.admin);
+
+        headers.insert("x-api-key", HeaderValue::from_static("sk-unknown"));
+        assert!(keys.authenticate(&headers).is_none());
+
+        assert!(ApiKeys::parse(["sk-a rpm=0"]).is_err());
+        assert!(ApiKeys::parse(["sk-a role=x"]).is_err());
+        assert!(ApiKeys::parse(["sk-a", "sk-a"]).is_err());
+        assert!(ApiKeys::parse(["sk-a name=x", "sk-b name=x"]).is_err());
+    }
+
+    #[test]
+    fn rate_limit_refills_over_time() {
+        let keys = ApiKeys::parse(["sk-a rpm=2"]).unwrap();
+        let key = keys.keys["sk-a"].clone();
+        let start = Instant::now();
+        assert!(keys.check_rate(&key, start).is_ok());
+        assert!(keys.check_rate(&key, start).is_ok());
+        assert_eq!(keys.check_rate(&key, start), Err(30));
+        assert!(keys
+            .check_rate(&key, start + Duration::from_secs(30))
+            .is_ok());
+    }
+}
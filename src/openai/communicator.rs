@@ -85,6 +85,11 @@ pub enum MessageType {
         seq_len: usize,
         verify_payload: ForwardPayload,
     },
+    /// Keep mamba state through `keep_tokens` verified MTP tokens.
+    MtpRollback {
+        seq_id: usize,
+        keep_tokens: usize,
+    },
     FinishSequences(Vec<usize>),
     MambaPrefixCapture {
         seq_id: usize,
@@ -463,6 +463,24 @@ impl ChatTemplate {
         env.set_lstrip_blocks(true);
         env.set_trim_blocks(true);
         env.set_unknown_method_callback(minijinja_contrib::pycompat::unknown_method_callback);
+        // Helpers provided by the HF template runtime (used by e.g. gpt-oss).
+        env.add_function("strftime_now", |format: String| {
+            use std::fmt::Write;
+            let mut out = String::new();
+            write!(out, "{}", chrono::Local::now().format(&format)).map_err(|_| {
+                minijinja::Error::new(
+                    minijinja::ErrorKind::InvalidOperation,
+                    format!("invalid strftime format `{format}`"),
+                )
+            })?;
+            Ok::<_, minijinja::Error>(out)
+        });
+        env.add_function("raise_exception", |message: String| {
+            Err::<String, _>(minijinja::Error::new(
+                minijinja::ErrorKind::InvalidOperation,
+                message,
+            ))
+        });
         let template = normalize_template_source(self.chat_template.as_ref().unwrap());
         let mut template = template.replace("[::-1]", "|reverse");
         if template.find("{{ meta }}").is_some() {
@@ -776,6 +794,21 @@ You are MiniMax.
         )
     }
 
+    #[test]
+    fn templates_can_call_strftime_now_and_raise_exception() {
+        let template = build_template(
+            "{{ strftime_now('%Y') }}{% if not messages %}{{ raise_exception('no messages') }}{% endif %}",
+            true,
+        );
+        let rendered = template.apply_chat_template(&Vec::new(), false);
+        let err = rendered.unwrap_err();
+        assert!(format!("{err:?}").contains("no messages"));
+
+        let template = build_template("{{ strftime_now('%Y') }}", true);
+        let rendered = template.apply_chat_template(&Vec::new(), false).unwrap();
+        assert_eq!(rendered, chrono::Local::now().format("%Y").to_string());
+    }
+
     #[test]
     fn generation_prompt_replay_suffix_extracts_thinking_suffix() {
         let template = build_template(THINKING_TEMPLATE, true);
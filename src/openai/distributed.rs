@@ -1655,8 +1655,24 @@ impl VocabParallelLinear {
         let local_weight = weight
             .narrow(0, rank * local_vocab, local_vocab)?
             .contiguous()?;
+        let local_bias = match bias {
+            Some(bias) => {
+                let bias = if bias.dim(0)? < padded_vocab {
+                    let padding =
+                        Tensor::zeros(padded_vocab - bias.dim(0)?, bias.dtype(), bias.device())?;
+                    Tensor::cat(&[&bias, &padding], 0)?
+                } else {
+                    bias
+                };
+                Some(
+                    bias.narrow(0, rank * local_vocab, local_vocab)?
+                        .contiguous()?,
+                )
+            }
+            None => None,
+        };
 
-        let linear = LinearX::Linear(Linear::new(local_weight, bias));
+        let linear = LinearX::Linear(Linear::new(local_weight, local_bias));
 
         #[cfg(feature = "nccl")]
         let all_gather = Some(AllGather::new(comm));
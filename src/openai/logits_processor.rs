@@ -6,6 +6,7 @@ use attention_rs::sort::ArgSortOp; //Use our custom sort kernel, fix kernel cras
 use rand::{distr::Distribution, SeedableRng};
 use rayon::iter::IntoParallelIterator;
 use rayon::iter::ParallelIterator;
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::sync::Mutex;
 #[derive(Clone, PartialEq, Debug)]
@@ -31,8 +32,14 @@ pub enum Sampling {
     },
 }
 
+/// A dedicated RNG stream for one sequence group: `(group_id, seed)`.
+pub type SeededStream = (usize, u64);
+
 pub struct LogitsProcessor {
     rng: Arc<Mutex<rand::rngs::StdRng>>,
+    /// Per-group RNGs for requests that specify a `seed`, created lazily on the
+    /// group's first sampled token.
+    seeded_rngs: Arc<Mutex<HashMap<usize, rand::rngs::StdRng>>>,
     pub sampling: Sampling,
     #[cfg(feature = "cuda")]
     fast_sampler: Arc<std::sync::Mutex<attention_rs::sampler::Sampler>>,
@@ -43,6 +50,7 @@ impl LogitsProcessor {
         let rng = rand::rngs::StdRng::seed_from_u64(seed);
         Self {
             rng: Arc::new(Mutex::new(rng)),
+            seeded_rngs: Arc::new(Mutex::new(HashMap::new())),
             sampling,
             #[cfg(feature = "cuda")]
             fast_sampler: Arc::new(std::sync::Mutex::new(attention_rs::sampler::Sampler::new())),
@@ -94,17 +102,72 @@ impl LogitsProcessor {
         Ok(next_tokens)
     }
 
-    fn sample_multinomial(&self, prs: &Vec<f32>) -> Result<u32> {
+    /// Run `f` with the group's seeded RNG stream, or the shared RNG.
+    fn with_rng<T>(
+        &self,
+        stream: Option<SeededStream>,
+        f: impl FnOnce(&mut rand::rngs::StdRng) -> T,
+    ) -> T {
+        match stream {
+            Some((group_id, seed)) => {
+                let mut rngs = self.seeded_rngs.lock().unwrap();
+                let rng = rngs
+                    .entry(group_id)
+                    .or_insert_with(|| rand::rngs::StdRng::seed_from_u64(seed));
+                f(rng)
+            }
+            None => f(&mut self.rng.lock().unwrap()),
+        }
+    }
+
+    fn sample_multinomial(&self, prs: &[f32], stream: Option<SeededStream>) -> Result<u32> {
         let distr = rand::distr::weighted::WeightedIndex::new(prs).map_err(Error::wrap)?;
-        let mut rng = self.rng.lock().unwrap();
-        let next_token = distr.sample(&mut *rng) as u32;
-        Ok(next_token)
+        Ok(self.with_rng(stream, |rng| distr.sample(rng) as u32))
+    }
+
+    /// Draw a token index from unnormalized weights `prs`.
+    pub fn sample_probs(&self, prs: &[f32], stream: Option<SeededStream>) -> Result<u32> {
+        self.sample_multinomial(prs, stream)
+    }
+
+    /// Draw uniformly from `[0, 1)`, e.g. for speculative acceptance tests.
+    pub fn uniform(&self, stream: Option<SeededStream>) -> f32 {
+        self.with_rng(stream, |rng| rand::Rng::random::<f32>(rng))
+    }
+
+    /// The distribution each row of `logits` is sampled from under
+    /// `sampling`, after temperature and top-k/top-p/min-p filtering.
+    pub fn strategy_probs(&self, logits: &Tensor, sampling: &Sampling) -> Result<Vec<Vec<f32>>> {
+        let rows: Vec<Vec<f32>> = logits.to_dtype(DType::F32)?.to_vec2()?;
+        Ok(rows
+            .into_par_iter()
+            .map(|row| filtered_probs(&row, sampling))
+            .collect())
+    }
+
+    /// Drop the RNG streams of seeded requests.
+    pub fn release_seeded_streams(&self) {
+        self.seeded_rngs.lock().unwrap().clear();
+    }
+
+    /// Drop the RNG streams of groups that are no longer `live`.
+    pub fn retain_seeded_streams(&self, live: impl Fn(usize) -> bool) {
+        self.seeded_rngs
+            .lock()
+            .unwrap()
+            .retain(|&group_id, _| live(group_id));
     }
 
     /// top-p sampling (or "nucleus sampling") samples from the smallest set of tokens that exceed
     /// probability top_p. This way we never sample tokens that have very low probabilities and are
     /// less likely to go "off the rails".
-    fn sample_topp(&self, logits: &Tensor, top_p: f32, min_p: Option<f32>) -> Result<Vec<u32>> {
+    fn sample_topp(
+        &self,
+        logits: &Tensor,
+        top_p: f32,
+        min_p: Option<f32>,
+        stream: Option<SeededStream>,
+    ) -> Result<Vec<u32>> {
         #[cfg(feature = "cuda")]
         let asort = logits.arg_sort(false)?;
         #[cfg(not(feature = "cuda"))]
@@ -144,14 +207,19 @@ impl LogitsProcessor {
                     }
                 }
                 // Sample with clamped probabilities.
-                self.sample_multinomial(&prs).unwrap()
+                self.sample_multinomial(&prs, stream).unwrap()
             })
             .collect();
         Ok(vec_ret)
     }
 
     // top-k sampling samples from the k tokens with the largest probabilities.
-    fn sample_topk(&self, logits: &Tensor, top_k: usize) -> Result<Vec<u32>> {
+    fn sample_topk(
+        &self,
+        logits: &Tensor,
+        top_k: usize,
+        stream: Option<SeededStream>,
+    ) -> Result<Vec<u32>> {
         #[cfg(feature = "cuda")]
         let (sorted, asort) = logits.sort(false)?;
         #[cfg(not(feature = "cuda"))]
@@ -166,7 +234,7 @@ impl LogitsProcessor {
             .map(|b| {
                 let indices: Vec<u32> = asort[b][0..top_k].to_vec();
                 let prs: Vec<f32> = sorted[b][0..top_k].to_vec();
-                let index = self.sample_multinomial(&prs).unwrap();
+                let index = self.sample_multinomial(&prs, stream).unwrap();
                 indices[index as usize] as u32
             })
             .collect();
@@ -181,6 +249,7 @@ impl LogitsProcessor {
         top_k: usize,
         top_p: f32,
         min_p: Option<f32>,
+        stream: Option<SeededStream>,
     ) -> Result<Vec<u32>> {
         #[cfg(feature = "cuda")]
         let (sorted, asort) = logits.sort(false)?;
@@ -199,7 +268,7 @@ impl LogitsProcessor {
                 let mut prs: Vec<f32> = sorted[b][0..top_k].to_vec();
                 let sum_p = prs.iter().sum::<f32>();
                 let index = if top_p <= 0.0 || top_p >= sum_p {
-                    self.sample_multinomial(&prs).unwrap()
+                    self.sample_multinomial(&prs, stream).unwrap()
                 } else {
                     let min_threshold = if min_p > 0. {
                         let max_prob = prs.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
@@ -216,7 +285,7 @@ impl LogitsProcessor {
                         }
                     }
                     // Sample with clamped probabilities.
-                    self.sample_multinomial(&prs).unwrap()
+                    self.sample_multinomial(&prs, stream).unwrap()
                 };
                 indices[index as usize] as u32
             })
@@ -272,6 +341,39 @@ impl LogitsProcessor {
         }
 
         // CPU fallback
+        self.sample_with_strategy(logits, &sampling, None)
+    }
+
+    /// Sample every row of `logits` with its own strategy. Rows that carry a
+    /// seeded stream draw from a dedicated per-group RNG, so their output does
+    /// not depend on the other requests sharing the batch.
+    pub fn sample_rows(
+        &self,
+        logits: &Tensor,
+        rows: &[(Sampling, Option<SeededStream>)],
+    ) -> Result<Vec<u32>> {
+        let batch = logits.layout().dims()[0];
+        if rows.len() != batch {
+            candle_core::bail!(
+                "sample_rows expects {} row configs, got {}",
+                batch,
+                rows.len()
+            );
+        }
+        let mut next_tokens = Vec::with_capacity(batch);
+        for (b, (sampling, stream)) in rows.iter().enumerate() {
+            let row = logits.narrow(0, b, 1)?;
+            next_tokens.extend(self.sample_with_strategy(&row, sampling, *stream)?);
+        }
+        Ok(next_tokens)
+    }
+
+    fn sample_with_strategy(
+        &self,
+        logits: &Tensor,
+        sampling: &Sampling,
+        stream: Option<SeededStream>,
+    ) -> Result<Vec<u32>> {
         let logits = logits.to_dtype(DType::F32)?;
         let batch = logits.layout().dims()[0];
         let prs = |temperature: f64| -> Result<Tensor> {
@@ -280,12 +382,12 @@ impl LogitsProcessor {
             Ok(prs)
         };
 
-        let next_tokens = match &sampling {
+        let next_tokens = match sampling {
             Sampling::ArgMax => self.sample_argmax(&logits)?,
             Sampling::All { temperature } => {
                 let prs = prs(*temperature as f64)?.to_vec2()?;
                 (0..batch)
-                    .map(|b| self.sample_multinomial(&prs[b]).unwrap())
+                    .map(|b| self.sample_multinomial(&prs[b], stream).unwrap())
                     .collect()
             }
             Sampling::TopP {
@@ -298,16 +400,16 @@ impl LogitsProcessor {
                     // simply sample from the predicted probability distribution
                     let prs = prs.to_vec2()?;
                     (0..batch)
-                        .map(|b| self.sample_multinomial(&prs[b]).unwrap())
+                        .map(|b| self.sample_multinomial(&prs[b], stream).unwrap())
                         .collect()
                 } else {
                     // top-p (nucleus) sampling, clamping the least likely tokens to zero
-                    self.sample_topp(&prs, *p, Some(*minp))?
+                    self.sample_topp(&prs, *p, Some(*minp), stream)?
                 }
             }
             Sampling::TopK { k, temperature } => {
                 let prs = prs(*temperature as f64)?;
-                self.sample_topk(&prs, *k)?
+                self.sample_topk(&prs, *k, stream)?
             }
             Sampling::TopKThenTopP {
                 k,
@@ -316,12 +418,40 @@ impl LogitsProcessor {
                 temperature,
             } => {
                 let prs = prs(*temperature as f64)?;
-                self.sample_topk_topp(&prs, *k, *p, Some(*minp))?
+                self.sample_topk_topp(&prs, *k, *p, Some(*minp), stream)?
             }
         };
         Ok(next_tokens)
     }
 
+    /// Log-probability of each row's sampled token under that row's logits.
+    pub fn token_logprobs(&self, logits: &Tensor, tokens: &[u32]) -> Result<Vec<f32>> {
+        let logits = logits.to_dtype(DType::F32)?;
+        let logprobs = candle_nn::ops::log_softmax(&logits, D::Minus1)?;
+        let index = Tensor::from_vec(tokens.to_vec(), (tokens.len(), 1), logits.device())?;
+        logprobs.gather(&index, 1)?.squeeze(1)?.to_vec1::<f32>()
+    }
+
+    /// The `k` most likely tokens of each row with their log-probabilities,
+    /// best first.
+    pub fn top_logprobs(&self, logits: &Tensor, k: usize) -> Result<Vec<Vec<(u32, f32)>>> {
+        let logprobs = candle_nn::ops::log_softmax(&logits.to_dtype(DType::F32)?, D::Minus1)?;
+        #[cfg(feature = "cuda")]
+        let (sorted, asort) = logprobs.sort(false)?;
+        #[cfg(not(feature = "cuda"))]
+        let (sorted, asort) = logprobs
+            .to_device(&candle_core::Device::Cpu)?
+            .sort_last_dim(false)?;
+        let k = k.min(sorted.dim(D::Minus1)?);
+        let asort: Vec<Vec<u32>> = asort.narrow(D::Minus1, 0, k)?.to_vec2()?;
+        let sorted: Vec<Vec<f32>> = sorted.narrow(D::Minus1, 0, k)?.to_vec2()?;
+        Ok(asort
+            .into_iter()
+            .zip(sorted)
+            .map(|(tokens, logprobs)| tokens.into_iter().zip(logprobs).collect())
+            .collect())
+    }
+
     fn apply_penalties(
         &self,
         logits: &mut [f32],
@@ -344,6 +474,83 @@ impl LogitsProcessor {
         }
     }
 
+    /// Add each row's `logit_bias` to its logits. Rows without a bias are left
+    /// untouched; token ids outside the logits width are ignored. Only the
+    /// biased entries are uploaded, and they are added on the device.
+    pub fn apply_batch_logit_bias(
+        &self,
+        logits: &Tensor,
+        logit_biases: Vec<Option<&HashMap<u32, f32>>>,
+    ) -> Result<Tensor> {
+        let (batch, logits_len) = logits.dims2()?;
+        let mut indices = Vec::new();
+        let mut values = Vec::new();
+        for (row, bias) in logit_biases.into_iter().enumerate() {
+            for (&token_id, &value) in bias.into_iter().flatten() {
+                if (token_id as usize) < logits_len {
+                    indices.push((row * logits_len) as u32 + token_id);
+                    values.push(value);
+                }
+            }
+        }
+        let logits = logits.to_dtype(DType::F32)?;
+        if indices.is_empty() {
+            return Ok(logits);
+        }
+        let num_entries = indices.len();
+        let indices = Tensor::from_vec(indices, (num_entries,), logits.device())?;
+        let values = Tensor::from_vec(values, (num_entries,), logits.device())?;
+        logits
+            .flatten_all()?
+            .index_add(&indices, &values, 0)?
+            .reshape((batch, logits_len))
+    }
+
+    /// Restrict each row to its allowed token ids (structured output); rows
+    /// without a mask are left untouched. Only the allowed ids are uploaded,
+    /// and the mask is built and applied on the device.
+    pub fn apply_batch_token_masks(
+        &self,
+        logits: &Tensor,
+        masks: Vec<Option<Arc<Vec<u32>>>>,
+    ) -> Result<Tensor> {
+        let (batch, logits_len) = logits.dims2()?;
+        let device = logits.device();
+        let mut allowed = Vec::new();
+        let mut unmasked_rows = Vec::with_capacity(batch);
+        for (row, mask) in masks.into_iter().enumerate() {
+            let Some(mask) = mask else {
+                unmasked_rows.push(1f32);
+                continue;
+            };
+            unmasked_rows.push(0f32);
+            allowed.extend(
+                mask.iter()
+                    .filter(|&&token_id| (token_id as usize) < logits_len)
+                    .map(|&token_id| (row * logits_len) as u32 + token_id),
+            );
+        }
+        let logits = logits.to_dtype(DType::F32)?;
+        if !unmasked_rows.contains(&0f32) {
+            return Ok(logits);
+        }
+        let mut keep = Tensor::zeros(batch * logits_len, DType::F32, device)?;
+        if !allowed.is_empty() {
+            let num_allowed = allowed.len();
+            keep = keep.index_add(
+                &Tensor::from_vec(allowed, (num_allowed,), device)?,
+                &Tensor::ones(num_allowed, DType::F32, device)?,
+                0,
+            )?;
+        }
+        let keep = keep
+            .reshape((batch, logits_len))?
+            .broadcast_add(&Tensor::from_vec(unmasked_rows, (batch, 1), device)?)?
+            .gt(0f64)?;
+        let masked = Tensor::full(f32::NEG_INFINITY, (batch, logits_len), device)?;
+        keep.where_cond(&logits, &masked)
+    }
+
     pub fn apply_batch_repeat_penalty(
         &self,
         logits: &Tensor,
@@ -382,3 +589,223 @@ impl LogitsProcessor {
         Tensor::from_vec(logits, (batch, logits_len), device)
     }
 }
+
+/// Probabilities of one row of logits as the matching `sample_*` path draws
+/// them: zeroed outside the kept set and normalized.
+fn filtered_probs(logits: &[f32], sampling: &Sampling) -> Vec<f32> {
+    let softmax = |temperature: f32| {
+        let max = logits.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+        let exp = logits
+            .iter()
+            .map(|&logit| ((logit - max) / temperature).exp())
+            .collect::<Vec<_>>();
+        let sum = exp.iter().sum::<f32>();
+        exp.into_iter().map(|p| p / sum).collect::<Vec<_>>()
+    };
+    let descending = |prs: &[f32]| {
+        let mut order = (0..prs.len()).collect::<Vec<_>>();
+        order.sort_by(|&a, &b| prs[b].total_cmp(&prs[a]));
+        order
+    };
+    let keep_top_k = |prs: &mut Vec<f32>, order: &[usize], k: usize| {
+        for &index in order.iter().skip(k) {
+            prs[index] = 0.0;
+        }
+    };
+    let mut prs = match *sampling {
+        Sampling::ArgMax => {
+            let mut prs = vec![0.0; logits.len()];
+            if let Some(best) = descending(logits).first() {
+                prs[*best] = 1.0;
+            }
+            prs
+        }
+        Sampling::All { temperature } => softmax(temperature),
+        Sampling::TopK { k, temperature } => {
+            let mut prs = softmax(temperature);
+            let order = descending(&prs);
+            keep_top_k(&mut prs, &order, k);
+            prs
+        }
+        Sampling::TopP {
+            p,
+            minp,
+            temperature,
+        } => {
+            let mut prs = softmax(temperature);
+            if p > 0.0 && p < 1.0 {
+                let order = descending(&prs);
+                let min_threshold = minp * prs[order[0]];
+                let mut cumsum = 0.;
+                for index in order {
+                    if cumsum > p || prs[index] < min_threshold {
+                        prs[index] = 0.0;
+                    } else {
+                        cumsum += prs[index];
+                    }
+                }
+            }
+            prs
+        }
+        Sampling::TopKThenTopP {
+            k,
+            p,
+            minp,
+            temperature,
+        } => {
+            let mut prs = softmax(temperature);
+            let order = descending(&prs);
+            keep_top_k(&mut prs, &order, k);
+            let sum_p = order.iter().take(k).map(|&index| prs[index]).sum::<f32>();
+            if p > 0.0 && p < sum_p {
+                let min_threshold = minp * prs[order[0]];
+                let mut cumsum = 0.;
+                for &index in order.iter().take(k) {
+                    if cumsum >= p || prs[index] < min_threshold {
+                        prs[index] = 0.0;
+                    } else {
+                        cumsum += prs[index];
+                    }
+                }
+            }
+            prs
+        }
+    };
+    let total = prs.iter().sum::<f32>();
+    if total > 0.0 {
+        prs.iter_mut().for_each(|p| *p /= total);
+    }
+    prs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{filtered_probs, LogitsProcessor, Sampling, SeededStream};
+    use candle_core::{Device, Tensor};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    #[test]
+    fn seeded_rows_do_not_depend_on_shared_rng_or_batch() {
+        let row = [0.1f32, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8];
+        let logits = Tensor::new(&[row, row], &Device::Cpu).unwrap();
+        let sampling = Sampling::All { temperature: 1.0 };
+        let draw = |processor: &LogitsProcessor, neighbour: Option<SeededStream>| {
+            let rows = [
+                (sampling.clone(), Some((7, 42))),
+                (sampling.clone(), neighbour),
+            ];
+            (0..32)
+                .map(|_| processor.sample_rows(&logits, &rows).unwrap()[0])
+                .collect::<Vec<_>>()
+        };
+
+        let first = draw(&LogitsProcessor::from_sampling(1, sampling.clone()), None);
+        let second = draw(
+            &LogitsProcessor::from_sampling(2, sampling.clone()),
+            Some((9, 3)),
+        );
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn released_streams_restart_from_their_seed() {
+        let processor = LogitsProcessor::from_sampling(0, Sampling::ArgMax);
+        let first = processor.uniform(Some((7, 42)));
+        let other = processor.uniform(Some((8, 42)));
+        assert_eq!(first, other);
+
+        processor.retain_seeded_streams(|group_id| group_id == 8);
+        assert_eq!(processor.uniform(Some((7, 42))), first);
+        assert_ne!(processor.uniform(Some((8, 42))), first);
+        assert_eq!(processor.seeded_rngs.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn logit_bias_only_changes_biased_rows() {
+        let logits = Tensor::new(&[[1f32, 2., 3.], [4., 5., 6.]], &Device::Cpu).unwrap();
+        let processor = LogitsProcessor::from_sampling(0, Sampling::ArgMax);
+        let bias = HashMap::from([(0u32, 10f32), (7, 1.)]);
+        let biased = processor
+            .apply_batch_logit_bias(&logits, vec![None, Some(&bias)])
+            .unwrap();
+        assert_eq!(
+            biased.to_vec2::<f32>().unwrap(),
+            [[1., 2., 3.], [14., 5., 6.]]
+        );
+    }
+
+    #[test]
+    fn token_masks_only_change_masked_rows() {
+        let logits = Tensor::new(&[[1f32, 2., 3.], [4., 5., 6.]], &Device::Cpu).unwrap();
+        let processor = LogitsProcessor::from_sampling(0, Sampling::ArgMax);
+        let masked = processor
+            .apply_batch_token_masks(&logits, vec![Some(Arc::new(vec![1, 9])), None])
+            .unwrap()
+            .to_vec2::<f32>()
+            .unwrap();
+        assert_eq!(masked[0][1], 2.);
+        assert!(masked[0][0].is_infinite() && masked[0][2].is_infinite());
+        assert_eq!(masked[1], [4., 5., 6.]);
+    }
+
+    #[test]
+    fn token_logprobs_follow_row_distributions() {
+        let logits =
+            Tensor::new(&[[0f32, 0., 0., 0.], [2f32.ln(), 0., 0., 0.]], &Device::Cpu).unwrap();
+        let processor = LogitsProcessor::from_sampling(0, Sampling::ArgMax);
+        let logprobs = processor.token_logprobs(&logits, &[3, 0]).unwrap();
+        assert!((logprobs[0] - 0.25f32.ln()).abs() < 1e-5);
+        assert!((logprobs[1] - 0.4f32.ln()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn top_logprobs_are_sorted_per_row() {
+        let logits = Tensor::new(&[[0f32, 3f32.ln(), 0., 2f32.ln()]], &Device::Cpu).unwrap();
+        let processor = LogitsProcessor::from_sampling(0, Sampling::ArgMax);
+        let top = processor.top_logprobs(&logits, 2).unwrap();
+        assert_eq!(top[0].len(), 2);
+        assert_eq!((top[0][0].0, top[0][1].0), (1, 3));
+        assert!((top[0][0].1 - (3f32 / 7.).ln()).abs() < 1e-5);
+        assert!((top[0][1].1 - (2f32 / 7.).ln()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn filtered_probs_follow_top_k_top_p_and_min_p() {
+        let logits = [4f32.ln(), 3f32.ln(), 2f32.ln(), 1f32.ln()];
+        let close = |a: &[f32], b: &[f32]| a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1e-5);
+
+        let all = filtered_probs(&logits, &Sampling::All { temperature: 1.0 });
+        assert!(close(&all, &[0.4, 0.3, 0.2, 0.1]));
+        let top_k = filtered_probs(
+            &logits,
+            &Sampling::TopK {
+                k: 2,
+                temperature: 1.0,
+            },
+        );
+        assert!(close(&top_k, &[4. / 7., 3. / 7., 0., 0.]));
+        // 0.4 + 0.3 passes 0.6, so the nucleus stops after two tokens.
+        let top_p = filtered_probs(
+            &logits,
+            &Sampling::TopP {
+                p: 0.6,
+                minp: 0.0,
+                temperature: 1.0,
+            },
+        );
+        assert!(close(&top_p, &[4. / 7., 3. / 7., 0., 0.]));
+        let min_p = filtered_probs(
+            &logits,
+            &Sampling::TopKThenTopP {
+                k: 3,
+                p: 0.85,
+                minp: 0.6,
+                temperature: 1.0,
+            },
+        );
+        assert!(close(&min_p, &[4. / 7., 3. / 7., 0., 0.]));
+        let argmax = filtered_probs(&logits, &Sampling::ArgMax);
+        assert_eq!(argmax, vec![1.0, 0.0, 0.0, 0.0]);
+    }
+}
@@ -0,0 +1,355 @@
+//! Prometheus metrics for the `/metrics` endpoint, rendered in the text
+//! exposition format. Counters and histograms are updated by the engine as it
+//! runs; scheduler gauges are sampled when the endpoint is scraped.
+
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::scheduler::SchedulerMetrics;
+
+const TTFT_BUCKETS: &[f64] = &[
+    0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 0.75, 1.0, 2.5, 5.0, 7.5, 10.0, 20.0, 40.0, 80.0,
+];
+const ITL_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.015, 0.02, 0.025, 0.03, 0.04, 0.05, 0.075, 0.1, 0.15, 0.2, 0.3, 0.4, 0.5, 1.0,
+    2.5,
+];
+
+/// Cumulative histogram with fixed upper bounds, in seconds.
+pub struct Histogram {
+    bounds: &'static [f64],
+    /// One count per bound, plus the `+Inf` bucket.
+    buckets: Vec<AtomicU64>,
+    sum_micros: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum_micros: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, seconds: f64) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|&bound| seconds <= bound)
+            .unwrap_or(self.bounds.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add((seconds * 1e6) as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        let mut cumulative = 0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let le = self
+                .bounds
+                .get(i)
+                .map_or("+Inf".to_string(), |bound| bound.to_string());
+            let _ = writeln!(out, "{name}_bucket{{le=\"{le}\"}} {cumulative}");
+        }
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+        let _ = writeln!(out, "{name}_sum {sum}");
+        let _ = writeln!(out, "{name}_count {}", self.count.load(Ordering::Relaxed));
+    }
+}
+
+/// Tokens accumulated by one API key.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct KeyUsage {
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+}
+
+impl KeyUsage {
+    pub fn merge(&mut self, other: &KeyUsage) {
+        self.requests += other.requests;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+    }
+}
+
+/// Engine-side counters and latency histograms.
+pub struct EngineMetrics {
+    pub prompt_tokens: AtomicU64,
+    pub generation_tokens: AtomicU64,
+    pub time_to_first_token: Histogram,
+    pub inter_token_latency: Histogram,
+    pub requests_rejected_queue_full: AtomicU64,
+    pub requests_rejected_queue_timeout: AtomicU64,
+    /// Usage of finished requests per API key name.
+    key_usage: Mutex<BTreeMap<String, KeyUsage>>,
+}
+
+impl Default for EngineMetrics {
+    fn default() -> Self {
+        Self {
+            prompt_tokens: AtomicU64::new(0),
+            generation_tokens: AtomicU64::new(0),
+            time_to_first_token: Histogram::new(TTFT_BUCKETS),
+            inter_token_latency: Histogram::new(ITL_BUCKETS),
+            requests_rejected_queue_full: AtomicU64::new(0),
+            requests_rejected_queue_timeout: AtomicU64::new(0),
+            key_usage: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+fn render_value(out: &mut String, name: &str, kind: &str, help: &str, value: impl ToString) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+    let _ = writeln!(out, "{name} {}", value.to_string());
+}
+
+impl EngineMetrics {
+    /// Charge a finished request to the API key it came in with.
+    pub fn record_key_usage(&self, key: &str, prompt_tokens: usize, completion_tokens: usize) {
+        let mut usage = self.key_usage.lock();
+        let usage = usage.entry(key.to_string()).or_default();
+        usage.requests += 1;
+        usage.prompt_tokens += prompt_tokens as u64;
+        usage.completion_tokens += completion_tokens as u64;
+        usage.total_tokens += (prompt_tokens + completion_tokens) as u64;
+    }
+
+    pub fn key_usage(&self) -> BTreeMap<String, KeyUsage> {
+        self.key_usage.lock().clone()
+    }
+
+    /// Render the engine counters together with a scheduler snapshot.
+    pub fn render(&self, scheduler: &SchedulerMetrics) -> String {
+        let mut out = String::new();
+        render_value(
+            &mut out,
+            "candle_vllm_num_running_seqs",
+            "gauge",
+            "Number of sequences being decoded or prefilled.",
+            scheduler.num_running_seqs,
+        );
+        render_value(
+            &mut out,
+            "candle_vllm_num_waiting_seqs",
+            "gauge",
+            "Number of sequences waiting to be scheduled.",
+            scheduler.num_waiting_seqs,
+        );
+        render_value(
+            &mut out,
+            "candle_vllm_num_swapped_seqs",
+            "gauge",
+            "Number of sequences swapped out to CPU memory.",
+            scheduler.num_swapped_seqs,
+        );
+        render_value(
+            &mut out,
+            "candle_vllm_gpu_kv_blocks_total",
+            "gauge",
+            "Number of GPU KV cache blocks.",
+            scheduler.num_gpu_blocks,
+        );
+        render_value(
+            &mut out,
+            "candle_vllm_gpu_kv_blocks_free",
+            "gauge",
+            "Number of free GPU KV cache blocks.",
+            scheduler.num_free_gpu_blocks,
+        );
+        let usage = if scheduler.num_gpu_blocks == 0 {
+            0.0
+        } else {
+            (scheduler.num_gpu_blocks - scheduler.num_free_gpu_blocks) as f64
+                / scheduler.num_gpu_blocks as f64
+        };
+        render_value(
+            &mut out,
+            "candle_vllm_gpu_kv_cache_usage_ratio",
+            "gauge",
+            "Fraction of GPU KV cache blocks in use.",
+            usage,
+        );
+        render_value(
+            &mut out,
+            "candle_vllm_prefix_cache_blocks",
+            "gauge",
+            "Number of KV blocks held by the prefix cache.",
+            scheduler.prefix_cache_blocks,
+        );
+        render_value(
+            &mut out,
+            "candle_vllm_prefix_cache_queried_tokens_total",
+            "counter",
+            "Prompt tokens looked up in the prefix cache.",
+            scheduler.prefix_cache_queried_tokens,
+        );
+        render_value(
+            &mut out,
+            "candle_vllm_prefix_cache_hit_tokens_total",
+            "counter",
+            "Prompt tokens served from the prefix cache.",
+            scheduler.prefix_cache_hit_tokens,
+        );
+        let hit_rate = if scheduler.prefix_cache_queried_tokens == 0 {
+            0.0
+        } else {
+            scheduler.prefix_cache_hit_tokens as f64 / scheduler.prefix_cache_queried_tokens as f64
+        };
+        render_value(
+            &mut out,
+            "candle_vllm_prefix_cache_hit_rate",
+            "gauge",
+            "Fraction of looked up prompt tokens served from the prefix cache.",
+            hit_rate,
+        );
+        render_value(
+            &mut out,
+            "candle_vllm_prompt_tokens_total",
+            "counter",
+            "Prompt tokens prefilled.",
+            self.prompt_tokens.load(Ordering::Relaxed),
+        );
+        render_value(
+            &mut out,
+            "candle_vllm_generation_tokens_total",
+            "counter",
+            "Tokens generated.",
+            self.generation_tokens.load(Ordering::Relaxed),
+        );
+        self.time_to_first_token.render(
+            &mut out,
+            "candle_vllm_time_to_first_token_seconds",
+            "Time from request arrival to its first generated token.",
+        );
+        self.inter_token_latency.render(
+            &mut out,
+            "candle_vllm_inter_token_latency_seconds",
+            "Time between consecutive generated tokens of a request.",
+        );
+        let _ = writeln!(
+            out,
+            "# HELP candle_vllm_preemptions_total Sequence groups preempted by the scheduler."
+        );
+        let _ = writeln!(out, "# TYPE candle_vllm_preemptions_total counter");
+        for (mode, count) in [
+            ("recompute", scheduler.num_preemptions_recompute),
+            ("swap", scheduler.num_preemptions_swap),
+            ("abort", scheduler.num_preemptions_aborted),
+        ] {
+            let _ = writeln!(
+                out,
+                "candle_vllm_preemptions_total{{mode=\"{mode}\"}} {count}"
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP candle_vllm_requests_rejected_total Requests refused by admission control."
+        );
+        let _ = writeln!(out, "# TYPE candle_vllm_requests_rejected_total counter");
+        for (reason, count) in [
+            ("queue_full", &self.requests_rejected_queue_full),
+            ("queue_timeout", &self.requests_rejected_queue_timeout),
+        ] {
+            let _ = writeln!(
+                out,
+                "candle_vllm_requests_rejected_total{{reason=\"{reason}\"}} {}",
+                count.load(Ordering::Relaxed)
+            );
+        }
+        render_value(
+            &mut out,
+            "candle_vllm_swapped_out_blocks_total",
+            "counter",
+            "KV blocks swapped out to CPU memory.",
+            scheduler.num_blocks_swapped_out,
+        );
+        render_value(
+            &mut out,
+            "candle_vllm_swapped_in_blocks_total",
+            "counter",
+            "KV blocks swapped back in to GPU memory.",
+            scheduler.num_blocks_swapped_in,
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EngineMetrics, Histogram, KeyUsage};
+    use crate::scheduler::SchedulerMetrics;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let histogram = Histogram::new(&[0.1, 1.0]);
+        histogram.observe(0.05);
+        histogram.observe(0.5);
+        histogram.observe(3.0);
+        let mut out = String::new();
+        histogram.render(&mut out, "latency_seconds", "Latency.");
+        assert!(out.contains("# TYPE latency_seconds histogram\n"));
+        assert!(out.contains("latency_seconds_bucket{le=\"0.1\"} 1\n"));
+        assert!(out.contains("latency_seconds_bucket{le=\"1\"} 2\n"));
+        assert!(out.contains("latency_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(out.contains("latency_seconds_sum 3.55\n"));
+        assert!(out.contains("latency_seconds_count 3\n"));
+    }
+
+    #[test]
+    fn render_includes_scheduler_gauges() {
+        let metrics = EngineMetrics::default();
+        metrics.generation_tokens.store(42, Ordering::Relaxed);
+        metrics
+            .requests_rejected_queue_full
+            .store(3, Ordering::Relaxed);
+        let out = metrics.render(&SchedulerMetrics {
+            num_running_seqs: 2,
+            num_gpu_blocks: 8,
+            num_free_gpu_blocks: 6,
+            prefix_cache_queried_tokens: 10,
+            prefix_cache_hit_tokens: 4,
+            num_preemptions_swap: 1,
+            ..Default::default()
+        });
+        assert!(out.contains("candle_vllm_num_running_seqs 2\n"));
+        assert!(out.contains("candle_vllm_gpu_kv_cache_usage_ratio 0.25\n"));
+        assert!(out.contains("candle_vllm_prefix_cache_hit_rate 0.4\n"));
+        assert!(out.contains("candle_vllm_generation_tokens_total 42\n"));
+        assert!(out.contains("candle_vllm_preemptions_total{mode=\"swap\"} 1\n"));
+        assert!(out.contains("candle_vllm_requests_rejected_total{reason=\"queue_full\"} 3\n"));
+    }
+
+    #[test]
+    fn key_usage_accumulates_per_key() {
+        let metrics = EngineMetrics::default();
+        metrics.record_key_usage("team-a", 10, 5);
+        metrics.record_key_usage("team-a", 3, 0);
+        metrics.record_key_usage("team-b", 1, 1);
+        let usage = metrics.key_usage();
+        assert_eq!(
+            usage["team-a"],
+            KeyUsage {
+                requests: 2,
+                prompt_tokens: 13,
+                completion_tokens: 5,
+                total_tokens: 18,
+            }
+        );
+        assert_eq!(usage["team-b"].total_tokens, 2);
+    }
+}
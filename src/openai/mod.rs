@@ -8,14 +8,19 @@ use parking_lot::RwLock;
 use std::sync::Arc;
 use std::time::SystemTime;
 use tokenizers::{EncodeInput, Encoding, Tokenizer};
+pub mod anthropic_api;
+pub mod auth;
 #[cfg(feature = "nccl")]
 pub mod communicator;
 pub mod distributed;
 pub mod logger;
+pub mod metrics;
 pub mod requests;
 pub mod responses;
+pub mod responses_api;
 pub mod sampling_params;
 pub mod streaming;
+pub mod structured_output;
 use either::Either;
 use serde::{Deserialize, Serialize};
 pub trait TokenizerWrapper<'s, E>
@@ -140,6 +145,41 @@ pub struct OpenAIServerData {
     pub record_conversation: bool,
     pub device: Device,
     pub mcp_manager: Option<Arc<crate::mcp::McpClientManager>>,
+    /// Responses kept for `previous_response_id` in `/v1/responses`,
+    /// shared by all served models.
+    pub response_store: Arc<responses_api::ResponseStore>,
+    /// Accepted API keys; the `/v1/*` routes are open when unset.
+    pub api_keys: Option<Arc<auth::ApiKeys>>,
+    /// Name matched against the request's `model` field.
+    pub served_name: String,
+    /// Further models loaded in this process (`--extra-model`); always empty
+    /// on the extra models themselves.
+    pub extra_models: Vec<Arc<OpenAIServerData>>,
+}
+
+impl OpenAIServerData {
+    /// This model followed by the extra ones.
+    pub fn served_models(self: &Arc<Self>) -> impl Iterator<Item = &Arc<Self>> {
+        std::iter::once(self).chain(self.extra_models.iter())
+    }
+
+    /// The served model a request's `model` field names; an omitted name or
+    /// `default` selects this model and any other unknown name is an error.
+    pub fn route(self: &Arc<Self>, requested: Option<&str>) -> Result<Arc<Self>, APIError> {
+        let name = match requested.map(str::trim) {
+            None | Some("") | Some("default") => return Ok(self.clone()),
+            Some(name) => name,
+        };
+        self.served_models()
+            .find(|data| data.served_name == name)
+            .cloned()
+            .ok_or_else(|| {
+                APIError::new(format!("The model `{name}` does not exist."))
+                    .with_kind(responses::ErrorKind::NotFound)
+                    .with_param("model")
+                    .with_code("model_not_found")
+            })
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
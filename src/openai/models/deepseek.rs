@@ -608,7 +608,7 @@ impl DeepSeek {
             }
         }
 
-        if !seqlens.is_empty() {
+        if !seqlens.is_empty() && !input_metadata.is_mtp_verify {
             let indices: Vec<_> = seqlens.iter().map(|x| x - 1).collect();
             let batch = indices.len();
             xs = xs.index_select(&Tensor::from_vec(indices, (batch,), xs.device())?, 0)?;
@@ -564,7 +564,7 @@ impl Gemma3 {
             }
         }
 
-        if !seqlens.is_empty() && !return_hidden {
+        if !seqlens.is_empty() && !return_hidden && !input_metadata.is_mtp_verify {
             let indices: Vec<_> = seqlens.iter().map(|x| x - 1 as u32).collect();
             let batch = indices.len();
             xs = xs.index_select(&Tensor::from_vec(indices, (batch,), xs.device())?, 0)?;
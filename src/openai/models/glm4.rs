@@ -306,7 +306,7 @@ impl GLM4 {
                 )?
             }
         }
-        if !seqlens.is_empty() {
+        if !seqlens.is_empty() && !input_metadata.is_mtp_verify {
             let indices: Vec<_> = seqlens.iter().map(|x| x - 1 as u32).collect();
             let batch = indices.len();
             xs = xs.index_select(&Tensor::from_vec(indices, (batch,), xs.device())?, 0)?;
@@ -0,0 +1,424 @@
+use super::{
+    attention::Attention,
+    layers::moe::{FusedMoe, FusedMoeMxfp4},
+    rotary_emb::ScalingRotaryEmbedding,
+    utils::resolve_input_seqlens,
+    Config, KvCacheDtype, MoEConfig, QwenMoEConfig,
+};
+use crate::backend::progress::{ProgressLike, ProgressReporter};
+use crate::openai::distributed::{embedding, Comm, VarBuilder, VocabParallelLinear};
+use crate::openai::models::layers::others::{rms_norm, NormX};
+use crate::openai::models::mask::get_attention_causal_mask;
+use crate::{InputMetadata, InputMetadataExt};
+use candle::{DType, Device, Module, Result, Tensor};
+use candle_core as candle;
+use parking_lot::RwLock;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// GPT-OSS sparse MLP: experts are stored fused as MXFP4 blocks or in BF16.
+enum GptOssMoe {
+    Mxfp4(FusedMoeMxfp4),
+    Dense(FusedMoe),
+}
+
+impl GptOssMoe {
+    fn new(
+        cfg: &Config,
+        vb: VarBuilder,
+        comm: Rc<Comm>,
+        dtype: DType,
+        swiglu_limit: f64,
+    ) -> Result<Self> {
+        if vb.pp("experts").contains_tensor("gate_up_proj_blocks") {
+            Ok(Self::Mxfp4(FusedMoeMxfp4::new_gpt_oss(
+                cfg,
+                vb,
+                comm,
+                dtype,
+                swiglu_limit,
+            )?))
+        } else {
+            Ok(Self::Dense(FusedMoe::new_gpt_oss(
+                cfg,
+                vb,
+                comm,
+                dtype,
+                swiglu_limit,
+            )?))
+        }
+    }
+
+    fn forward(&self, xs: &Tensor, is_prefill: bool) -> Result<Tensor> {
+        match self {
+            Self::Mxfp4(m) => m.forward(xs, is_prefill),
+            Self::Dense(m) => m.forward(xs, is_prefill),
+        }
+    }
+}
+
+struct GptOssDecoderLayer {
+    self_attn: Attention,
+    mlp: GptOssMoe,
+    input_layernorm: NormX,
+    post_attention_layernorm: NormX,
+    is_sliding: bool,
+}
+
+impl GptOssDecoderLayer {
+    fn new(
+        rotary_emb: Arc<ScalingRotaryEmbedding>,
+        cfg: &Config,
+        vb: VarBuilder,
+        comm: Rc<Comm>,
+        dtype: DType,
+        is_sliding: bool,
+        swiglu_limit: f64,
+    ) -> Result<Self> {
+        let self_attn = Attention::new(
+            rotary_emb,
+            cfg,
+            vb.pp("self_attn"),
+            comm.clone(),
+            if is_sliding { cfg.sliding_window } else { None },
+        )?;
+        let mlp = GptOssMoe::new(cfg, vb.pp("mlp"), comm, dtype, swiglu_limit)?;
+
+        let input_layernorm = rms_norm(
+            cfg.hidden_size,
+            cfg.rms_norm_eps,
+            vb.pp("input_layernorm"),
+            DType::F32,
+            false,
+        )?;
+        let post_attention_layernorm = rms_norm(
+            cfg.hidden_size,
+            cfg.rms_norm_eps,
+            vb.pp("post_attention_layernorm"),
+            DType::F32,
+            false,
+        )?;
+
+        Ok(Self {
+            self_attn,
+            mlp,
+            input_layernorm,
+            post_attention_layernorm,
+            is_sliding,
+        })
+    }
+
+    fn forward(
+        &self,
+        xs: &Tensor,
+        attention_mask: Option<&Vec<Tensor>>,
+        input_positions: &Tensor,
+        cache: Option<(&Tensor, &Tensor)>,
+        input_metadata: &InputMetadata,
+    ) -> Result<Tensor> {
+        let residual = xs;
+        let xs = self.input_layernorm.forward(xs)?;
+        let attn_output =
+            self.self_attn
+                .forward(&xs, attention_mask, input_positions, cache, input_metadata)?;
+        let xs = (attn_output + residual)?;
+        let residual = &xs;
+        let xs = self.post_attention_layernorm.forward(&xs)?;
+        let mlp_output = self.mlp.forward(&xs, input_metadata.moe_is_prefill())?;
+        residual + mlp_output
+    }
+}
+
+pub struct GptOssForCausalLM {
+    embed_tokens: candle_nn::Embedding,
+    layers: Vec<GptOssDecoderLayer>,
+    norm: NormX,
+    lm_head: VocabParallelLinear,
+    device: Device,
+    dtype: DType,
+    cfg: Config,
+    vocab_size: usize,
+}
+
+impl GptOssForCausalLM {
+    pub fn load_config(filename: &PathBuf, isq: Option<String>) -> Result<Config> {
+        let mut config = Config::load_config(filename.clone())?;
+        config.head_dim = Some(
+            config
+                .head_dim
+                .unwrap_or(config.hidden_size / config.num_attention_heads),
+        );
+        config.num_key_value_heads = Some(
+            config
+                .num_key_value_heads
+                .unwrap_or(config.num_attention_heads),
+        );
+        config.attention_bias = Some(config.attention_bias.unwrap_or(true));
+        config.max_seq_len = config.effective_max_seq_len();
+        // The YaRN table covers `max_position_embeddings * factor` positions,
+        // while GPT-OSS already reports the extended length here.
+        if let Some(original) = config.original_max_position_embeddings {
+            config.max_position_embeddings = Some(original);
+        }
+        // Harmony turns end with `<|return|>`, or `<|call|>` after a tool call.
+        config.custom_stop_tokens = Some(vec!["<|return|>".to_string(), "<|call|>".to_string()]);
+
+        config.isq_quant = if config.quantization_config.is_some() {
+            None
+        } else {
+            isq
+        };
+
+        if config.moe_config.is_none() {
+            let f = std::fs::read(filename).map_err(candle::Error::wrap)?;
+            if let Some(mut moe_cfg) = Self::parse_gpt_oss_moe_config(&f) {
+                moe_cfg.norm_topk_prob = true;
+                config.moe_config = Some(MoEConfig::QwenMoE(moe_cfg));
+            }
+        }
+
+        Ok(config)
+    }
+
+    fn parse_gpt_oss_moe_config(raw_cfg: &[u8]) -> Option<QwenMoEConfig> {
+        let mut raw_cfg_json: serde_json::Value = serde_json::from_slice(raw_cfg).ok()?;
+        let raw_cfg_obj = raw_cfg_json.as_object_mut()?;
+        if !raw_cfg_obj.contains_key("moe_intermediate_size") {
+            let intermediate_size = raw_cfg_obj.get("intermediate_size")?.clone();
+            raw_cfg_obj.insert("moe_intermediate_size".to_string(), intermediate_size);
+        }
+        if !raw_cfg_obj.contains_key("num_experts_per_tok") {
+            let experts_per_token = raw_cfg_obj.get("experts_per_token")?.clone();
+            raw_cfg_obj.insert("num_experts_per_tok".to_string(), experts_per_token);
+        }
+        serde_json::from_value(raw_cfg_json).ok()
+    }
+
+    pub fn new(
+        vb: VarBuilder,
+        cfg: &Config,
+        dtype: DType,
+        device: &Device,
+        comm: Rc<Comm>,
+        progress_reporter: Arc<RwLock<ProgressReporter>>,
+    ) -> Result<Self> {
+        // Attention sinks re-read keys from the cache to rescale the output.
+        if cfg.kvcache_dtype != KvCacheDtype::Auto {
+            candle::bail!(
+                "GPT-OSS attention sinks do not support {:?} KV cache, use the default KV cache dtype",
+                cfg.kvcache_dtype
+            );
+        }
+        let vb_m = vb.pp("model");
+
+        let raw_cfg: Option<serde_json::Value> = cfg
+            .extra_config_json
+            .as_ref()
+            .and_then(|s| serde_json::from_str(s).ok());
+        let layer_types: Vec<String> = raw_cfg
+            .as_ref()
+            .and_then(|v| v.get("layer_types"))
+            .and_then(|v| v.as_array())
+            .map(|arr| {
+                arr.iter()
+                    .map(|v| v.as_str().unwrap_or("full_attention").to_string())
+                    .collect()
+            })
+            .unwrap_or_else(|| {
+                (0..cfg.num_hidden_layers)
+                    .map(|i| {
+                        if i % 2 == 0 {
+                            "sliding_attention".to_string()
+                        } else {
+                            "full_attention".to_string()
+                        }
+                    })
+                    .collect()
+            });
+        let swiglu_limit = raw_cfg
+            .as_ref()
+            .and_then(|v| v.get("swiglu_limit"))
+            .and_then(|v| v.as_f64())
+            .unwrap_or(7.0);
+
+        let embed_tokens = embedding(cfg.vocab_size, cfg.hidden_size, vb_m.pp("embed_tokens"))?;
+        let rotary_emb = Arc::new(ScalingRotaryEmbedding::new(DType::F32, cfg, device, true)?);
+
+        let mut layers = Vec::with_capacity(cfg.num_hidden_layers);
+        let vb_l = vb_m.pp("layers");
+        let reporter = progress_reporter.clone();
+
+        for layer_idx in 0..cfg.num_hidden_layers {
+            let is_sliding = layer_types
+                .get(layer_idx)
+                .is_some_and(|t| t == "sliding_attention");
+            let layer = GptOssDecoderLayer::new(
+                rotary_emb.clone(),
+                cfg,
+                vb_l.pp(layer_idx),
+                comm.clone(),
+                dtype,
+                is_sliding,
+                swiglu_limit,
+            )?;
+            layers.push(layer);
+            reporter.write().set_progress(layer_idx + 1);
+        }
+
+        let norm = rms_norm(
+            cfg.hidden_size,
+            cfg.rms_norm_eps,
+            vb_m.pp("norm"),
+            DType::F32,
+            false,
+        )?;
+
+        let lm_head = VocabParallelLinear::load_no_bias(
+            cfg.hidden_size,
+            cfg.vocab_size,
+            if cfg.tie_word_embeddings {
+                vb_m.pp("embed_tokens")
+            } else {
+                vb.pp("lm_head")
+            },
+            comm.clone(),
+            &None,
+            &cfg.quantization_config,
+            dtype,
+        )?;
+
+        Ok(Self {
+            embed_tokens,
+            layers,
+            norm,
+            lm_head,
+            device: device.clone(),
+            dtype,
+            cfg: cfg.clone(),
+            vocab_size: cfg.vocab_size,
+        })
+    }
+
+    pub fn embed_forward(&self, input_ids: &Tensor) -> Result<Tensor> {
+        let xs = self.embed_tokens.forward(input_ids)?;
+        if self.cfg.isq_quant.is_some() && xs.dtype() != DType::F32 {
+            xs.to_dtype(DType::F32)
+        } else {
+            Ok(xs)
+        }
+    }
+
+    fn forward_inner(
+        &self,
+        input_ids: &Tensor,
+        input_positions: &Tensor,
+        kv_caches: Option<&Vec<(Tensor, Tensor)>>,
+        input_metadata: &InputMetadata,
+        embedded_inputs: bool,
+        return_hidden: bool,
+    ) -> Result<Tensor> {
+        let seqlens = resolve_input_seqlens(input_metadata)?;
+
+        let attention_mask = get_attention_causal_mask(
+            &self.device,
+            self.dtype,
+            input_positions,
+            &seqlens,
+            None,
+            input_metadata.is_prefill,
+        );
+        let sliding_mask = get_attention_causal_mask(
+            &self.device,
+            self.dtype,
+            input_positions,
+            &seqlens,
+            self.cfg.sliding_window,
+            input_metadata.is_prefill,
+        );
+
+        let mut xs = if embedded_inputs {
+            input_ids.to_owned()
+        } else {
+            self.embed_forward(input_ids)?
+        };
+
+        if let Some(kv_caches) = kv_caches {
+            for ((k_cache, v_cache), layer) in kv_caches.iter().zip(self.layers.iter()) {
+                let mask = if layer.is_sliding {
+                    sliding_mask.as_ref()
+                } else {
+                    attention_mask.as_ref()
+                };
+                xs = layer.forward(
+                    &xs,
+                    mask,
+                    input_positions,
+                    Some((k_cache, v_cache)),
+                    input_metadata,
+                )?;
+            }
+        }
+
+        if !seqlens.is_empty() && !return_hidden && !input_metadata.is_mtp_verify {
+            let indices: Vec<_> = seqlens.iter().map(|x| x - 1 as u32).collect();
+            let batch = indices.len();
+            xs = xs.index_select(&Tensor::from_vec(indices, (batch,), xs.device())?, 0)?;
+        }
+
+        let xs = self.norm.forward(&xs)?;
+
+        if return_hidden {
+            return xs.to_dtype(DType::F32);
+        }
+        self.lm_head
+            .forward(&xs.to_dtype(self.dtype)?)?
+            .to_dtype(DType::F32)
+    }
+
+    pub fn forward(
+        &self,
+        input_ids: &Tensor,
+        input_positions: &Tensor,
+        kv_caches: Option<&Vec<(Tensor, Tensor)>>,
+        input_metadata: &InputMetadata,
+    ) -> Result<Tensor> {
+        self.forward_inner(
+            input_ids,
+            input_positions,
+            kv_caches,
+            input_metadata,
+            false,
+            false,
+        )
+    }
+
+    pub fn forward_embedding(
+        &self,
+        input_ids: &Tensor,
+        input_positions: &Tensor,
+        kv_caches: Option<&Vec<(Tensor, Tensor)>>,
+        input_metadata: &InputMetadata,
+    ) -> Result<Tensor> {
+        self.forward_inner(
+            input_ids,
+            input_positions,
+            kv_caches,
+            input_metadata,
+            false,
+            true,
+        )
+    }
+
+    pub fn get_vocab_size(&self) -> usize {
+        self.vocab_size
+    }
+
+    pub fn dtype(&self) -> DType {
+        self.dtype
+    }
+
+    pub fn get_config(&self) -> &Config {
+        &self.cfg
+    }
+}
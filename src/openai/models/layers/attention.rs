@@ -8,6 +8,7 @@ use crate::openai::distributed::{
 };
 use crate::openai::models::layers::qrmsnorm::QRmsNorm;
 use crate::openai::models::linear::{is_channel_scale_shape, qmatmul_forward};
+use crate::openai::models::utils::resolve_input_seqlens;
 use crate::openai::models::Config;
 use crate::{InputMetadata, PagedAttention};
 use candle_core::quantized::QMatMul;
@@ -17,6 +18,13 @@ use candle_nn::RmsNorm;
 
 use std::sync::Arc;
 
+/// Elements per intermediate tensor when recomputing attention-sink
+/// log-sum-exp, and the longest query run handled at once.
+const SINK_CHUNK_ELEMS: usize = 1 << 26;
+const SINK_QUERY_CHUNK: usize = 1024;
+/// Logit penalty for keys a query cannot see.
+const SINK_MASK_PENALTY: f64 = 1e30;
+
 enum QkvProjection {
     Separate {
         q_proj: TensorParallelColumnLinear,
@@ -42,6 +50,9 @@ pub struct Attention {
     full_dim_qk_norm: bool,
     qk_l2_norm: bool,
     v_norm_eps: Option<f64>,
+    sinks: Option<Tensor>,
+    sliding_window: Option<usize>,
+    scale: f32,
 }
 
 impl Attention {
@@ -526,15 +537,28 @@ impl Attention {
             }
         };
 
+        let is_gpt_oss = arch == "GptOssForCausalLM";
+        let o_proj_bias = matches!(arch.as_str(), "GptOssForCausalLM" | "PhiMoEForCausalLM");
         let o_proj = TensorParallelRowLinear::load_with_hints(
             num_heads * head_dim,
             hidden_sz,
-            false,
+            o_proj_bias && attention_bias,
             vb.pp("o_proj"),
             comm.clone(),
             &cfg.isq_quant,
             &cfg.quantization_config,
         )?;
+        // GPT-OSS learns one attention-sink logit per head.
+        let sinks = if is_gpt_oss {
+            Some(vb.get_with_hints_dtype(
+                (num_heads,),
+                "sinks",
+                shard(0, comm.rank(), comm.world_size()),
+                DType::F32,
+            )?)
+        } else {
+            None
+        };
 
         let q_norm_perhead = rms_norm_x(
             head_dim,
@@ -593,6 +617,7 @@ impl Attention {
         assert!(cfg.num_attention_heads % comm.world_size() == 0);
 
         let attention_heads = cfg.num_attention_heads / comm.world_size();
+        let scale = attention_scale.unwrap_or(1. / ((head_dim as f32).sqrt()));
         Ok(Self {
             qkv_proj,
             o_proj,
@@ -605,7 +630,7 @@ impl Attention {
             attn: PagedAttention::new(
                 attention_heads,
                 head_dim,
-                attention_scale.unwrap_or(1. / ((head_dim as f32).sqrt())),
+                scale,
                 Some(kv_heads),
                 sliding_window,
                 vb.device().clone(),
@@ -618,6 +643,9 @@ impl Attention {
             full_dim_qk_norm,
             qk_l2_norm,
             v_norm_eps,
+            sinks,
+            sliding_window,
+            scale,
         })
     }
 
@@ -743,19 +771,29 @@ impl Attention {
             v
         };
 
-        let y = self
-            .attn
-            .forward(
+        let y = self.attn.forward(
+            &q,
+            &k,
+            &v,
+            attention_mask,
+            cache.map(|(k_, _)| k_.clone()),
+            cache.map(|(_, v_)| v_.clone()),
+            input_metadata,
+            self.softcapping,
+        )?;
+        let y = if let Some(sinks) = &self.sinks {
+            self.apply_attention_sinks(
+                &y,
                 &q,
-                &k,
-                &v,
-                attention_mask,
-                cache.map(|(k_, _)| k_.clone()),
-                cache.map(|(_, v_)| v_.clone()),
+                sinks,
+                cache.map(|(k_, _)| k_),
+                input_positions,
                 input_metadata,
-                self.softcapping,
             )?
-            .reshape((seq_len, ()))?;
+        } else {
+            y
+        };
+        let y = y.reshape((seq_len, ()))?;
 
         let y = if let Some(gate) = q_gate {
             let gate = if gate.dtype() != y.dtype() {
@@ -791,6 +829,203 @@ impl Attention {
         )
     }
 
+    /// Rescale the attention output for learned attention sinks.
+    ///
+    /// A sink adds `exp(sink[h])` to the softmax denominator of every query
+    /// without contributing a value, so the sink-free output `y` becomes
+    /// `y * sigmoid(lse - sink[h])`, where `lse` is the log-sum-exp of the
+    /// masked attention logits. The paged attention kernels do not return
+    /// `lse`, so it is recomputed from the key cache, which already holds this
+    /// step's keys. Nothing is read back to the host: keys are gathered block
+    /// by block through the block tables and masked by position on the device.
+    fn apply_attention_sinks(
+        &self,
+        y: &Tensor,
+        q: &Tensor,
+        sinks: &Tensor,
+        key_cache: Option<&Tensor>,
+        input_positions: &Tensor,
+        input_metadata: &InputMetadata,
+    ) -> Result<Tensor> {
+        let (Some(key_cache), Some(block_tables)) = (key_cache, &input_metadata.block_tables)
+        else {
+            candle_core::bail!("attention sinks need the key cache and block tables");
+        };
+        let seq_len = q.dim(0)?;
+        let block_size = if key_cache.rank() == 5 {
+            key_cache.dim(3)?
+        } else {
+            key_cache.dim(1)?
+        };
+        let max_keys = block_tables.dim(1)? * block_size;
+        let max_keys = match input_metadata
+            .max_context_len
+            .max(input_metadata.max_seqlen_k)
+        {
+            0 => max_keys,
+            len => len.min(max_keys),
+        };
+        let q = q.to_dtype(DType::F32)?;
+        let positions = input_positions.to_dtype(DType::F32)?;
+        let lse = if input_metadata.is_prefill {
+            let mut lse = Vec::new();
+            let mut q_start = 0usize;
+            for (i, &q_end) in resolve_input_seqlens(input_metadata)?.iter().enumerate() {
+                let blocks = block_tables.narrow(0, i, 1)?.contiguous()?;
+                while q_start < q_end as usize {
+                    let len = SINK_QUERY_CHUNK.min(q_end as usize - q_start);
+                    let chunk = self.sink_lse(
+                        &q.narrow(0, q_start, len)?.unsqueeze(0)?,
+                        &positions.narrow(0, q_start, len)?.unsqueeze(0)?,
+                        &blocks,
+                        key_cache,
+                        max_keys,
+                    )?;
+                    lse.push(chunk.squeeze(0)?);
+                    q_start += len;
+                }
+            }
+            Tensor::cat(&lse, 0)?
+        } else {
+            // Decode runs one query per sequence, in block-table order.
+            self.sink_lse(
+                &q.unsqueeze(1)?,
+                &positions.unsqueeze(1)?,
+                block_tables,
+                key_cache,
+                max_keys,
+            )?
+            .squeeze(1)?
+        };
+        let scale = candle_nn::ops::sigmoid(&lse.broadcast_sub(&sinks.unsqueeze(0)?)?)?;
+        let y = y.reshape((seq_len, self.num_heads, self.head_dim))?;
+        y.broadcast_mul(&scale.unsqueeze(2)?.to_dtype(y.dtype())?)
+    }
+
+    /// Log-sum-exp of the masked attention logits, `[seqs, queries, heads]`.
+    ///
+    /// `q` is `[seqs, queries, heads, head_dim]` and `positions` holds each
+    /// query's position. Keys are visited a few blocks at a time and merged
+    /// into a running log-sum-exp, so memory stays bounded for long contexts;
+    /// sliding-window layers start at the first block inside their window.
+    fn sink_lse(
+        &self,
+        q: &Tensor,
+        positions: &Tensor,
+        block_tables: &Tensor,
+        key_cache: &Tensor,
+        max_keys: usize,
+    ) -> Result<Tensor> {
+        let (num_seqs, num_queries) = positions.dims2()?;
+        let device = q.device();
+        let group = self.num_heads / self.num_kv_heads;
+        let block_size = if key_cache.rank() == 5 {
+            key_cache.dim(3)?
+        } else {
+            key_cache.dim(1)?
+        };
+        let max_blocks = block_tables.dim(1)?;
+        let mut num_blocks = max_keys.div_ceil(block_size).clamp(1, max_blocks);
+        let first_block = match self.sliding_window {
+            Some(window) => {
+                num_blocks = num_blocks.min((num_queries + window - 1).div_ceil(block_size) + 1);
+                positions
+                    .min_keepdim(1)?
+                    .affine(1.0, 1.0 - window as f64)?
+                    .relu()?
+                    .affine(1.0 / block_size as f64, 0.0)?
+                    .floor()?
+            }
+            None => Tensor::zeros((num_seqs, 1), DType::F32, device)?,
+        };
+        let widest = (self.num_kv_heads * self.head_dim).max(self.num_heads * num_queries);
+        let chunk = (SINK_CHUNK_ELEMS / (num_seqs * block_size * widest)).max(1);
+
+        // [seqs, kv_heads, group * queries, head_dim]
+        let q = q.permute((0, 2, 1, 3))?.contiguous()?.reshape((
+            num_seqs,
+            self.num_kv_heads,
+            group * num_queries,
+            self.head_dim,
+        ))?;
+        let positions = positions.unsqueeze(1)?.unsqueeze(3)?;
+        let offsets = Tensor::arange(0u32, block_size as u32, device)?
+            .to_dtype(DType::F32)?
+            .reshape((1, 1, block_size))?;
+        let mut lse: Option<Tensor> = None;
+        let mut visited = 0;
+        while visited < num_blocks {
+            let c = chunk.min(num_blocks - visited);
+            let num_keys = c * block_size;
+            let block_idx = first_block.broadcast_add(
+                &Tensor::arange(visited as u32, (visited + c) as u32, device)?
+                    .to_dtype(DType::F32)?
+                    .unsqueeze(0)?,
+            )?;
+            // Blocks past the table are clamped here but keep their true
+            // positions below, which the causal mask then hides.
+            let ids = block_idx
+                .clamp(0f32, (max_blocks - 1) as f32)?
+                .to_dtype(DType::U32)?;
+            let ids = block_tables.gather(&ids, 1)?.flatten_all()?;
+            let keys = key_cache.index_select(&ids, 0)?;
+            let keys = if keys.rank() == 5 {
+                // [blocks, kv_heads, head_dim / x, block_size, x]
+                keys.permute((0, 3, 1, 2, 4))?
+            } else {
+                keys
+            };
+            // [seqs, kv_heads, head_dim, keys]
+            let keys = keys
+                .reshape((num_seqs, num_keys, self.num_kv_heads, self.head_dim))?
+                .to_dtype(DType::F32)?
+                .permute((0, 2, 3, 1))?
+                .contiguous()?;
+            let logits = (q.matmul(&keys)? * self.scale as f64)?.reshape((
+                num_seqs,
+                self.num_heads,
+                num_queries,
+                num_keys,
+            ))?;
+
+            let key_pos = block_idx
+                .affine(block_size as f64, 0.0)?
+                .unsqueeze(2)?
+                .broadcast_add(&offsets)?
+                .reshape((num_seqs, 1, 1, num_keys))?;
+            let distance = positions.broadcast_sub(&key_pos)?;
+            let mut visible = distance.ge(0f64)?;
+            if let Some(window) = self.sliding_window {
+                visible = (visible * distance.lt(window as f64)?)?;
+            }
+            // A large finite penalty keeps fully masked chunks free of NaNs.
+            let mask = visible
+                .to_dtype(DType::F32)?
+                .affine(SINK_MASK_PENALTY, -SINK_MASK_PENALTY)?;
+            let logits = logits.broadcast_add(&mask)?;
+            let max = logits.max_keepdim(candle_core::D::Minus1)?;
+            let chunk_lse = (logits
+                .broadcast_sub(&max)?
+                .exp()?
+                .sum_keepdim(candle_core::D::Minus1)?
+                .log()?
+                + max)?;
+            lse = Some(match lse {
+                Some(prev) => {
+                    let max = prev.maximum(&chunk_lse)?;
+                    ((prev - &max)?.exp()? + (chunk_lse - &max)?.exp()?)?.log()? + max
+                }?,
+                None => chunk_lse,
+            });
+            visited += c;
+        }
+        let Some(lse) = lse else {
+            candle_core::bail!("attention sinks found no key blocks to visit");
+        };
+        // [seqs, queries, heads]
+        lse.squeeze(3)?.permute((0, 2, 1))?.contiguous()
+    }
+
     /// Single-token attention without KV cache or paged attention.
     ///
     /// Qwen3.5 MTP drafting uses this for the lightweight MTP head. For a
@@ -1,6 +1,6 @@
 use crate::candle::quantized::QTensor;
 use crate::openai::distributed::{shard, AllReduce, Comm, VarBuilder};
-use crate::openai::models::linear::{linear_no_bias, linear_no_bias_x, Linear, LinearX};
+use crate::openai::models::linear::{linear, linear_no_bias, linear_no_bias_x, Linear, LinearX};
 use crate::openai::models::{Config, MoEConfig, QuantConfig, QwenMoEConfig};
 use attention_rs::moe;
 use attention_rs::moe::moe_gemm_fp8;
@@ -32,6 +32,110 @@ fn gated_activation(gate_up: &Tensor, half_dim: usize, act: &Activation) -> Resu
     }
 }
 
+/// Split interleaved gate/up rows (`gate = 2i`, `up = 2i + 1`) along dim 1
+/// into the `[gate; up]` layout the fused MoE kernels expect.
+fn deinterleave_gate_up(t: &Tensor) -> Result<Tensor> {
+    let dims = t.dims().to_vec();
+    let rest: usize = dims[2..].iter().product();
+    let t = t.reshape((dims[0], dims[1] / 2, 2, rest))?;
+    Tensor::cat(&[t.narrow(2, 0, 1)?, t.narrow(2, 1, 1)?], 1)?.reshape(dims)
+}
+
+/// GPT-OSS expert variant: per-expert gate/up and down biases around a
+/// clamped SwiGLU, `(up + 1) * gate * sigmoid(1.702 * gate)` with gate and
+/// up clamped to `limit`.
+struct ClampedSwiglu {
+    gate_up_bias: Tensor,
+    // The down projection is row-parallel, so only rank 0 adds its bias.
+    down_bias: Option<Tensor>,
+    limit: f64,
+}
+
+impl ClampedSwiglu {
+    fn new(
+        experts_vb: &VarBuilder,
+        num_experts: usize,
+        intermediate_size: usize,
+        hidden_size: usize,
+        comm: &Comm,
+        limit: f64,
+    ) -> Result<Self> {
+        let gate_up_bias = experts_vb.get_with_hints_dtype(
+            (num_experts, intermediate_size * 2),
+            "gate_up_proj_bias",
+            shard(1, comm.rank(), comm.world_size()),
+            DType::F32,
+        )?;
+        let down_bias = if comm.rank() == 0 {
+            Some(experts_vb.get_with_hints_dtype(
+                (num_experts, hidden_size),
+                "down_proj_bias",
+                Shard::default(),
+                DType::F32,
+            )?)
+        } else {
+            None
+        };
+        Ok(Self {
+            gate_up_bias: deinterleave_gate_up(&gate_up_bias)?,
+            down_bias,
+            limit,
+        })
+    }
+
+    /// `gate_up` holds one row per (token, slot), in `topk_ids` order.
+    fn activate(&self, gate_up: &Tensor, topk_ids: &Tensor, half_dim: usize) -> Result<Tensor> {
+        let dtype = gate_up.dtype();
+        let expert_rows = topk_ids.flatten_all()?;
+        let gate_up =
+            (gate_up.to_dtype(DType::F32)? + self.gate_up_bias.index_select(&expert_rows, 0)?)?;
+        let gate = gate_up
+            .narrow(D::Minus1, 0, half_dim)?
+            .minimum(self.limit)?;
+        let up = gate_up
+            .narrow(D::Minus1, half_dim, half_dim)?
+            .clamp(-self.limit, self.limit)?;
+        let glu = (&gate * candle_nn::ops::sigmoid(&(&gate * 1.702)?)?)?;
+        ((up + 1.0)? * glu)?.to_dtype(dtype)
+    }
+
+    /// Add the routing-weighted down biases to the combined expert output.
+    fn add_down_bias(
+        &self,
+        ys: Tensor,
+        topk_weights: &Tensor,
+        topk_ids: &Tensor,
+    ) -> Result<Tensor> {
+        let Some(down_bias) = &self.down_bias else {
+            return Ok(ys);
+        };
+        let (num_tokens, topk) = topk_ids.dims2()?;
+        let bias = down_bias
+            .index_select(&topk_ids.flatten_all()?, 0)?
+            .reshape((num_tokens, topk, ()))?
+            .broadcast_mul(&topk_weights.to_dtype(DType::F32)?.unsqueeze(D::Minus1)?)?
+            .sum(1)?;
+        (ys.to_dtype(DType::F32)? + bias)?.to_dtype(ys.dtype())
+    }
+}
+
+fn gpt_oss_moe_cfg(cfg: &Config, comm: &Comm) -> Result<(usize, usize)> {
+    let moe_cfg = qwen_moe_cfg(cfg)?;
+    let num_experts = moe_cfg.num_experts.unwrap_or(0);
+    if num_experts == 0 {
+        candle::bail!("GPT-OSS requires num_local_experts and experts_per_token")
+    }
+    let inter = moe_cfg.moe_intermediate_size;
+    if (inter / 32) % comm.world_size() != 0 {
+        candle::bail!(
+            "GPT-OSS intermediate_size {} cannot be split into MXFP4 blocks across {} ranks",
+            inter,
+            comm.world_size()
+        );
+    }
+    Ok((num_experts, inter))
+}
+
 pub(crate) fn sort_expert_assignments(
     topk_ids: &Tensor,
     is_prefill: bool,
@@ -337,6 +441,7 @@ pub struct FusedMoe {
     w_size_n: usize,
     down_w: Tensor,
     act: Activation,
+    swiglu: Option<ClampedSwiglu>,
     norm_topk_prob: bool,
     routed_scaling_factor: Option<f64>,
     num_experts_per_tok: usize,
@@ -375,6 +480,7 @@ impl FusedMoe {
             w_size_n,
             down_w,
             act: get_hidden_act(cfg),
+            swiglu: None,
             norm_topk_prob: moe_cfg.norm_topk_prob,
             routed_scaling_factor: moe_cfg.routed_scaling_factor,
             num_experts_per_tok: moe_cfg.num_experts_per_tok,
@@ -410,6 +516,7 @@ impl FusedMoe {
             w_size_n,
             down_w,
             act: get_hidden_act(cfg),
+            swiglu: None,
             norm_topk_prob: moe_cfg.norm_topk_prob,
             routed_scaling_factor: moe_cfg.routed_scaling_factor,
             num_experts_per_tok: moe_cfg.num_experts_per_tok,
@@ -419,6 +526,58 @@ impl FusedMoe {
         })
     }
 
+    /// BF16 GPT-OSS experts: a biased router, fused interleaved gate/up
+    /// weights, per-expert biases and a clamped SwiGLU.
+    pub fn new_gpt_oss(
+        cfg: &Config,
+        vb: VarBuilder,
+        comm: Rc<Comm>,
+        dtype: DType,
+        swiglu_limit: f64,
+    ) -> Result<Self> {
+        let moe_cfg = qwen_moe_cfg(cfg)?;
+        let (num_experts, inter) = gpt_oss_moe_cfg(cfg, &comm)?;
+        let hidden = cfg.hidden_size;
+        let (rank, world_size) = (comm.rank(), comm.world_size());
+        let gate = linear(hidden, num_experts, vb.pp("router"), Shard::default())?;
+
+        let experts_vb = vb.pp("experts");
+        // [experts, hidden, 2 * intermediate] and [experts, intermediate, hidden]
+        let gate_up_w = experts_vb
+            .get_with_hints(
+                (num_experts, hidden, inter * 2),
+                "gate_up_proj",
+                shard(2, rank, world_size),
+            )?
+            .t()?
+            .contiguous()?;
+        let down_w = experts_vb
+            .get_with_hints(
+                (num_experts, inter, hidden),
+                "down_proj",
+                shard(1, rank, world_size),
+            )?
+            .t()?
+            .contiguous()?;
+        let swiglu =
+            ClampedSwiglu::new(&experts_vb, num_experts, inter, hidden, &comm, swiglu_limit)?;
+
+        Ok(Self {
+            gate,
+            gate_up_w: deinterleave_gate_up(&gate_up_w)?.to_dtype(dtype)?,
+            w_size_n: inter / world_size,
+            down_w: down_w.to_dtype(dtype)?,
+            act: Activation::Silu,
+            swiglu: Some(swiglu),
+            norm_topk_prob: true,
+            routed_scaling_factor: None,
+            num_experts_per_tok: moe_cfg.num_experts_per_tok,
+            all_reduce: AllReduce::new(comm),
+            world_size,
+            dtype,
+        })
+    }
+
     pub fn forward(&self, xs: &Tensor, is_prefill: bool) -> Result<Tensor> {
         let router_logits = self.gate.forward(&xs)?;
 
@@ -459,12 +618,15 @@ impl FusedMoe {
             is_prefill,
         )?;
 
-        let down_inputs = gated_activation(&gate_up, self.w_size_n, &self.act)?;
+        let down_inputs = match &self.swiglu {
+            Some(swiglu) => swiglu.activate(&gate_up, &topk_ids, self.w_size_n)?,
+            None => gated_activation(&gate_up, self.w_size_n, &self.act)?,
+        };
 
         let mut ys = moe::moe_gemm(
             &down_inputs,
             &self.down_w,
-            &Some(topk_weights),
+            &Some(topk_weights.clone()),
             &sorted_token_ids,
             &expert_ids,
             self.num_experts_per_tok,
@@ -472,6 +634,9 @@ impl FusedMoe {
         )?
         .reshape((num_tokens, (), hidden_dim))?
         .sum(D::Minus2)?;
+        if let Some(swiglu) = &self.swiglu {
+            ys = swiglu.add_down_bias(ys, &topk_weights, &topk_ids)?;
+        }
 
         if self.world_size > 1 {
             ys = self.all_reduce.apply(&ys)?;
@@ -1848,6 +2013,7 @@ pub struct FusedMoeMxfp4 {
     down_scales: Tensor,
     w_size_n: usize,
     act: Activation,
+    swiglu: Option<ClampedSwiglu>,
     norm_topk_prob: bool,
     routed_scaling_factor: Option<f64>,
     num_experts_per_tok: usize,
@@ -1987,6 +2153,7 @@ impl FusedMoeMxfp4 {
             down_scales,
             w_size_n,
             act: get_hidden_act(cfg),
+            swiglu: None,
             norm_topk_prob: moe_cfg.norm_topk_prob,
             routed_scaling_factor: moe_cfg.routed_scaling_factor,
             num_experts_per_tok: moe_cfg.num_experts_per_tok,
@@ -2110,6 +2277,7 @@ impl FusedMoeMxfp4 {
             down_scales,
             w_size_n,
             act: get_hidden_act(cfg),
+            swiglu: None,
             norm_topk_prob: moe_cfg.norm_topk_prob,
             routed_scaling_factor: moe_cfg.routed_scaling_factor,
             num_experts_per_tok: moe_cfg.num_experts_per_tok,
@@ -2119,6 +2287,77 @@ impl FusedMoeMxfp4 {
         })
     }
 
+    /// GPT-OSS experts: a biased BF16 router, fused interleaved gate/up
+    /// MXFP4 blocks, per-expert biases and a clamped SwiGLU.
+    pub fn new_gpt_oss(
+        cfg: &Config,
+        vb: VarBuilder,
+        comm: Rc<Comm>,
+        dtype: DType,
+        swiglu_limit: f64,
+    ) -> Result<Self> {
+        let moe_cfg = qwen_moe_cfg(cfg)?;
+        let (num_experts, inter) = gpt_oss_moe_cfg(cfg, &comm)?;
+        let hidden = cfg.hidden_size;
+        let (rank, world_size) = (comm.rank(), comm.world_size());
+        let local_inter = inter / world_size;
+        let gate = LinearX::Linear(linear(
+            hidden,
+            num_experts,
+            vb.pp("router"),
+            Shard::default(),
+        )?);
+
+        let experts_vb = vb.pp("experts");
+        let gate_up_blocks = experts_vb.get_with_hints_dtype(
+            (num_experts, inter * 2, hidden / 32, 16),
+            "gate_up_proj_blocks",
+            shard(1, rank, world_size),
+            DType::U8,
+        )?;
+        let gate_up_scales = experts_vb.get_with_hints_dtype(
+            (num_experts, inter * 2, hidden / 32),
+            "gate_up_proj_scales",
+            shard(1, rank, world_size),
+            DType::U8,
+        )?;
+        let down_blocks = experts_vb.get_with_hints_dtype(
+            (num_experts, hidden, inter / 32, 16),
+            "down_proj_blocks",
+            shard(2, rank, world_size),
+            DType::U8,
+        )?;
+        let down_scales = experts_vb.get_with_hints_dtype(
+            (num_experts, hidden, inter / 32),
+            "down_proj_scales",
+            shard(2, rank, world_size),
+            DType::U8,
+        )?;
+        let swiglu =
+            ClampedSwiglu::new(&experts_vb, num_experts, inter, hidden, &comm, swiglu_limit)?;
+
+        Ok(Self {
+            gate,
+            gate_up_blocks: deinterleave_gate_up(&gate_up_blocks)?.reshape((
+                num_experts,
+                local_inter * 2,
+                hidden / 2,
+            ))?,
+            gate_up_scales: deinterleave_gate_up(&gate_up_scales)?,
+            down_blocks: down_blocks.reshape((num_experts, hidden, local_inter / 2))?,
+            down_scales: down_scales.contiguous()?,
+            w_size_n: local_inter,
+            act: Activation::Silu,
+            swiglu: Some(swiglu),
+            norm_topk_prob: true,
+            routed_scaling_factor: None,
+            num_experts_per_tok: moe_cfg.num_experts_per_tok,
+            all_reduce: AllReduce::new(comm),
+            world_size,
+            dtype,
+        })
+    }
+
     pub fn forward(&self, xs: &Tensor, is_prefill: bool) -> Result<Tensor> {
         let router_logits = self.gate.forward(xs)?;
 
@@ -2168,7 +2407,10 @@ impl FusedMoeMxfp4 {
             None,
         )?;
 
-        let down_inputs = gated_activation(&gate_up, self.w_size_n, &self.act)?;
+        let down_inputs = match &self.swiglu {
+            Some(swiglu) => swiglu.activate(&gate_up, &topk_ids, self.w_size_n)?,
+            None => gated_activation(&gate_up, self.w_size_n, &self.act)?,
+        };
         let down_inputs = if down_inputs.dtype() != moe_dtype {
             down_inputs.to_dtype(moe_dtype)?
         } else {
@@ -2186,6 +2428,9 @@ impl FusedMoeMxfp4 {
         )?
         .reshape((num_tokens, self.num_experts_per_tok, hidden_dim))?
         .sum(1)?;
+        if let Some(swiglu) = &self.swiglu {
+            ys = swiglu.add_down_bias(ys, &topk_weights, &topk_ids)?;
+        }
 
         if self.world_size > 1 {
             ys = self.all_reduce.apply(&ys)?;
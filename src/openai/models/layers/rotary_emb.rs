@@ -295,6 +295,12 @@ impl ScalingRotaryEmbedding {
                             ScalingValue::Single(beta_slow),
                             Some(ScalingValue::Single(factor)),
                         ) => {
+                            // GPT-OSS sets `truncate: false` to keep the
+                            // correction range unrounded.
+                            let truncate = !matches!(
+                                rope_scaling.get("truncate"),
+                                Some(ScalingValue::Bool(false))
+                            );
                             let embed = YarnRotaryEmbedding::new_yarn(
                                 dtype,
                                 dev,
@@ -307,6 +313,7 @@ impl ScalingRotaryEmbedding {
                                 *attn_factor as f32,
                                 *extrapolation_factor as f32,
                                 *factor as f32,
+                                truncate,
                             )?;
                             Self(DefaultRotaryEmbedding {
                                 sin: embed.sin,
@@ -323,6 +330,75 @@ impl ScalingRotaryEmbedding {
                             candle_core::bail!("yarn rope_type requires factor to be set");
                         }
                     }
+                } else if rope_type == "su" || rope_type == "longrope" {
+                    match (
+                        rope_scaling.get("short_factor"),
+                        rope_scaling.get("long_factor"),
+                    ) {
+                        (
+                            Some(ScalingValue::Vec(short_factor)),
+                            Some(ScalingValue::Vec(long_factor)),
+                        ) if short_factor.len() == rotary_dim / 2
+                            && long_factor.len() == rotary_dim / 2 =>
+                        {
+                            let scale = cfg.max_position_embeddings.unwrap_or(cfg.max_seq_len)
+                                as f64
+                                / original_max_position_embeddings;
+                            let default_mscale = if scale <= 1.0 {
+                                1.0
+                            } else {
+                                (1.0 + scale.ln() / original_max_position_embeddings.ln()).sqrt()
+                            };
+                            let mscale = |key: &str| match rope_scaling.get(key) {
+                                Some(ScalingValue::Single(v)) => *v,
+                                _ => default_mscale,
+                            };
+                            // One static table: positions inside the original window use the
+                            // short factors, the rest the long ones.
+                            let split =
+                                (original_max_position_embeddings as usize).min(cfg.max_seq_len);
+                            let mut cos = Vec::with_capacity(2);
+                            let mut sin = Vec::with_capacity(2);
+                            for (start, end, factors, table_mscale) in [
+                                (0, split, short_factor, mscale("short_mscale")),
+                                (split, cfg.max_seq_len, long_factor, mscale("long_mscale")),
+                            ] {
+                                if start == end {
+                                    continue;
+                                }
+                                let inv_freq: Vec<_> =
+                                    calculate_default_inv_freq(cfg.rope_theta, rotary_dim)
+                                        .into_iter()
+                                        .zip(factors.iter())
+                                        .map(|(freq, factor)| freq / *factor as f32)
+                                        .collect();
+                                let inv_freq_len = inv_freq.len();
+                                let inv_freq = Tensor::from_vec(inv_freq, (1, inv_freq_len), dev)?;
+                                let t = Tensor::arange(start as u32, end as u32, dev)?
+                                    .to_dtype(DType::F32)?
+                                    .reshape((end - start, 1))?;
+                                let freqs = t.matmul(&inv_freq)?;
+                                cos.push((freqs.cos()? * table_mscale)?);
+                                sin.push((freqs.sin()? * table_mscale)?);
+                            }
+                            Self(DefaultRotaryEmbedding {
+                                cos: Tensor::cat(&cos, 0)?.to_dtype(dtype)?,
+                                sin: Tensor::cat(&sin, 0)?.to_dtype(dtype)?,
+                                is_gpt_neox,
+                                rotary_dim: if cfg.partial_rotary_factor.is_some() {
+                                    Some(rotary_dim)
+                                } else {
+                                    None
+                                },
+                            })
+                        }
+                        _ => {
+                            candle_core::bail!(
+                                "{rope_type} rope_type requires short_factor and long_factor of length {}",
+                                rotary_dim / 2
+                            );
+                        }
+                    }
                 } else {
                     candle_core::bail!("Unknown rope_type: {rope_type}");
                 };
@@ -372,11 +448,15 @@ impl YarnRotaryEmbedding {
         dim: usize,
         base: f32,
         max_position_embeddings: usize,
+        truncate: bool,
     ) -> (f32, f32) {
-        let low =
-            Self::yarn_find_correction_dim(low_rot, dim, base, max_position_embeddings).floor();
-        let high =
-            Self::yarn_find_correction_dim(high_rot, dim, base, max_position_embeddings).ceil();
+        let low = Self::yarn_find_correction_dim(low_rot, dim, base, max_position_embeddings);
+        let high = Self::yarn_find_correction_dim(high_rot, dim, base, max_position_embeddings);
+        let (low, high) = if truncate {
+            (low.floor(), high.ceil())
+        } else {
+            (low, high)
+        };
         (low.max(0.), high.min(dim as f32 - 1.))
     }
 
@@ -409,6 +489,7 @@ impl YarnRotaryEmbedding {
         attn_factor: f32,
         extrapolation_factor: f32,
         factor: f32,
+        truncate: bool,
     ) -> Result<Self> {
         let freq_extra: Vec<_> = (0..dim)
             .step_by(2)
@@ -429,6 +510,7 @@ impl YarnRotaryEmbedding {
             dim,
             rope_theta,
             original_max_position_embeddings,
+            truncate,
         );
         let inv_freq_mask = ((1.
             - Self::yarn_linear_ramp_mask(low, high, dim / 2, &Device::Cpu)?)?
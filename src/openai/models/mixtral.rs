@@ -0,0 +1,540 @@
+use super::{
+    attention::Attention,
+    layers::moe::FusedMoeWNA16,
+    layers::moe::{FusedMoe, FusedMoeFp8, FusedMoeISQ, FusedMoeMxfp4, FusedMoeNvfp4},
+    rotary_emb::ScalingRotaryEmbedding,
+    utils::resolve_input_seqlens,
+    Config, MoEConfig, QwenMoEConfig,
+};
+use crate::backend::progress::{ProgressLike, ProgressReporter};
+use crate::openai::distributed::{embedding, Comm, VarBuilder, VocabParallelLinear};
+use crate::openai::models::layers::others::{layer_norm, rms_norm, NormX};
+use crate::openai::models::mask::get_attention_causal_mask;
+use crate::{InputMetadata, InputMetadataExt};
+use candle::{DType, Device, Module, Result, Tensor, D};
+use candle_core as candle;
+use candle_nn::Linear;
+use parking_lot::RwLock;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::Arc;
+
+enum MoeVariant {
+    FusedMoe(FusedMoe),
+    FusedMoeISQ(FusedMoeISQ),
+    FusedMoeFp8(FusedMoeFp8),
+    FusedMoeMxfp4(FusedMoeMxfp4),
+    FusedMoeNvfp4(FusedMoeNvfp4),
+    FusedMoeWNA16(FusedMoeWNA16),
+}
+
+impl MoeVariant {
+    fn forward(&self, xs: &Tensor, is_prefill: bool) -> Result<Tensor> {
+        match self {
+            Self::FusedMoe(m) => m.forward(xs, is_prefill),
+            Self::FusedMoeISQ(m) => m.forward(xs, is_prefill),
+            Self::FusedMoeFp8(m) => m.forward(xs, is_prefill),
+            Self::FusedMoeMxfp4(m) => m.forward(xs, is_prefill),
+            Self::FusedMoeNvfp4(m) => m.forward(xs, is_prefill),
+            Self::FusedMoeWNA16(m) => m.forward(xs, is_prefill),
+        }
+    }
+
+    fn forward_with_routing(
+        &self,
+        xs: &Tensor,
+        topk_weights: Tensor,
+        topk_ids: Tensor,
+        is_prefill: bool,
+    ) -> Result<Tensor> {
+        match self {
+            Self::FusedMoe(m) => m.forward_with_routing(xs, topk_weights, topk_ids, is_prefill),
+            Self::FusedMoeISQ(m) => m.forward_with_routing(xs, topk_weights, topk_ids, is_prefill),
+            Self::FusedMoeFp8(m) => m.forward_with_routing(xs, topk_weights, topk_ids, is_prefill),
+            Self::FusedMoeMxfp4(m) => {
+                m.forward_with_routing(xs, topk_weights, topk_ids, is_prefill)
+            }
+            Self::FusedMoeNvfp4(m) => {
+                m.forward_with_routing(xs, topk_weights, topk_ids, is_prefill)
+            }
+            Self::FusedMoeWNA16(m) => {
+                m.forward_with_routing(xs, topk_weights, topk_ids, is_prefill)
+            }
+        }
+    }
+}
+
+/// Top-2 "sparsemixer" router used by PhiMoE (inference path).
+struct SparseMixerRouter {
+    gate: Linear,
+    jitter_eps: f64,
+}
+
+impl SparseMixerRouter {
+    fn new(cfg: &Config, num_experts: usize, jitter_eps: f64, vb: VarBuilder) -> Result<Self> {
+        let weight = vb
+            .get((num_experts, cfg.hidden_size), "weight")?
+            .to_dtype(DType::F32)?;
+        Ok(Self {
+            gate: Linear::new(weight, None),
+            jitter_eps,
+        })
+    }
+
+    /// Picks the best expert among `candidates` and weights it by a softmax
+    /// over the experts whose score lies within `2 * jitter_eps` of it.
+    fn select(&self, candidates: &Tensor, scores: &Tensor) -> Result<(Tensor, Tensor)> {
+        let threshold = candidates.max_keepdim(D::Minus1)?;
+        let ids = candidates.argmax_keepdim(D::Minus1)?;
+        let factor = scores.abs()?.broadcast_maximum(&threshold)?;
+        let dropped = (threshold.broadcast_sub(scores)? / factor)?.gt(2.0 * self.jitter_eps)?;
+        let neg_inf = Tensor::full(f32::NEG_INFINITY, scores.shape(), scores.device())?;
+        let gates = candle_nn::ops::softmax_last_dim(&dropped.where_cond(&neg_inf, candidates)?)?;
+        let weights = gates.gather(&ids, D::Minus1)?;
+        Ok((weights, ids))
+    }
+
+    fn forward(&self, xs: &Tensor) -> Result<(Tensor, Tensor)> {
+        let scores = self.gate.forward(&xs.to_dtype(DType::F32)?)?;
+        let (first_weight, first_id) = self.select(&scores, &scores)?;
+
+        let num_experts = scores.dim(D::Minus1)?;
+        let chosen = Tensor::arange(0u32, num_experts as u32, scores.device())?
+            .unsqueeze(0)?
+            .broadcast_eq(&first_id)?;
+        let neg_inf = Tensor::full(f32::NEG_INFINITY, scores.shape(), scores.device())?;
+        let remaining = chosen.where_cond(&neg_inf, &scores)?;
+        let (second_weight, second_id) = self.select(&remaining, &scores)?;
+
+        let topk_weights = Tensor::cat(&[&first_weight, &second_weight], D::Minus1)?;
+        let topk_ids = Tensor::cat(&[&first_id, &second_id], D::Minus1)?;
+        Ok((topk_weights.contiguous()?, topk_ids.contiguous()?))
+    }
+}
+
+struct MixtralSparseMoe {
+    experts: MoeVariant,
+    router: Option<SparseMixerRouter>,
+}
+
+impl MixtralSparseMoe {
+    fn new(cfg: &Config, vb: VarBuilder, comm: Rc<Comm>, dtype: DType) -> Result<Self> {
+        let experts = if let Some(ref quant_cfg) = cfg.quantization_config {
+            if quant_cfg.quant_method == "fp8" {
+                MoeVariant::FusedMoeFp8(FusedMoeFp8::new(
+                    cfg,
+                    vb.clone(),
+                    comm.clone(),
+                    dtype,
+                    quant_cfg,
+                )?)
+            } else if quant_cfg.quant_method == "mxfp4" {
+                MoeVariant::FusedMoeMxfp4(FusedMoeMxfp4::new(cfg, vb.clone(), comm.clone(), dtype)?)
+            } else if quant_cfg.quant_method == "nvfp4" {
+                MoeVariant::FusedMoeNvfp4(FusedMoeNvfp4::new(cfg, vb.clone(), comm.clone(), dtype)?)
+            } else if quant_cfg.is_compressed_tensors || quant_cfg.quant_method == "gptq" {
+                MoeVariant::FusedMoeWNA16(FusedMoeWNA16::new(
+                    cfg,
+                    vb.clone(),
+                    comm.clone(),
+                    dtype,
+                    quant_cfg,
+                )?)
+            } else {
+                candle::bail!(
+                    "Unsupported quantization for Mixtral MoE: {}",
+                    quant_cfg.quant_method
+                );
+            }
+        } else if cfg.isq_quant.is_some() {
+            MoeVariant::FusedMoeISQ(FusedMoeISQ::new(cfg, vb.clone(), comm.clone(), dtype)?)
+        } else {
+            MoeVariant::FusedMoe(FusedMoe::new(cfg, vb.clone(), comm.clone(), dtype)?)
+        };
+
+        let router = if MixtralForCausalLM::is_phimoe(cfg) {
+            let Some(MoEConfig::QwenMoE(moe_cfg)) = &cfg.moe_config else {
+                candle::bail!("PhiMoE requires a MoE config");
+            };
+            if moe_cfg.num_experts_per_tok != 2 {
+                candle::bail!(
+                    "PhiMoE sparsemixer routing requires num_experts_per_tok = 2, got {}",
+                    moe_cfg.num_experts_per_tok
+                );
+            }
+            let jitter_eps = cfg
+                .extra_config_json
+                .as_ref()
+                .and_then(|raw| serde_json::from_str::<serde_json::Value>(raw).ok())
+                .and_then(|v| v.get("router_jitter_noise").and_then(|v| v.as_f64()))
+                .unwrap_or(0.01);
+            Some(SparseMixerRouter::new(
+                cfg,
+                moe_cfg.num_experts.unwrap_or(0),
+                jitter_eps,
+                vb.pp("gate"),
+            )?)
+        } else {
+            None
+        };
+
+        Ok(Self { experts, router })
+    }
+
+    fn forward(&self, xs: &Tensor, is_prefill: bool) -> Result<Tensor> {
+        match &self.router {
+            Some(router) => {
+                let (topk_weights, topk_ids) = router.forward(xs)?;
+                self.experts
+                    .forward_with_routing(xs, topk_weights, topk_ids, is_prefill)
+            }
+            None => self.experts.forward(xs, is_prefill),
+        }
+    }
+}
+
+struct MixtralDecoderLayer {
+    self_attn: Attention,
+    moe: MixtralSparseMoe,
+    input_layernorm: NormX,
+    post_attention_layernorm: NormX,
+}
+
+impl MixtralDecoderLayer {
+    fn new(
+        rotary_emb: Arc<ScalingRotaryEmbedding>,
+        cfg: &Config,
+        vb: VarBuilder,
+        comm: Rc<Comm>,
+        dtype: DType,
+    ) -> Result<Self> {
+        let self_attn = Attention::new(
+            rotary_emb,
+            cfg,
+            vb.pp("self_attn"),
+            comm.clone(),
+            cfg.sliding_window,
+        )?;
+
+        let moe = MixtralSparseMoe::new(cfg, vb.pp("block_sparse_moe"), comm.clone(), dtype)?;
+
+        let input_layernorm = MixtralForCausalLM::norm(cfg, vb.pp("input_layernorm"))?;
+        let post_attention_layernorm =
+            MixtralForCausalLM::norm(cfg, vb.pp("post_attention_layernorm"))?;
+
+        Ok(Self {
+            self_attn,
+            moe,
+            input_layernorm,
+            post_attention_layernorm,
+        })
+    }
+
+    fn forward(
+        &self,
+        xs: &Tensor,
+        attention_mask: Option<&Vec<Tensor>>,
+        input_positions: &Tensor,
+        cache: Option<(&Tensor, &Tensor)>,
+        input_metadata: &InputMetadata,
+    ) -> Result<Tensor> {
+        let residual = xs;
+        let xs = self.input_layernorm.forward(xs)?;
+        let attn_output =
+            self.self_attn
+                .forward(&xs, attention_mask, input_positions, cache, input_metadata)?;
+        let xs = (attn_output + residual)?;
+        let residual = &xs;
+        let xs = self.post_attention_layernorm.forward(&xs)?;
+        let mlp_output = self.moe.forward(&xs, input_metadata.moe_is_prefill())?;
+        residual + mlp_output
+    }
+}
+
+/// Mixtral-style sparse MoE, shared by `MixtralForCausalLM` and
+/// `PhiMoEForCausalLM` (LayerNorm, biased attention/lm_head and
+/// sparsemixer routing).
+pub struct MixtralForCausalLM {
+    embed_tokens: candle_nn::Embedding,
+    layers: Vec<MixtralDecoderLayer>,
+    norm: NormX,
+    lm_head: VocabParallelLinear,
+    device: Device,
+    dtype: DType,
+    cfg: Config,
+    vocab_size: usize,
+}
+
+impl MixtralForCausalLM {
+    fn is_phimoe(cfg: &Config) -> bool {
+        cfg.architectures
+            .as_ref()
+            .and_then(|a| a.first())
+            .is_some_and(|arch| arch == "PhiMoEForCausalLM")
+    }
+
+    fn norm(cfg: &Config, vb: VarBuilder) -> Result<NormX> {
+        if Self::is_phimoe(cfg) {
+            layer_norm(cfg.hidden_size, cfg.rms_norm_eps, true, vb, DType::F32)
+        } else {
+            rms_norm(cfg.hidden_size, cfg.rms_norm_eps, vb, DType::F32, false)
+        }
+    }
+
+    pub fn load_config(filename: &PathBuf, isq: Option<String>) -> Result<Config> {
+        let mut config = Config::load_config(filename.clone())?;
+        config.head_dim = Some(
+            config
+                .head_dim
+                .unwrap_or(config.hidden_size / config.num_attention_heads),
+        );
+        config.num_key_value_heads = Some(
+            config
+                .num_key_value_heads
+                .unwrap_or(config.num_attention_heads),
+        );
+        config.max_seq_len = config.effective_max_seq_len();
+        config.attention_bias = Some(config.attention_bias.unwrap_or(false));
+
+        config.isq_quant = if config.quantization_config.is_some() {
+            None
+        } else {
+            isq
+        };
+
+        if config.moe_config.is_none() {
+            let f = std::fs::read(filename).map_err(candle::Error::wrap)?;
+            if let Some(moe_cfg) = Self::parse_mixtral_moe_config(&f) {
+                config.moe_config = Some(MoEConfig::QwenMoE(moe_cfg));
+            }
+        }
+        if let Some(MoEConfig::QwenMoE(ref mut moe_cfg)) = config.moe_config {
+            moe_cfg.norm_topk_prob = true;
+        }
+
+        Ok(config)
+    }
+
+    /// Mixtral configs size the experts with `intermediate_size` and count
+    /// them with `num_local_experts`.
+    fn parse_mixtral_moe_config(raw_cfg: &[u8]) -> Option<QwenMoEConfig> {
+        let mut raw_cfg_json: serde_json::Value = serde_json::from_slice(raw_cfg).ok()?;
+        let raw_cfg_obj = raw_cfg_json.as_object_mut()?;
+        if !raw_cfg_obj.contains_key("moe_intermediate_size") {
+            let intermediate_size = raw_cfg_obj.get("intermediate_size")?.clone();
+            raw_cfg_obj.insert("moe_intermediate_size".to_string(), intermediate_size);
+        }
+        serde_json::from_value(raw_cfg_json).ok()
+    }
+
+    pub fn new(
+        vb: VarBuilder,
+        cfg: &Config,
+        dtype: DType,
+        device: &Device,
+        comm: Rc<Comm>,
+        progress_reporter: Arc<RwLock<ProgressReporter>>,
+    ) -> Result<Self> {
+        let vb_m = vb.pp("model");
+
+        let embed_tokens = embedding(cfg.vocab_size, cfg.hidden_size, vb_m.pp("embed_tokens"))?;
+        let rotary_emb = Arc::new(ScalingRotaryEmbedding::new(DType::F32, cfg, device, true)?);
+
+        let mut layers = Vec::with_capacity(cfg.num_hidden_layers);
+        let vb_l = vb_m.pp("layers");
+        let reporter = progress_reporter.clone();
+
+        for layer_idx in 0..cfg.num_hidden_layers {
+            let layer = MixtralDecoderLayer::new(
+                rotary_emb.clone(),
+                cfg,
+                vb_l.pp(layer_idx),
+                comm.clone(),
+                dtype,
+            )?;
+            layers.push(layer);
+            reporter.write().set_progress(layer_idx + 1);
+        }
+
+        let norm = Self::norm(cfg, vb_m.pp("norm"))?;
+
+        let lm_head_vb = if cfg.tie_word_embeddings {
+            vb_m.pp("embed_tokens")
+        } else {
+            vb.pp("lm_head")
+        };
+        // PhiMoE ships a biased lm_head.
+        let lm_head = if lm_head_vb.contains_tensor("bias") {
+            let weight = lm_head_vb
+                .get((cfg.vocab_size, cfg.hidden_size), "weight")?
+                .to_dtype(dtype)?;
+            let bias = lm_head_vb.get(cfg.vocab_size, "bias")?.to_dtype(dtype)?;
+            VocabParallelLinear::from_weight_bias(
+                weight,
+                Some(bias),
+                comm.clone(),
+                cfg.vocab_size,
+                dtype,
+            )?
+        } else {
+            VocabParallelLinear::load_no_bias(
+                cfg.hidden_size,
+                cfg.vocab_size,
+                lm_head_vb,
+                comm.clone(),
+                &None,
+                &cfg.quantization_config,
+                dtype,
+            )?
+        };
+
+        Ok(Self {
+            embed_tokens,
+            layers,
+            norm,
+            lm_head,
+            device: device.clone(),
+            dtype,
+            cfg: cfg.clone(),
+            vocab_size: cfg.vocab_size,
+        })
+    }
+
+    pub fn embed_forward(&self, input_ids: &Tensor) -> Result<Tensor> {
+        let xs = self.embed_tokens.forward(input_ids)?;
+        if self.cfg.isq_quant.is_some() && xs.dtype() != DType::F32 {
+            xs.to_dtype(DType::F32)
+        } else {
+            Ok(xs)
+        }
+    }
+
+    fn forward_inner(
+        &self,
+        input_ids: &Tensor,
+        input_positions: &Tensor,
+        kv_caches: Option<&Vec<(Tensor, Tensor)>>,
+        input_metadata: &InputMetadata,
+        embedded_inputs: bool,
+        return_hidden: bool,
+    ) -> Result<Tensor> {
+        let seqlens = resolve_input_seqlens(input_metadata)?;
+
+        let attention_mask = get_attention_causal_mask(
+            &self.device,
+            self.dtype,
+            input_positions,
+            &seqlens,
+            self.cfg.sliding_window,
+            input_metadata.is_prefill,
+        );
+
+        let mut xs = if embedded_inputs {
+            input_ids.to_owned()
+        } else {
+            self.embed_forward(input_ids)?
+        };
+
+        if let Some(kv_caches) = kv_caches {
+            for ((k_cache, v_cache), layer) in kv_caches.iter().zip(self.layers.iter()) {
+                xs = layer.forward(
+                    &xs,
+                    attention_mask.as_ref(),
+                    input_positions,
+                    Some((k_cache, v_cache)),
+                    input_metadata,
+                )?;
+            }
+        } else {
+            for layer in self.layers.iter() {
+                xs = layer.forward(
+                    &xs,
+                    attention_mask.as_ref(),
+                    input_positions,
+                    None,
+                    input_metadata,
+                )?;
+            }
+        }
+
+        if !seqlens.is_empty() && !return_hidden && !input_metadata.is_mtp_verify {
+            let indices: Vec<_> = seqlens.iter().map(|x| x - 1 as u32).collect();
+            let batch = indices.len();
+            xs = xs.index_select(&Tensor::from_vec(indices, (batch,), xs.device())?, 0)?;
+        }
+
+        let xs = self.norm.forward(&xs)?;
+
+        if return_hidden {
+            return xs.to_dtype(DType::F32);
+        }
+        self.lm_head
+            .forward(&xs.to_dtype(self.dtype)?)?
+            .to_dtype(DType::F32)
+    }
+
+    pub fn forward(
+        &self,
+        input_ids: &Tensor,
+        input_positions: &Tensor,
+        kv_caches: Option<&Vec<(Tensor, Tensor)>>,
+        input_metadata: &InputMetadata,
+    ) -> Result<Tensor> {
+        self.forward_inner(
+            input_ids,
+            input_positions,
+            kv_caches,
+            input_metadata,
+            false,
+            false,
+        )
+    }
+
+    pub fn forward_embedding(
+        &self,
+        input_ids: &Tensor,
+        input_positions: &Tensor,
+        kv_caches: Option<&Vec<(Tensor, Tensor)>>,
+        input_metadata: &InputMetadata,
+    ) -> Result<Tensor> {
+        self.forward_inner(
+            input_ids,
+            input_positions,
+            kv_caches,
+            input_metadata,
+            false,
+            true,
+        )
+    }
+
+    pub fn get_vocab_size(&self) -> usize {
+        self.vocab_size
+    }
+
+    pub fn dtype(&self) -> DType {
+        self.dtype
+    }
+
+    pub fn get_config(&self) -> &Config {
+        &self.cfg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MixtralForCausalLM;
+
+    #[test]
+    fn test_parse_mixtral_moe_config_maps_expert_fields() {
+        let raw = br#"{
+            "architectures": ["MixtralForCausalLM"],
+            "intermediate_size": 14336,
+            "num_local_experts": 8,
+            "num_experts_per_tok": 2
+        }"#;
+        let moe_cfg = MixtralForCausalLM::parse_mixtral_moe_config(raw).unwrap();
+        assert_eq!(moe_cfg.moe_intermediate_size, 14336);
+        assert_eq!(moe_cfg.num_experts, Some(8));
+        assert_eq!(moe_cfg.num_experts_per_tok, 2);
+    }
+}
@@ -6,6 +6,7 @@ pub mod gemma3_vl;
 pub mod gemma4;
 pub mod glm4;
 pub mod glm4_moe_lite;
+pub mod gpt_oss;
 pub mod layers;
 pub mod linear;
 pub mod llama;
@@ -13,6 +14,7 @@ pub mod llama4;
 pub mod minimax;
 pub mod mistral;
 pub mod mistral3_vl;
+pub mod mixtral;
 pub mod phi2;
 pub mod phi4;
 pub mod quantized_deepseek;
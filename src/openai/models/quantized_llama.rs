@@ -1,6 +1,8 @@
 use super::layers::quantized_var_builder::VarBuilder as QVarBuilder;
+use super::quantized_qwen3_moe::FusedMoe;
 use super::{
     attention::QuantizedAttention, rotary_emb::ScalingRotaryEmbedding, Config, KvCacheDtype,
+    MoEConfig, QwenMoEConfig,
 };
 use crate::backend::progress::{ProgressLike, ProgressReporter};
 #[cfg(feature = "nccl")]
@@ -8,7 +10,7 @@ use crate::openai::distributed::AllReduce;
 use crate::openai::distributed::{Comm, Rc, VocabParallelLinear};
 use crate::openai::models::layers::qrmsnorm::QRmsNorm;
 use crate::openai::models::mask::get_attention_causal_mask;
-use crate::InputMetadata;
+use crate::{InputMetadata, InputMetadataExt};
 use candle_core::quantized::QMatMul;
 use candle_core::{DType, Device, Result, Tensor};
 use candle_nn::{Embedding, Module};
@@ -46,6 +48,7 @@ impl Mlp {
 
 enum MlpOrMoe {
     Mlp(Mlp),
+    FusedMoe(FusedMoe),
     MoE {
         n_expert_used: usize,
         feed_forward_gate_inp: QMatMul,
@@ -54,7 +57,7 @@ enum MlpOrMoe {
 }
 
 impl MlpOrMoe {
-    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+    fn forward(&self, xs: &Tensor, is_prefill: bool) -> Result<Tensor> {
         match self {
             Self::MoE {
                 feed_forward_gate_inp,
@@ -118,6 +121,7 @@ impl MlpOrMoe {
                 Ok(ys)
             }
             Self::Mlp(mlp) => mlp.forward(xs),
+            Self::FusedMoe(moe) => moe.forward(xs, is_prefill),
         }
     }
 }
@@ -309,6 +313,32 @@ impl GGUFLLaMa {
             partial_rotary_factor,
             kv_cache_dtype,
         );
+        // Mixtral-style GGUFs keep the llama arch and carry the experts as merged tensors.
+        let moe_cfg = if n_expert > 1 {
+            let moe_intermediate_size =
+                md_get(format!("{arch}.expert_feed_forward_length").as_str())
+                    .or_else(|_| md_get(format!("{arch}.feed_forward_length").as_str()))?
+                    .to_u32()? as usize;
+            Some(QwenMoEConfig {
+                moe_intermediate_size,
+                shared_expert_intermediate_size: None,
+                num_experts: Some(n_expert),
+                mlp_only_layers: Some(vec![]),
+                decoder_sparse_step: Some(1),
+                norm_topk_prob: true,
+                num_experts_per_tok: n_expert_used,
+                routed_scaling_factor: None,
+                first_k_dense_replace: None,
+                n_shared_experts: None,
+                n_group: None,
+                topk_group: None,
+                scoring_func: None,
+                topk_method: None,
+            })
+        } else {
+            None
+        };
+        cfg.moe_config = moe_cfg.clone().map(MoEConfig::QwenMoE);
         cfg.apply_runtime_rope_overrides(yarn_scaling_factor);
         let rotary_emb = Arc::new(ScalingRotaryEmbedding::new(
             DType::F32,
@@ -321,7 +351,20 @@ impl GGUFLLaMa {
         for layer_idx in 0..block_count {
             let prefix = format!("blk.{layer_idx}");
             let prefix_vb = vb.pp(&prefix);
-            let mlp_or_moe = if n_expert <= 1 {
+            let fused_moe_cfg = moe_cfg
+                .as_ref()
+                .filter(|_| prefix_vb.contains_key("ffn_gate_exps.weight"));
+            let mlp_or_moe = if let Some(moe_cfg) = fused_moe_cfg {
+                MlpOrMoe::FusedMoe(FusedMoe::load(
+                    &prefix_vb,
+                    moe_cfg,
+                    None,
+                    rank,
+                    world_size,
+                    comm.clone(),
+                    dtype,
+                )?)
+            } else if n_expert <= 1 {
                 let feed_forward_w1 =
                     prefix_vb.get_sharded_no_shape("ffn_gate.weight", 0, rank, world_size)?;
                 let feed_forward_w2 =
@@ -342,6 +385,7 @@ impl GGUFLLaMa {
                     dtype,
                 })
             } else {
+                // Older conversions store one tensor per expert; these run unsharded.
                 let feed_forward_gate_inp = prefix_vb.get_no_shape("ffn_gate_inp.weight")?;
                 let mut experts = Vec::with_capacity(n_expert);
                 for i in 0..n_expert {
@@ -466,7 +510,9 @@ impl GGUFLLaMa {
                 // MLP
                 let residual = &x;
                 let x = layer.ffn_norm.forward(&x)?;
-                let x = layer.mlp_or_moe.forward(&x)?;
+                let x = layer
+                    .mlp_or_moe
+                    .forward(&x, input_metadata.moe_is_prefill())?;
                 let x = (x + residual)?;
                 xs = x
             }
@@ -487,12 +533,14 @@ impl GGUFLLaMa {
                 // MLP
                 let residual = &x;
                 let x = layer.ffn_norm.forward(&x)?;
-                let x = layer.mlp_or_moe.forward(&x)?;
+                let x = layer
+                    .mlp_or_moe
+                    .forward(&x, input_metadata.moe_is_prefill())?;
                 let x = (x + residual)?;
                 xs = x
             }
         }
-        if !seqlens.is_empty() && !return_hidden {
+        if !seqlens.is_empty() && !return_hidden && !input_metadata.is_mtp_verify {
             let indices: Vec<_> = seqlens.iter().map(|x| x - 1 as u32).collect();
             let batch = indices.len();
             xs = xs.index_select(&Tensor::from_vec(indices, (batch,), xs.device())?, 0)?;
@@ -45,7 +45,9 @@ impl Mlp {
     }
 }
 
-struct FusedMoe {
+/// Fused GGUF experts (`ffn_{gate,up,down}_exps`) with the expert
+/// intermediate dimension sharded across ranks.
+pub(crate) struct FusedMoe {
     gate: QMatMul,
     gate_experts: Arc<QTensor>,
     up_experts: Arc<QTensor>,
@@ -66,8 +68,46 @@ struct FusedMoe {
 }
 
 impl FusedMoe {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn load(
+        prefix_vb: &QVarBuilder,
+        moe_cfg: &QwenMoEConfig,
+        e_score_correction_bias: Option<Tensor>,
+        rank: usize,
+        world_size: usize,
+        #[allow(unused_variables)] comm: Rc<Comm>,
+        dtype: DType,
+    ) -> Result<Self> {
+        let gate = prefix_vb.get_no_shape("ffn_gate_inp.weight")?;
+        let gate_experts =
+            prefix_vb.get_sharded_no_shape("ffn_gate_exps.weight", 1, rank, world_size)?;
+        let up_experts =
+            prefix_vb.get_sharded_no_shape("ffn_up_exps.weight", 1, rank, world_size)?;
+        let down_experts =
+            prefix_vb.get_sharded_no_shape("ffn_down_exps.weight", 2, rank, world_size)?;
+        Ok(Self {
+            gate: QMatMul::from_arc(gate)?,
+            gate_experts,
+            up_experts,
+            down_experts,
+            act: candle_nn::Activation::Silu,
+            norm_topk_prob: moe_cfg.norm_topk_prob,
+            routed_scaling_factor: moe_cfg.routed_scaling_factor,
+            num_experts_per_tok: moe_cfg.num_experts_per_tok,
+            e_score_correction_bias,
+            #[cfg(feature = "nccl")]
+            all_reduce: if world_size > 1 {
+                Some(AllReduce::new(comm))
+            } else {
+                None
+            },
+            dtype,
+            world_size,
+        })
+    }
+
     #[allow(unused_mut, unused_variables)]
-    fn forward(&self, xs: &Tensor, is_prefill: bool) -> Result<Tensor> {
+    pub(crate) fn forward(&self, xs: &Tensor, is_prefill: bool) -> Result<Tensor> {
         let (num_tokens, hidden_dim) = xs.dims2()?;
         let original_dtype = xs.dtype();
         let xs = if xs.dtype() != DType::F32 {
@@ -413,38 +453,21 @@ impl GGUFQWenMoE {
                 && (moe_cfg.num_experts.unwrap_or(0) > 0
                     && (layer_idx + 1) % moe_cfg.decoder_sparse_step.unwrap_or(1) == 0)
             {
-                let gate = prefix_vb.get_no_shape("ffn_gate_inp.weight")?;
-                let gate_experts =
-                    prefix_vb.get_sharded_no_shape("ffn_gate_exps.weight", 1, rank, world_size)?;
-                let up_experts =
-                    prefix_vb.get_sharded_no_shape("ffn_up_exps.weight", 1, rank, world_size)?;
-                let down_experts =
-                    prefix_vb.get_sharded_no_shape("ffn_down_exps.weight", 2, rank, world_size)?;
                 let bias = try_load_e_score_correction_bias(
                     vb,
                     &prefix,
                     moe_cfg.num_experts.unwrap_or(0),
                     device,
                 );
-                let moe = FusedMoe {
-                    gate: QMatMul::from_arc(gate)?,
-                    gate_experts,
-                    up_experts,
-                    down_experts,
-                    act: candle_nn::Activation::Silu,
-                    norm_topk_prob: moe_cfg.norm_topk_prob,
-                    routed_scaling_factor: moe_cfg.routed_scaling_factor,
-                    num_experts_per_tok: moe_cfg.num_experts_per_tok,
-                    e_score_correction_bias: bias,
-                    #[cfg(feature = "nccl")]
-                    all_reduce: if world_size > 1 {
-                        Some(AllReduce::new(comm.clone()))
-                    } else {
-                        None
-                    },
-                    dtype,
+                let moe = FusedMoe::load(
+                    &prefix_vb,
+                    &moe_cfg,
+                    bias,
+                    rank,
                     world_size,
-                };
+                    comm.clone(),
+                    dtype,
+                )?;
 
                 MoeOrMlp::FusedMoe(moe)
             } else {
@@ -675,7 +698,7 @@ impl GGUFQWenMoE {
             }
         }
 
-        if !seqlens.is_empty() && !return_hidden {
+        if !seqlens.is_empty() && !return_hidden && !input_metadata.is_mtp_verify {
             let indices: Vec<_> = seqlens.iter().map(|x| x - 1 as u32).collect();
             let batch = indices.len();
             xs = xs.index_select(&Tensor::from_vec(indices, (batch,), xs.device())?, 0)?;
@@ -62,6 +62,56 @@ pub fn verify_draft_greedy(
     })
 }
 
+/// Speculative-sampling verification of greedy drafts. `target_probs` holds
+/// the target's sampling distribution at each verify position, one row more
+/// than there are drafts. A greedy draft is a point mass q = δ(d), so `d` is
+/// accepted with probability min(1, p(d)/q(d)) = p(d); on rejection the
+/// continuation is drawn from the residual norm(max(p - q, 0)), which is p
+/// without `d`. When every draft is accepted the continuation is drawn from
+/// the last row. The output follows the same distribution as sampling the
+/// target one token at a time.
+pub fn verify_draft_sampled(
+    target_probs: &[Vec<f32>],
+    draft_tokens: &[u32],
+    mut uniform: impl FnMut() -> f32,
+    mut sample: impl FnMut(&[f32]) -> Result<u32>,
+) -> Result<MtpVerifyResult> {
+    let num_proposed = draft_tokens.len();
+    if target_probs.is_empty() {
+        candle_core::bail!("speculative verify needs at least one target row");
+    }
+    let compare_len = num_proposed.min(target_probs.len() - 1);
+    let mut num_accepted = 0usize;
+    while num_accepted < compare_len {
+        let probs = &target_probs[num_accepted];
+        let draft = draft_tokens[num_accepted] as usize;
+        let p_draft = probs.get(draft).copied().unwrap_or(0.0);
+        if uniform() >= p_draft {
+            break;
+        }
+        num_accepted += 1;
+    }
+    let continuation_token = if num_accepted < compare_len {
+        let mut residual = target_probs[num_accepted].clone();
+        if let Some(p) = residual.get_mut(draft_tokens[num_accepted] as usize) {
+            *p = 0.0;
+        }
+        if residual.iter().any(|&p| p > 0.0) {
+            sample(&residual)?
+        } else {
+            sample(&target_probs[num_accepted])?
+        }
+    } else {
+        sample(&target_probs[num_accepted])?
+    };
+    Ok(MtpVerifyResult {
+        accepted_tokens: draft_tokens[..num_accepted].to_vec(),
+        continuation_token,
+        num_accepted,
+        num_proposed,
+    })
+}
+
 pub fn mtp_stats_update(proposed: usize, accepted: usize) {
     MTP_TOTAL_PROPOSED.fetch_add(proposed, Ordering::Relaxed);
     MTP_TOTAL_ACCEPTED.fetch_add(accepted, Ordering::Relaxed);
@@ -1005,3 +1055,36 @@ impl Qwen3_5MtpHead {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::verify_draft_sampled;
+
+    fn first_nonzero(weights: &[f32]) -> candle_core::Result<u32> {
+        Ok(weights.iter().position(|&p| p > 0.0).unwrap() as u32)
+    }
+
+    #[test]
+    fn sampled_verify_accepts_with_target_probability_and_resamples_residual() {
+        let probs = vec![
+            vec![0.5, 0.5, 0.0, 0.0],
+            vec![0.0, 0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 0.0, 1.0],
+        ];
+        let mut draws = [0.4f32, 0.9].into_iter();
+        let result =
+            verify_draft_sampled(&probs, &[1, 2], || draws.next().unwrap(), first_nonzero).unwrap();
+        assert_eq!(result.accepted_tokens, vec![1, 2]);
+        assert_eq!(result.continuation_token, 3);
+
+        // Rejected at the first draft: the residual excludes the draft token.
+        let result = verify_draft_sampled(&probs, &[1, 2], || 0.6, first_nonzero).unwrap();
+        assert_eq!(result.num_accepted, 0);
+        assert_eq!(result.continuation_token, 0);
+
+        // A draft the target never samples is always rejected.
+        let result = verify_draft_sampled(&probs, &[2, 2], || 0.0, first_nonzero).unwrap();
+        assert_eq!(result.num_accepted, 0);
+        assert_eq!(result.num_proposed, 2);
+    }
+}
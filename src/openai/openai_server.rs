@@ -1,21 +1,37 @@
+use super::anthropic_api::{self, MessageStreamState, MessagesRequest, MessagesResponse};
+use super::auth::{require_admin, AuthenticatedKey};
 use super::logger::ChatCompletionLogger;
+use super::metrics::KeyUsage;
+use super::pipelines::llm_engine::LLMEngine;
 use super::requests::Messages;
 use super::requests::{
     normalize_empty_openai_tool_results, validate_openai_tool_messages, ChatCompletionRequest,
-    EmbeddingRequest, EmbeddingType, EncodingFormat,
+    CompletionPromptItem, CompletionRequest, DetokenizeRequest, EmbeddingRequest, EmbeddingType,
+    EncodingFormat, TokenizeRequest,
+};
+use super::responses::{
+    APIError, ChatChoice, ChatCompletionResponse, ChatCompletionUsageResponse, ChatResponder,
+    CompletionChoice, CompletionChunk, CompletionLogprobs, CompletionResponse, DetokenizeResponse,
+    EmbeddingResponse, EngineStatus, ErrorKind, PromptTokensDetails, TokenizeResponse,
+};
+use super::responses_api::{
+    self, ResponseObject, ResponseStreamState, ResponsesRequest, StoredResponse,
 };
-use super::responses::{APIError, ChatCompletionResponse, ChatResponder};
 use super::sampling_params::{EarlyStoppingCondition, SamplingParams};
 use super::streaming::{ChatResponse, Streamer, StreamingStatus};
+use super::utils::{check_prompt_token_ids, get_created_time_secs};
 use super::OpenAIServerData;
 use crate::openai::multimodal::{build_messages_and_images, ImageData};
 use crate::openai::{resolve_tools_for_request, ResolvedToolConfig};
 use crate::tools::stream_parser::detect_prefilled_reasoning_end_marker;
-use axum::response::sse::KeepAlive;
+use axum::response::sse::{KeepAlive, KeepAliveStream};
 use axum::{
-    extract::{Json, State},
+    extract::{Json, Path, State},
+    http::{header, HeaderMap, StatusCode},
     response::Sse,
+    Extension,
 };
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 use std::sync::Arc;
 use std::time::SystemTime;
@@ -25,23 +41,8 @@ use tracing::debug;
 use uuid::Uuid;
 
 const REQUEST_ADMISSION_DECODE_BUDGET_TOKENS: usize = 4096;
-
-fn current_model_name(data: &OpenAIServerData) -> Result<String, APIError> {
-    let model = data.model.read();
-    let model_name = model.model_name();
-    if model_name.is_empty() {
-        Err(APIError::new("Missing pipeline".to_string()))
-    } else {
-        Ok(model_name.to_string())
-    }
-}
-
-fn resolve_response_model_name(requested: Option<&str>, current: &str) -> String {
-    match requested.map(str::trim) {
-        None | Some("") | Some("default") => current.to_string(),
-        Some(name) => name.to_string(),
-    }
-}
+/// `Retry-After` seconds suggested to clients refused by admission control.
+const ADMISSION_RETRY_AFTER_SECS: u64 = 1;
 
 // Get prompt, roles
 async fn get_gen_prompt(
@@ -117,7 +118,9 @@ async fn get_gen_prompt(
             return Err(APIError::new(format!(
                 "Unsupported reasoning_effort '{}'; expected none, low, medium, high, or xhigh",
                 other
-            )))
+            ))
+            .with_kind(ErrorKind::InvalidRequest)
+            .with_param("reasoning_effort"))
         }
     };
     let prompt = conversation.get_prompt(enable_thinking, reasoning_effort, &tool_config.tools);
@@ -143,93 +146,62 @@ async fn check_length(
     let max_gen_tokens = request
         .max_tokens
         .unwrap_or(data.pipeline_config.default_max_tokens);
+    validate_prompt_length(data, token_ids.len(), max_gen_tokens)?;
+    Ok(token_ids)
+}
 
-    if token_ids.len() >= data.pipeline_config.max_model_len {
+fn validate_prompt_length(
+    data: &OpenAIServerData,
+    prompt_len: usize,
+    max_gen_tokens: usize,
+) -> Result<(), APIError> {
+    if prompt_len >= data.pipeline_config.max_model_len {
         Err(APIError::new(format!(
             "This model's maximum context length is {} tokens. \
             However, you requested {} tokens ({} in the messages, \
             {} in the completion). \nPlease clear the chat history or reduce the length of the \
             messages.",
             data.pipeline_config.max_model_len,
-            max_gen_tokens + token_ids.len(),
-            token_ids.len(),
+            max_gen_tokens + prompt_len,
+            prompt_len,
             max_gen_tokens
-        )))
+        ))
+        .with_kind(ErrorKind::ContextLengthExceeded)
+        .with_param("messages"))
     } else {
-        Ok(token_ids)
+        Ok(())
     }
 }
 
-#[utoipa::path(
-    post,
-    tag = "candle-vllm",
-    path = "/v1/chat/completions",
-    request_body = ChatCompletionRequest,
-    responses((status = 200, description = "Chat completions"))
-)]
-pub async fn chat_completions(
-    State(data): State<Arc<OpenAIServerData>>,
-    request: Json<ChatCompletionRequest>,
-) -> ChatResponder {
-    let mut request = request.0;
-    let logger = ChatCompletionLogger::new();
-    if let Some(ref l) = logger {
-        l.log_request(&request);
-    }
-    if let Messages::Chat(messages) = &mut request.messages {
-        normalize_empty_openai_tool_results(messages);
-        if let Err(err) = validate_openai_tool_messages(messages) {
-            return ChatResponder::ValidationError(APIError::new(err));
-        }
-    }
-
-    #[cfg(feature = "nccl")]
-    use crate::openai::communicator::DaemonManager;
-    #[cfg(feature = "nccl")]
-    if !DaemonManager::is_master_rank() {
-        return ChatResponder::ModelError(APIError::from(
-            "Daemon process unable to generate response, please request server port of the main process!",
-        ));
-    }
-
-    if request.logit_bias.as_ref().is_some()
-        && request.logit_bias.as_ref().is_some_and(|x| !x.is_empty())
-    {
-        return ChatResponder::ValidationError(APIError::new_str(
-            "`logit_bias` is not currently supported.",
-        ));
-    }
-
-    let tool_config = match resolve_tools_for_request(
-        &request.tools,
-        &request.tool_choice,
-        data.mcp_manager.as_ref(),
-    ) {
-        Ok(config) => config,
-        Err(e) => return ChatResponder::ValidationError(e),
-    };
-
-    let (prompt, image_data) = match get_gen_prompt(&data, &request, &tool_config).await {
-        Ok(p) => p,
-        Err(e) => return ChatResponder::ValidationError(e),
-    };
-
-    let token_ids = match check_length(&request, prompt.clone(), &data).await {
-        Ok(ids) => ids,
-        Err(e) => return ChatResponder::ValidationError(e),
-    };
-
-    debug!("\n\n\nPrompt {:?}", prompt);
-    if let Some(ref l) = logger {
-        l.log_prompt(&prompt);
-    }
-
-    let request_id = format!("cmpl-{}", Uuid::new_v4());
+/// 429 for a request refused because the waiting queue is at its limits.
+fn queue_full_responder(message: String) -> ChatResponder {
+    ChatResponder::TooManyRequests(
+        APIError::new(message).with_code("queue_full"),
+        ADMISSION_RETRY_AFTER_SECS,
+    )
+}
 
-    let mut max_request_tokens = request
-        .max_tokens
-        .unwrap_or(data.pipeline_config.default_max_tokens);
+/// Run blocking engine work, typically an admission check and enqueue under
+/// one engine write lock, off the async runtime.
+async fn with_engine_blocking<F>(f: F) -> Result<(), ChatResponder>
+where
+    F: FnOnce() -> Result<(), ChatResponder> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f).await.map_err(|e| {
+        ChatResponder::InternalError(APIError::new(format!("Failed to submit request: {e}")))
+    })?
+}
 
+/// Clamp the requested decode length to the remaining model context and make
+/// sure the KV cache can hold the uncached prompt tokens plus a minimum decode
+/// budget, evicting prefix-cache blocks when needed. Returns the effective
+/// `max_tokens` for the request.
+fn admit_request_tokens(
+    data: &OpenAIServerData,
+    token_ids: &[u32],
+    requested_max_tokens: usize,
+) -> Result<usize, APIError> {
+    let mut max_request_tokens = requested_max_tokens;
     let max_model_decode_tokens = data
         .pipeline_config
         .max_model_len
@@ -245,17 +217,18 @@ pub async fn chat_completions(
         max_request_tokens = max_model_decode_tokens;
     }
     if max_request_tokens == 0 {
-        return ChatResponder::ValidationError(APIError::new(format!(
+        return Err(APIError::new(format!(
             "Requested prompt({} tokens) leaves no room for generated tokens within maximum model context {}.",
             token_ids.len(),
             data.pipeline_config.max_model_len
-        )));
+        ))
+        .with_kind(ErrorKind::ContextLengthExceeded));
     }
 
     // Query prefix cache to determine how many prompt tokens are already cached
     let mut cached_tokens = {
         let mut model = data.model.write();
-        model.query_prefix_cache_match_tokens(&token_ids)
+        model.query_prefix_cache_match_tokens(token_ids)
     };
     let mut new_tokens = token_ids.len().saturating_sub(cached_tokens);
     let minimum_decode_budget_tokens =
@@ -280,7 +253,7 @@ pub async fn chat_completions(
     loop {
         let refreshed_cached_tokens = {
             let mut model = data.model.write();
-            model.query_prefix_cache_match_tokens(&token_ids)
+            model.query_prefix_cache_match_tokens(token_ids)
         };
         if refreshed_cached_tokens == cached_tokens {
             break;
@@ -311,16 +284,17 @@ pub async fn chat_completions(
 
     if minimum_required_tokens > available_tokens {
         if available_tokens <= new_tokens {
-            return ChatResponder::ValidationError(APIError::new(format!(
+            return Err(APIError::new(format!(
                 "Requested prompt({} tokens, {} new after prefix cache) is  \
                 larger than available kvcache (maximum {} tokens).\n \
                 You can increase kvcache by setting `--kv-fraction` (default 0.6) to a larger value!",
                 token_ids.len(),
                 new_tokens,
                 available_tokens
-            )));
+            ))
+            .with_kind(ErrorKind::Unavailable));
         }
-        return ChatResponder::ValidationError(APIError::new(format!(
+        return Err(APIError::new(format!(
             "Requested prompt({} tokens, {} new after prefix cache) plus {} decode budget tokens is \
             larger than available kvcache (maximum {} tokens).\n \
             You can increase kvcache by setting `--kv-fraction` (default 0.6) to a larger value!",
@@ -328,7 +302,8 @@ pub async fn chat_completions(
             new_tokens,
             minimum_decode_budget_tokens,
             available_tokens
-        )));
+        ))
+        .with_kind(ErrorKind::Unavailable));
     }
 
     if target_required_tokens > available_tokens {
@@ -342,8 +317,203 @@ pub async fn chat_completions(
         );
     }
 
-    let generation_cfg = data.pipeline_config.generation_cfg.as_ref().unwrap();
-    let mut sampling_params = match SamplingParams::new(
+    Ok(max_request_tokens)
+}
+
+/// Check that the prompts of a multi-prompt request fit the KV cache together.
+/// `admit_request_tokens` checks each prompt against the same free cache, so
+/// this runs again on their sum under the lock that enqueues them.
+fn admit_combined_tokens(
+    model: &mut LLMEngine,
+    prompts: &[(&[u32], usize)],
+) -> Result<(), APIError> {
+    let (mut new_tokens, mut minimum_required_tokens, mut target_required_tokens) = (0, 0, 0);
+    for &(token_ids, max_tokens) in prompts {
+        let new = token_ids
+            .len()
+            .saturating_sub(model.query_prefix_cache_match_tokens(token_ids));
+        new_tokens += new;
+        minimum_required_tokens += new + max_tokens.min(REQUEST_ADMISSION_DECODE_BUDGET_TOKENS);
+        target_required_tokens += new + max_tokens;
+    }
+    let (available_tokens, evicted) = model.ensure_available_kv_tokens(target_required_tokens);
+    if evicted > 0 {
+        tracing::warn!(
+            "Evicted {} prefix cache block(s) to reserve {} KV tokens for {} prompts.",
+            evicted,
+            target_required_tokens,
+            prompts.len()
+        );
+    }
+    if minimum_required_tokens > available_tokens {
+        return Err(APIError::new(format!(
+            "Requested {} prompts ({} new tokens after prefix cache) plus their decode budgets \
+            need {} tokens together, more than the available kvcache (maximum {} tokens).\n \
+            Send fewer prompts per request or increase kvcache with `--kv-fraction`.",
+            prompts.len(),
+            new_tokens,
+            minimum_required_tokens,
+            available_tokens
+        ))
+        .with_kind(ErrorKind::Unavailable));
+    }
+    Ok(())
+}
+
+/// Reject `n`/`best_of` combinations the engine cannot serve.
+fn validate_parallel_sampling(
+    data: &OpenAIServerData,
+    sampling_params: &SamplingParams,
+    stream: bool,
+) -> Result<(), APIError> {
+    if sampling_params.best_of > 1 && !data.model.read().supports_parallel_sampling() {
+        return Err(APIError::new_str(
+            "`n` and `best_of` greater than 1 are not supported for hybrid models.",
+        ));
+    }
+    // Candidates are only ranked once they all finish, so nothing can be
+    // streamed while some of them will be discarded.
+    if stream && sampling_params.best_of > sampling_params.n {
+        return Err(APIError::new_str(
+            "`best_of` greater than `n` cannot be used with streaming.",
+        ));
+    }
+    if sampling_params.use_beam_search {
+        // Beams are reordered and replaced until the search ends.
+        if stream {
+            return Err(APIError::new_str(
+                "`use_beam_search` cannot be used with streaming.",
+            ));
+        }
+        if sampling_params.mcp_mode.is_some() {
+            return Err(APIError::new_str(
+                "`use_beam_search` cannot be used with tools.",
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// A chat request accepted by the engine.
+struct ChatSubmission {
+    request_id: String,
+    model_name: String,
+    /// Streamed chunks of a streaming request.
+    stream_rx: Option<tokio::sync::mpsc::Receiver<ChatResponse>>,
+    /// Fires once a non-streaming request has its completion record.
+    sync_notify: Arc<Notify>,
+    logger: Option<Arc<ChatCompletionLogger>>,
+}
+
+fn sse_streamer(
+    rx: tokio::sync::mpsc::Receiver<ChatResponse>,
+    logger: Option<Arc<ChatCompletionLogger>>,
+) -> Sse<KeepAliveStream<Streamer>> {
+    Sse::new(Streamer {
+        rx,
+        status: StreamingStatus::Uninitialized,
+        logger,
+    })
+    .keep_alive(
+        KeepAlive::new()
+            .interval(Duration::from_millis(
+                env::var("KEEP_ALIVE_INTERVAL")
+                    .map(|val| val.parse::<u64>().unwrap_or(100))
+                    .unwrap_or(100),
+            ))
+            .text("keep-alive-text"),
+    )
+}
+
+/// Name of the API key that authenticated the request, when keys are configured.
+fn api_key_name(key: Option<Extension<AuthenticatedKey>>) -> Option<String> {
+    key.map(|Extension(AuthenticatedKey(name))| name)
+}
+
+/// The client's `request_id` if it is free, otherwise a generated one.
+fn new_request_id(data: &OpenAIServerData, requested: Option<&str>) -> Result<String, APIError> {
+    let Some(request_id) = requested else {
+        return Ok(format!("cmpl-{}", Uuid::new_v4()));
+    };
+    if request_id.trim().is_empty() {
+        return Err(APIError::new_str("`request_id` must not be empty."));
+    }
+    if data.model.read().request_id_in_use(request_id) {
+        return Err(APIError::new(format!(
+            "`request_id` '{request_id}' is already in use."
+        )));
+    }
+    Ok(request_id.to_string())
+}
+
+/// Validate a chat request, render its prompt and hand it to the engine.
+async fn submit_chat_request(
+    data: &Arc<OpenAIServerData>,
+    mut request: ChatCompletionRequest,
+    api_key: Option<String>,
+) -> Result<ChatSubmission, ChatResponder> {
+    let logger = ChatCompletionLogger::new();
+    if let Some(ref l) = logger {
+        l.log_request(&request);
+    }
+    if let Messages::Chat(messages) = &mut request.messages {
+        normalize_empty_openai_tool_results(messages);
+        if let Err(err) = validate_openai_tool_messages(messages) {
+            return Err(ChatResponder::ValidationError(
+                APIError::new(err).with_param("messages"),
+            ));
+        }
+    }
+
+    #[cfg(feature = "nccl")]
+    use crate::openai::communicator::DaemonManager;
+    #[cfg(feature = "nccl")]
+    if !DaemonManager::is_master_rank() {
+        return Err(ChatResponder::ModelError(APIError::from(
+            "Daemon process unable to generate response, please request server port of the main process!",
+        )));
+    }
+
+    let tool_config = resolve_tools_for_request(
+        &request.tools,
+        &request.tool_choice,
+        data.mcp_manager.as_ref(),
+    )
+    .map_err(ChatResponder::ValidationError)?;
+
+    let (prompt, image_data) = get_gen_prompt(data, &request, &tool_config)
+        .await
+        .map_err(ChatResponder::ValidationError)?;
+
+    let token_ids = check_length(&request, prompt.clone(), data)
+        .await
+        .map_err(ChatResponder::ValidationError)?;
+
+    debug!("\n\n\nPrompt {:?}", prompt);
+    if let Some(ref l) = logger {
+        l.log_prompt(&prompt);
+    }
+
+    let request_id = new_request_id(data, request.request_id.as_deref())
+        .map_err(ChatResponder::ValidationError)?;
+
+    let max_request_tokens = admit_request_tokens(
+        data,
+        &token_ids,
+        request
+            .max_tokens
+            .unwrap_or(data.pipeline_config.default_max_tokens),
+    )
+    .map_err(ChatResponder::ValidationError)?;
+
+    let use_beam_search = request.use_beam_search.unwrap_or(false);
+    let generation_cfg = data
+        .pipeline_config
+        .generation_cfg
+        .as_ref()
+        .unwrap()
+        .for_request(use_beam_search);
+    let mut sampling_params = SamplingParams::new(
         request.n.unwrap_or(1),
         request.best_of,
         request
@@ -357,9 +527,12 @@ pub async fn chat_completions(
         request.top_p.or(generation_cfg.top_p),
         request.min_p.or(generation_cfg.min_p),
         request.top_k.or(generation_cfg.top_k),
-        request.use_beam_search.unwrap_or(false),
-        1.0,
-        EarlyStoppingCondition::UnlikelyBetterCandidates,
+        use_beam_search,
+        request.length_penalty.unwrap_or(1.0),
+        request
+            .early_stopping
+            .clone()
+            .unwrap_or(EarlyStoppingCondition::UnlikelyBetterCandidates),
         request.stop.clone(),
         request.stop_token_ids.clone().unwrap_or_default(),
         request.ignore_eos.unwrap_or(false),
@@ -368,12 +541,34 @@ pub async fn chat_completions(
         None,
         request.skip_special_tokens.unwrap_or(true),
         request.thinking,
-    ) {
-        Ok(params) => params,
-        Err(e) => return ChatResponder::ValidationError(e),
-    };
+    )
+    .map_err(ChatResponder::ValidationError)?;
     let has_tools = !tool_config.tools.is_empty();
     sampling_params.mcp_mode = if has_tools { Some(true) } else { None };
+    sampling_params.seed = request.seed;
+    sampling_params.priority = request.priority.unwrap_or_default();
+    sampling_params.user = request.user.clone();
+    sampling_params.api_key = api_key;
+    sampling_params
+        .set_timeout(request.timeout)
+        .map_err(ChatResponder::ValidationError)?;
+    validate_parallel_sampling(data, &sampling_params, request.stream.is_some_and(|x| x))
+        .map_err(ChatResponder::ValidationError)?;
+    let vocab_size = data.model.read().tokenizer().get_vocab_size(true);
+    sampling_params
+        .set_logit_bias(request.logit_bias.as_ref(), vocab_size)
+        .map_err(ChatResponder::ValidationError)?;
+    sampling_params
+        .set_response_format(request.response_format.as_ref())
+        .map_err(ChatResponder::ValidationError)?;
+    let tool_call_envelope = data.model.read().tool_call_envelope().cloned();
+    sampling_params
+        .set_tool_choice(
+            &tool_config.tools,
+            &tool_config.choice,
+            tool_call_envelope.as_ref(),
+        )
+        .map_err(ChatResponder::ValidationError)?;
 
     let prefilled_reasoning_end = detect_prefilled_reasoning_end_marker(&prompt);
 
@@ -384,17 +579,12 @@ pub async fn chat_completions(
     let (response_tx, rx) = tokio::sync::mpsc::channel(sse_buffer_size);
     tracing::info!("{:?}", sampling_params);
 
-    let data_clone = data.clone();
-    let request_id_clone = request_id.clone();
     let stream_request = request.stream.is_some_and(|x| x);
     let include_usage = request
         .stream_options
         .as_ref()
         .is_some_and(|options| options.include_usage);
-    let model_name = match current_model_name(&data) {
-        Ok(current) => resolve_response_model_name(request.model.as_deref(), &current),
-        Err(e) => return ChatResponder::ModelError(e),
-    };
+    let model_name = data.served_name.clone();
     let sync_notify = Arc::new(Notify::new());
     let sync_completion_notify = if stream_request {
         None
@@ -403,85 +593,646 @@ pub async fn chat_completions(
     };
     let request_tools_for_engine = tool_config.tools.clone();
 
-    let _ = tokio::task::spawn_blocking(move || {
-        tokio::runtime::Handle::current().block_on(async move {
-            {
-                let mut model = data.model.write();
-                model.add_request(
-                    token_ids,
-                    request_id.clone(),
-                    SystemTime::now(),
-                    sampling_params,
-                    request.logprobs.unwrap_or(false),
-                    false,
-                    EncodingFormat::default(),
-                    EmbeddingType::default(),
-                    request_tools_for_engine.clone(),
-                    tool_config.choice.clone(),
-                    image_data,
-                    if stream_request {
-                        Some(Arc::new(response_tx))
-                    } else {
-                        None
-                    },
-                    sync_completion_notify,
-                    include_usage,
-                    prefilled_reasoning_end,
-                );
-                model.notify.notify_one();
+    let data_clone = data.clone();
+    let request_id_clone = request_id.clone();
+    with_engine_blocking(move || {
+        let mut model = data_clone.model.write();
+        // Check the queue under the same lock as the enqueue so concurrent
+        // requests cannot all pass the limits.
+        model
+            .check_admission(token_ids.len())
+            .map_err(queue_full_responder)?;
+        model.add_request(
+            token_ids,
+            request_id_clone,
+            SystemTime::now(),
+            sampling_params,
+            request.logprobs.unwrap_or(false),
+            false,
+            EncodingFormat::default(),
+            EmbeddingType::default(),
+            request_tools_for_engine.clone(),
+            tool_config.choice.clone(),
+            image_data,
+            if stream_request {
+                Some(Arc::new(response_tx))
+            } else {
+                None
+            },
+            sync_completion_notify,
+            include_usage,
+            prefilled_reasoning_end,
+        );
+        model.notify.notify_one();
+        Ok(())
+    })
+    .await?;
+
+    Ok(ChatSubmission {
+        request_id,
+        model_name,
+        stream_rx: stream_request.then_some(rx),
+        sync_notify,
+        logger,
+    })
+}
+
+/// Response for a request the engine failed without a result: a queue
+/// timeout may be retried, anything else is a generation error.
+fn rejected_request_responder(error: APIError) -> ChatResponder {
+    match error.kind() {
+        Some(ErrorKind::RateLimited) => {
+            ChatResponder::TooManyRequests(error, ADMISSION_RETRY_AFTER_SECS)
+        }
+        _ => ChatResponder::ModelError(error),
+    }
+}
+
+/// Wait for a non-streaming chat request and build its response.
+async fn wait_chat_completion(
+    data: &OpenAIServerData,
+    submission: &ChatSubmission,
+) -> Result<ChatCompletionResponse, ChatResponder> {
+    let request_id = &submission.request_id;
+    tracing::warn!("waiting response for sync request {}", request_id);
+    submission.sync_notify.as_ref().notified().await;
+    if let Some(error) = data.model.write().rejected_requests.remove(request_id) {
+        return Err(rejected_request_responder(error));
+    }
+    let model = data.model.read();
+    let Some((choices, usage)) = model.completion_records.get(request_id) else {
+        return Err(ChatResponder::ModelError(APIError::from(format!(
+            "Unable to generate response for request {request_id}"
+        ))));
+    };
+    Ok(ChatCompletionResponse {
+        id: request_id.clone(),
+        choices: choices.clone(),
+        created: usage.created,
+        model: submission.model_name.clone(),
+        object: "chat.completion",
+        system_fingerprint: Some(model.system_fingerprint().to_string()),
+        usage: usage.clone(),
+    })
+}
+
+#[utoipa::path(
+    post,
+    tag = "candle-vllm",
+    path = "/v1/chat/completions",
+    request_body = ChatCompletionRequest,
+    responses((status = 200, description = "Chat completions"))
+)]
+pub async fn chat_completions(
+    State(data): State<Arc<OpenAIServerData>>,
+    key: Option<Extension<AuthenticatedKey>>,
+    request: Json<ChatCompletionRequest>,
+) -> ChatResponder {
+    let data = match data.route(request.model.as_deref()) {
+        Ok(data) => data,
+        Err(e) => return ChatResponder::NotFound(e),
+    };
+    let mut submission = match submit_chat_request(&data, request.0, api_key_name(key)).await {
+        Ok(submission) => submission,
+        Err(e) => return e,
+    };
+    if let Some(rx) = submission.stream_rx.take() {
+        if let Some(ref l) = submission.logger {
+            l.log_start_response();
+        }
+        return ChatResponder::Streamer(sse_streamer(rx, submission.logger));
+    }
+    match wait_chat_completion(&data, &submission).await {
+        Ok(response) => {
+            if let Some(ref l) = submission.logger {
+                l.log_response(&response);
             }
-        });
+            ChatResponder::Completion(response)
+        }
+        Err(e) => e,
+    }
+}
+
+/// Fill-in-the-middle marker triples (prefix, suffix, middle) understood by
+/// common code models, probed against the tokenizer vocabulary in order.
+const FIM_TOKEN_SETS: [(&str, &str, &str); 3] = [
+    ("<|fim_prefix|>", "<|fim_suffix|>", "<|fim_middle|>"),
+    ("<fim_prefix>", "<fim_suffix>", "<fim_middle>"),
+    ("<｜fim▁begin｜>", "<｜fim▁hole｜>", "<｜fim▁end｜>"),
+];
+
+/// Tokenize one completion prompt without any chat template. Returns the text
+/// echoed back to the client and the prompt token ids.
+fn build_completion_prompt(
+    tokenizer: &tokenizers::Tokenizer,
+    item: CompletionPromptItem,
+    suffix: Option<&str>,
+) -> Result<(String, Vec<u32>), APIError> {
+    match item {
+        CompletionPromptItem::Text(text) => {
+            let prompt = match suffix {
+                Some(suffix) => {
+                    let (prefix_token, suffix_token, middle_token) = FIM_TOKEN_SETS
+                        .iter()
+                        .find(|(prefix, hole, middle)| {
+                            tokenizer.token_to_id(prefix).is_some()
+                                && tokenizer.token_to_id(hole).is_some()
+                                && tokenizer.token_to_id(middle).is_some()
+                        })
+                        .ok_or(APIError::new_str(
+                            "`suffix` is not supported: the model tokenizer has no fill-in-the-middle tokens.",
+                        ))?;
+                    format!("{prefix_token}{text}{suffix_token}{suffix}{middle_token}")
+                }
+                None => text.clone(),
+            };
+            let token_ids = tokenizer
+                .encode_fast(prompt, true)
+                .map_err(APIError::from)?
+                .get_ids()
+                .to_vec();
+            Ok((text, token_ids))
+        }
+        CompletionPromptItem::Tokens(token_ids) => {
+            if suffix.is_some() {
+                return Err(APIError::new_str(
+                    "`suffix` is only supported with text prompts.",
+                ));
+            }
+            check_prompt_token_ids(tokenizer, &token_ids).map_err(APIError::from)?;
+            let text = tokenizer
+                .decode(&token_ids, false)
+                .map_err(APIError::from)?;
+            Ok((text, token_ids))
+        }
+    }
+}
+
+fn completion_choice_from_chat(
+    choice: ChatChoice,
+    index: usize,
+    echo_text: Option<&str>,
+    top_logprobs: Option<usize>,
+) -> CompletionChoice {
+    let mut text = echo_text.unwrap_or_default().to_string();
+    text.push_str(&choice.message.reasoning_content.unwrap_or_default());
+    text.push_str(&choice.message.content.unwrap_or_default());
+    let logprobs = top_logprobs.map(|top_n| {
+        let offset = echo_text.map(|t| t.chars().count()).unwrap_or(0);
+        let content = choice.logprobs.map(|l| l.content).unwrap_or_default();
+        CompletionLogprobs::from_logprobs(&content, top_n, offset)
     });
+    CompletionChoice {
+        text,
+        index,
+        logprobs,
+        finish_reason: choice.finish_reason,
+    }
+}
+
+fn completion_chunk(
+    id: &str,
+    model: &str,
+    created: u64,
+    system_fingerprint: &str,
+    choice: Option<CompletionChoice>,
+    usage: Option<ChatCompletionUsageResponse>,
+) -> CompletionChunk {
+    CompletionChunk {
+        id: id.to_string(),
+        choices: choice.into_iter().collect(),
+        created,
+        model: model.to_string(),
+        object: "text_completion",
+        system_fingerprint: Some(system_fingerprint.to_string()),
+        usage,
+    }
+}
+
+fn merge_completion_usage(
+    total: Option<ChatCompletionUsageResponse>,
+    usage: ChatCompletionUsageResponse,
+) -> ChatCompletionUsageResponse {
+    let Some(mut total) = total else {
+        return usage;
+    };
+    total.prompt_tokens += usage.prompt_tokens;
+    total.completion_tokens += usage.completion_tokens;
+    total.total_tokens += usage.total_tokens;
+    total.prompt_time_costs = total.prompt_time_costs.max(usage.prompt_time_costs);
+    total.completion_time_costs = total.completion_time_costs.max(usage.completion_time_costs);
+    total.prompt_tokens_details = match (total.prompt_tokens_details, usage.prompt_tokens_details) {
+        (Some(a), Some(b)) => Some(PromptTokensDetails {
+            cached_tokens: a.cached_tokens + b.cached_tokens,
+        }),
+        (a, b) => a.or(b),
+    };
+    total
+}
+
+#[utoipa::path(
+    post,
+    tag = "candle-vllm",
+    path = "/v1/completions",
+    request_body = CompletionRequest,
+    responses((status = 200, description = "Text completions"))
+)]
+pub async fn completions(
+    State(data): State<Arc<OpenAIServerData>>,
+    key: Option<Extension<AuthenticatedKey>>,
+    request: Json<CompletionRequest>,
+) -> ChatResponder {
+    let request = request.0;
+    let data = match data.route(request.model.as_deref()) {
+        Ok(data) => data,
+        Err(e) => return ChatResponder::NotFound(e),
+    };
+    let api_key = api_key_name(key);
+
+    #[cfg(feature = "nccl")]
+    use crate::openai::communicator::DaemonManager;
+    #[cfg(feature = "nccl")]
+    if !DaemonManager::is_master_rank() {
+        return ChatResponder::ModelError(APIError::from(
+            "Daemon process unable to generate response, please request server port of the main process!",
+        ));
+    }
+
+    let n = request.n.unwrap_or(1);
+    if n == 0 {
+        return ChatResponder::ValidationError(APIError::new_str("`n` must be at least 1."));
+    }
+
+    let items = request.prompt.clone().into_items();
+    if items.is_empty() {
+        return ChatResponder::ValidationError(APIError::new_str("`prompt` must not be empty."));
+    }
+
+    let requested_max_tokens = request
+        .max_tokens
+        .unwrap_or(data.pipeline_config.default_max_tokens);
+    let mut prompts = Vec::with_capacity(items.len());
+    for item in items {
+        let built = {
+            let model = data.model.read();
+            build_completion_prompt(model.tokenizer(), item, request.suffix.as_deref())
+        };
+        let (text, token_ids) = match built {
+            Ok(prompt) => prompt,
+            Err(e) => return ChatResponder::ValidationError(e),
+        };
+        if token_ids.is_empty() {
+            return ChatResponder::ValidationError(APIError::new_str(
+                "Prompt must contain at least one token.",
+            ));
+        }
+        if let Err(e) = validate_prompt_length(&data, token_ids.len(), requested_max_tokens) {
+            return ChatResponder::ValidationError(e);
+        }
+        let max_tokens = match admit_request_tokens(&data, &token_ids, requested_max_tokens) {
+            Ok(max_tokens) => max_tokens,
+            Err(e) => return ChatResponder::ValidationError(e),
+        };
+        debug!("\n\n\nCompletion prompt {:?}", text);
+        prompts.push((text, token_ids, max_tokens));
+    }
+    let prompt_tokens = prompts
+        .iter()
+        .map(|(_, token_ids, _)| token_ids.len())
+        .sum();
+
+    let use_beam_search = request.use_beam_search.unwrap_or(false);
+    let generation_cfg = data
+        .pipeline_config
+        .generation_cfg
+        .as_ref()
+        .unwrap()
+        .for_request(use_beam_search);
+    let vocab_size = data.model.read().tokenizer().get_vocab_size(true);
+    let mut sampling_params = Vec::with_capacity(prompts.len());
+    for (_, _, max_tokens) in &prompts {
+        match SamplingParams::new(
+            n,
+            request.best_of,
+            request
+                .presence_penalty
+                .unwrap_or(generation_cfg.presence_penalty.unwrap_or(0.0)),
+            request
+                .frequency_penalty
+                .unwrap_or(generation_cfg.frequency_penalty.unwrap_or(0.0)),
+            request.repeat_last_n,
+            request.temperature.or(generation_cfg.temperature),
+            request.top_p.or(generation_cfg.top_p),
+            request.min_p.or(generation_cfg.min_p),
+            request.top_k.or(generation_cfg.top_k),
+            use_beam_search,
+            request.length_penalty.unwrap_or(1.0),
+            request
+                .early_stopping
+                .clone()
+                .unwrap_or(EarlyStoppingCondition::UnlikelyBetterCandidates),
+            request.stop.clone(),
+            request.stop_token_ids.clone().unwrap_or_default(),
+            request.ignore_eos.unwrap_or(false),
+            *max_tokens,
+            None,
+            None,
+            request.skip_special_tokens.unwrap_or(true),
+            None,
+        ) {
+            Ok(mut params) => {
+                params.seed = request.seed;
+                params.priority = request.priority.unwrap_or_default();
+                params.user = request.user.clone();
+                params.api_key = api_key.clone();
+                if let Err(e) = params.set_timeout(request.timeout) {
+                    return ChatResponder::ValidationError(e);
+                }
+                if let Err(e) = params.set_logit_bias(request.logit_bias.as_ref(), vocab_size) {
+                    return ChatResponder::ValidationError(e);
+                }
+                if let Err(e) =
+                    validate_parallel_sampling(&data, &params, request.stream.is_some_and(|x| x))
+                {
+                    return ChatResponder::ValidationError(e);
+                }
+                sampling_params.push(params)
+            }
+            Err(e) => return ChatResponder::ValidationError(e),
+        }
+    }
+
+    let model_name = data.served_name.clone();
+    let system_fingerprint = data.model.read().system_fingerprint().to_string();
+    let request_id = match new_request_id(&data, request.request_id.as_deref()) {
+        Ok(request_id) => request_id,
+        Err(e) => return ChatResponder::ValidationError(e),
+    };
+    let created = get_created_time_secs();
+    let echo = request.echo.unwrap_or(false);
+    let top_logprobs = request.logprobs;
+    let stream_request = request.stream.is_some_and(|x| x);
+    let include_usage = request
+        .stream_options
+        .as_ref()
+        .is_some_and(|options| options.include_usage);
+    let sse_buffer_size: usize = env::var("CANDLE_VLLM_SSE_BUFFER_SIZE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(1024);
+
+    // Each prompt is submitted as one sequence group sampling all `n`
+    // choices; the choice index follows the OpenAI layout
+    // `prompt_index * n + sample_index`.
+    struct SubRequest {
+        base_index: usize,
+        request_id: String,
+        echo_text: Option<String>,
+        token_ids: Vec<u32>,
+        sampling_params: SamplingParams,
+        sender: Option<Arc<tokio::sync::mpsc::Sender<ChatResponse>>>,
+        sync_notify: Option<Arc<Notify>>,
+    }
+    let mut sub_requests = Vec::with_capacity(prompts.len());
+    let mut receivers = Vec::new();
+    for (prompt_index, ((text, token_ids, _), sampling_params)) in
+        prompts.into_iter().zip(sampling_params).enumerate()
+    {
+        let base_index = prompt_index * n;
+        let (sender, sync_notify) = if stream_request {
+            let (tx, rx) = tokio::sync::mpsc::channel(sse_buffer_size);
+            receivers.push((base_index, rx));
+            (Some(Arc::new(tx)), None)
+        } else {
+            (None, Some(Arc::new(Notify::new())))
+        };
+        sub_requests.push(SubRequest {
+            base_index,
+            request_id: format!("{request_id}-{prompt_index}"),
+            echo_text: echo.then_some(text),
+            token_ids,
+            sampling_params,
+            sender,
+            sync_notify,
+        });
+    }
+
+    let pending = sub_requests
+        .iter()
+        .map(|sub| {
+            (
+                sub.base_index,
+                sub.request_id.clone(),
+                sub.echo_text.clone(),
+                sub.sync_notify.clone(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let sub_request_ids = pending
+        .iter()
+        .map(|(_, sub_request_id, _, _)| sub_request_id.clone())
+        .collect::<Vec<_>>();
+    let base_request_id = request_id.clone();
+    let data_clone = data.clone();
+    let submitted = with_engine_blocking(move || {
+        let mut model = data_clone.model.write();
+        // Check the queue under the same lock as the enqueue so concurrent
+        // requests cannot all pass the limits.
+        model
+            .check_admission(prompt_tokens)
+            .map_err(queue_full_responder)?;
+        if sub_requests.len() > 1 {
+            let prompts = sub_requests
+                .iter()
+                .map(|sub| (sub.token_ids.as_slice(), sub.sampling_params.max_tokens))
+                .collect::<Vec<_>>();
+            admit_combined_tokens(&mut model, &prompts).map_err(ChatResponder::ValidationError)?;
+        }
+        model.add_sub_requests(base_request_id, sub_request_ids);
+        for sub in sub_requests {
+            model.add_request(
+                sub.token_ids,
+                sub.request_id,
+                SystemTime::now(),
+                sub.sampling_params,
+                top_logprobs.is_some(),
+                false,
+                EncodingFormat::default(),
+                EmbeddingType::default(),
+                Vec::new(),
+                crate::openai::ToolChoiceKind::Auto,
+                None,
+                sub.sender,
+                sub.sync_notify,
+                include_usage,
+                None,
+            );
+        }
+        model.notify.notify_one();
+        Ok(())
+    })
+    .await;
+    if let Err(e) = submitted {
+        return e;
+    }
 
     if stream_request {
-        if let Some(ref l) = logger {
-            l.log_start_response();
+        let (response_tx, rx) = tokio::sync::mpsc::channel(sse_buffer_size);
+        let mut forwarders = Vec::with_capacity(receivers.len());
+        for ((base_index, mut sub_rx), (_, _, echo_text, _)) in receivers.into_iter().zip(pending) {
+            let response_tx = response_tx.clone();
+            let request_id = request_id.clone();
+            let model_name = model_name.clone();
+            let system_fingerprint = system_fingerprint.clone();
+            forwarders.push(tokio::spawn(async move {
+                let mut usage = None;
+                // Character offset of the next token in each choice's text.
+                let echo_len = echo_text.as_deref().map_or(0, |text| text.chars().count());
+                let mut text_offsets = HashMap::new();
+                if let Some(text) = echo_text.filter(|text| !text.is_empty()) {
+                    for index in base_index..base_index + n {
+                        let choice = CompletionChoice {
+                            text: text.clone(),
+                            index,
+                            logprobs: None,
+                            finish_reason: None,
+                        };
+                        let chunk = completion_chunk(
+                            &request_id,
+                            &model_name,
+                            created,
+                            &system_fingerprint,
+                            Some(choice),
+                            None,
+                        );
+                        if response_tx
+                            .send(ChatResponse::TextChunk(chunk))
+                            .await
+                            .is_err()
+                        {
+                            return usage;
+                        }
+                    }
+                }
+                while let Some(response) = sub_rx.recv().await {
+                    let response = match response {
+                        ChatResponse::Chunk(chunk) => {
+                            if chunk.usage.is_some() {
+                                usage = chunk.usage;
+                                continue;
+                            }
+                            let Some(choice) = chunk.choices.into_iter().next() else {
+                                continue;
+                            };
+                            let mut text = choice.delta.reasoning_content.unwrap_or_default();
+                            text.push_str(&choice.delta.content.unwrap_or_default());
+                            let logprobs = top_logprobs.zip(choice.logprobs).map(|(top_n, l)| {
+                                let offset = text_offsets.entry(choice.index).or_insert(echo_len);
+                                let logprobs =
+                                    CompletionLogprobs::from_logprobs(&l.content, top_n, *offset);
+                                *offset += l
+                                    .content
+                                    .iter()
+                                    .map(|token| token.bytes.chars().count())
+                                    .sum::<usize>();
+                                logprobs
+                            });
+                            if text.is_empty()
+                                && logprobs.is_none()
+                                && choice.finish_reason.is_none()
+                            {
+                                continue;
+                            }
+                            let choice = CompletionChoice {
+                                text,
+                                index: base_index + choice.index,
+                                logprobs,
+                                finish_reason: choice.finish_reason,
+                            };
+                            ChatResponse::TextChunk(completion_chunk(
+                                &request_id,
+                                &model_name,
+                                created,
+                                &system_fingerprint,
+                                Some(choice),
+                                None,
+                            ))
+                        }
+                        ChatResponse::Done => break,
+                        other => other,
+                    };
+                    if response_tx.send(response).await.is_err() {
+                        break;
+                    }
+                }
+                usage
+            }));
         }
-        ChatResponder::Streamer(
-            Sse::new(Streamer {
-                rx,
-                status: StreamingStatus::Uninitialized,
-                logger,
-            })
-            .keep_alive(
-                KeepAlive::new()
-                    .interval(Duration::from_millis(
-                        env::var("KEEP_ALIVE_INTERVAL")
-                            .map(|val| val.parse::<u64>().unwrap_or(100))
-                            .unwrap_or(100),
-                    ))
-                    .text("keep-alive-text"),
-            ),
-        )
+        tokio::spawn(async move {
+            let mut total_usage = None;
+            for forwarder in forwarders {
+                if let Ok(Some(usage)) = forwarder.await {
+                    total_usage = Some(merge_completion_usage(total_usage, usage));
+                }
+            }
+            if let Some(usage) = total_usage.filter(|_| include_usage) {
+                let chunk = completion_chunk(
+                    &request_id,
+                    &model_name,
+                    created,
+                    &system_fingerprint,
+                    None,
+                    Some(usage),
+                );
+                let _ = response_tx.send(ChatResponse::TextChunk(chunk)).await;
+            }
+            let _ = response_tx.send(ChatResponse::Done).await;
+        });
+
+        ChatResponder::Streamer(sse_streamer(rx, None))
     } else {
-        // wait until current response finished
-        tracing::warn!("waiting response for sync request {}", request_id_clone);
-        sync_notify.as_ref().notified().await;
-        // Re-acquire read lock to get the response
-        // Note: we need to drop the lock later
-        let (choices, usage) = {
-            let model = data_clone.model.read();
-            if !model.completion_records.contains_key(&request_id_clone) {
+        let mut choices = Vec::with_capacity(pending.len());
+        let mut total_usage = None;
+        for (base_index, sub_request_id, echo_text, sync_notify) in pending {
+            if let Some(notify) = sync_notify {
+                notify.notified().await;
+            }
+            if let Some(error) = data.model.write().rejected_requests.remove(&sub_request_id) {
+                return rejected_request_responder(error);
+            }
+            let record = {
+                let model = data.model.read();
+                model.completion_records.get(&sub_request_id).cloned()
+            };
+            let Some((chat_choices, usage)) = record else {
                 return ChatResponder::ModelError(APIError::from(format!(
-                    "Unable to generate response for request {request_id_clone}"
+                    "Unable to generate response for request {sub_request_id}"
                 )));
+            };
+            for choice in chat_choices {
+                let index = base_index + choice.index;
+                choices.push(completion_choice_from_chat(
+                    choice,
+                    index,
+                    echo_text.as_deref(),
+                    top_logprobs,
+                ));
             }
-            let record = &model.completion_records[&request_id_clone];
-            (record.0.clone(), record.1.clone())
-        };
+            total_usage = Some(merge_completion_usage(total_usage, usage));
+        }
+        let mut usage = total_usage.unwrap();
+        usage.request_id = request_id.clone();
 
-        let response = ChatCompletionResponse {
-            id: request_id_clone,
+        ChatResponder::TextCompletion(CompletionResponse {
+            id: request_id,
             choices,
-            created: usage.created,
+            created,
             model: model_name,
-            object: "chat.completion",
-            usage: usage,
-        };
-        if let Some(ref l) = logger {
-            l.log_response(&response);
-        }
-        ChatResponder::Completion(response)
+            object: "text_completion",
+            system_fingerprint: Some(system_fingerprint),
+            usage,
+        })
     }
 }
 
@@ -494,51 +1245,70 @@ pub async fn chat_completions(
 )]
 pub async fn create_embeddings(
     State(data): State<Arc<OpenAIServerData>>,
+    key: Option<Extension<AuthenticatedKey>>,
     request: Json<EmbeddingRequest>,
 ) -> ChatResponder {
-    let input = request.input.clone();
-    let prompts = input.into_vec();
-
-    //For now only support single prompt for simplicity, loop if multiple
-    if prompts.len() != 1 {
-        return ChatResponder::ValidationError(APIError::new_str(
-            "Currently only support single string or token array input.",
-        ));
+    let data = match data.route(request.model.as_deref()) {
+        Ok(data) => data,
+        Err(e) => return ChatResponder::NotFound(e),
+    };
+    let items = request.input.clone().into_items();
+    if items.is_empty() {
+        return ChatResponder::ValidationError(APIError::new_str("`input` must not be empty."));
     }
 
-    let prompt_str = prompts[0].clone();
-
-    //TODO: Reuse check_length or similar logic. For now simplified.
-    let token_ids = {
-        let model = data.model.read();
-        match model.tokenizer().encode_fast(prompt_str, true) {
-            Ok(encoding) => encoding.get_ids().to_vec(),
-            Err(e) => return ChatResponder::ValidationError(APIError::from(e)),
+    let mut prompts = Vec::with_capacity(items.len());
+    for item in items {
+        let token_ids = {
+            let model = data.model.read();
+            match item {
+                CompletionPromptItem::Text(text) => {
+                    match model.tokenizer().encode_fast(text, true) {
+                        Ok(encoding) => encoding.get_ids().to_vec(),
+                        Err(e) => return ChatResponder::ValidationError(APIError::from(e)),
+                    }
+                }
+                CompletionPromptItem::Tokens(token_ids) => {
+                    if let Err(e) = check_prompt_token_ids(model.tokenizer(), &token_ids) {
+                        return ChatResponder::ValidationError(APIError::from(e));
+                    }
+                    token_ids
+                }
+            }
+        };
+        if token_ids.is_empty() {
+            return ChatResponder::ValidationError(APIError::new_str(
+                "Embedding input must contain at least one token.",
+            ));
+        }
+        if let Err(e) = validate_prompt_length(&data, token_ids.len(), 0) {
+            return ChatResponder::ValidationError(e);
         }
-    };
 
-    let available_tokens = {
-        let mut model = data.model.write();
-        let (available_tokens, evicted) = model.ensure_available_kv_tokens(token_ids.len());
-        if evicted > 0 {
-            tracing::warn!(
-                "Evicted {} prefix cache block(s) before embedding length check.",
-                evicted
+        let available_tokens = {
+            let mut model = data.model.write();
+            let (available_tokens, evicted) = model.ensure_available_kv_tokens(token_ids.len());
+            if evicted > 0 {
+                tracing::warn!(
+                    "Evicted {} prefix cache block(s) before embedding length check.",
+                    evicted
+                );
+            }
+            available_tokens
+        };
+        if token_ids.len() >= available_tokens {
+            return ChatResponder::ValidationError(
+                APIError::new_str("Prompt too long.").with_kind(ErrorKind::ContextLengthExceeded),
             );
         }
-        available_tokens
-    };
-
-    if token_ids.len() >= available_tokens {
-        return ChatResponder::ValidationError(APIError::new_str("Prompt too long."));
+        prompts.push(token_ids);
     }
 
     let request_id = format!("embd-{}", Uuid::new_v4());
 
     // Create sampling params for embedding (max_tokens=0, etc)
     // We reuse SamplingParams but most fields irrelevant.
-    let _generation_cfg = data.pipeline_config.generation_cfg.as_ref().unwrap();
-    let sampling_params = match SamplingParams::new(
+    let mut sampling_params = match SamplingParams::new(
         1,
         None,
         0.0,
@@ -563,47 +1333,571 @@ pub async fn create_embeddings(
         Ok(params) => params,
         Err(e) => return ChatResponder::ValidationError(e),
     };
+    sampling_params.api_key = api_key_name(key);
 
     let sse_buffer_size2: usize = env::var("CANDLE_VLLM_SSE_BUFFER_SIZE")
         .ok()
         .and_then(|v| v.parse().ok())
         .unwrap_or(1024);
-    let (response_tx, mut rx) = tokio::sync::mpsc::channel(sse_buffer_size2);
-
-    let request_id_clone = request_id.clone();
+    // Each input is embedded as its own engine request.
+    let mut receivers = Vec::with_capacity(prompts.len());
+    let mut sub_requests = Vec::with_capacity(prompts.len());
+    for (index, token_ids) in prompts.into_iter().enumerate() {
+        let (response_tx, rx) = tokio::sync::mpsc::channel(sse_buffer_size2);
+        receivers.push(rx);
+        sub_requests.push((format!("{request_id}-{index}"), token_ids, response_tx));
+    }
 
     let _ = tokio::task::spawn_blocking(move || {
         tokio::runtime::Handle::current().block_on(async move {
             {
                 let mut model = data.model.write();
-                model.add_request(
-                    token_ids,
-                    request_id_clone,
-                    SystemTime::now(),
-                    sampling_params,
-                    false,
-                    true, //is_embedding
-                    request.encoding_format.clone(),
-                    request.embedding_type.clone(),
-                    Vec::new(),
-                    crate::openai::ToolChoiceKind::Auto,
-                    None,
-                    Some(Arc::new(response_tx)),
-                    None,
-                    false,
-                    None,
-                );
+                for (sub_request_id, token_ids, response_tx) in sub_requests {
+                    model.add_request(
+                        token_ids,
+                        sub_request_id,
+                        SystemTime::now(),
+                        sampling_params.clone(),
+                        false,
+                        true, //is_embedding
+                        request.encoding_format.clone(),
+                        request.embedding_type.clone(),
+                        Vec::new(),
+                        crate::openai::ToolChoiceKind::Auto,
+                        None,
+                        Some(Arc::new(response_tx)),
+                        None,
+                        false,
+                        None,
+                    );
+                }
                 model.notify.notify_one();
             }
         });
     });
 
     // Wait for response from channel
-    // Embedding is strictly one response.
-    match rx.recv().await {
-        Some(ChatResponse::Embedding(resp)) => ChatResponder::Embedding(resp),
-        Some(ChatResponse::ModelError(e)) => ChatResponder::ModelError(APIError::new_str(&e)),
-        Some(_) => ChatResponder::InternalError(APIError::new(format!("Unexpected response type"))),
-        None => ChatResponder::InternalError(APIError::new("Channel closed".to_string())),
+    // Each embedding request yields strictly one response.
+    let mut parts = Vec::with_capacity(receivers.len());
+    for mut rx in receivers {
+        let part = match rx.recv().await {
+            Some(ChatResponse::Embedding(resp)) => resp,
+            Some(ChatResponse::ModelError(e)) => return ChatResponder::ModelError(e),
+            Some(_) => {
+                return ChatResponder::InternalError(APIError::new_str("Unexpected response type"))
+            }
+            None => {
+                return ChatResponder::InternalError(APIError::new("Channel closed".to_string()))
+            }
+        };
+        parts.push(part);
+    }
+    match EmbeddingResponse::concat(parts) {
+        Some(response) => ChatResponder::Embedding(response),
+        None => ChatResponder::InternalError(APIError::new_str("No embedding produced")),
     }
 }
+
+#[utoipa::path(
+    post,
+    tag = "candle-vllm",
+    path = "/tokenize",
+    request_body = TokenizeRequest,
+    responses((status = 200, description = "Prompt token ids"))
+)]
+pub async fn tokenize(
+    State(data): State<Arc<OpenAIServerData>>,
+    request: Json<TokenizeRequest>,
+) -> Result<Json<TokenizeResponse>, ChatResponder> {
+    let request = request.0;
+    let data = data
+        .route(request.model.as_deref())
+        .map_err(ChatResponder::NotFound)?;
+    let (text, rendered) = match (request.prompt, request.messages) {
+        (Some(prompt), None) => (prompt, false),
+        (None, Some(mut messages)) => {
+            if let Messages::Chat(messages) = &mut messages {
+                normalize_empty_openai_tool_results(messages);
+                validate_openai_tool_messages(messages)
+                    .map_err(|err| ChatResponder::ValidationError(APIError::new(err)))?;
+            }
+            let chat_request = ChatCompletionRequest {
+                messages,
+                tools: request.tools,
+                tool_choice: request.tool_choice,
+                thinking: request.thinking,
+                reasoning_effort: request.reasoning_effort,
+                ..Default::default()
+            };
+            let tool_config = resolve_tools_for_request(
+                &chat_request.tools,
+                &chat_request.tool_choice,
+                data.mcp_manager.as_ref(),
+            )
+            .map_err(ChatResponder::ValidationError)?;
+            let (prompt, _) = get_gen_prompt(&data, &chat_request, &tool_config)
+                .await
+                .map_err(ChatResponder::ValidationError)?;
+            (prompt, true)
+        }
+        _ => {
+            return Err(ChatResponder::ValidationError(APIError::new_str(
+                "Exactly one of `prompt` or `messages` must be provided.",
+            )))
+        }
+    };
+    let tokens = data
+        .model
+        .read()
+        .tokenizer()
+        .encode_fast(text.as_str(), request.add_special_tokens.unwrap_or(true))
+        .map_err(|e| ChatResponder::ValidationError(APIError::from(e)))?
+        .get_ids()
+        .to_vec();
+    let prefix_cache_match_tokens = request
+        .return_prefix_cache_match
+        .unwrap_or(false)
+        .then(|| data.model.write().query_prefix_cache_match_tokens(&tokens));
+    Ok(Json(TokenizeResponse {
+        count: tokens.len(),
+        max_model_len: data.pipeline_config.max_model_len,
+        tokens,
+        prompt: rendered.then_some(text),
+        prefix_cache_match_tokens,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    tag = "candle-vllm",
+    path = "/detokenize",
+    request_body = DetokenizeRequest,
+    responses((status = 200, description = "Decoded text"))
+)]
+pub async fn detokenize(
+    State(data): State<Arc<OpenAIServerData>>,
+    request: Json<DetokenizeRequest>,
+) -> Result<Json<DetokenizeResponse>, ChatResponder> {
+    let data = data
+        .route(request.model.as_deref())
+        .map_err(ChatResponder::NotFound)?;
+    let model = data.model.read();
+    let tokenizer = model.tokenizer();
+    check_prompt_token_ids(tokenizer, &request.tokens)
+        .map_err(|e| ChatResponder::ValidationError(APIError::from(e)))?;
+    let prompt = tokenizer
+        .decode(
+            &request.tokens,
+            request.skip_special_tokens.unwrap_or(false),
+        )
+        .map_err(|e| ChatResponder::ValidationError(APIError::from(e)))?;
+    Ok(Json(DetokenizeResponse { prompt }))
+}
+
+#[utoipa::path(
+    get,
+    tag = "candle-vllm",
+    path = "/metrics",
+    responses((status = 200, description = "Prometheus metrics", content_type = "text/plain"))
+)]
+pub async fn metrics(
+    State(data): State<Arc<OpenAIServerData>>,
+) -> ([(header::HeaderName, &'static str); 1], String) {
+    let body = data.model.read().render_metrics();
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
+
+#[utoipa::path(
+    get,
+    tag = "candle-vllm",
+    path = "/admin/usage",
+    responses(
+        (status = 200, description = "Token usage accumulated per API key"),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 403, description = "API key is not an admin key")
+    )
+)]
+pub async fn key_usage(
+    State(data): State<Arc<OpenAIServerData>>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, ChatResponder> {
+    let Some(keys) = &data.api_keys else {
+        return Err(ChatResponder::NotFound(APIError::new_str(
+            "API keys are not configured; start the server with --api-keys.",
+        )));
+    };
+    require_admin(keys, &headers)?;
+    let mut usage = BTreeMap::<String, KeyUsage>::new();
+    for served in data.served_models() {
+        for (name, model_usage) in served.model.read().metrics.key_usage() {
+            usage.entry(name).or_default().merge(&model_usage);
+        }
+    }
+    for name in keys.names() {
+        usage.entry(name.to_string()).or_default();
+    }
+    let usage = usage
+        .into_iter()
+        .map(|(name, usage)| {
+            let mut entry = serde_json::to_value(usage).unwrap_or_default();
+            entry["name"] = serde_json::Value::String(name);
+            entry
+        })
+        .collect::<Vec<_>>();
+    Ok(Json(serde_json::json!({ "object": "list", "data": usage })))
+}
+
+#[utoipa::path(
+    get,
+    tag = "candle-vllm",
+    path = "/health",
+    responses((status = 200, description = "Server process is up"))
+)]
+pub async fn health() -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "status": "ok" }))
+}
+
+#[utoipa::path(
+    get,
+    tag = "candle-vllm",
+    path = "/ready",
+    responses(
+        (status = 200, description = "Engine is ready to serve requests"),
+        (status = 503, description = "Engine is not ready")
+    )
+)]
+pub async fn ready(
+    State(data): State<Arc<OpenAIServerData>>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    // With several models, every one must be ready and reasons name the model.
+    let mut reasons = Vec::new();
+    for served in data.served_models() {
+        let status = served.model.read().status();
+        reasons.extend(status.not_ready_reasons.into_iter().map(|reason| {
+            if data.extra_models.is_empty() {
+                reason
+            } else {
+                format!("{}: {reason}", served.served_name)
+            }
+        }));
+    }
+    if reasons.is_empty() {
+        (
+            StatusCode::OK,
+            Json(serde_json::json!({ "status": "ready" })),
+        )
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({
+                "status": "not_ready",
+                "reasons": reasons,
+            })),
+        )
+    }
+}
+
+#[utoipa::path(
+    get,
+    tag = "candle-vllm",
+    path = "/status",
+    responses((status = 200, description = "Ranks, KV cache capacity and queue depth"))
+)]
+pub async fn status(State(data): State<Arc<OpenAIServerData>>) -> Json<EngineStatus> {
+    Json(data.model.read().status())
+}
+
+#[utoipa::path(
+    post,
+    tag = "candle-vllm",
+    path = "/v1/responses",
+    request_body = ResponsesRequest,
+    responses((status = 200, description = "Responses"))
+)]
+pub async fn create_response(
+    State(data): State<Arc<OpenAIServerData>>,
+    key: Option<Extension<AuthenticatedKey>>,
+    request: Json<ResponsesRequest>,
+) -> ChatResponder {
+    let request = request.0;
+    let data = match data.route(request.model.as_deref()) {
+        Ok(data) => data,
+        Err(e) => return ChatResponder::NotFound(e),
+    };
+    let owner = api_key_name(key);
+    let history = match &request.previous_response_id {
+        Some(id) => match data.response_store.get(id, owner.as_deref()) {
+            Some(previous) => previous.messages,
+            None => {
+                return ChatResponder::NotFound(APIError::new(format!(
+                    "Previous response with id '{id}' not found."
+                )))
+            }
+        },
+        None => Vec::new(),
+    };
+    let input = match responses_api::input_messages(request.input.clone()) {
+        Ok(input) => input,
+        Err(e) => return ChatResponder::ValidationError(e),
+    };
+    let chat_request = match responses_api::chat_request(&request, &history, &input) {
+        Ok(chat_request) => chat_request,
+        Err(e) => return ChatResponder::ValidationError(e),
+    };
+    let mut submission = match submit_chat_request(&data, chat_request, owner.clone()).await {
+        Ok(submission) => submission,
+        Err(e) => return e,
+    };
+    let response = ResponseObject::new(
+        responses_api::new_response_id(),
+        get_created_time_secs(),
+        submission.model_name.clone(),
+        &request,
+    );
+    let mut messages = history;
+    messages.extend(input);
+
+    if let Some(mut chat_rx) = submission.stream_rx.take() {
+        let (response_tx, rx) = tokio::sync::mpsc::channel(1024);
+        tokio::spawn(async move {
+            let mut state = ResponseStreamState::new(response);
+            let mut events = state.start();
+            loop {
+                for event in events.drain(..) {
+                    if response_tx
+                        .send(ChatResponse::NamedEvent(event.event, event.data))
+                        .await
+                        .is_err()
+                    {
+                        return;
+                    }
+                }
+                match chat_rx.recv().await {
+                    Some(ChatResponse::Chunk(chunk)) => events.extend(state.on_chunk(chunk)),
+                    Some(
+                        ChatResponse::InternalError(e)
+                        | ChatResponse::ValidationError(e)
+                        | ChatResponse::ModelError(e),
+                    ) => {
+                        // A failed generation is neither completed nor stored.
+                        let event = state.error(e.message().to_string());
+                        let _ = response_tx
+                            .send(ChatResponse::NamedEvent(event.event, event.data))
+                            .await;
+                        return;
+                    }
+                    Some(ChatResponse::Done) | None => break,
+                    Some(_) => {}
+                }
+            }
+            events.extend(state.finish());
+            if state.response.store {
+                messages.extend(responses_api::assistant_message(&state.response.output));
+                data.response_store.insert(StoredResponse {
+                    response: state.response.clone(),
+                    messages,
+                    owner,
+                });
+            }
+            for event in events {
+                let _ = response_tx
+                    .send(ChatResponse::NamedEvent(event.event, event.data))
+                    .await;
+            }
+        });
+        return ChatResponder::Streamer(sse_streamer(rx, submission.logger));
+    }
+
+    let completion = match wait_chat_completion(&data, &submission).await {
+        Ok(completion) => completion,
+        Err(e) => return e,
+    };
+    let mut response = response;
+    let choice = completion.choices.first();
+    response.finish(
+        choice
+            .map(|choice| responses_api::output_items(&choice.message))
+            .unwrap_or_default(),
+        choice.and_then(|choice| choice.finish_reason.as_deref()),
+        Some(&completion.usage),
+    );
+    if response.store {
+        messages.extend(responses_api::assistant_message(&response.output));
+        data.response_store.insert(StoredResponse {
+            response: response.clone(),
+            messages,
+            owner,
+        });
+    }
+    ChatResponder::Response(response)
+}
+
+#[utoipa::path(
+    get,
+    tag = "candle-vllm",
+    path = "/v1/responses/{response_id}",
+    responses((status = 200, description = "Stored response"))
+)]
+pub async fn retrieve_response(
+    State(data): State<Arc<OpenAIServerData>>,
+    key: Option<Extension<AuthenticatedKey>>,
+    Path(response_id): Path<String>,
+) -> ChatResponder {
+    let owner = api_key_name(key);
+    match data.response_store.get(&response_id, owner.as_deref()) {
+        Some(stored) => ChatResponder::Response(stored.response),
+        None => ChatResponder::NotFound(APIError::new(format!(
+            "Response with id '{response_id}' not found."
+        ))),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    tag = "candle-vllm",
+    path = "/v1/responses/{response_id}",
+    responses((status = 200, description = "Deleted response"))
+)]
+pub async fn delete_response(
+    State(data): State<Arc<OpenAIServerData>>,
+    key: Option<Extension<AuthenticatedKey>>,
+    Path(response_id): Path<String>,
+) -> Result<Json<serde_json::Value>, ChatResponder> {
+    let owner = api_key_name(key);
+    if data.response_store.remove(&response_id, owner.as_deref()) {
+        Ok(Json(serde_json::json!({
+            "id": response_id,
+            "object": "response.deleted",
+            "deleted": true,
+        })))
+    } else {
+        Err(ChatResponder::NotFound(APIError::new(format!(
+            "Response with id '{response_id}' not found."
+        ))))
+    }
+}
+
+#[utoipa::path(
+    delete,
+    tag = "candle-vllm",
+    path = "/v1/requests/{request_id}",
+    responses(
+        (status = 200, description = "Cancelled request"),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 403, description = "API key is not an admin key")
+    )
+)]
+pub async fn cancel_request(
+    State(data): State<Arc<OpenAIServerData>>,
+    headers: HeaderMap,
+    Path(request_id): Path<String>,
+) -> Result<Json<serde_json::Value>, ChatResponder> {
+    // Request ids span tenants, so only admin keys may cancel them.
+    if let Some(keys) = &data.api_keys {
+        require_admin(keys, &headers)?;
+    }
+    // Request ids are only unique per engine, so every served model is tried.
+    let mut cancelled = false;
+    for served in data.served_models() {
+        let mut model = served.model.write();
+        if model.cancel_request(&request_id) {
+            model.notify.notify_one();
+            cancelled = true;
+        }
+    }
+    if cancelled {
+        Ok(Json(serde_json::json!({
+            "id": request_id,
+            "object": "request.cancelled",
+            "cancelled": true,
+        })))
+    } else {
+        Err(ChatResponder::NotFound(APIError::new(format!(
+            "No running request with id '{request_id}'."
+        ))))
+    }
+}
+
+#[utoipa::path(
+    post,
+    tag = "candle-vllm",
+    path = "/v1/messages",
+    request_body = MessagesRequest,
+    responses((status = 200, description = "Anthropic messages"))
+)]
+pub async fn create_message(
+    State(data): State<Arc<OpenAIServerData>>,
+    key: Option<Extension<AuthenticatedKey>>,
+    request: Json<MessagesRequest>,
+) -> ChatResponder {
+    let request = request.0;
+    let data = match data.route(request.model.as_deref()) {
+        Ok(data) => data,
+        Err(e) => return ChatResponder::NotFound(e),
+    };
+    let chat_request = match anthropic_api::chat_request(&request) {
+        Ok(chat_request) => chat_request,
+        Err(e) => return ChatResponder::ValidationError(e),
+    };
+    let mut submission = match submit_chat_request(&data, chat_request, api_key_name(key)).await {
+        Ok(submission) => submission,
+        Err(e) => return e,
+    };
+    let message = MessagesResponse::new(submission.model_name.clone());
+
+    if let Some(mut chat_rx) = submission.stream_rx.take() {
+        let (response_tx, rx) = tokio::sync::mpsc::channel(1024);
+        tokio::spawn(async move {
+            let mut state = MessageStreamState::new(message);
+            let mut events = state.start();
+            loop {
+                for event in events.drain(..) {
+                    if response_tx
+                        .send(ChatResponse::NamedEvent(event.event, event.data))
+                        .await
+                        .is_err()
+                    {
+                        return;
+                    }
+                }
+                match chat_rx.recv().await {
+                    Some(ChatResponse::Chunk(chunk)) => events.extend(state.on_chunk(chunk)),
+                    Some(
+                        ChatResponse::InternalError(e)
+                        | ChatResponse::ValidationError(e)
+                        | ChatResponse::ModelError(e),
+                    ) => {
+                        let event = MessageStreamState::error(e.message().to_string());
+                        let _ = response_tx
+                            .send(ChatResponse::NamedEvent(event.event, event.data))
+                            .await;
+                        return;
+                    }
+                    Some(ChatResponse::Done) | None => break,
+                    Some(_) => {}
+                }
+            }
+            for event in state.finish() {
+                let _ = response_tx
+                    .send(ChatResponse::NamedEvent(event.event, event.data))
+                    .await;
+            }
+        });
+        return ChatResponder::Streamer(sse_streamer(rx, submission.logger));
+    }
+
+    let completion = match wait_chat_completion(&data, &submission).await {
+        Ok(completion) => completion,
+        Err(e) => return e,
+    };
+    let mut message = message;
+    let choice = completion.choices.first();
+    message.finish(
+        choice
+            .map(|choice| anthropic_api::output_blocks(&choice.message))
+            .unwrap_or_default(),
+        choice.and_then(|choice| choice.finish_reason.as_deref()),
+        Some(&completion.usage),
+    );
+    ChatResponder::Message(message)
+}
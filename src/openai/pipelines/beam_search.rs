@@ -0,0 +1,385 @@
+//! Beam search over the sequences of a group. Each unfinished sequence is a
+//! beam; a beam that keeps several continuations forks into the slots of beams
+//! that keep none, sharing its KV blocks copy-on-write.
+
+use std::sync::Arc;
+
+use either::Either;
+
+use super::{LLMEngine, Logprobs, Sequence, SequenceGroup, TokenOrFinishReason};
+use crate::openai::requests::StopTokens;
+use crate::openai::sampling_params::EarlyStoppingCondition;
+use crate::scheduler::sequence::BeamHypothesis;
+
+struct BeamCandidate {
+    beam: usize,
+    logprobs: Logprobs,
+    cumulative_logprob: f32,
+}
+
+/// Length-penalised score of a beam, as in Hugging Face and vLLM.
+fn beam_score(cumulative_logprob: f32, output_len: usize, length_penalty: f32) -> f32 {
+    cumulative_logprob / (output_len.max(1) as f32).powf(length_penalty)
+}
+
+/// Split the best `2 * width` candidates into finished and live beams. A stop
+/// token only ends a hypothesis when it ranks within the beam width.
+fn select_candidates(
+    mut candidates: Vec<BeamCandidate>,
+    width: usize,
+    is_stop: impl Fn(&Logprobs) -> bool,
+) -> (Vec<BeamCandidate>, Vec<BeamCandidate>) {
+    candidates.sort_by(|a, b| b.cumulative_logprob.total_cmp(&a.cumulative_logprob));
+    let mut finished = Vec::new();
+    let mut live = Vec::new();
+    for (rank, candidate) in candidates.into_iter().take(2 * width).enumerate() {
+        if is_stop(&candidate.logprobs) {
+            if rank < width {
+                finished.push(candidate);
+            }
+        } else if live.len() < width {
+            live.push(candidate);
+        }
+    }
+    (finished, live)
+}
+
+/// Assign each live candidate (given by its parent beam) a slot among
+/// `num_slots`. A parent keeps its first continuation in its own slot; further
+/// continuations take slots whose beam keeps none. Returns the slot of each
+/// candidate that got one, and the slots left idle.
+fn assign_slots(parents: &[usize], num_slots: usize) -> (Vec<usize>, Vec<usize>) {
+    let mut kept = vec![false; num_slots];
+    for &parent in parents {
+        kept[parent] = true;
+    }
+    let mut free_slots = (0..num_slots)
+        .filter(|&slot| !kept[slot])
+        .rev()
+        .collect::<Vec<_>>();
+    let mut assigned = vec![false; num_slots];
+    let mut slots = Vec::with_capacity(parents.len());
+    for &parent in parents {
+        let slot = if assigned[parent] {
+            match free_slots.pop() {
+                Some(slot) => slot,
+                None => break,
+            }
+        } else {
+            parent
+        };
+        assigned[parent] = true;
+        slots.push(slot);
+    }
+    (slots, free_slots)
+}
+
+/// Whether no live beam can improve on the finished hypotheses (sorted best
+/// first) under the request's early stopping condition.
+fn search_done(
+    early_stopping: &EarlyStoppingCondition,
+    length_penalty: f32,
+    width: usize,
+    hypotheses: &[BeamHypothesis],
+    best_live_logprob: f32,
+    cur_len: usize,
+    max_len: usize,
+) -> bool {
+    if hypotheses.len() < width {
+        return false;
+    }
+    let worst = hypotheses[width - 1].score;
+    match early_stopping {
+        EarlyStoppingCondition::BestOfCompleteCandidates => true,
+        EarlyStoppingCondition::UnlikelyBetterCandidates => {
+            worst >= beam_score(best_live_logprob, cur_len, length_penalty)
+        }
+        EarlyStoppingCondition::CanonicalNoBetterCandidates => {
+            // With a positive penalty, longer beams score higher, so the
+            // best a live beam can reach is at the length limit.
+            let len = if length_penalty > 0.0 {
+                max_len
+            } else {
+                cur_len
+            };
+            worst >= beam_score(best_live_logprob, len, length_penalty)
+        }
+    }
+}
+
+impl LLMEngine {
+    /// Advance a beam search group by one step. `beams` are the unfinished
+    /// sequences of the group in id order, with their sample results.
+    pub(super) fn apply_beam_search_step(
+        &mut self,
+        rank: usize,
+        group: &Arc<SequenceGroup>,
+        beams: Vec<Arc<Sequence>>,
+        results: Vec<TokenOrFinishReason>,
+    ) {
+        let params = &group.sampling_params;
+        let width = params.best_of;
+        let pipeline = &self.get_pipeline(rank).unwrap().0;
+        let stop_strings = match &params.stop {
+            Some(StopTokens::Multi(v)) => v.clone(),
+            Some(StopTokens::Single(v)) => vec![v.clone()],
+            _ => vec![],
+        };
+        let is_stop = |logprobs: &Logprobs| {
+            pipeline.stop_token_ids.contains(&logprobs.token)
+                || stop_strings.contains(&logprobs.bytes.trim().to_string())
+        };
+
+        // Right after the prompt every beam holds the same sequence, so only
+        // the first one is expanded. Parked slots hold no beam.
+        let mut parked = group
+            .parked_beams
+            .write()
+            .unwrap_or_else(|e| e.into_inner());
+        let expand = if beams[0].deref().is_prompt() {
+            1
+        } else {
+            beams.len()
+        };
+        let mut candidates = Vec::new();
+        let mut cur_len = 0;
+        for (beam, result) in results.into_iter().enumerate().take(expand) {
+            if parked.contains(&beams[beam].deref().get_id()) {
+                continue;
+            }
+            cur_len = beams[beam].deref().get_output_tokens().len() + 1;
+            let Either::Left(logprobs) = result else {
+                continue;
+            };
+            let cumulative_logprob = beams[beam].deref().get_cumulative_logprob();
+            candidates.extend(logprobs.top_logprobs.into_iter().map(|top| BeamCandidate {
+                beam,
+                cumulative_logprob: cumulative_logprob + top.logprob,
+                logprobs: Logprobs {
+                    token: top.token,
+                    logprob: top.logprob,
+                    bytes: top.bytes,
+                    top_logprobs: Vec::new(),
+                },
+            }));
+        }
+        let (finished, mut live) = select_candidates(candidates, width, is_stop);
+        live.truncate(beams.len());
+
+        let mut hypotheses = group
+            .beam_hypotheses
+            .write()
+            .unwrap_or_else(|e| e.into_inner());
+        for candidate in finished {
+            hypotheses.push(BeamHypothesis {
+                output_tokens: beams[candidate.beam].deref().get_output_tokens(),
+                score: beam_score(
+                    candidate.cumulative_logprob,
+                    cur_len - 1,
+                    params.length_penalty,
+                ),
+                finish_reason: "stop".to_string(),
+            });
+        }
+        let out_of_length = cur_len >= params.max_tokens || live.is_empty();
+        if out_of_length {
+            for candidate in &live {
+                let mut output_tokens = beams[candidate.beam].deref().get_output_tokens();
+                output_tokens.push(candidate.logprobs.clone());
+                hypotheses.push(BeamHypothesis {
+                    output_tokens,
+                    score: beam_score(candidate.cumulative_logprob, cur_len, params.length_penalty),
+                    finish_reason: "length".to_string(),
+                });
+            }
+        }
+        hypotheses.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hypotheses.truncate(width);
+
+        let done = out_of_length
+            || search_done(
+                &params.early_stopping,
+                params.length_penalty,
+                width,
+                &hypotheses,
+                live[0].cumulative_logprob,
+                cur_len,
+                params.max_tokens,
+            );
+        if done {
+            let mut seqs = group.get_seqs().values().collect::<Vec<_>>();
+            seqs.sort_by_key(|seq| seq.deref().get_id());
+            for (seq, hypothesis) in seqs.into_iter().zip(hypotheses.iter()) {
+                let mut seq = seq.deref_mut();
+                seq.set_output_tokens(hypothesis.output_tokens.clone());
+                seq.set_finish_reason(hypothesis.finish_reason.clone());
+            }
+            // Fewer hypotheses than beams only happens with a tiny vocabulary.
+            for seq in group.get_seqs().values() {
+                if !seq.deref().is_finished() {
+                    seq.deref_mut().set_finish_reason("length".to_string());
+                }
+            }
+            return;
+        }
+        drop(hypotheses);
+
+        let parents = live.iter().map(|c| c.beam).collect::<Vec<_>>();
+        let (slots, idle) = assign_slots(&parents, beams.len());
+        let slots = slots.into_iter().zip(live).collect::<Vec<_>>();
+        // Free slots are never parents, so forking before any token is added
+        // copies each parent as it was when sampled.
+        for (slot, candidate) in &slots {
+            if *slot != candidate.beam {
+                let (parent, child) = (&beams[candidate.beam], &beams[*slot]);
+                self.scheduler.block_engine.fork_sequence(parent, child);
+                child.deref_mut().fork_from(&parent.deref());
+            }
+        }
+        for (slot, candidate) in slots {
+            beams[slot].deref_mut().add_token(candidate.logprobs);
+        }
+        // Idle slots stay unfinished so later steps can still fork into them.
+        *parked = idle
+            .into_iter()
+            .map(|slot| beams[slot].deref().get_id())
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(beam: usize, token: u32, cumulative_logprob: f32) -> BeamCandidate {
+        BeamCandidate {
+            beam,
+            logprobs: Logprobs {
+                token,
+                logprob: 0.0,
+                bytes: String::new(),
+                top_logprobs: Vec::new(),
+            },
+            cumulative_logprob,
+        }
+    }
+
+    fn hypothesis(score: f32) -> BeamHypothesis {
+        BeamHypothesis {
+            output_tokens: Vec::new(),
+            score,
+            finish_reason: "stop".to_string(),
+        }
+    }
+
+    #[test]
+    fn stop_tokens_only_finish_within_the_beam_width() {
+        const EOS: u32 = 0;
+        let candidates = vec![
+            candidate(0, 5, -1.0),
+            candidate(1, EOS, -0.5),
+            candidate(0, EOS, -3.0),
+            candidate(1, 6, -2.0),
+            candidate(0, 7, -2.5),
+        ];
+        let (finished, live) = select_candidates(candidates, 2, |l| l.token == EOS);
+        assert_eq!(finished.iter().map(|c| c.beam).collect::<Vec<_>>(), vec![1]);
+        assert_eq!(
+            live.iter().map(|c| c.logprobs.token).collect::<Vec<_>>(),
+            vec![5, 6]
+        );
+    }
+
+    #[test]
+    fn idle_slots_stay_available_after_many_stop_candidates() {
+        const EOS: u32 = 0;
+        // Four of the top six candidates stop, leaving one slot idle.
+        let candidates = vec![
+            candidate(0, EOS, -0.1),
+            candidate(1, EOS, -0.2),
+            candidate(0, 5, -0.3),
+            candidate(2, EOS, -0.4),
+            candidate(0, EOS, -0.5),
+            candidate(1, 6, -0.6),
+        ];
+        let (finished, live) = select_candidates(candidates, 3, |l| l.token == EOS);
+        assert_eq!(finished.len(), 2);
+        let parents = live.iter().map(|c| c.beam).collect::<Vec<_>>();
+        let (slots, idle) = assign_slots(&parents, 3);
+        assert_eq!(slots, vec![0, 1]);
+        assert_eq!(idle, vec![2]);
+
+        // Next step, every live candidate continues beam 0 and the idle slot
+        // takes one of them.
+        let candidates = vec![
+            candidate(0, 7, -1.0),
+            candidate(0, 8, -1.1),
+            candidate(0, 9, -1.2),
+            candidate(1, 10, -2.0),
+        ];
+        let (_, live) = select_candidates(candidates, 3, |l| l.token == EOS);
+        let parents = live.iter().map(|c| c.beam).collect::<Vec<_>>();
+        let (slots, idle) = assign_slots(&parents, 3);
+        assert_eq!(slots, vec![0, 1, 2]);
+        assert!(idle.is_empty());
+    }
+
+    #[test]
+    fn assign_slots_never_runs_out() {
+        let (slots, idle) = assign_slots(&[0, 0, 0, 0], 3);
+        assert_eq!(slots, vec![0, 1, 2]);
+        assert!(idle.is_empty());
+    }
+
+    #[test]
+    fn length_penalty_favours_longer_beams() {
+        assert_eq!(beam_score(-4.0, 4, 0.0), -4.0);
+        assert_eq!(beam_score(-4.0, 4, 1.0), -1.0);
+        assert_eq!(beam_score(-4.0, 0, 1.0), -4.0);
+    }
+
+    #[test]
+    fn early_stopping_conditions() {
+        let hypotheses = vec![hypothesis(-1.0), hypothesis(-2.0)];
+        let done = |condition, length_penalty, best_live| {
+            search_done(&condition, length_penalty, 2, &hypotheses, best_live, 4, 16)
+        };
+        // Not enough hypotheses yet.
+        assert!(!search_done(
+            &EarlyStoppingCondition::BestOfCompleteCandidates,
+            1.0,
+            3,
+            &hypotheses,
+            -100.0,
+            4,
+            16
+        ));
+        assert!(done(
+            EarlyStoppingCondition::BestOfCompleteCandidates,
+            1.0,
+            -0.1
+        ));
+        // A live beam scoring -8 / 4 = -2 cannot beat the worst hypothesis.
+        assert!(done(
+            EarlyStoppingCondition::UnlikelyBetterCandidates,
+            1.0,
+            -8.0
+        ));
+        assert!(!done(
+            EarlyStoppingCondition::UnlikelyBetterCandidates,
+            1.0,
+            -4.0
+        ));
+        // At the length limit the same beam would score -8 / 16 = -0.5.
+        assert!(!done(
+            EarlyStoppingCondition::CanonicalNoBetterCandidates,
+            1.0,
+            -8.0
+        ));
+        assert!(done(
+            EarlyStoppingCondition::CanonicalNoBetterCandidates,
+            0.0,
+            -8.0
+        ));
+    }
+}
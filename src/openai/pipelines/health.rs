@@ -0,0 +1,207 @@
+//! Readiness and status reporting for the health endpoints.
+
+use std::sync::atomic::Ordering;
+use std::time::{Duration, SystemTime};
+
+use super::LLMEngine;
+use crate::openai::responses::{
+    CudaGraphStatus, EngineLoopStatus, EngineStatus, KvCacheStatus, QueueStatus, RanksStatus,
+};
+
+/// Daemons are pinged every second; a few missed beats mean a rank is gone.
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Queued work that has seen no scheduling step for this long means the
+/// engine loop is wedged.
+const ENGINE_STALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Reasons the engine cannot take traffic; empty when it is ready.
+fn not_ready_reasons(
+    cuda_graph: CudaGraphStatus,
+    engine_loop: &EngineLoopStatus,
+    ranks: &RanksStatus,
+) -> Vec<String> {
+    let mut reasons = Vec::new();
+    if cuda_graph == CudaGraphStatus::Pending {
+        reasons.push("CUDA graphs are still being captured".to_string());
+    }
+    if !engine_loop.running {
+        reasons.push("engine loop has exited".to_string());
+    }
+    if let Some(stalled) = engine_loop.seconds_stalled {
+        if stalled > ENGINE_STALL_TIMEOUT.as_secs_f64() {
+            reasons.push(format!(
+                "engine loop made no progress on queued requests for {stalled:.0}s"
+            ));
+        }
+    }
+    if ranks.heartbeat_peers.is_some() {
+        match ranks.seconds_since_heartbeat {
+            None => reasons.push("waiting for the first rank heartbeat".to_string()),
+            Some(elapsed) if elapsed > HEARTBEAT_TIMEOUT.as_secs_f64() => reasons.push(format!(
+                "no rank heartbeat for {elapsed:.0}s ({} failures)",
+                ranks.heartbeat_failures
+            )),
+            _ => {}
+        }
+    }
+    reasons
+}
+
+impl LLMEngine {
+    pub(super) fn initial_cuda_graph_status(disable_cuda_graph: bool) -> CudaGraphStatus {
+        if !cfg!(all(feature = "cuda", feature = "graph")) {
+            CudaGraphStatus::Unsupported
+        } else if disable_cuda_graph {
+            CudaGraphStatus::Disabled
+        } else {
+            CudaGraphStatus::Pending
+        }
+    }
+
+    fn ranks_status(&self) -> RanksStatus {
+        let mut local_ranks = self.pipelines.keys().copied().collect::<Vec<_>>();
+        local_ranks.sort_unstable();
+        #[cfg(feature = "nccl")]
+        let heartbeat = crate::backend::heartbeat::heartbeat_status().map(|heartbeat| {
+            (
+                heartbeat.num_peers,
+                heartbeat
+                    .since_last_heartbeat
+                    .map(|elapsed| elapsed.as_secs_f64()),
+                heartbeat.consecutive_failures,
+            )
+        });
+        #[cfg(not(feature = "nccl"))]
+        let heartbeat: Option<(usize, Option<f64>, usize)> = None;
+        RanksStatus {
+            num_shards: self.num_shards,
+            multi_process: self.multi_process,
+            local_ranks,
+            heartbeat_peers: heartbeat.map(|(peers, _, _)| peers),
+            seconds_since_heartbeat: heartbeat.and_then(|(_, elapsed, _)| elapsed),
+            heartbeat_failures: heartbeat.map_or(0, |(_, _, failures)| failures),
+        }
+    }
+
+    fn engine_loop_status(&self) -> EngineLoopStatus {
+        let now = SystemTime::now();
+        let seconds_since = |time: SystemTime| {
+            now.duration_since(time)
+                .map_or(0.0, |elapsed| elapsed.as_secs_f64())
+        };
+        let oldest_pending = self
+            .waiting_tasks
+            .read()
+            .iter()
+            .map(|task| task.created)
+            .chain(self.scheduler.oldest_unfinished_time())
+            .min();
+        // Work is stalled from whichever came last: the previous step, or the
+        // arrival of the oldest request still queued.
+        let seconds_stalled = oldest_pending.map(|pending| {
+            let since = self
+                .last_step_time
+                .map_or(pending, |step| step.max(pending));
+            seconds_since(since)
+        });
+        EngineLoopStatus {
+            running: !self.exit_flag.load(Ordering::Relaxed),
+            seconds_since_last_step: self.last_step_time.map(seconds_since),
+            seconds_stalled,
+        }
+    }
+
+    /// Snapshot of ranks, KV cache capacity and queue depth, with whether the
+    /// engine is ready to serve.
+    pub fn status(&self) -> EngineStatus {
+        let scheduler = self.scheduler.metrics();
+        let block_size = self.cache_config.block_size;
+        let engine_loop = self.engine_loop_status();
+        let ranks = self.ranks_status();
+        let not_ready_reasons = not_ready_reasons(self.cuda_graph, &engine_loop, &ranks);
+        EngineStatus {
+            ready: not_ready_reasons.is_empty(),
+            not_ready_reasons,
+            model: self.model_name.clone(),
+            cuda_graph: self.cuda_graph,
+            engine_loop,
+            ranks,
+            kv_cache: KvCacheStatus {
+                block_size,
+                num_gpu_blocks: scheduler.num_gpu_blocks,
+                num_free_gpu_blocks: scheduler.num_free_gpu_blocks,
+                capacity_tokens: scheduler.num_gpu_blocks * block_size,
+                free_tokens: scheduler.num_free_gpu_blocks * block_size,
+                usage: if scheduler.num_gpu_blocks == 0 {
+                    0.0
+                } else {
+                    (scheduler.num_gpu_blocks - scheduler.num_free_gpu_blocks) as f64
+                        / scheduler.num_gpu_blocks as f64
+                },
+                prefix_cache_blocks: scheduler.prefix_cache_blocks,
+            },
+            queue: QueueStatus {
+                running: scheduler.num_running_seqs,
+                waiting: scheduler.num_waiting_seqs,
+                swapped: scheduler.num_swapped_seqs,
+                pending: self.waiting_tasks.read().len(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn engine_loop(running: bool, seconds_stalled: Option<f64>) -> EngineLoopStatus {
+        EngineLoopStatus {
+            running,
+            seconds_since_last_step: None,
+            seconds_stalled,
+        }
+    }
+
+    fn ranks(seconds_since_heartbeat: Option<f64>, multi_process: bool) -> RanksStatus {
+        RanksStatus {
+            num_shards: 2,
+            multi_process,
+            local_ranks: vec![0],
+            heartbeat_peers: multi_process.then_some(1),
+            seconds_since_heartbeat,
+            heartbeat_failures: 0,
+        }
+    }
+
+    #[test]
+    fn ready_when_loop_runs_and_ranks_heartbeat() {
+        let reasons = not_ready_reasons(
+            CudaGraphStatus::Captured,
+            &engine_loop(true, Some(1.0)),
+            &ranks(Some(1.0), true),
+        );
+        assert!(reasons.is_empty(), "{reasons:?}");
+        let reasons = not_ready_reasons(
+            CudaGraphStatus::Unsupported,
+            &engine_loop(true, None),
+            &ranks(None, false),
+        );
+        assert!(reasons.is_empty(), "{reasons:?}");
+    }
+
+    #[test]
+    fn not_ready_reasons_cover_each_failure() {
+        let reasons = not_ready_reasons(
+            CudaGraphStatus::Pending,
+            &engine_loop(false, Some(120.0)),
+            &ranks(Some(30.0), true),
+        );
+        assert_eq!(reasons.len(), 4, "{reasons:?}");
+        let reasons = not_ready_reasons(
+            CudaGraphStatus::Disabled,
+            &engine_loop(true, None),
+            &ranks(None, true),
+        );
+        assert_eq!(reasons, vec!["waiting for the first rank heartbeat"]);
+    }
+}
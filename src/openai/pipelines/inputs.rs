@@ -23,16 +23,11 @@ impl LLMEngine {
 
     pub fn prepare_block_tables(
         &self,
-        groups: &VecDeque<Arc<SequenceGroup>>,
+        ordered_sequences: &[Arc<Sequence>],
         device: &Device,
     ) -> Result<Tensor> {
-        let mut ordered_sequences = Vec::<Arc<Sequence>>::new();
-        for group in groups {
-            ordered_sequences.extend(Self::ordered_group_sequences(group));
-        }
-
         let mut max_len = 0;
-        for seq in &ordered_sequences {
+        for seq in ordered_sequences {
             let len = self
                 .scheduler
                 .block_engine
@@ -46,7 +41,7 @@ impl LLMEngine {
         }
         let mut flat: Vec<u32> = Vec::with_capacity(ordered_sequences.len() * max_len);
 
-        for seq in &ordered_sequences {
+        for seq in ordered_sequences {
             let table = self
                 .scheduler
                 .block_engine
@@ -104,94 +99,94 @@ impl LLMEngine {
         let mut slot_mapping = Vec::new();
         let chunk_size = self.prefill_chunk_size.unwrap_or(PREFILL_CHUNK_SIZE);
         let mut max_context_len = 0;
-        #[cfg(feature = "flashinfer")]
-        let mut ordered_sequences = Vec::<Arc<Sequence>>::new();
+        // Sequences of a group share the prompt blocks, so only the primary
+        // sequence is prefilled and yields one logits row for the group.
+        let ordered_sequences = groups
+            .iter()
+            .map(|group| Arc::clone(group.get_primary_seq()))
+            .collect::<Vec<_>>();
         #[cfg(feature = "flashinfer")]
         let mut prefill_tokens = Vec::new();
         #[cfg(feature = "flashinfer")]
         let mut batch_indices_vec = Vec::<u32>::new();
         #[cfg(feature = "flashinfer")]
         let mut positions_vec = Vec::<u32>::new();
-        for group in groups {
-            for seq in Self::ordered_group_sequences(group) {
-                #[cfg(feature = "flashinfer")]
-                ordered_sequences.push(Arc::clone(&seq));
-                let prompt_ids = seq.deref_mut().get_token_ids();
-                sequence_ids.push(seq.deref().get_id());
-                let num_cached_tokens = seq.deref().get_num_cached_tokens();
-                let num_tokens = seq.deref().prefill_chunk_tokens(chunk_size);
-                let effective_context = num_cached_tokens + num_tokens;
-                if effective_context > max_context_len {
-                    max_context_len = effective_context;
-                }
-                #[cfg(feature = "flashinfer")]
-                prefill_tokens.push(num_tokens);
-
-                context_lens.push((num_cached_tokens + num_tokens) as u32);
-
-                let seqlen_q = num_tokens;
-                let use_cached_kv = num_cached_tokens > 0
-                    && ((cfg!(feature = "flash")
-                        || cfg!(feature = "flashattn")
-                        || cfg!(feature = "metal")
-                        || cfg!(feature = "flashinfer"))
-                        || self.scheduler.prefix_cache_enabled());
-                let seqlen_k = if use_cached_kv {
-                    num_cached_tokens + num_tokens
-                } else {
-                    num_tokens
-                };
+        for seq in &ordered_sequences {
+            let prompt_ids = seq.deref_mut().get_token_ids();
+            sequence_ids.push(seq.deref().get_id());
+            let num_cached_tokens = seq.deref().get_num_cached_tokens();
+            let num_tokens = seq.deref().prefill_chunk_tokens(chunk_size);
+            let effective_context = num_cached_tokens + num_tokens;
+            if effective_context > max_context_len {
+                max_context_len = effective_context;
+            }
+            #[cfg(feature = "flashinfer")]
+            prefill_tokens.push(num_tokens);
+
+            context_lens.push((num_cached_tokens + num_tokens) as u32);
+
+            let seqlen_q = num_tokens;
+            let use_cached_kv = num_cached_tokens > 0
+                && ((cfg!(feature = "flash")
+                    || cfg!(feature = "flashattn")
+                    || cfg!(feature = "metal")
+                    || cfg!(feature = "flashinfer"))
+                    || self.scheduler.prefix_cache_enabled());
+            let seqlen_k = if use_cached_kv {
+                num_cached_tokens + num_tokens
+            } else {
+                num_tokens
+            };
 
-                cu_seqlens_q.push(cu_seqlens_q.last().unwrap() + seqlen_q as u32);
-                cu_seqlens_k.push(cu_seqlens_k.last().unwrap() + seqlen_k as u32);
-                max_seqlen_q = std::cmp::max(max_seqlen_q, seqlen_q);
-                max_seqlen_k = std::cmp::max(max_seqlen_k, seqlen_k);
+            cu_seqlens_q.push(cu_seqlens_q.last().unwrap() + seqlen_q as u32);
+            cu_seqlens_k.push(cu_seqlens_k.last().unwrap() + seqlen_k as u32);
+            max_seqlen_q = std::cmp::max(max_seqlen_q, seqlen_q);
+            max_seqlen_k = std::cmp::max(max_seqlen_k, seqlen_k);
 
-                input_ids
-                    .extend(prompt_ids[num_cached_tokens..num_cached_tokens + num_tokens].to_vec());
-                positions.extend(
-                    (num_cached_tokens as i64..(num_cached_tokens + num_tokens) as i64)
+            input_ids
+                .extend(prompt_ids[num_cached_tokens..num_cached_tokens + num_tokens].to_vec());
+            positions.extend(
+                (num_cached_tokens as i64..(num_cached_tokens + num_tokens) as i64)
+                    .collect::<Vec<_>>(),
+            );
+            #[cfg(feature = "flashinfer")]
+            {
+                let batch_idx = (sequence_ids.len() - 1) as u32;
+                batch_indices_vec.extend(std::iter::repeat(batch_idx).take(num_tokens));
+                positions_vec.extend(
+                    (num_cached_tokens as u32..(num_cached_tokens + num_tokens) as u32)
                         .collect::<Vec<_>>(),
                 );
-                #[cfg(feature = "flashinfer")]
-                {
-                    let batch_idx = (sequence_ids.len() - 1) as u32;
-                    batch_indices_vec.extend(std::iter::repeat(batch_idx).take(num_tokens));
-                    positions_vec.extend(
-                        (num_cached_tokens as u32..(num_cached_tokens + num_tokens) as u32)
-                            .collect::<Vec<_>>(),
-                    );
-                }
-                let table = self
-                    .scheduler
-                    .block_engine
-                    .block_tables
-                    .get(&seq.deref().get_id());
-                if table.is_none() {
-                    slot_mapping.extend([_PAD_SLOT_ID].repeat(num_tokens));
-                    continue;
-                }
-                let table = table
-                    .unwrap()
-                    .iter()
-                    .map(|block| block.deref_mut().block_id)
-                    .collect::<Vec<_>>();
+            }
+            let table = self
+                .scheduler
+                .block_engine
+                .block_tables
+                .get(&seq.deref().get_id());
+            if table.is_none() {
+                slot_mapping.extend([_PAD_SLOT_ID].repeat(num_tokens));
+                continue;
+            }
+            let table = table
+                .unwrap()
+                .iter()
+                .map(|block| block.deref_mut().block_id)
+                .collect::<Vec<_>>();
 
-                for i in num_cached_tokens..num_cached_tokens + num_tokens {
-                    let block_number = if i / self.cache_config.block_size >= table.len() {
-                        candle_core::bail!(
-                            "Block table is too small (prompt)! i={} block_size={} table_len={}",
-                            i,
-                            self.cache_config.block_size,
-                            table.len()
-                        );
-                    } else {
-                        table.get(i / self.cache_config.block_size).unwrap()
-                    };
-                    let block_offset = i % self.cache_config.block_size;
-                    let slot = block_number * self.cache_config.block_size + block_offset;
-                    slot_mapping.push(slot as i64);
-                }
+            for i in num_cached_tokens..num_cached_tokens + num_tokens {
+                let block_number = if i / self.cache_config.block_size >= table.len() {
+                    candle_core::bail!(
+                        "Block table is too small (prompt)! i={} block_size={} table_len={}",
+                        i,
+                        self.cache_config.block_size,
+                        table.len()
+                    );
+                } else {
+                    table.get(i / self.cache_config.block_size).unwrap()
+                };
+                let block_offset = i % self.cache_config.block_size;
+                let slot = block_number * self.cache_config.block_size + block_offset;
+                slot_mapping.push(slot as i64);
             }
         }
 
@@ -221,7 +216,7 @@ impl LLMEngine {
 
         let len = context_lens.len();
         let context_lens = Some(Tensor::from_vec(context_lens, len, device)?);
-        let block_tables = Some(self.prepare_block_tables(groups, device)?);
+        let block_tables = Some(self.prepare_block_tables(&ordered_sequences, device)?);
         let cu_seqlens_q_vec = cu_seqlens_q.clone();
         let cu_seqlens_q = Tensor::from_vec(cu_seqlens_q, (q_len,), device)?;
         let cu_seqlens_k = Tensor::from_vec(cu_seqlens_k, (k_len,), device)?;
@@ -388,7 +383,7 @@ impl LLMEngine {
         let mut context_lens = Vec::new();
         let mut block_tables = Vec::new();
         for group in groups {
-            for seq in Self::ordered_group_sequences(group) {
+            for seq in group.get_unfinished_seqs() {
                 sequence_ids.push(seq.deref().get_id());
                 let last_token_id = seq.deref_mut().get_last_token_id();
                 input_ids.push(last_token_id);
@@ -481,7 +476,7 @@ impl LLMEngine {
             let mut indices = Vec::new();
             let mut last_len = Vec::new();
             for group in groups {
-                for seq in Self::ordered_group_sequences(group) {
+                for seq in group.get_unfinished_seqs() {
                     let table = self
                         .scheduler
                         .block_engine
@@ -1,9 +1,15 @@
 use super::DefaultPipeline;
+#[path = "beam_search.rs"]
+mod beam_search;
+#[path = "health.rs"]
+mod health;
 #[path = "inputs.rs"]
 mod inputs;
 #[cfg(feature = "nccl")]
 #[path = "multiprocess.rs"]
 mod multiprocess;
+#[path = "speculative.rs"]
+mod speculative;
 #[path = "streaming.rs"]
 mod streaming;
 #[path = "threaded.rs"]
@@ -16,18 +22,20 @@ const HYBRID_MAMBA_MIN_ACTIVE_SLOTS: usize = 4;
 
 #[cfg(feature = "nccl")]
 use crate::openai::communicator::{DaemonManager, MessageType};
+use crate::openai::metrics::EngineMetrics;
 use crate::openai::models::linear::set_linear_is_prefill;
 use crate::openai::pipelines::TokenOrFinishReason;
 use crate::openai::streaming::ChatResponse;
+use crate::openai::structured_output::{GuidedDecoder, ToolCallEnvelope, GUIDED_DECODING_FAILED};
 use crate::openai::TaskData;
-use crate::scheduler::Scheduler;
+use crate::scheduler::{Scheduler, QUEUE_TIMEOUT};
 use crate::tools::helpers::{
     build_invalid_tool_call_feedback, build_tool_schema_map, filter_tool_calls, log_tool_calls,
     retain_tool_calls_forced_name,
 };
 use crate::tools::stream_parser::{
     extract_reasoning_content, strip_reasoning_markers, BufferedFinalizeResult, ParserState,
-    StreamResult, StreamToolParser,
+    StreamResult, StreamToolParser, ToolModelType,
 };
 #[cfg(feature = "flashinfer")]
 use crate::FlashInferKvParams;
@@ -39,9 +47,10 @@ use crate::{
         multimodal::ImageData,
         multimodal::ImageProcessConfig,
         responses::{
-            ChatChoice, ChatChoiceData, ChatCompletionChunk, ChatCompletionUsageResponse, Choice,
-            ChoiceData, CompletionTokensDetails, EmbeddingData, EmbeddingOutput, EmbeddingResponse,
-            EmbeddingUsage, PromptTokensDetails, WrapperLogprobs,
+            APIError, ChatChoice, ChatChoiceData, ChatCompletionChunk, ChatCompletionUsageResponse,
+            Choice, ChoiceData, CompletionTokensDetails, CudaGraphStatus, EmbeddingData,
+            EmbeddingOutput, EmbeddingResponse, EmbeddingUsage, ErrorKind, PromptTokensDetails,
+            WrapperLogprobs,
         },
         sampling_params::Logprobs,
         sampling_params::SamplingParams,
@@ -83,6 +92,7 @@ pub struct StreamEmission {
     content: Option<String>,
     reasoning_content: Option<String>,
     tool_calls: Option<Vec<crate::tools::ToolCall>>,
+    logprobs: Option<Logprobs>,
 }
 
 pub struct BatchExecution {
@@ -108,10 +118,21 @@ pub struct LLMEngine {
     pub sync_notifies: HashMap<String, Option<Arc<Notify>>>,
     pub senders: HashMap<String, Option<Arc<Sender<ChatResponse>>>>,
     pub completion_records: HashMap<String, (Vec<ChatChoice>, ChatCompletionUsageResponse)>,
+    /// Requests that failed without a response, with the error for their
+    /// sync waiters.
+    pub rejected_requests: HashMap<String, APIError>,
+    /// Groups already answered with an error mid-generation, which the
+    /// normal finish path must skip.
+    failed_groups: HashSet<usize>,
     sequence_groups: RwLock<VecDeque<Arc<SequenceGroup>>>,
     multi_process: bool,
     num_shards: usize,
     waiting_tasks: RwLock<Vec<TaskData>>,
+    /// Requests cancelled while still in `waiting_tasks`.
+    pending_cancellations: HashSet<String>,
+    /// Engine request ids of each multi-prompt completion, by its client
+    /// request id.
+    sub_requests: HashMap<String, Vec<String>>,
     #[cfg(feature = "nccl")]
     pub daemon_manager: RwLock<Option<DaemonManager>>,
     prefill_chunk_size: Option<usize>,
@@ -122,7 +143,16 @@ pub struct LLMEngine {
     tokenizer: tokenizers::Tokenizer,
     conversation: DefaultConversation,
     image_config: Option<ImageProcessConfig>,
+    tool_call_envelope: Option<ToolCallEnvelope>,
     multiprocess_mtp_hidden: Option<Tensor>,
+    /// Small model proposing tokens for the target to verify (`--draft-model`).
+    draft: Option<speculative::DraftModel>,
+    /// N-gram proposals from the sequence itself (`--prompt-lookup`).
+    prompt_lookup: Option<speculative::PromptLookup>,
+    system_fingerprint: String,
+    pub metrics: EngineMetrics,
+    cuda_graph: CudaGraphStatus,
+    last_step_time: Option<SystemTime>,
 }
 
 impl LLMEngine {
@@ -265,17 +295,29 @@ impl LLMEngine {
             .expect("sequence group must contain at least one sequence")
     }
 
+    /// Whether a decode batch may use MTP, draft or prompt-lookup speculation.
+    /// Speculative steps emit accepted tokens without real logprobs, and
+    /// grammar masks depend on every accepted token, so those requests take
+    /// the normal path.
+    fn can_speculate(scheduled: &VecDeque<Arc<SequenceGroup>>) -> bool {
+        scheduled.len() == 1
+            && !scheduled[0].is_embedding
+            && !scheduled[0].use_logprobs
+            && scheduled[0].sampling_params.best_of == 1
+            && scheduled[0].sampling_params.mcp_mode.is_none()
+            && scheduled[0].sampling_params.guided_decoding.is_none()
+    }
+
     fn disconnected_stream_sequence_ids(scheduled: &VecDeque<Arc<SequenceGroup>>) -> Vec<usize> {
         scheduled
             .iter()
-            .filter_map(|group| {
-                let sender = group.sender.as_ref()?;
-                if sender.is_closed() {
-                    Some(Self::primary_sequence(group).deref().get_id())
-                } else {
-                    None
-                }
+            .filter(|group| {
+                group
+                    .sender
+                    .as_ref()
+                    .is_some_and(|sender| sender.is_closed())
             })
+            .flat_map(|group| group.get_seqs().keys().copied().collect::<Vec<_>>())
             .collect()
     }
 
@@ -500,11 +542,13 @@ impl LLMEngine {
         }
         captured.retain(|c| self.broadcast_mamba_prefix_capture(c.seq_id, c.hash, true));
         self.scheduler.record_mamba_prefix_captures(captured);
+        let live_groups = self.scheduler.group_ids();
         {
             let pipeline = self.get_mut_pipeline(rank).unwrap().0.as_mut();
             for seq_id in sync.released_ids {
                 pipeline.release_sequence_state(seq_id);
             }
+            pipeline.retain_seeded_streams(|group_id| live_groups.contains(&group_id));
         }
     }
 
@@ -829,7 +873,7 @@ impl LLMEngine {
         }
 
         let num_threads: usize = pipelines.len();
-        let (model_name, tokenizer, conversation, image_config) = {
+        let (model_name, tokenizer, conversation, image_config, tool_call_envelope) = {
             let (pipeline, _) = pipelines
                 .values()
                 .next()
@@ -839,8 +883,11 @@ impl LLMEngine {
                 pipeline.tokenizer.clone(),
                 pipeline.conversation.clone(),
                 pipeline.image_config.clone(),
+                pipeline.tool_call_envelope.clone(),
             )
         };
+        let system_fingerprint =
+            Self::compute_system_fingerprint(&model_name, model_dtype, cache_config, num_shards);
         let engine = Arc::new(RwLock::new(Self {
             pipelines,
             scheduler: Scheduler::new(
@@ -855,10 +902,14 @@ impl LLMEngine {
             group_id: 0,
             notify: notify.clone(),
             completion_records: HashMap::new(),
+            rejected_requests: HashMap::new(),
+            failed_groups: HashSet::new(),
             sequence_groups: RwLock::new(VecDeque::new()),
             multi_process,
             num_shards,
             waiting_tasks: RwLock::new(Vec::<TaskData>::new()),
+            pending_cancellations: HashSet::new(),
+            sub_requests: HashMap::new(),
             #[cfg(feature = "nccl")]
             daemon_manager: RwLock::new(daemon_manager),
             sync_notifies: HashMap::new(),
@@ -871,7 +922,14 @@ impl LLMEngine {
             tokenizer,
             conversation,
             image_config,
+            tool_call_envelope,
             multiprocess_mtp_hidden: None,
+            draft: None,
+            prompt_lookup: None,
+            system_fingerprint,
+            metrics: EngineMetrics::default(),
+            cuda_graph: Self::initial_cuda_graph_status(disable_cuda_graph),
+            last_step_time: None,
         }));
         {
             let mut e = engine.write();
@@ -901,7 +959,11 @@ impl LLMEngine {
                     } else {
                         let graph_capture_result = {
                             let mut e = engine.write();
-                            e.graph_capture_all_pipelines()
+                            let result = e.graph_capture_all_pipelines();
+                            if result.is_ok() {
+                                e.cuda_graph = CudaGraphStatus::Captured;
+                            }
+                            result
                         };
                         let _ = graph_capture_tx.send(
                             graph_capture_result
@@ -1082,6 +1144,9 @@ impl LLMEngine {
             if let Some(replay_ids) = self.match_prompt_replay_candidate(&task.prompt) {
                 seq_group.prompt_replay_token_ids = Some(replay_ids);
             }
+            if self.pending_cancellations.remove(&task.request_id) {
+                seq_group.cancel();
+            }
             tracing::debug!("Main process: add_sequence to group {}", task.group_id);
             self.scheduler.add_sequence(seq_group);
         }
@@ -1090,6 +1155,63 @@ impl LLMEngine {
         active_tasks
     }
 
+    /// Record the engine requests a multi-prompt completion was split into,
+    /// so that cancelling `request_id` cancels all of them.
+    pub fn add_sub_requests(&mut self, request_id: String, sub_request_ids: Vec<String>) {
+        self.sub_requests.insert(request_id, sub_request_ids);
+    }
+
+    /// Cancel a queued or running request, with the sub-requests of a
+    /// multi-prompt completion. Returns whether anything matched.
+    pub fn cancel_request(&mut self, request_id: &str) -> bool {
+        let sub_requests = self.sub_requests.remove(request_id).unwrap_or_default();
+        let matches = |id: &str| id == request_id || sub_requests.iter().any(|sub| sub == id);
+        let waiting = self
+            .waiting_tasks
+            .read()
+            .iter()
+            .filter(|task| matches(&task.request_id))
+            .map(|task| task.request_id.clone())
+            .collect::<Vec<_>>();
+        let found_waiting = !waiting.is_empty();
+        self.pending_cancellations.extend(waiting);
+        let found_scheduled = self.scheduler.cancel_requests(matches);
+        found_waiting || found_scheduled
+    }
+
+    /// Refuse a new request of `prompt_tokens` tokens when the queue of
+    /// requests waiting for prefill is at its configured limits.
+    pub fn check_admission(&self, prompt_tokens: usize) -> std::result::Result<(), String> {
+        let (requests, tokens) = self
+            .waiting_tasks
+            .read()
+            .iter()
+            .fold((0, 0), |(requests, tokens), task| {
+                (requests + 1, tokens + task.prompt.len())
+            });
+        match self
+            .scheduler
+            .admission_rejection(requests, tokens, prompt_tokens)
+        {
+            Some(message) => {
+                warn!("Rejecting request: {message}");
+                self.metrics
+                    .requests_rejected_queue_full
+                    .fetch_add(1, Ordering::Relaxed);
+                Err(message)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Whether `request_id` was already submitted, by itself, as the base of
+    /// a multi-prompt completion or as one of its sub-requests.
+    pub fn request_id_in_use(&self, request_id: &str) -> bool {
+        self.senders.contains_key(request_id)
+            || self.sync_notifies.contains_key(request_id)
+            || self.sub_requests.contains_key(request_id)
+    }
+
     pub fn get_pipeline(&self, rank: usize) -> Option<&(Box<DefaultPipeline>, CacheEngine)> {
         self.pipelines.get(&rank)
     }
@@ -1117,6 +1239,41 @@ impl LLMEngine {
         self.image_config.clone()
     }
 
+    /// Identifier of the serving configuration. Seeded sampling is only
+    /// reproducible between responses that report the same fingerprint.
+    /// Layout of a forced tool call, or `None` when the model's tool-call
+    /// format cannot be constrained.
+    pub fn tool_call_envelope(&self) -> Option<&ToolCallEnvelope> {
+        self.tool_call_envelope.as_ref()
+    }
+
+    pub fn system_fingerprint(&self) -> &str {
+        &self.system_fingerprint
+    }
+
+    /// Engine and scheduler metrics in the Prometheus text format.
+    pub fn render_metrics(&self) -> String {
+        self.metrics.render(&self.scheduler.metrics())
+    }
+
+    fn compute_system_fingerprint(
+        model_name: &str,
+        dtype: candle_core::DType,
+        cache_config: &CacheConfig,
+        num_shards: usize,
+    ) -> String {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        env!("CARGO_PKG_VERSION").hash(&mut hasher);
+        model_name.hash(&mut hasher);
+        format!("{:?}", dtype).hash(&mut hasher);
+        format!("{:?}", cache_config.kvcache_dtype).hash(&mut hasher);
+        cache_config.block_size.hash(&mut hasher);
+        num_shards.hash(&mut hasher);
+        cfg!(feature = "cuda").hash(&mut hasher);
+        format!("fp_{:010x}", hasher.finish() & 0xff_ffff_ffff)
+    }
+
     /// Build prompt-replay candidates from the chat template.  Each candidate
     /// is a token-ID suffix (e.g. the IDs for `<think>\n`) that the template
     /// appends after the assistant header when `add_generation_prompt` is true.
@@ -1386,6 +1543,12 @@ impl LLMEngine {
 
         match result {
             Ok(()) => {
+                if !scheduler_output.blocks_to_swap_in.is_empty()
+                    || !scheduler_output.blocks_to_swap_out.is_empty()
+                    || !scheduler_output.blocks_to_copy.is_empty()
+                {
+                    self.invalidate_draft_cache();
+                }
                 for group_id in &scheduler_output.swap_in_groups {
                     self.scheduler.block_engine.finalize_swap_in(*group_id);
                 }
@@ -1616,16 +1779,20 @@ impl LLMEngine {
     }
 
     fn schedule_current_batch(&mut self, rank: usize) -> Result<()> {
+        self.last_step_time = Some(SystemTime::now());
         self.validate_mamba_prefix_hashes_before_schedule(rank);
         let scheduler_outputs = self.scheduler.schedule();
         let pending_runner_releases = self.scheduler.take_pending_runner_releases();
         self.release_sequence_states(&pending_runner_releases);
+        self.finish_stopped_groups(rank);
         if !scheduler_outputs.ignored_seq_groups.is_empty() {
             for group in scheduler_outputs.ignored_seq_groups.iter() {
                 if let Some(sender) = &group.sender {
                     let _ = sender.try_send(ChatResponse::ModelError(
-                        candle_core::Error::msg("Ignored sequence group: allocation impossible")
-                            .to_string(),
+                        APIError::new_str(
+                            "Ignored sequence group: the prompt cannot fit in the KV cache",
+                        )
+                        .with_kind(ErrorKind::ContextLengthExceeded),
                     ));
                 }
             }
@@ -1645,16 +1812,22 @@ impl LLMEngine {
         }
         self.execute_scheduler_ops(&scheduler_outputs, rank)?;
         if let Some((pipeline, _)) = self.get_pipeline(rank) {
-            if pipeline.has_mtp() && scheduler_outputs.scheduled.len() == 1 {
+            if Self::can_speculate(&scheduler_outputs.scheduled) {
                 let group = scheduler_outputs.scheduled.front().unwrap();
                 let seq = Self::primary_sequence(group);
-                if !seq.deref().is_prompt() {
-                    self.scheduler
-                        .block_engine
-                        .reserve_additional_token_slots_for_seq(
-                            &seq,
-                            pipeline.mtp_num_speculative + 1,
-                        );
+                let num_speculative = if pipeline.has_mtp() {
+                    Some(pipeline.mtp_num_speculative)
+                } else {
+                    let seq_len = seq.deref().get_len();
+                    self.draft_tokens_for(seq_len)
+                        .or_else(|| self.prompt_lookup_tokens_for(seq_len))
+                };
+                if let Some(num_speculative) = num_speculative {
+                    if !seq.deref().is_prompt() {
+                        self.scheduler
+                            .block_engine
+                            .reserve_additional_token_slots_for_seq(&seq, num_speculative + 1);
+                    }
                 }
             }
         }
@@ -1666,6 +1839,77 @@ impl LLMEngine {
         Ok(())
     }
 
+    /// Finish groups the scheduler stopped early (cancelled or timed out):
+    /// flush pending stream output, then answer them like any finished group.
+    fn finish_stopped_groups(&mut self, rank: usize) {
+        let stopped = self.scheduler.take_stopped_groups();
+        if stopped.is_empty() {
+            return;
+        }
+        let mut prompt_finish_times = HashMap::new();
+        let mut finished = VecDeque::new();
+        for (group, reason) in stopped {
+            if reason == QUEUE_TIMEOUT {
+                self.reject_queue_timeout(&group);
+                continue;
+            }
+            for seq in group.get_seqs().values() {
+                if seq.deref().is_finished() {
+                    continue;
+                }
+                if let Some(sender) = &group.sender {
+                    let emission = self.collect_stream_emission_on_finish(rank, group, seq);
+                    self.send_stream_emission(
+                        rank,
+                        sender,
+                        group,
+                        seq,
+                        emission,
+                        Some(reason.to_string()),
+                    );
+                }
+                seq.deref_mut().set_finish_reason(reason.to_string());
+            }
+            prompt_finish_times.insert(*group.get_id(), group.created_time);
+            finished.push_back(group);
+        }
+        self.collect_finished_responses(&finished, &mut HashMap::new(), &prompt_finish_times, true);
+    }
+
+    /// Fail a request that was never prefilled within the queue timeout.
+    fn reject_queue_timeout(&mut self, group: &SequenceGroup) {
+        let waited = SystemTime::now()
+            .duration_since(group.created_time)
+            .unwrap_or_default();
+        let message = format!(
+            "Request {} waited {:.1}s in the queue without being scheduled.",
+            group.request_id,
+            waited.as_secs_f64()
+        );
+        warn!("{message}");
+        self.metrics
+            .requests_rejected_queue_timeout
+            .fetch_add(1, Ordering::Relaxed);
+        let error = APIError::new(message)
+            .with_kind(ErrorKind::RateLimited)
+            .with_code(QUEUE_TIMEOUT);
+        self.send_request_error(group, error);
+    }
+
+    /// Answer a request with `error` instead of a completion.
+    fn send_request_error(&mut self, group: &SequenceGroup, error: APIError) {
+        if let Some(sender) = &group.sender {
+            let _ = sender.try_send(ChatResponse::ModelError(error));
+            let _ = sender.try_send(ChatResponse::Done);
+        } else {
+            self.rejected_requests
+                .insert(group.request_id.clone(), error);
+            if let Some(Some(notify)) = self.sync_notifies.get(&group.request_id) {
+                notify.notify_one();
+            }
+        }
+    }
+
     fn current_scheduled_groups(&self) -> VecDeque<Arc<SequenceGroup>> {
         self.sequence_groups.read().clone()
     }
@@ -1681,14 +1925,22 @@ impl LLMEngine {
         }
 
         let message = format!("Generation failed: {err:?}");
+        // Running out of device memory is transient; report it as unavailable.
+        let kind = if message.to_lowercase().contains("out of memory") {
+            ErrorKind::Unavailable
+        } else {
+            ErrorKind::Internal
+        };
         let seq_ids = scheduled
             .iter()
-            .map(|group| Self::primary_sequence(group).deref().get_id())
+            .flat_map(|group| group.get_seqs().keys().copied().collect::<Vec<_>>())
             .collect::<Vec<_>>();
 
         for group in &scheduled {
             if let Some(sender) = &group.sender {
-                let _ = sender.try_send(ChatResponse::ModelError(message.clone()));
+                let _ = sender.try_send(ChatResponse::ModelError(
+                    APIError::new(message.clone()).with_kind(kind),
+                ));
                 let _ = sender.try_send(ChatResponse::Done);
             }
         }
@@ -1842,6 +2094,8 @@ impl LLMEngine {
             is_embedding,
             model_name,
             mtp_context,
+            draft_step,
+            lookup_step,
         ) = {
             let mut guard = engine.write();
             let is_embedding = scheduled[0].is_embedding;
@@ -1854,11 +2108,18 @@ impl LLMEngine {
             let (pipeline, _) = guard.get_pipeline(rank).unwrap();
             let device = pipeline.device();
             let model_name = pipeline.name().to_string();
-            let use_mtp = pipeline.has_mtp()
-                && !is_prompt_request
-                && !is_embedding
-                && scheduled.len() == 1
-                && scheduled[0].sampling_params.mcp_mode.is_none();
+            let speculate = !is_prompt_request && Self::can_speculate(scheduled);
+            let use_mtp = speculate && pipeline.has_mtp();
+            let seq_len = Self::primary_sequence(&scheduled[0]).deref().get_len();
+            let draft_tokens = if speculate && !use_mtp {
+                guard.draft_tokens_for(seq_len)
+            } else {
+                None
+            };
+            let use_lookup = speculate
+                && !use_mtp
+                && draft_tokens.is_none()
+                && guard.prompt_lookup_tokens_for(seq_len).is_some();
             #[cfg_attr(not(feature = "flashinfer"), allow(unused_mut))]
             let mut prepared = if is_prompt_request {
                 guard.prepare_prompt(scheduled, device, rank)
@@ -1888,20 +2149,11 @@ impl LLMEngine {
                 metadata,
             } = prepared;
 
-            let mtp_context = if use_mtp {
+            let (mtp_context, draft_step) = if use_mtp || draft_tokens.is_some() {
                 let seq = Self::primary_sequence(&scheduled[0]);
                 let seq_id = seq.deref().get_id();
-                let seq_len = seq.deref().get_len();
-                let block_table = guard
-                    .scheduler
-                    .block_engine
-                    .block_tables
-                    .get(&seq_id)
-                    .ok_or_else(|| candle_core::Error::msg("missing MTP block table"))?
-                    .iter()
-                    .map(|block| block.deref_mut().block_id as u32)
-                    .collect::<Vec<_>>();
-                let verify_len = pipeline.mtp_num_speculative + 1;
+                let block_table = guard.speculative_block_table(seq_id)?;
+                let verify_len = draft_tokens.unwrap_or(pipeline.mtp_num_speculative) + 1;
                 let slot_mappings = Self::compute_mtp_slot_mappings(
                     &block_table,
                     seq_len,
@@ -1924,7 +2176,27 @@ impl LLMEngine {
                     slot_mappings,
                     verify_len,
                 )?;
-                Some((seq_id, seq_len, verify_positions, verify_metadata))
+                if use_mtp {
+                    (
+                        Some((seq_id, seq_len, verify_positions, verify_metadata)),
+                        None,
+                    )
+                } else {
+                    let step = guard.prepare_draft_step(
+                        device,
+                        &seq,
+                        &block_table,
+                        verify_positions,
+                        verify_metadata,
+                    )?;
+                    (None, Some(step))
+                }
+            } else {
+                (None, None)
+            };
+            let lookup_step = if use_lookup {
+                let seq = Self::primary_sequence(&scheduled[0]);
+                Some(guard.prepare_lookup_step(rank, device, &seq)?)
             } else {
                 None
             };
@@ -1964,6 +2236,10 @@ impl LLMEngine {
             let pipeline_entry = guard.pipelines.remove(&rank).ok_or_else(|| {
                 candle_core::Error::msg(format!("missing pipeline for rank {rank}"))
             })?;
+            let draft_step = match draft_step {
+                Some(step) => guard.take_draft().map(|draft| (draft, step)),
+                None => None,
+            };
             (
                 pipeline_entry,
                 tokens,
@@ -1973,14 +2249,44 @@ impl LLMEngine {
                 is_embedding,
                 model_name,
                 mtp_context,
+                draft_step,
+                lookup_step,
             )
         };
 
         let mut pipeline_entry = pipeline_entry;
         let (pipeline, cache_engine) = (pipeline_entry.0.as_mut(), &pipeline_entry.1);
         let mut mtp_results = None;
+        let mut draft = None;
         let run_result: Result<Tensor> = (|| {
-            if let Some((seq_id, seq_len, verify_positions, verify_metadata)) = mtp_context {
+            if let Some((mut draft_model, step)) = draft_step {
+                let result = Self::run_draft_step(
+                    pipeline,
+                    cache_engine,
+                    &mut draft_model,
+                    scheduled,
+                    tokens,
+                    &positions,
+                    &metadata,
+                    step,
+                );
+                draft = Some(draft_model);
+                let (logits, results) = result?;
+                mtp_results = Some(results);
+                Ok(logits)
+            } else if let Some(step) = lookup_step {
+                let (logits, results) = Self::run_lookup_step(
+                    pipeline,
+                    cache_engine,
+                    scheduled,
+                    tokens,
+                    &positions,
+                    &metadata,
+                    step,
+                )?;
+                mtp_results = Some(results);
+                Ok(logits)
+            } else if let Some((seq_id, seq_len, verify_positions, verify_metadata)) = mtp_context {
                 let logits = pipeline.forward(
                     tokens,
                     &positions,
@@ -2052,11 +2358,12 @@ impl LLMEngine {
                                     None,
                                 )
                             }?;
-                            let verify_result =
-                                crate::openai::models::qwen3_5_mtp::verify_draft_greedy(
-                                    &all_logits,
-                                    &draft_tokens,
-                                )?;
+                            let verify_result = pipeline.verify_speculative(
+                                &all_logits,
+                                &scheduled[0],
+                                anchor_token,
+                                &draft_tokens,
+                            )?;
                             if verify_result.num_accepted < verify_result.num_proposed {
                                 let commit_len = 1 + verify_result.num_accepted;
                                 let restored = pipeline.mtp_rollback_mamba(seq_id, commit_len)?;
@@ -2069,28 +2376,13 @@ impl LLMEngine {
                                 }
                             }
 
-                            let mut results = anchor_result;
-                            let mut extra_tokens = verify_result.accepted_tokens.clone();
-                            extra_tokens.push(verify_result.continuation_token);
-
-                            let seq = Self::primary_sequence(scheduled.front().unwrap());
-                            let generated_before_anchor = seq
-                                .deref()
-                                .get_len()
-                                .saturating_sub(seq.deref().get_prompt_len());
-                            let remaining_after_anchor = scheduled
-                                .front()
-                                .unwrap()
-                                .sampling_params
-                                .max_tokens
-                                .saturating_sub(generated_before_anchor + 1);
-                            extra_tokens.truncate(remaining_after_anchor);
-
-                            if !extra_tokens.is_empty() {
-                                let mut extra_results =
-                                    pipeline.tokens_to_results(&extra_tokens, scheduled)?;
-                                results.append(&mut extra_results);
-                            }
+                            let results = Self::speculative_results(
+                                pipeline,
+                                scheduled,
+                                anchor_result,
+                                &verify_result.accepted_tokens,
+                                verify_result.continuation_token,
+                            )?;
                             crate::openai::models::qwen3_5_mtp::mtp_stats_update(
                                 verify_result.num_proposed,
                                 verify_result.num_accepted,
@@ -2103,7 +2395,7 @@ impl LLMEngine {
                                     mtp_steps,
                                     verify_result.num_proposed,
                                     verify_result.num_accepted,
-                                    extra_tokens.len(),
+                                    results.len().saturating_sub(1),
                                     crate::openai::models::qwen3_5_mtp::mtp_stats_summary()
                                 );
                             }
@@ -2141,6 +2433,9 @@ impl LLMEngine {
         if guard.pipelines.insert(rank, pipeline_entry).is_some() {
             candle_core::bail!("pipeline for rank {rank} was replaced while detached");
         }
+        if let Some(draft) = draft {
+            guard.restore_draft(draft);
+        }
         let logits = run_result?;
 
         Ok(BatchExecution {
@@ -2200,6 +2495,9 @@ impl LLMEngine {
                 }
             };
 
+            if let Some(key) = &group.sampling_params.api_key {
+                self.metrics.record_key_usage(key, prompt_len, 0);
+            }
             if let Some(sender) = &group.sender {
                 let response = EmbeddingResponse {
                     object: "list",
@@ -2266,80 +2564,186 @@ impl LLMEngine {
         results: Vec<TokenOrFinishReason>,
         prompt_finish_times: &mut HashMap<usize, SystemTime>,
     ) -> Result<()> {
-        if results.len() != scheduled.len() {
+        // One result per unfinished sequence, in group order and then by id.
+        let num_rows = scheduled
+            .iter()
+            .map(|group| group.get_unfinished_seqs().len())
+            .sum::<usize>();
+        if results.len() != num_rows {
             candle_core::bail!(
-                "Sample result and scheduled group length mismatch on rank {}: {} vs {}",
+                "Sample result and scheduled sequence length mismatch on rank {}: {} vs {}",
                 rank,
                 results.len(),
-                scheduled.len()
+                num_rows
             );
         }
 
-        for (result_, group) in zip(results, scheduled) {
-            match result_ {
-                Either::Left(logprobs) => {
-                    let seq = Self::primary_sequence(group);
-                    if seq.deref().is_prompt() {
-                        self.scheduler.print_free_blocks();
-                        let prompt_finish_time = SystemTime::now();
-                        prompt_finish_times.insert(*group.get_id(), prompt_finish_time);
-
-                        #[cfg(feature = "nccl")]
-                        let do_log = DaemonManager::is_master_rank();
-                        #[cfg(not(feature = "nccl"))]
-                        let do_log = true;
-                        if do_log {
-                            let prompt_time_costs = prompt_finish_time
-                                .duration_since(group.created_time)
-                                .unwrap()
-                                .as_millis();
-                            if prompt_time_costs > 0 {
-                                warn!(
-                                    "Prefilling {} tokens finished in {} seconds ({} tokens/s) ({})",
-                                    seq.deref().get_prompt_len(),
-                                    prompt_time_costs / 1000,
-                                    seq.deref().get_prompt_len() * 1000 / prompt_time_costs as usize,
-                                    group.request_id,
-                                );
-                            }
-                        }
+        let mut results = results.into_iter();
+        for group in scheduled {
+            let seqs = group.get_unfinished_seqs();
+            let group_results = results.by_ref().take(seqs.len()).collect::<Vec<_>>();
+            if seqs.iter().any(|seq| {
+                seq.deref()
+                    .deref()
+                    .guided_decoder
+                    .as_ref()
+                    .is_some_and(GuidedDecoder::failed)
+            }) {
+                self.fail_guided_decoding(group);
+                continue;
+            }
+            let is_prompt = seqs.first().is_some_and(|seq| seq.deref().is_prompt());
+            if is_prompt {
+                self.record_prompt_finish(group, prompt_finish_times);
+            }
+            // Every rank samples the same tokens; only count them once.
+            if rank == 0 {
+                self.record_token_metrics(group, &group_results, is_prompt);
+            }
+            if group.sampling_params.use_beam_search {
+                self.apply_beam_search_step(rank, group, seqs, group_results);
+                continue;
+            }
+            for (result_, seq) in zip(group_results, seqs) {
+                self.apply_sample_result(rank, group, seq, result_);
+            }
+        }
+        Ok(())
+    }
+
+    /// Fail a request whose output left its `response_format` or forced
+    /// tool-call grammar, rather than returning text that may not parse. The
+    /// group leaves the scheduler here and skips the normal finish path.
+    fn fail_guided_decoding(&mut self, group: &Arc<SequenceGroup>) {
+        let message = format!(
+            "Request {}: the generated output no longer matches the requested format.",
+            group.request_id
+        );
+        warn!("{message}");
+        let error = APIError::new(message)
+            .with_kind(ErrorKind::Internal)
+            .with_code(GUIDED_DECODING_FAILED);
+        self.send_request_error(group, error);
+        let seq_ids = group.get_seqs().keys().copied().collect::<Vec<_>>();
+        let aborted = self.scheduler.abort_sequences(&seq_ids);
+        self.release_sequence_states(&aborted);
+        self.failed_groups.insert(*group.get_id());
+    }
+
+    fn record_prompt_finish(
+        &self,
+        group: &Arc<SequenceGroup>,
+        prompt_finish_times: &mut HashMap<usize, SystemTime>,
+    ) {
+        self.scheduler.print_free_blocks();
+        let prompt_finish_time = SystemTime::now();
+        prompt_finish_times.insert(*group.get_id(), prompt_finish_time);
+
+        #[cfg(feature = "nccl")]
+        let do_log = DaemonManager::is_master_rank();
+        #[cfg(not(feature = "nccl"))]
+        let do_log = true;
+        if do_log {
+            let prompt_time_costs = prompt_finish_time
+                .duration_since(group.created_time)
+                .unwrap()
+                .as_millis();
+            if prompt_time_costs > 0 {
+                let prompt_len = group.get_primary_seq().deref().get_prompt_len();
+                warn!(
+                    "Prefilling {} tokens finished in {} seconds ({} tokens/s) ({})",
+                    prompt_len,
+                    prompt_time_costs / 1000,
+                    prompt_len * 1000 / prompt_time_costs as usize,
+                    group.request_id,
+                );
+            }
+        }
+    }
+
+    fn record_token_metrics(
+        &self,
+        group: &Arc<SequenceGroup>,
+        results: &[TokenOrFinishReason],
+        is_prompt: bool,
+    ) {
+        let num_tokens = results.iter().filter(|result| result.is_left()).count();
+        if num_tokens == 0 {
+            return;
+        }
+        self.metrics
+            .generation_tokens
+            .fetch_add(num_tokens as u64, Ordering::Relaxed);
+        let now = SystemTime::now();
+        if is_prompt {
+            let prompt_len = group.get_primary_seq().deref().get_prompt_len();
+            self.metrics
+                .prompt_tokens
+                .fetch_add(prompt_len as u64, Ordering::Relaxed);
+            if let Ok(elapsed) = now.duration_since(group.created_time) {
+                self.metrics
+                    .time_to_first_token
+                    .observe(elapsed.as_secs_f64());
+            }
+        }
+        let mut last_token_time = group
+            .last_token_time
+            .write()
+            .unwrap_or_else(|e| e.into_inner());
+        if let Some(elapsed) = (*last_token_time).and_then(|last| now.duration_since(last).ok()) {
+            self.metrics
+                .inter_token_latency
+                .observe(elapsed.as_secs_f64());
+        }
+        *last_token_time = Some(now);
+    }
+
+    fn apply_sample_result(
+        &mut self,
+        rank: usize,
+        group: &Arc<SequenceGroup>,
+        seq: Arc<Sequence>,
+        result_: TokenOrFinishReason,
+    ) {
+        match result_ {
+            Either::Left(logprobs) => {
+                if let Some(sender) = &group.sender {
+                    let mut emission =
+                        self.collect_stream_emission_for_token(rank, group, &seq, &logprobs);
+                    if group.use_logprobs {
+                        emission.logprobs = Some(logprobs.clone());
                     }
-                    if let Some(sender) = &group.sender {
-                        let emission =
-                            self.collect_stream_emission_for_token(rank, group, &seq, &logprobs);
-                        if emission.tool_calls.is_some()
-                            || emission.content.is_some()
-                            || emission.reasoning_content.is_some()
-                        {
-                            self.send_stream_emission(rank, sender, group, &seq, emission, None);
-                        }
+                    if emission.tool_calls.is_some()
+                        || emission.content.is_some()
+                        || emission.reasoning_content.is_some()
+                        || emission.logprobs.is_some()
+                    {
+                        self.send_stream_emission(rank, sender, group, &seq, emission, None);
                     }
-                    seq.deref_mut().add_token(logprobs);
                 }
-                Either::Right(finish_reason) => {
-                    let seq = Self::primary_sequence(group);
-                    self.apply_pending_finish_logprobs(rank, group, &seq);
-                    if let Some(sender) = &group.sender {
-                        let emission = self.collect_stream_emission_on_finish(rank, group, &seq);
-                        // When emission contains tool_calls, send_stream_emission
-                        // handles the two-chunk protocol (tool delta + finish) and
-                        // ignores this finish_reason.  For the non-tool branch the
-                        // sampler's finish_reason is forwarded as-is ("stop",
-                        // "length", etc.).
-                        self.send_stream_emission(
-                            rank,
-                            sender,
-                            group,
-                            &seq,
-                            emission,
-                            Some(finish_reason.clone()),
-                        );
-                    }
-                    seq.deref_mut().set_finish_reason(finish_reason);
+                seq.deref_mut().add_token(logprobs);
+            }
+            Either::Right(finish_reason) => {
+                self.apply_pending_finish_logprobs(rank, group, &seq);
+                if let Some(sender) = &group.sender {
+                    let emission = self.collect_stream_emission_on_finish(rank, group, &seq);
+                    // When emission contains tool_calls, send_stream_emission
+                    // handles the two-chunk protocol (tool delta + finish) and
+                    // ignores this finish_reason.  For the non-tool branch the
+                    // sampler's finish_reason is forwarded as-is ("stop",
+                    // "length", etc.).
+                    self.send_stream_emission(
+                        rank,
+                        sender,
+                        group,
+                        &seq,
+                        emission,
+                        Some(finish_reason.clone()),
+                    );
                 }
+                seq.deref_mut().set_finish_reason(finish_reason);
             }
         }
-        Ok(())
     }
 
     fn finalize_post_sampling(
@@ -2366,6 +2770,9 @@ impl LLMEngine {
     ) -> Vec<usize> {
         let mut aborted_sequences = Vec::new();
         for group in scheduled.iter() {
+            if self.failed_groups.remove(group.get_id()) {
+                continue;
+            }
             if group.is_finished() && !responses.contains_key(&group.request_id) {
                 let end_time = SystemTime::now();
                 let prompt_finish_time = prompt_finish_times
@@ -2399,19 +2806,22 @@ impl LLMEngine {
                 }
 
                 let mut seqs = group.get_seqs().values().collect::<Vec<_>>();
-                seqs.sort_by(|seq_a, seq_b| {
-                    let logprob_cmp = seq_b
-                        .deref_mut()
-                        .get_cumulative_logprob()
-                        .partial_cmp(&seq_a.deref_mut().get_cumulative_logprob())
-                        .unwrap_or(std::cmp::Ordering::Equal);
-                    if logprob_cmp == std::cmp::Ordering::Equal {
-                        seq_a.deref().get_id().cmp(&seq_b.deref().get_id())
-                    } else {
-                        logprob_cmp
-                    }
-                });
-                let top_n = seqs.get(0..group.sampling_params.n).unwrap();
+                seqs.sort_by_key(|seq| seq.deref().get_id());
+                // Only `best_of` candidates are ranked; `n` samples keep their
+                // sampling order so streamed and final choice indices agree.
+                // Beam search has already written its hypotheses best first.
+                if group.sampling_params.best_of > group.sampling_params.n
+                    && !group.sampling_params.use_beam_search
+                {
+                    seqs.sort_by(|seq_a, seq_b| {
+                        seq_b
+                            .deref_mut()
+                            .get_cumulative_logprob()
+                            .partial_cmp(&seq_a.deref_mut().get_cumulative_logprob())
+                            .unwrap_or(std::cmp::Ordering::Equal)
+                    });
+                }
+                let top_n = &seqs[..group.sampling_params.n.min(seqs.len())];
 
                 let mut choices = Vec::new();
                 let do_sync_response = allow_sync_response && group.sender.is_none();
@@ -2504,6 +2914,9 @@ impl LLMEngine {
                                 log_tool_calls("Valid", &valid_calls);
                                 (None, Some(valid_calls))
                             }
+                        } else if pipeline.tool_model_type == ToolModelType::GptOss {
+                            // Decoded text is already stripped of harmony framing.
+                            (Some(raw_output.clone()), None)
                         } else {
                             let data = outputs
                                 .iter()
@@ -2594,6 +3007,13 @@ impl LLMEngine {
                     completion_tokens_details: (reasoning_tokens > 0)
                         .then_some(CompletionTokensDetails { reasoning_tokens }),
                 };
+                if let Some(key) = &group.sampling_params.api_key {
+                    self.metrics.record_key_usage(
+                        key,
+                        usage.prompt_tokens,
+                        usage.completion_tokens,
+                    );
+                }
 
                 responses.insert(group.request_id.clone(), (choices, usage.clone()));
 
@@ -2619,6 +3039,7 @@ impl LLMEngine {
                                 let usage_chunk = self.get_stream_response(
                                     group.request_id.clone(),
                                     usage.created,
+                                    0,
                                     None,
                                     None,
                                     None,
@@ -2665,14 +3086,20 @@ impl LLMEngine {
         sender: Option<Sender<ChatResponse>>,
         include_usage: bool,
     ) -> SequenceGroup {
-        let seq = Arc::new(Sequence(std::sync::RwLock::new(_Sequence::new(
-            prompt,
-            seq_id,
-            self.cache_config.block_size,
-            images,
-        ))));
+        // `best_of` sequences with consecutive ids; they share the prompt
+        // blocks and fork on their first generated token.
+        let seqs = (seq_id..seq_id + sampling_params.best_of)
+            .map(|seq_id| {
+                Arc::new(Sequence(std::sync::RwLock::new(_Sequence::new(
+                    prompt,
+                    seq_id,
+                    self.cache_config.block_size,
+                    images.clone(),
+                ))))
+            })
+            .collect::<Vec<_>>();
         SequenceGroup::new(
-            &[seq],
+            &seqs,
             get_created_time_secs(),
             group_id,
             request_id.to_owned(),
@@ -2708,6 +3135,7 @@ impl LLMEngine {
         prefilled_reasoning_end: Option<String>,
     ) {
         let prompt_len = prompt.len();
+        let num_seqs = sampling_params.best_of;
         let sync_notify = sync_notify.clone();
         if let Some(sync) = sync_notify {
             self.sync_notifies.insert(request_id.clone(), Some(sync));
@@ -2749,10 +3177,20 @@ impl LLMEngine {
         };
         let mut waiting_tasks = self.waiting_tasks.write();
         waiting_tasks.push(task);
-        self.seq_id += 1;
+        self.seq_id += num_seqs;
         self.group_id += 1;
     }
 
+    /// Whether requests may fan out into several sequences (`n`/`best_of` > 1).
+    /// Hybrid models keep per-sequence recurrent state that is not forked
+    /// alongside the shared prompt blocks.
+    pub fn supports_parallel_sampling(&self) -> bool {
+        !self
+            .scheduler
+            .block_engine
+            .requires_mamba_prefix_snapshots()
+    }
+
     pub fn get_available_kv_tokens(&self) -> usize {
         self.scheduler.get_available_kv_tokens()
     }
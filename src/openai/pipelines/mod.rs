@@ -156,5 +156,19 @@ where
     }
 }
 
+/// Translates GPT-OSS harmony framing on top of the token decoder.
+struct HarmonyDecodeStream {
+    inner: DecodeStreamType,
+    channels: crate::tools::stream_parser::HarmonyChannels,
+}
+
+impl DecodeStreamTrait for HarmonyDecodeStream {
+    fn step(&mut self, id: u32) -> Option<String> {
+        let text = self.inner.step(id)?;
+        let text = self.channels.push(&text);
+        (!text.is_empty()).then_some(text)
+    }
+}
+
 type DecodeStreamType = Box<dyn DecodeStreamTrait + Send + Sync>;
 type StreamDecoderMap = HashMap<usize, DecodeStreamType>;
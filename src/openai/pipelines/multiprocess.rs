@@ -482,7 +482,6 @@ impl LLMEngine {
     fn daemon_run_mtp_step2(
         engine: &Arc<RwLock<Self>>,
         anchor_token: u32,
-        seq_id: usize,
         seq_len: usize,
         verify_payload: &ForwardPayload,
     ) -> Result<()> {
@@ -497,80 +496,76 @@ impl LLMEngine {
 
         let mut pipeline_entry = pipeline_entry;
         let (pipeline, cache_engine) = (pipeline_entry.0.as_mut(), &pipeline_entry.1);
-        let run_result: Result<Option<crate::openai::models::qwen3_5_mtp::MtpVerifyResult>> =
-            (|| {
-                let mtp_head = pipeline.mtp_head.as_ref().ok_or_else(|| {
-                    candle_core::Error::msg("daemon MTP step2 requires loaded MTP head")
-                })?;
-                let embed_weight = pipeline.mtp_embed_weight()?;
-                let anchor_token_tensor =
-                    Tensor::from_vec(vec![anchor_token], (1,), pipeline.device())?;
-                let (draft_tokens, _) = mtp_head.draft_tokens_gpu(
-                    &seq_hidden,
-                    &anchor_token_tensor,
-                    pipeline.mtp_num_speculative,
-                    &embed_weight,
-                    |hidden| pipeline.mtp_forward_lm_head(hidden),
-                    seq_len.saturating_sub(1),
-                )?;
+        let run_result: Result<()> = (|| {
+            let mtp_head = pipeline.mtp_head.as_ref().ok_or_else(|| {
+                candle_core::Error::msg("daemon MTP step2 requires loaded MTP head")
+            })?;
+            let embed_weight = pipeline.mtp_embed_weight()?;
+            let anchor_token_tensor =
+                Tensor::from_vec(vec![anchor_token], (1,), pipeline.device())?;
+            let (draft_tokens, _) = mtp_head.draft_tokens_gpu(
+                &seq_hidden,
+                &anchor_token_tensor,
+                pipeline.mtp_num_speculative,
+                &embed_weight,
+                |hidden| pipeline.mtp_forward_lm_head(hidden),
+                seq_len.saturating_sub(1),
+            )?;
 
-                if draft_tokens.is_empty() {
-                    return Ok(None);
-                }
+            if draft_tokens.is_empty() {
+                return Ok(());
+            }
 
-                let mut verify_tokens = vec![anchor_token];
-                verify_tokens.extend_from_slice(&draft_tokens);
-                let verify_input =
-                    Tensor::from_vec(verify_tokens, (draft_tokens.len() + 1,), pipeline.device())?;
+            let mut verify_tokens = vec![anchor_token];
+            verify_tokens.extend_from_slice(&draft_tokens);
+            let verify_input =
+                Tensor::from_vec(verify_tokens, (draft_tokens.len() + 1,), pipeline.device())?;
+            #[cfg(all(feature = "cuda", feature = "graph"))]
+            let use_mtp_graph = pipeline.capturer.is_mtp_captured(draft_tokens.len() + 1);
+            #[cfg(not(all(feature = "cuda", feature = "graph")))]
+            let use_mtp_graph = false;
+            // The master decides which drafts are accepted and sends an
+            // `MtpRollback` when some are rejected.
+            if use_mtp_graph {
                 #[cfg(all(feature = "cuda", feature = "graph"))]
-                let use_mtp_graph = pipeline.capturer.is_mtp_captured(draft_tokens.len() + 1);
-                #[cfg(not(all(feature = "cuda", feature = "graph")))]
-                let use_mtp_graph = false;
-                let all_logits = if use_mtp_graph {
-                    #[cfg(all(feature = "cuda", feature = "graph"))]
-                    {
-                        pipeline.capturer.replay_mtp(
-                            &verify_input,
-                            &prepared.positions,
-                            &prepared.metadata,
-                        )
-                    }
-                    #[cfg(not(all(feature = "cuda", feature = "graph")))]
-                    {
-                        unreachable!()
-                    }
-                } else {
-                    pipeline.forward(
-                        verify_input,
+                {
+                    pipeline.capturer.replay_mtp(
+                        &verify_input,
                         &prepared.positions,
-                        Some(&cache_engine.get_kv_cache()),
                         &prepared.metadata,
-                        None,
-                    )
-                }?;
-                let verify_result = crate::openai::models::qwen3_5_mtp::verify_draft_greedy(
-                    &all_logits,
-                    &draft_tokens,
+                    )?;
+                }
+            } else {
+                pipeline.forward(
+                    verify_input,
+                    &prepared.positions,
+                    Some(&cache_engine.get_kv_cache()),
+                    &prepared.metadata,
+                    None,
                 )?;
-                Ok(Some(verify_result))
-            })();
+            }
+            Ok(())
+        })();
 
         let mut guard = engine.write();
         if guard.pipelines.insert(0, pipeline_entry).is_some() {
             candle_core::bail!("pipeline for daemon rank 0 was replaced while detached");
         }
-        if let Some(verify_result) = run_result? {
-            if verify_result.num_accepted < verify_result.num_proposed {
-                let (pipeline, _) = guard.get_pipeline(0).unwrap();
-                let restored =
-                    pipeline.mtp_rollback_mamba(seq_id, 1 + verify_result.num_accepted)?;
-                if !restored {
-                    candle_core::bail!(
-                        "daemon MTP failed to roll back mamba state for seq {}",
-                        seq_id
-                    );
-                }
-            }
+        run_result
+    }
+
+    fn daemon_mtp_rollback(
+        engine: &Arc<RwLock<Self>>,
+        seq_id: usize,
+        keep_tokens: usize,
+    ) -> Result<()> {
+        let guard = engine.read();
+        let (pipeline, _) = guard.get_pipeline(0).unwrap();
+        if !pipeline.mtp_rollback_mamba(seq_id, keep_tokens)? {
+            candle_core::bail!(
+                "daemon MTP failed to roll back mamba state for seq {}",
+                seq_id
+            );
         }
         Ok(())
     }
@@ -743,6 +738,17 @@ impl LLMEngine {
             });
     }
 
+    fn broadcast_mtp_rollback(&self, seq_id: usize, keep_tokens: usize) {
+        let mut dm = self.daemon_manager.write();
+        let _ = dm
+            .as_mut()
+            .unwrap()
+            .send_message(&MessageType::MtpRollback {
+                seq_id,
+                keep_tokens,
+            });
+    }
+
     fn broadcast_finish_sequences(&self, seq_ids: &[usize]) {
         if seq_ids.is_empty() {
             return;
@@ -841,7 +847,9 @@ impl LLMEngine {
                 && !is_prompt_request
                 && !is_embedding
                 && scheduled.len() == 1
-                && scheduled[0].sampling_params.mcp_mode.is_none();
+                && scheduled[0].sampling_params.best_of == 1
+                && scheduled[0].sampling_params.mcp_mode.is_none()
+                && scheduled[0].sampling_params.guided_decoding.is_none();
             #[cfg_attr(not(feature = "flashinfer"), allow(unused_mut))]
             let mut prepared = if is_prompt_request {
                 guard.prepare_prompt(scheduled, device, 0)
@@ -1065,14 +1073,16 @@ impl LLMEngine {
                                     None,
                                 )
                             }?;
-                            let verify_result =
-                                crate::openai::models::qwen3_5_mtp::verify_draft_greedy(
-                                    &all_logits,
-                                    &draft_tokens,
-                                )?;
+                            let verify_result = pipeline.verify_speculative(
+                                &all_logits,
+                                &scheduled[0],
+                                anchor_token,
+                                &draft_tokens,
+                            )?;
                             if verify_result.num_accepted < verify_result.num_proposed {
-                                let restored = pipeline
-                                    .mtp_rollback_mamba(seq_id, 1 + verify_result.num_accepted)?;
+                                let keep_tokens = 1 + verify_result.num_accepted;
+                                engine.read().broadcast_mtp_rollback(seq_id, keep_tokens);
+                                let restored = pipeline.mtp_rollback_mamba(seq_id, keep_tokens)?;
                                 if !restored {
                                     candle_core::bail!(
                                         "MTP failed to roll back mamba state for seq {}",
@@ -1185,20 +1195,24 @@ impl LLMEngine {
                 }
                 Ok(MessageType::RunMtpStep2 {
                     anchor_token,
-                    seq_id,
                     seq_len,
                     verify_payload,
+                    ..
                 }) => {
-                    if let Err(e) = Self::daemon_run_mtp_step2(
-                        &engine,
-                        anchor_token,
-                        seq_id,
-                        seq_len,
-                        &verify_payload,
-                    ) {
+                    if let Err(e) =
+                        Self::daemon_run_mtp_step2(&engine, anchor_token, seq_len, &verify_payload)
+                    {
                         tracing::error!("Daemon MTP step2 failed: {:?}", e);
                     }
                 }
+                Ok(MessageType::MtpRollback {
+                    seq_id,
+                    keep_tokens,
+                }) => {
+                    if let Err(e) = Self::daemon_mtp_rollback(&engine, seq_id, keep_tokens) {
+                        tracing::error!("Daemon MTP rollback failed: {:?}", e);
+                    }
+                }
                 Ok(MessageType::FinishSequences(seq_ids)) => {
                     Self::daemon_finish_sequences(&engine, &seq_ids);
                 }
@@ -1358,6 +1372,7 @@ impl LLMEngine {
                             &mut prompt_finish_times,
                         )?;
                     }
+                    e.release_speculative_slots(&scheduled);
                 } else {
                     let results = {
                         let default_pipeline = e.get_mut_pipeline(0usize).unwrap().0.as_mut();
@@ -1376,7 +1391,7 @@ impl LLMEngine {
                 let finished_seq_ids: Vec<usize> = scheduled
                     .iter()
                     .filter(|g| g.is_finished())
-                    .map(|g| Self::primary_sequence(g).deref().get_id())
+                    .flat_map(|g| g.get_seqs().keys().copied().collect::<Vec<_>>())
                     .collect();
                 if !finished_seq_ids.is_empty() {
                     e.broadcast_finish_sequences(&finished_seq_ids);
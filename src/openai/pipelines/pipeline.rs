@@ -3,16 +3,22 @@ use crate::backend::gguf;
 #[cfg(all(feature = "cuda", feature = "graph"))]
 use crate::backend::graph::{CudaGraphFn, CudaGraphWrapper, GraphCapturer, ModelFn};
 use crate::backend::progress::{progress_worker, ProgressLike, ProgressReporter};
-use crate::openai::logits_processor::LogitsProcessor;
+use crate::openai::logits_processor::{LogitsProcessor, Sampling};
 use crate::openai::models::layers::quantized_var_builder::VarBuilder as QVarBuilder;
 use crate::openai::models::linear::set_linear_is_prefill;
+use crate::openai::models::qwen3_5_mtp::{
+    verify_draft_greedy, verify_draft_sampled, MtpVerifyResult,
+};
 use crate::openai::models::TokenID;
 use crate::openai::multimodal::{get_image_config, ImageProcessConfig};
 use crate::openai::requests::StopTokens;
 use crate::openai::sampling_params::{GenerationConfig, Logprobs, TopLogprob};
+use crate::openai::structured_output::{
+    EnvelopeMarker, GuidedDecoder, TokenVocab, ToolCallEnvelope,
+};
 use crate::openai::TokenizerConfig;
 use crate::scheduler::sequence::{Sequence, SequenceGroup};
-use crate::tools::stream_parser::{ToolConfig, ToolModelType};
+use crate::tools::stream_parser::{HarmonyChannels, StreamToolParser, ToolConfig, ToolModelType};
 #[cfg(all(feature = "cuda", feature = "graph", feature = "flashinfer"))]
 use crate::FlashInferKvParams;
 use crate::{
@@ -28,11 +34,13 @@ use crate::{
             gemma4::Gemma4,
             glm4::GLM4,
             glm4_moe_lite::GLM4MoeLiteForCausalLM,
+            gpt_oss::GptOssForCausalLM,
             llama::Llama,
             llama4::LLama4ForConditionalGeneration,
             minimax::MiniMaxForCausalLM,
             mistral::Mistral,
             mistral3_vl::Mistral3ForConditionalGeneration,
+            mixtral::MixtralForCausalLM,
             phi2::Phi2,
             phi4::Phi4ForCausalLM as Phi4,
             quantized_deepseek::GGUFDeepSeek,
@@ -68,7 +76,10 @@ use regex::Regex;
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::path::Path;
 pub use std::rc::Rc;
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    path::PathBuf,
+    sync::{Arc, OnceLock},
+};
 use tokenizers::Tokenizer;
 use tracing::{info, warn};
 
@@ -93,10 +104,13 @@ pub enum LLMModel {
     MiniMax(Arc<MiniMaxForCausalLM>),
     Mistral(Arc<Mistral>),
     Mistral3VL(Arc<Mistral3ForConditionalGeneration>),
+    Mixtral(Arc<MixtralForCausalLM>),
+    PhiMoE(Arc<MixtralForCausalLM>),
     Yi(Arc<Yi>),
     StableLM(Arc<StableLM>),
     GLM4(Arc<GLM4>),
     GLM4MoeLite(Arc<GLM4MoeLiteForCausalLM>),
+    GptOss(Arc<GptOssForCausalLM>),
     DeepSeek(Arc<DeepSeek>),
     GLM5(Arc<DeepSeek>),
     LlamaGGUF(Arc<GGUFLLaMa>),
@@ -127,7 +141,10 @@ fn tool_model_type_for(model: &LLMModel) -> ToolModelType {
         LLMModel::Gemma3(_) | LLMModel::Gemma3VL(_) => ToolModelType::Gemma3,
         LLMModel::Gemma4(_) => ToolModelType::Gemma4,
         LLMModel::MiniMax(_) => ToolModelType::MiniMax,
-        LLMModel::Mistral(_) | LLMModel::Mistral3VL(_) => ToolModelType::Mistral,
+        LLMModel::GptOss(_) => ToolModelType::GptOss,
+        LLMModel::Mistral(_) | LLMModel::Mistral3VL(_) | LLMModel::Mixtral(_) => {
+            ToolModelType::Mistral
+        }
         LLMModel::Yi(_) => ToolModelType::Yi,
         LLMModel::StableLM(_) => ToolModelType::StableLM,
         LLMModel::GLM4(_)
@@ -136,12 +153,13 @@ fn tool_model_type_for(model: &LLMModel) -> ToolModelType {
         | LLMModel::GLM5(_)
         | LLMModel::GLM5GGUF(_) => ToolModelType::GLM4,
         LLMModel::DeepSeek(_) | LLMModel::DeepSeekGGUF(_) => ToolModelType::DeepSeek,
-        LLMModel::Phi2(_) | LLMModel::Phi3GGUF(_) => ToolModelType::Phi,
+        LLMModel::Phi2(_) | LLMModel::Phi3GGUF(_) | LLMModel::PhiMoE(_) => ToolModelType::Phi,
         LLMModel::Phi4(_) => ToolModelType::Phi4,
     }
 }
 
-/// top-p, multinomial, and argmax sampling are implemented. Beam search is not implemented.
+/// top-p, multinomial, and argmax sampling are implemented. Beam search rows yield their
+/// best continuations, which the engine expands into beams.
 pub struct DefaultPipeline {
     pub model: LLMModel,
     pub tokenizer: Tokenizer,
@@ -159,11 +177,16 @@ pub struct DefaultPipeline {
     pub tool_call_regex: Regex,
     pub tool_config: ToolConfig,
     pub tool_model_type: ToolModelType,
+    /// Layout enforced for `tool_choice: "required"` / named-function calls;
+    /// `None` when the model's tool-call format cannot be constrained.
+    pub tool_call_envelope: Option<ToolCallEnvelope>,
     pub tool_parser_model_id: String,
     pub enforce_parser: Option<String>,
     pub image_config: Option<ImageProcessConfig>,
     pub mtp_head: Option<Arc<Qwen3_5MtpHead>>,
     pub mtp_num_speculative: usize,
+    /// Vocabulary index for structured output, built on first use.
+    pub guided_vocab: OnceLock<Arc<TokenVocab>>,
     #[cfg(all(feature = "cuda", feature = "graph"))]
     pub capturer: GraphCapturer<CudaGraphWrapper<CudaGraphFn>>,
 }
@@ -1209,6 +1232,10 @@ impl DefaultLoader {
                 | "DeepseekV32ForCausalLM"
                 | "GlmMoeDsaForCausalLM" => DeepSeek::load_config(&cfile, isq.clone())?,
                 "MiniMaxM2ForCausalLM" => MiniMaxForCausalLM::load_config(&cfile, isq.clone())?,
+                "MixtralForCausalLM" | "PhiMoEForCausalLM" => {
+                    MixtralForCausalLM::load_config(&cfile, isq.clone())?
+                }
+                "GptOssForCausalLM" => GptOssForCausalLM::load_config(&cfile, isq.clone())?,
                 _ => panic!("Model not supported!"),
             };
             if !matches!(
@@ -1220,6 +1247,7 @@ impl DefaultLoader {
                     | "Glm4MoeLiteForCausalLM"
                     | "Llama4ForConditionalGeneration"
                     | "MiniMaxM2ForCausalLM"
+                    | "GptOssForCausalLM"
             ) {
                 config.apply_runtime_rope_overrides(self.yarn_scaling_factor);
             }
@@ -1698,6 +1726,48 @@ impl DefaultLoader {
                             )),
                             SeparatorStyle::Qwen,
                         ),
+                        "GptOssForCausalLM" => (
+                            LLMModel::GptOss(Arc::new(
+                                GptOssForCausalLM::new(
+                                    vb,
+                                    &config,
+                                    dtype,
+                                    &device,
+                                    comm,
+                                    Arc::clone(&reporter),
+                                )
+                                .map_err(|e| {
+                                    candle_core::Error::msg(format!(
+                                        "Failed to load GPT-OSS model for arch {} on rank {}: {}",
+                                        arch, rank, e
+                                    ))
+                                })?,
+                            )),
+                            SeparatorStyle::Qwen,
+                        ),
+                        "MixtralForCausalLM" | "PhiMoEForCausalLM" => {
+                            let model = Arc::new(
+                                MixtralForCausalLM::new(
+                                    vb,
+                                    &config,
+                                    dtype,
+                                    &device,
+                                    comm,
+                                    Arc::clone(&reporter),
+                                )
+                                .map_err(|e| {
+                                    candle_core::Error::msg(format!(
+                                        "Failed to load Mixtral model for arch {} on rank {}: {}",
+                                        arch, rank, e
+                                    ))
+                                })?,
+                            );
+                            if arch == "PhiMoEForCausalLM" {
+                                (LLMModel::PhiMoE(model), SeparatorStyle::Phi)
+                            } else {
+                                (LLMModel::Mixtral(model), SeparatorStyle::Mistral)
+                            }
+                        }
                         _ => panic!("Model not supported!"),
                     };
 
@@ -2042,7 +2112,10 @@ impl DefaultPipeline {
             Gemma3,
             Gemma4,
             MiniMax,
+            GptOss,
             Mistral,
+            Mixtral,
+            PhiMoE,
             Yi,
             StableLM,
             GLM4,
@@ -2119,6 +2192,17 @@ impl DefaultPipeline {
         tool_config.validate_with_tokenizer(&tokenizer, &tool_model_type);
         let tool_call_start_token_ids = tool_config.tool_call_start_ids(&tokenizer);
         let tool_call_end_token_ids = tool_config.tool_call_end_ids(&tokenizer);
+        let envelope_marker = |text: &str| {
+            if text.is_empty() {
+                return None;
+            }
+            Some(match tokenizer.encode(text, false) {
+                Ok(encoded) if encoded.get_ids().len() == 1 => {
+                    EnvelopeMarker::Token(encoded.get_ids()[0])
+                }
+                _ => EnvelopeMarker::Text(text.to_string()),
+            })
+        };
         let json_end_token_id = tokenizer
             .encode("}", false)
             .ok()
@@ -2131,6 +2215,19 @@ impl DefaultPipeline {
             config.architectures.as_ref().unwrap()[0].clone()
         };
         let tool_parser_model_id = tool_parser_model_id.unwrap_or_else(|| pipeline_name.clone());
+        let tool_parser_name = enforce_parser
+            .as_deref()
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| {
+                StreamToolParser::parser_name_for_model(&tool_model_type, &tool_parser_model_id)
+            });
+        let tool_call_envelope = ToolCallEnvelope::for_model_type(
+            &tool_model_type,
+            tool_parser_name,
+            envelope_marker(&tool_config.start_token_str),
+            envelope_marker(&tool_config.end_token_str),
+        );
         let tool_markers = [
             tool_config.start_token_str.as_str(),
             tool_config.end_token_str.as_str(),
@@ -2164,11 +2261,13 @@ impl DefaultPipeline {
             tool_call_regex,
             tool_config,
             tool_model_type,
+            tool_call_envelope,
             tool_parser_model_id,
             enforce_parser,
             image_config,
             mtp_head,
             mtp_num_speculative,
+            guided_vocab: OnceLock::new(),
             #[cfg(all(feature = "cuda", feature = "graph"))]
             capturer: GraphCapturer::new(
                 wrapper,
@@ -2290,6 +2389,12 @@ impl DefaultPipeline {
             LLMModel::MiniMax(m) => {
                 m.forward(&input_tokens, input_positions, kv_cache, input_metadata)
             }
+            LLMModel::GptOss(m) => {
+                m.forward(&input_tokens, input_positions, kv_cache, input_metadata)
+            }
+            LLMModel::Mixtral(m) | LLMModel::PhiMoE(m) => {
+                m.forward(&input_tokens, input_positions, kv_cache, input_metadata)
+            }
             LLMModel::Mistral(mistral) => {
                 mistral.forward(&input_tokens, input_positions, kv_cache, input_metadata)
             }
@@ -2444,6 +2549,12 @@ impl DefaultPipeline {
             LLMModel::MiniMax(m) => {
                 m.forward_embedding(&input_tokens, input_positions, kv_cache, input_metadata)
             }
+            LLMModel::GptOss(m) => {
+                m.forward_embedding(&input_tokens, input_positions, kv_cache, input_metadata)
+            }
+            LLMModel::Mixtral(m) | LLMModel::PhiMoE(m) => {
+                m.forward_embedding(&input_tokens, input_positions, kv_cache, input_metadata)
+            }
             LLMModel::Mistral(mistral) => {
                 mistral.forward_embedding(&input_tokens, input_positions, kv_cache, input_metadata)
             }
@@ -2521,11 +2632,111 @@ impl DefaultPipeline {
         }
     }
 
+    /// GPT-OSS output is harmony-framed; rewrite it into reasoning and tool-call
+    /// markup as it is decoded so the streaming and final responses can use it.
+    fn wrap_stream_decoder(&self, decoder: super::DecodeStreamType) -> super::DecodeStreamType {
+        match &self.model {
+            LLMModel::GptOss(_) => Box::new(super::HarmonyDecodeStream {
+                inner: decoder,
+                channels: HarmonyChannels::new(),
+            }),
+            _ => decoder,
+        }
+    }
+
+    /// Harmony ends a tool call with the `<|call|>` stop token, whose decoded
+    /// text closes the translated `<tool_call>` envelope; keep it as the final
+    /// output token instead of dropping it with the stop.
+    fn harmony_stop_logprobs(&self, token: u32, logprob: f32, text: &str) -> Option<Logprobs> {
+        if text.is_empty() || !matches!(self.model, LLMModel::GptOss(_)) {
+            return None;
+        }
+        Some(Logprobs {
+            token,
+            logprob,
+            top_logprobs: Vec::<TopLogprob>::new(),
+            bytes: text.to_string(),
+        })
+    }
+
+    /// Allowed-token masks for sequences decoding under a `response_format` or
+    /// forced tool-call constraint, advancing each sequence's grammar state to its output so far.
+    fn guided_token_masks(
+        &self,
+        batch: &[(&Arc<SequenceGroup>, Arc<Sequence>)],
+    ) -> Vec<Option<Arc<Vec<u32>>>> {
+        let vocab = self.guided_vocab.get_or_init(|| {
+            info!("Building tokenizer vocabulary index for structured output");
+            Arc::new(TokenVocab::from_tokenizer(&self.tokenizer))
+        });
+        batch
+            .par_iter()
+            .map(|(group, seq)| {
+                let constraint = group.sampling_params.guided_decoding.as_ref()?;
+                let seq = seq.deref();
+                let output_tokens: Vec<u32> = seq
+                    .get_output_tokens()
+                    .iter()
+                    .map(|logprob| logprob.token)
+                    .collect();
+                let mut data = seq.deref_mut();
+                if data.guided_decoder.is_none() {
+                    match constraint.compile(self.tool_call_envelope.as_ref()) {
+                        Ok(grammar) => {
+                            data.guided_decoder = Some(GuidedDecoder::new(
+                                Arc::new(grammar),
+                                group.active_reasoning_end.clone(),
+                            ))
+                        }
+                        Err(e) => {
+                            warn!(
+                                "Unable to compile decoding constraint for request {}: {}",
+                                group.request_id, e
+                            );
+                            return None;
+                        }
+                    }
+                }
+                let decoder = data.guided_decoder.as_mut()?;
+                decoder.sync(vocab, &output_tokens);
+                decoder.allowed_tokens(vocab, &self.stop_token_ids)
+            })
+            .collect()
+    }
+
     pub fn sample(
         &mut self,
         logits: &Tensor,
         groups: &VecDeque<Arc<SequenceGroup>>,
     ) -> Result<Vec<TokenOrFinishReason>> {
+        // One row per unfinished sequence. A group that was just prefilled has
+        // a single logits row for its shared prompt, repeated for each sequence.
+        let mut batch = Vec::new();
+        let mut row_indices = Vec::new();
+        let mut logits_rows = 0u32;
+        for group in groups {
+            let shared_prompt = group.get_primary_seq().deref().is_prompt();
+            for seq in group.get_unfinished_seqs() {
+                row_indices.push(logits_rows);
+                if !shared_prompt {
+                    logits_rows += 1;
+                }
+                batch.push((group, seq));
+            }
+            if shared_prompt {
+                logits_rows += 1;
+            }
+        }
+        let logits = if logits_rows as usize == batch.len() {
+            logits.to_owned()
+        } else {
+            let num_rows = row_indices.len();
+            logits.index_select(
+                &Tensor::from_vec(row_indices, (num_rows,), logits.device())?,
+                0,
+            )?
+        };
+
         let (
             tokens_generated,
             custom_stop_tokens,
@@ -2538,11 +2749,10 @@ impl DefaultPipeline {
             Vec<f32>,
             Vec<f32>,
             Vec<Vec<u32>>,
-        ) = groups
-            .into_par_iter()
-            .map(|group| {
+        ) = batch
+            .par_iter()
+            .map(|(group, seq)| {
                 let sampling_params = &group.sampling_params;
-                let seq = group.get_seqs().values().next().unwrap();
                 let sq = seq.deref_mut();
                 let tokens = sq
                     .get_token_ids()
@@ -2600,17 +2810,47 @@ impl DefaultPipeline {
             || presence_panalties.iter().any(|&v| v != 1.0 && v != 0.)
         {
             self.logits_processor.apply_batch_repeat_penalty(
-                logits,
+                &logits,
                 frequency_panalties,
                 presence_panalties,
                 reference_tokens,
             )?
         } else {
-            logits.to_owned()
+            logits
+        };
+
+        let logits = if groups
+            .iter()
+            .any(|group| group.sampling_params.logit_bias.is_some())
+        {
+            self.logits_processor.apply_batch_logit_bias(
+                &logits,
+                batch
+                    .iter()
+                    .map(|(group, _)| group.sampling_params.logit_bias.as_ref())
+                    .collect(),
+            )?
+        } else {
+            logits
+        };
+
+        let logits = if groups
+            .iter()
+            .any(|group| group.sampling_params.guided_decoding.is_some())
+        {
+            let masks = self.guided_token_masks(&batch);
+            self.logits_processor
+                .apply_batch_token_masks(&logits, masks)?
+        } else {
+            logits
         };
 
-        let group_ids: Vec<usize> = groups.iter().map(|group| group.group_id).collect();
-        let param = &groups[0].sampling_params;
+        // Unseeded rows share one strategy, taken from the first of them.
+        let param = &groups
+            .iter()
+            .find(|group| group.sampling_params.seed.is_none())
+            .unwrap_or(&groups[0])
+            .sampling_params;
         let sampling_params =
             if param.temperature.is_some() && (param.top_k.is_some() || param.top_p.is_some()) {
                 Some(param.to_owned())
@@ -2618,18 +2858,118 @@ impl DefaultPipeline {
                 None
             };
 
-        let next_tokens = self.logits_processor.sample(&logits, &sampling_params)?;
+        // Seeded requests are sampled row by row with their own parameters and
+        // RNG stream so their output is independent of the rest of the batch;
+        // the other rows keep the batched sampler.
+        let seeded_rows = batch
+            .iter()
+            .enumerate()
+            .filter(|(_, (group, _))| group.sampling_params.seed.is_some())
+            .map(|(i, _)| i as u32)
+            .collect::<Vec<_>>();
+        let next_tokens = if seeded_rows.is_empty() {
+            self.logits_processor.sample(&logits, &sampling_params)?
+        } else {
+            let select = |rows: &[u32]| -> Result<Tensor> {
+                let ids = Tensor::from_vec(rows.to_vec(), (rows.len(),), logits.device())?;
+                logits.index_select(&ids, 0)
+            };
+            let seeded_configs = seeded_rows
+                .iter()
+                .map(|&i| {
+                    let group = &batch[i as usize].0;
+                    let param = &group.sampling_params;
+                    (
+                        LogitsProcessor::get_strategy(
+                            param.temperature,
+                            param.top_k,
+                            param.top_p,
+                            param.min_p,
+                        ),
+                        param.seed.map(|seed| (group.group_id, seed)),
+                    )
+                })
+                .collect::<Vec<_>>();
+            let seeded_tokens = self
+                .logits_processor
+                .sample_rows(&select(&seeded_rows)?, &seeded_configs)?;
+            let unseeded_rows = (0..batch.len() as u32)
+                .filter(|i| !seeded_rows.contains(i))
+                .collect::<Vec<_>>();
+            let unseeded_tokens = if unseeded_rows.is_empty() {
+                Vec::new()
+            } else {
+                self.logits_processor
+                    .sample(&select(&unseeded_rows)?, &sampling_params)?
+            };
+            let mut next_tokens = vec![0u32; batch.len()];
+            for (&i, token) in seeded_rows.iter().zip(seeded_tokens) {
+                next_tokens[i as usize] = token;
+            }
+            for (&i, token) in unseeded_rows.iter().zip(unseeded_tokens) {
+                next_tokens[i as usize] = token;
+            }
+            next_tokens
+        };
+        // Beam search rows report their best continuations instead of a
+        // sampled token; the engine decides which beams survive.
+        let beam_rows = batch
+            .iter()
+            .enumerate()
+            .filter(|(_, (group, _))| group.sampling_params.use_beam_search)
+            .map(|(i, _)| i as u32)
+            .collect::<Vec<_>>();
+        let mut beam_candidates = HashMap::new();
+        if !beam_rows.is_empty() {
+            let beam_width = beam_rows
+                .iter()
+                .map(|&i| batch[i as usize].0.sampling_params.best_of)
+                .max()
+                .unwrap_or(1);
+            let num_rows = beam_rows.len();
+            let beam_logits = logits.index_select(
+                &Tensor::from_vec(beam_rows.clone(), (num_rows,), logits.device())?,
+                0,
+            )?;
+            let top = self
+                .logits_processor
+                .top_logprobs(&beam_logits, 2 * beam_width)?;
+            beam_candidates.extend(beam_rows.into_iter().map(|i| i as usize).zip(top));
+        }
+        // `best_of` candidates are ranked by cumulative logprob.
+        let logprobs = if batch.iter().any(|(group, _)| {
+            group.sampling_params.best_of > 1 && !group.sampling_params.use_beam_search
+        }) {
+            self.logits_processor
+                .token_logprobs(&logits, &next_tokens)?
+        } else {
+            vec![0.0; next_tokens.len()]
+        };
         let result: Vec<TokenOrFinishReason> = next_tokens
             .into_par_iter()
             .enumerate()
             .map(|(i, next_token)| {
-                let group_id = group_ids[i];
-                let group = groups
-                    .get(i)
-                    .expect("group index out of range for sampling");
+                if let Some(candidates) = beam_candidates.get(&i) {
+                    return Left(Logprobs {
+                        token: candidates[0].0,
+                        logprob: candidates[0].1,
+                        bytes: String::new(),
+                        top_logprobs: candidates
+                            .iter()
+                            .map(|&(token, logprob)| TopLogprob {
+                                token,
+                                logprob,
+                                bytes: self.tokenizer.decode(&[token], false).unwrap_or_default(),
+                            })
+                            .collect(),
+                    });
+                }
+                let (group, seq) = &batch[i];
+                let seq_id = seq.deref().get_id();
+                let logprob = logprobs[i];
                 let mut text = "".to_string();
                 let mut decoder_map = self.stream_decoders.write();
-                match decoder_map.get_mut(&group_id) {
+                match decoder_map.get_mut(&seq_id) {
                     Some(decoder) => {
                         if let Some(output) = decoder.step(next_token) {
                             text = output
@@ -2642,13 +2982,12 @@ impl DefaultPipeline {
                             _tokenizer: unsafe { Box::from_raw(leaked as *const _ as *mut _) },
                             stream: decoder,
                         };
-                        let mut boxed_decoder: Box<dyn super::DecodeStreamTrait + Send + Sync> =
-                            Box::new(wrapped);
+                        let mut boxed_decoder = self.wrap_stream_decoder(Box::new(wrapped));
                         if let Some(output) = boxed_decoder.step(next_token) {
                             text = output
                         }
-                        //stream decoder for the new request
-                        decoder_map.insert(group_id, boxed_decoder);
+                        //stream decoder for the new sequence
+                        decoder_map.insert(seq_id, boxed_decoder);
                     }
                 }
 
@@ -2656,14 +2995,13 @@ impl DefaultPipeline {
                     && custom_stop_tokens[i].contains(&text.trim().to_string());
 
                 if group.sampling_params.mcp_mode.is_some() {
-                    let seq = group.get_seqs().values().next().unwrap();
                     if self.tool_call_end_token_ids.contains(&next_token) {
                         if !self.tool_call_start_token_ids.is_empty() {
                             let has_start = self.output_contains_tool_call_start(seq);
                             if !has_start {
                                 return Left(Logprobs {
                                     token: next_token,
-                                    logprob: 0.0,
+                                    logprob,
                                     top_logprobs: Vec::<TopLogprob>::new(),
                                     bytes: text,
                                 });
@@ -2671,7 +3009,7 @@ impl DefaultPipeline {
                         }
                         let finish_logprobs = Logprobs {
                             token: next_token,
-                            logprob: 0.0,
+                            logprob,
                             top_logprobs: Vec::<TopLogprob>::new(),
                             bytes: text,
                         };
@@ -2685,7 +3023,7 @@ impl DefaultPipeline {
                         );
                         let finish_logprobs = Logprobs {
                             token: next_token,
-                            logprob: 0.0,
+                            logprob,
                             top_logprobs: Vec::<TopLogprob>::new(),
                             bytes: text,
                         };
@@ -2706,7 +3044,7 @@ impl DefaultPipeline {
                             if self.tool_call_regex.is_match(&decoded) {
                                 let finish_logprobs = Logprobs {
                                     token: next_token,
-                                    logprob: 0.0,
+                                    logprob,
                                     top_logprobs: Vec::<TopLogprob>::new(),
                                     bytes: text,
                                 };
@@ -2721,11 +3059,16 @@ impl DefaultPipeline {
                 if tokens_generated[i] < 0 {
                     Right("length".to_string())
                 } else if custom_stop_token_match || self.stop_token_ids.contains(&next_token) {
+                    if let Some(finish_logprobs) =
+                        self.harmony_stop_logprobs(next_token, logprob, &text)
+                    {
+                        seq.deref_mut().deref_mut().pending_finish_logprobs = Some(finish_logprobs);
+                    }
                     Right("stop".to_string())
                 } else {
                     Left(Logprobs {
                         token: next_token,
-                        logprob: 0.0,
+                        logprob,
                         top_logprobs: Vec::<TopLogprob>::new(),
                         bytes: text,
                     })
@@ -2735,6 +3078,85 @@ impl DefaultPipeline {
         Ok(result)
     }
 
+    /// Verify speculative `draft_tokens` following `anchor` against the
+    /// target's `verify_logits` (one row per draft plus the continuation row)
+    /// under the group's sampling parameters, penalties and logit bias.
+    /// Greedy requests keep the drafts that match the argmax; sampled ones
+    /// use speculative sampling, so their output keeps the distribution of
+    /// ordinary decoding.
+    pub fn verify_speculative(
+        &self,
+        verify_logits: &Tensor,
+        group: &SequenceGroup,
+        anchor: u32,
+        draft_tokens: &[u32],
+    ) -> Result<MtpVerifyResult> {
+        let params = &group.sampling_params;
+        let num_rows = verify_logits.dim(0)?;
+        let logits = if params.frequency_penalty != 0. || params.presence_penalty != 0. {
+            // Row i is predicted after the anchor and the first i drafts.
+            let (mut context, prompt_len) = {
+                let seq = group.get_primary_seq().deref();
+                (seq.get_token_ids(), seq.get_prompt_len())
+            };
+            context.push(anchor);
+            let repeat_last_n = params.repeat_last_n.unwrap_or(128);
+            let reference_tokens = (0..num_rows)
+                .map(|i| {
+                    let row_context = context
+                        .iter()
+                        .chain(&draft_tokens[..i.min(draft_tokens.len())])
+                        .copied()
+                        .collect::<Vec<_>>();
+                    if repeat_last_n < row_context.len() - prompt_len {
+                        row_context[row_context.len() - repeat_last_n..].to_vec()
+                    } else {
+                        vec![]
+                    }
+                })
+                .collect();
+            self.logits_processor.apply_batch_repeat_penalty(
+                verify_logits,
+                vec![params.frequency_penalty; num_rows],
+                vec![params.presence_penalty; num_rows],
+                reference_tokens,
+            )?
+        } else {
+            verify_logits.clone()
+        };
+        let logits = match &params.logit_bias {
+            Some(bias) => self
+                .logits_processor
+                .apply_batch_logit_bias(&logits, vec![Some(bias); num_rows])?,
+            None => logits,
+        };
+
+        // Same strategy `sample` picks for the group.
+        let sampling = if params.seed.is_some()
+            || (params.temperature.is_some() && (params.top_k.is_some() || params.top_p.is_some()))
+        {
+            LogitsProcessor::get_strategy(
+                params.temperature,
+                params.top_k,
+                params.top_p,
+                params.min_p,
+            )
+        } else {
+            self.logits_processor.sampling.clone()
+        };
+        if sampling == Sampling::ArgMax {
+            return verify_draft_greedy(&logits, draft_tokens);
+        }
+        let target_probs = self.logits_processor.strategy_probs(&logits, &sampling)?;
+        let stream = params.seed.map(|seed| (group.group_id, seed));
+        verify_draft_sampled(
+            &target_probs,
+            draft_tokens,
+            || self.logits_processor.uniform(stream),
+            |weights| self.logits_processor.sample_probs(weights, stream),
+        )
+    }
+
     pub fn tokens_to_results(
         &mut self,
         tokens: &[u32],
@@ -2744,12 +3166,13 @@ impl DefaultPipeline {
             candle_core::bail!("MTP token conversion supports a single sequence group");
         }
         let group = groups.front().unwrap();
-        let seq = group.get_seqs().values().next().unwrap();
+        let seq = group.get_primary_seq();
+        let seq_id = seq.deref().get_id();
         let mut results = Vec::with_capacity(tokens.len());
         for &next_token in tokens {
             let mut text = String::new();
             let mut decoder_map = self.stream_decoders.write();
-            match decoder_map.get_mut(&group.group_id) {
+            match decoder_map.get_mut(&seq_id) {
                 Some(decoder) => {
                     if let Some(output) = decoder.step(next_token) {
                         text = output;
@@ -2762,12 +3185,11 @@ impl DefaultPipeline {
                         _tokenizer: unsafe { Box::from_raw(leaked as *const _ as *mut _) },
                         stream: decoder,
                     };
-                    let mut boxed_decoder: Box<dyn super::DecodeStreamTrait + Send + Sync> =
-                        Box::new(wrapped);
+                    let mut boxed_decoder = self.wrap_stream_decoder(Box::new(wrapped));
                     if let Some(output) = boxed_decoder.step(next_token) {
                         text = output;
                     }
-                    decoder_map.insert(group.group_id, boxed_decoder);
+                    decoder_map.insert(seq_id, boxed_decoder);
                 }
             }
             drop(decoder_map);
@@ -2781,6 +3203,9 @@ impl DefaultPipeline {
             let result = if generated > group.sampling_params.max_tokens {
                 Right("length".to_string())
             } else if custom_stop_token_match || self.stop_token_ids.contains(&next_token) {
+                if let Some(finish_logprobs) = self.harmony_stop_logprobs(next_token, 0.0, &text) {
+                    seq.deref_mut().deref_mut().pending_finish_logprobs = Some(finish_logprobs);
+                }
                 Right("stop".to_string())
             } else {
                 Left(Logprobs {
@@ -2827,6 +3252,8 @@ impl DefaultPipeline {
             LLMModel::Gemma3VL(gemma3) => gemma3.get_config().clone(),
             LLMModel::Gemma4(gemma4) => gemma4.get_config().clone(),
             LLMModel::MiniMax(m) => m.get_config().clone(),
+            LLMModel::GptOss(m) => m.get_config().clone(),
+            LLMModel::Mixtral(m) | LLMModel::PhiMoE(m) => m.get_config().clone(),
             LLMModel::Mistral(mistral) => mistral.get_config().clone(),
             LLMModel::Mistral3VL(mistral) => mistral.get_config().clone(),
             LLMModel::Yi(yi) => yi.get_config().clone(),
@@ -2857,6 +3284,12 @@ impl DefaultPipeline {
     pub fn reset_decoder(&mut self) {
         let mut map = self.stream_decoders.write();
         map.clear();
+        self.logits_processor.release_seeded_streams();
+    }
+
+    /// Drop the seeded RNG streams of groups that finished or were cancelled.
+    pub fn retain_seeded_streams(&self, live: impl Fn(usize) -> bool) {
+        self.logits_processor.retain_seeded_streams(live);
     }
 
     pub fn rank(&self) -> usize {
@@ -2987,6 +3420,9 @@ impl DefaultPipeline {
         match &self.model {
             LLMModel::Phi4(_) => Ok(()),
             LLMModel::Phi3GGUF(_) => Ok(()),
+            // Attention sinks scan keys up to the batch's longest context,
+            // which a captured graph would pin at the maximum model length.
+            LLMModel::GptOss(_) => Ok(()),
             #[cfg(not(feature = "flashinfer"))]
             LLMModel::GLM4MoeLite(_) => Ok(()),
             #[cfg(not(feature = "flashinfer"))]
@@ -0,0 +1,805 @@
+//! Draft-model and prompt-lookup speculative decoding.
+//!
+//! A small model sharing the target's tokenizer proposes a few tokens per
+//! decode step, and the target scores them all in one forward using the MTP
+//! verify layout, keeping the prefix it accepts under the request's sampling
+//! parameters (see `DefaultPipeline::verify_speculative`).
+//! The draft's paged KV cache has as many blocks as the target's and is
+//! addressed through the target's block tables, so the `BlockEngine` owns
+//! allocation and rollback for both caches.
+//!
+//! Prompt lookup needs no second model: the tokens that followed an earlier
+//! occurrence of the sequence's trailing n-gram are proposed instead, and
+//! the decode of the last token is folded into the verify forward.
+
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use candle_core::{DType, Device, Result, Tensor, D};
+use either::Either;
+use tracing::info;
+
+use super::{
+    DefaultPipeline, LLMEngine, Sequence, SequenceGroup, TokenOrFinishReason, PREFILL_CHUNK_SIZE,
+};
+use crate::openai::models::linear::set_linear_is_prefill;
+use crate::openai::models::qwen3_5_mtp::MTP_STATS_LOG_INTERVAL_STEPS;
+use crate::scheduler::cache_engine::CacheEngine;
+use crate::InputMetadata;
+
+/// Running acceptance counters for one kind of speculation.
+pub struct SpeculationStats {
+    label: &'static str,
+    tokens_per_step: usize,
+    proposed: AtomicUsize,
+    accepted: AtomicUsize,
+    steps: AtomicUsize,
+}
+
+impl SpeculationStats {
+    /// `tokens_per_step` is what a step emits besides accepted proposals.
+    pub const fn new(label: &'static str, tokens_per_step: usize) -> Self {
+        Self {
+            label,
+            tokens_per_step,
+            proposed: AtomicUsize::new(0),
+            accepted: AtomicUsize::new(0),
+            steps: AtomicUsize::new(0),
+        }
+    }
+
+    /// Record one verify step and return the number of steps so far.
+    pub fn update(&self, proposed: usize, accepted: usize) -> usize {
+        self.proposed.fetch_add(proposed, Ordering::Relaxed);
+        self.accepted.fetch_add(accepted, Ordering::Relaxed);
+        self.steps.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub fn summary(&self) -> String {
+        let proposed = self.proposed.load(Ordering::Relaxed);
+        let accepted = self.accepted.load(Ordering::Relaxed);
+        let steps = self.steps.load(Ordering::Relaxed);
+        format!(
+            "{} Stats: proposed={}, accepted={}, acceptance_rate={:.2}%, avg_tokens/step={:.2}",
+            self.label,
+            proposed,
+            accepted,
+            if proposed > 0 {
+                accepted as f64 / proposed as f64 * 100.0
+            } else {
+                0.0
+            },
+            if steps > 0 {
+                (accepted + self.tokens_per_step * steps) as f64 / steps as f64
+            } else {
+                1.0
+            }
+        )
+    }
+}
+
+/// Draft steps emit the anchor and the target's continuation on top.
+pub static DRAFT_STATS: SpeculationStats = SpeculationStats::new("Draft", 2);
+/// Prompt-lookup proposals start at the anchor, so only the target's
+/// continuation is extra.
+pub static LOOKUP_STATS: SpeculationStats = SpeculationStats::new("Prompt lookup", 1);
+
+/// Shortest trailing n-gram prompt lookup will match.
+const PROMPT_LOOKUP_MIN_NGRAM: usize = 1;
+
+/// Up to `num_tokens` tokens that followed the most recent earlier
+/// occurrence of the trailing n-gram of `tokens`, trying the longest n-gram
+/// (`max_ngram` tokens) first.
+fn lookup_continuation(tokens: &[u32], num_tokens: usize, max_ngram: usize) -> Vec<u32> {
+    for n in (PROMPT_LOOKUP_MIN_NGRAM..=max_ngram).rev() {
+        if tokens.len() <= n {
+            continue;
+        }
+        let tail = &tokens[tokens.len() - n..];
+        if let Some(start) = tokens[..tokens.len() - 1]
+            .windows(n)
+            .rposition(|window| window == tail)
+        {
+            let from = start + n;
+            return tokens[from..(from + num_tokens).min(tokens.len())].to_vec();
+        }
+    }
+    Vec::new()
+}
+
+/// Settings of `--prompt-lookup`.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct PromptLookup {
+    num_speculative: usize,
+    max_ngram: usize,
+}
+
+/// How many leading tokens of each sequence have KV in the draft cache.
+#[derive(Debug, Default)]
+struct DraftProgress {
+    cached_lens: HashMap<usize, usize>,
+}
+
+impl DraftProgress {
+    /// First position the draft still has to run for a sequence of `seq_len`
+    /// tokens. The last token never has KV yet, so at least it is pending.
+    fn pending_from(&self, seq_id: usize, seq_len: usize) -> usize {
+        self.cached_lens
+            .get(&seq_id)
+            .copied()
+            .unwrap_or(0)
+            .min(seq_len.saturating_sub(1))
+    }
+
+    /// After a step that proposed `num_proposed` tokens past the anchor at
+    /// `seq_len`, the draft KV stays valid through the anchor and the
+    /// accepted tokens it wrote itself; the last draft is never written.
+    fn commit(&mut self, seq_id: usize, seq_len: usize, num_proposed: usize, num_accepted: usize) {
+        let written = num_accepted.min(num_proposed.saturating_sub(1));
+        self.cached_lens.insert(seq_id, seq_len + 1 + written);
+    }
+
+    fn retain(&mut self, live: impl Fn(usize) -> bool) {
+        self.cached_lens.retain(|seq_id, _| live(*seq_id));
+    }
+
+    fn clear(&mut self) {
+        self.cached_lens.clear();
+    }
+}
+
+/// Split positions `start..end` into prefill chunks of at most `chunk_size`.
+fn chunk_ranges(start: usize, end: usize, chunk_size: usize) -> Vec<Range<usize>> {
+    (start..end)
+        .step_by(chunk_size.max(1))
+        .map(|from| from..(from + chunk_size.max(1)).min(end))
+        .collect()
+}
+
+pub(super) struct DraftModel {
+    pipeline: Box<DefaultPipeline>,
+    cache_engine: CacheEngine,
+    num_speculative: usize,
+    max_model_len: usize,
+    is_mla: bool,
+    progress: DraftProgress,
+}
+
+/// One draft forward: the positions it covers and its metadata.
+struct DraftForward {
+    positions: Range<usize>,
+    position_ids: Tensor,
+    metadata: InputMetadata,
+}
+
+/// A prompt-lookup verify prepared while the engine lock is held.
+pub(super) struct LookupStep {
+    seq_id: usize,
+    /// The sequence's last token followed by the proposed tokens.
+    verify_tokens: Vec<u32>,
+    verify_positions: Tensor,
+    verify_metadata: InputMetadata,
+    target_is_hybrid: bool,
+}
+
+/// A speculative step prepared while the engine lock is held.
+pub(super) struct DraftStep {
+    seq_id: usize,
+    seq_len: usize,
+    /// Sequence tokens the draft has not cached, starting at `forwards[0]`.
+    pending_tokens: Vec<u32>,
+    /// Catch-up chunks ending with the anchor, then one forward per draft.
+    forwards: Vec<DraftForward>,
+    verify_positions: Tensor,
+    verify_metadata: InputMetadata,
+    target_is_hybrid: bool,
+}
+
+impl DraftModel {
+    fn argmax(logits: &Tensor) -> Result<u32> {
+        let last = logits.dim(0)? - 1;
+        logits
+            .get(last)?
+            .to_dtype(DType::F32)?
+            .argmax(D::Minus1)?
+            .to_scalar::<u32>()
+    }
+
+    /// Catch up on the pending tokens, then propose `num_speculative` tokens
+    /// after `anchor`, greedily.
+    fn propose(&self, step: &DraftStep, anchor: u32) -> Result<Vec<u32>> {
+        let device = self.pipeline.device();
+        let kv_cache = self.cache_engine.get_kv_cache();
+        let _prefill_guard = set_linear_is_prefill(true);
+        let base = step.seq_len - step.pending_tokens.len();
+        let mut tokens = step.pending_tokens.clone();
+        tokens.push(anchor);
+        let mut drafts = Vec::with_capacity(self.num_speculative);
+        for forward in &step.forwards {
+            let input = if forward.positions.start > step.seq_len {
+                vec![*drafts.last().unwrap()]
+            } else {
+                tokens[forward.positions.start - base..forward.positions.end - base].to_vec()
+            };
+            let len = input.len();
+            let logits = self.pipeline.forward(
+                Tensor::from_vec(input, (len,), device)?,
+                &forward.position_ids,
+                Some(&kv_cache),
+                &forward.metadata,
+                None,
+            )?;
+            if forward.positions.end > step.seq_len {
+                drafts.push(Self::argmax(&logits)?);
+            }
+        }
+        Ok(drafts)
+    }
+}
+
+impl LLMEngine {
+    /// Whether ranks run in daemon processes rather than on engine threads;
+    /// without nccl, `multi_process` engines still step on threads.
+    fn uses_multiprocess_runner(&self) -> bool {
+        self.multi_process && cfg!(feature = "nccl")
+    }
+
+    /// Use `pipeline` as a draft model proposing `num_speculative` tokens per
+    /// decode step. Its cache must have as many GPU blocks as the target's.
+    pub fn set_draft_model(
+        &mut self,
+        pipeline: Box<DefaultPipeline>,
+        cache_engine: CacheEngine,
+        num_speculative: usize,
+    ) -> Result<()> {
+        if num_speculative == 0 {
+            candle_core::bail!("--draft-tokens must be at least 1");
+        }
+        if cfg!(feature = "flashinfer") {
+            candle_core::bail!("draft-model speculation is not supported with flashinfer");
+        }
+        if self.uses_multiprocess_runner() || self.pipelines.len() != 1 {
+            candle_core::bail!("draft-model speculation requires a single-rank engine");
+        }
+        let (target, _) = self.get_pipeline(0).unwrap();
+        if target.has_mtp() {
+            candle_core::bail!("use either --mtp or --draft-model, not both");
+        }
+        let target_vocab = target.tokenizer.get_vocab_size(true);
+        let draft_vocab = pipeline.tokenizer.get_vocab_size(true);
+        if target_vocab != draft_vocab {
+            candle_core::bail!(
+                "draft model tokenizer has {} tokens but the target has {}; they must share a tokenizer",
+                draft_vocab,
+                target_vocab
+            );
+        }
+        let config = pipeline.get_model_config();
+        if crate::estimate_hybrid_mamba_cache(&config, pipeline.dtype, 1).is_some() {
+            candle_core::bail!("hybrid (mamba) models cannot be used as draft models");
+        }
+        info!(
+            "Draft model {} proposes {} token(s) per decode step",
+            pipeline.name(),
+            num_speculative
+        );
+        self.draft = Some(DraftModel {
+            pipeline,
+            cache_engine,
+            num_speculative,
+            max_model_len: config.max_seq_len,
+            is_mla: config.is_mla(),
+            progress: DraftProgress::default(),
+        });
+        Ok(())
+    }
+
+    /// Tokens the draft proposes for a sequence of `seq_len` tokens, or `None`
+    /// when there is no draft model or the proposals would pass the draft's or
+    /// the target's context.
+    pub(super) fn draft_tokens_for(&self, seq_len: usize) -> Option<usize> {
+        let draft = self.draft.as_ref()?;
+        (seq_len + draft.num_speculative < draft.max_model_len
+            && seq_len + draft.num_speculative <= self.config.max_seq_len)
+            .then_some(draft.num_speculative)
+    }
+
+    /// Forget draft KV after the target cache was swapped or copied; the
+    /// draft catches up from the start of each sequence on its next step.
+    pub(super) fn invalidate_draft_cache(&mut self) {
+        if let Some(draft) = self.draft.as_mut() {
+            draft.progress.clear();
+        }
+    }
+
+    /// Propose up to `num_speculative` tokens per decode step by prompt
+    /// lookup, matching trailing n-grams of at most `max_ngram` tokens.
+    pub fn set_prompt_lookup(&mut self, num_speculative: usize, max_ngram: usize) -> Result<()> {
+        if num_speculative == 0 || max_ngram == 0 {
+            candle_core::bail!("--prompt-lookup and --prompt-lookup-ngram must be at least 1");
+        }
+        if self.uses_multiprocess_runner() {
+            candle_core::bail!("prompt-lookup speculation is not supported in multi-process mode");
+        }
+        if self.draft.is_some() || self.pipelines.values().any(|(p, _)| p.has_mtp()) {
+            candle_core::bail!("use only one of --mtp, --draft-model and --prompt-lookup");
+        }
+        info!(
+            "Prompt lookup proposes up to {} token(s) per decode step from trailing {}-grams",
+            num_speculative, max_ngram
+        );
+        self.prompt_lookup = Some(PromptLookup {
+            num_speculative,
+            max_ngram,
+        });
+        Ok(())
+    }
+
+    /// Tokens prompt lookup may propose for a sequence of `seq_len` tokens,
+    /// or `None` when it is off or the proposals would pass the context.
+    pub(super) fn prompt_lookup_tokens_for(&self, seq_len: usize) -> Option<usize> {
+        let lookup = self.prompt_lookup?;
+        (seq_len + lookup.num_speculative <= self.config.max_seq_len)
+            .then_some(lookup.num_speculative)
+    }
+
+    /// Physical block ids of a sequence, for speculative slot mappings.
+    pub(super) fn speculative_block_table(&self, seq_id: usize) -> Result<Vec<u32>> {
+        Ok(self
+            .scheduler
+            .block_engine
+            .block_tables
+            .get(&seq_id)
+            .ok_or_else(|| candle_core::Error::msg("missing MTP block table"))?
+            .iter()
+            .map(|block| block.deref_mut().block_id as u32)
+            .collect())
+    }
+
+    /// Look up proposals for `seq` and lay out their verify forward, which
+    /// starts at the sequence's last token. `None` when nothing matched.
+    pub(super) fn prepare_lookup_step(
+        &self,
+        rank: usize,
+        device: &Device,
+        seq: &Arc<Sequence>,
+    ) -> Result<Option<LookupStep>> {
+        let lookup = self.prompt_lookup.unwrap();
+        let (seq_id, token_ids) = {
+            let seq = seq.deref();
+            (seq.get_id(), seq.get_token_ids())
+        };
+        let proposed = lookup_continuation(&token_ids, lookup.num_speculative, lookup.max_ngram);
+        if proposed.is_empty() {
+            return Ok(None);
+        }
+        let start = token_ids.len() - 1;
+        let mut verify_tokens = vec![token_ids[start]];
+        verify_tokens.extend(proposed);
+        let verify_len = verify_tokens.len();
+
+        let block_table = self.speculative_block_table(seq_id)?;
+        let slot_mappings = Self::compute_mtp_slot_mappings(
+            &block_table,
+            start,
+            verify_len,
+            self.cache_config.block_size,
+        )?;
+        let verify_positions = Tensor::from_vec(
+            (start..start + verify_len)
+                .map(|pos| pos as i64)
+                .collect::<Vec<_>>(),
+            (verify_len,),
+            device,
+        )?;
+        let verify_metadata = self.build_mtp_metadata(
+            rank,
+            device,
+            seq_id,
+            start,
+            &block_table,
+            slot_mappings,
+            verify_len,
+        )?;
+        Ok(Some(LookupStep {
+            seq_id,
+            verify_tokens,
+            verify_positions,
+            verify_metadata,
+            target_is_hybrid: self
+                .scheduler
+                .block_engine
+                .requires_mamba_prefix_snapshots(),
+        }))
+    }
+
+    fn draft_forward(
+        &self,
+        device: &Device,
+        seq_id: usize,
+        block_table: &[u32],
+        positions: Range<usize>,
+        is_mla: bool,
+    ) -> Result<DraftForward> {
+        let q_len = positions.len();
+        let total_kv_len = positions.end as u32;
+        let slot_mappings = Self::compute_mtp_slot_mappings(
+            block_table,
+            positions.start,
+            q_len,
+            self.cache_config.block_size,
+        )?;
+        let position_ids = Tensor::from_vec(
+            positions.clone().map(|pos| pos as i64).collect::<Vec<_>>(),
+            (q_len,),
+            device,
+        )?;
+        let metadata = InputMetadata {
+            is_prefill: true,
+            is_mla,
+            sequence_ids: Some(vec![seq_id]),
+            mamba_slot_mapping: None,
+            slot_mapping: Tensor::from_vec(slot_mappings, (q_len,), device)?,
+            block_tables: Some(Tensor::from_vec(
+                block_table.to_vec(),
+                (1, block_table.len()),
+                device,
+            )?),
+            block_tables_host: None,
+            context_lens_host: None,
+            context_lens: Some(Tensor::from_vec(vec![total_kv_len], (1,), device)?),
+            cu_seqlens_q: Some(Tensor::from_vec(vec![0u32, q_len as u32], (2,), device)?),
+            cu_seqlens_k: Some(Tensor::from_vec(vec![0u32, total_kv_len], (2,), device)?),
+            max_seqlen_q: q_len,
+            max_seqlen_k: positions.end,
+            max_context_len: positions.end,
+            seqlens: Some(vec![q_len as u32]),
+            flashinfer_metadata: None,
+            is_mtp_verify: false,
+        };
+        Ok(DraftForward {
+            positions,
+            position_ids,
+            metadata,
+        })
+    }
+
+    /// Plan the draft forwards of one step: catch-up chunks over the tokens
+    /// the draft has not seen, ending with the anchor at `seq_len`, then one
+    /// single-token forward per further draft.
+    pub(super) fn prepare_draft_step(
+        &self,
+        device: &Device,
+        seq: &Arc<Sequence>,
+        block_table: &[u32],
+        verify_positions: Tensor,
+        verify_metadata: InputMetadata,
+    ) -> Result<DraftStep> {
+        let seq_id = seq.deref().get_id();
+        let seq_len = seq.deref().get_len();
+        let draft = self.draft.as_ref().unwrap();
+        let (num_speculative, is_mla) = (draft.num_speculative, draft.is_mla);
+        let start = draft.progress.pending_from(seq_id, seq_len);
+        let pending_tokens = seq.deref().get_token_ids()[start..seq_len].to_vec();
+
+        let chunk_size = self.prefill_chunk_size.unwrap_or(PREFILL_CHUNK_SIZE);
+        let mut forwards = Vec::new();
+        for positions in chunk_ranges(start, seq_len + 1, chunk_size)
+            .into_iter()
+            .chain((seq_len + 1..seq_len + num_speculative).map(|pos| pos..pos + 1))
+        {
+            forwards.push(self.draft_forward(device, seq_id, block_table, positions, is_mla)?);
+        }
+        Ok(DraftStep {
+            seq_id,
+            seq_len,
+            pending_tokens,
+            forwards,
+            verify_positions,
+            verify_metadata,
+            target_is_hybrid: self
+                .scheduler
+                .block_engine
+                .requires_mamba_prefix_snapshots(),
+        })
+    }
+
+    /// Results of a verified speculative step: the anchor, the accepted
+    /// drafts and the target's continuation, cut at the request's max tokens.
+    pub(super) fn speculative_results(
+        pipeline: &mut DefaultPipeline,
+        scheduled: &VecDeque<Arc<SequenceGroup>>,
+        anchor_result: Vec<TokenOrFinishReason>,
+        accepted_tokens: &[u32],
+        continuation_token: u32,
+    ) -> Result<Vec<TokenOrFinishReason>> {
+        let mut results = anchor_result;
+        let mut extra_tokens = accepted_tokens.to_vec();
+        extra_tokens.push(continuation_token);
+
+        let group = scheduled.front().unwrap();
+        let seq = Self::primary_sequence(group);
+        let generated_before_anchor = seq
+            .deref()
+            .get_len()
+            .saturating_sub(seq.deref().get_prompt_len());
+        let remaining_after_anchor = group
+            .sampling_params
+            .max_tokens
+            .saturating_sub(generated_before_anchor + 1);
+        extra_tokens.truncate(remaining_after_anchor);
+
+        if !extra_tokens.is_empty() {
+            let mut extra_results = pipeline.tokens_to_results(&extra_tokens, scheduled)?;
+            results.append(&mut extra_results);
+        }
+        Ok(results)
+    }
+
+    /// Decode the anchor token with the target, let the draft propose from
+    /// it, and verify the proposals in one target forward.
+    #[allow(clippy::too_many_arguments)]
+    pub(super) fn run_draft_step(
+        pipeline: &mut DefaultPipeline,
+        cache_engine: &CacheEngine,
+        draft: &mut DraftModel,
+        scheduled: &VecDeque<Arc<SequenceGroup>>,
+        tokens: Tensor,
+        positions: &Tensor,
+        metadata: &InputMetadata,
+        step: DraftStep,
+    ) -> Result<(Tensor, Vec<TokenOrFinishReason>)> {
+        let logits = pipeline.forward(
+            tokens,
+            positions,
+            Some(&cache_engine.get_kv_cache()),
+            metadata,
+            None,
+        )?;
+        let anchor_result = pipeline.sample(&logits, scheduled)?;
+        let anchor_token = match anchor_result.first() {
+            Some(Either::Left(anchor_logprobs)) => anchor_logprobs.token,
+            _ => return Ok((logits, anchor_result)),
+        };
+        let draft_tokens = draft.propose(&step, anchor_token)?;
+
+        let mut verify_tokens = vec![anchor_token];
+        verify_tokens.extend_from_slice(&draft_tokens);
+        let verify_len = verify_tokens.len();
+        let verify_input = Tensor::from_vec(verify_tokens, (verify_len,), pipeline.device())?;
+        let all_logits = {
+            let _prefill_guard = set_linear_is_prefill(true);
+            pipeline.forward(
+                verify_input,
+                &step.verify_positions,
+                Some(&cache_engine.get_kv_cache()),
+                &step.verify_metadata,
+                None,
+            )?
+        };
+        let verify_result =
+            pipeline.verify_speculative(&all_logits, &scheduled[0], anchor_token, &draft_tokens)?;
+        if verify_result.num_accepted < verify_result.num_proposed && step.target_is_hybrid {
+            let commit_len = 1 + verify_result.num_accepted;
+            if !pipeline.mtp_rollback_mamba(step.seq_id, commit_len)? {
+                candle_core::bail!(
+                    "Draft verify failed to roll back mamba state for seq {} to {} verified token(s)",
+                    step.seq_id,
+                    commit_len
+                );
+            }
+        }
+        draft.progress.commit(
+            step.seq_id,
+            step.seq_len,
+            verify_result.num_proposed,
+            verify_result.num_accepted,
+        );
+
+        let results = Self::speculative_results(
+            pipeline,
+            scheduled,
+            anchor_result,
+            &verify_result.accepted_tokens,
+            verify_result.continuation_token,
+        )?;
+        let steps = DRAFT_STATS.update(verify_result.num_proposed, verify_result.num_accepted);
+        if steps % MTP_STATS_LOG_INTERVAL_STEPS == 0 {
+            info!(
+                "Draft step={} proposed={} accepted={} {}",
+                steps,
+                verify_result.num_proposed,
+                verify_result.num_accepted,
+                DRAFT_STATS.summary()
+            );
+        }
+        Ok((all_logits, results))
+    }
+
+    /// Decode the last token and verify the looked-up proposals in one
+    /// forward: the first row is sampled as the anchor and must match the
+    /// first proposal for the rest to be checked. Without proposals this is
+    /// a plain decode step. Copied spans carry no real logprobs, so requests
+    /// that asked for them never reach this path.
+    #[allow(clippy::too_many_arguments)]
+    pub(super) fn run_lookup_step(
+        pipeline: &mut DefaultPipeline,
+        cache_engine: &CacheEngine,
+        scheduled: &VecDeque<Arc<SequenceGroup>>,
+        tokens: Tensor,
+        positions: &Tensor,
+        metadata: &InputMetadata,
+        step: Option<LookupStep>,
+    ) -> Result<(Tensor, Vec<TokenOrFinishReason>)> {
+        let Some(step) = step else {
+            let logits = pipeline.forward(
+                tokens,
+                positions,
+                Some(&cache_engine.get_kv_cache()),
+                metadata,
+                None,
+            )?;
+            let results = pipeline.sample(&logits, scheduled)?;
+            return Ok((logits, results));
+        };
+        debug_assert!(!scheduled[0].use_logprobs);
+        let verify_len = step.verify_tokens.len();
+        let proposed = &step.verify_tokens[1..];
+        let verify_input =
+            Tensor::from_vec(step.verify_tokens.clone(), (verify_len,), pipeline.device())?;
+        let all_logits = {
+            let _prefill_guard = set_linear_is_prefill(true);
+            pipeline.forward(
+                verify_input,
+                &step.verify_positions,
+                Some(&cache_engine.get_kv_cache()),
+                &step.verify_metadata,
+                None,
+            )?
+        };
+        let anchor_result = pipeline.sample(&all_logits.narrow(0, 0, 1)?, scheduled)?;
+        let verify_result = match anchor_result.first() {
+            // Sampling the anchor and keeping the first proposal only when
+            // they match is already its speculative-sampling test.
+            Some(Either::Left(anchor_logprobs)) if anchor_logprobs.token == proposed[0] => {
+                Some(pipeline.verify_speculative(
+                    &all_logits.narrow(0, 1, verify_len - 1)?,
+                    &scheduled[0],
+                    proposed[0],
+                    &proposed[1..],
+                )?)
+            }
+            _ => None,
+        };
+        let num_accepted = verify_result
+            .as_ref()
+            .map_or(0, |result| 1 + result.num_accepted);
+        if num_accepted < proposed.len() && step.target_is_hybrid {
+            let commit_len = 1 + num_accepted;
+            if !pipeline.mtp_rollback_mamba(step.seq_id, commit_len)? {
+                candle_core::bail!(
+                    "Prompt lookup failed to roll back mamba state for seq {} to {} verified token(s)",
+                    step.seq_id,
+                    commit_len
+                );
+            }
+        }
+
+        let results = match verify_result {
+            Some(verify_result) => Self::speculative_results(
+                pipeline,
+                scheduled,
+                anchor_result,
+                &verify_result.accepted_tokens,
+                verify_result.continuation_token,
+            )?,
+            None => anchor_result,
+        };
+        let steps = LOOKUP_STATS.update(proposed.len(), num_accepted);
+        if steps % MTP_STATS_LOG_INTERVAL_STEPS == 0 {
+            info!(
+                "Prompt lookup step={} proposed={} accepted={} {}",
+                steps,
+                proposed.len(),
+                num_accepted,
+                LOOKUP_STATS.summary()
+            );
+        }
+        Ok((all_logits, results))
+    }
+
+    /// Roll back the KV slots reserved for a speculative step once its
+    /// accepted tokens are applied.
+    pub(super) fn release_speculative_slots(&mut self, scheduled: &VecDeque<Arc<SequenceGroup>>) {
+        for group in scheduled {
+            for seq in group.get_seqs().values() {
+                self.scheduler
+                    .block_engine
+                    .release_speculative_slots_for_seq(seq);
+            }
+        }
+    }
+
+    /// Detach the draft model while a batch runs without the engine lock.
+    pub(super) fn take_draft(&mut self) -> Option<DraftModel> {
+        self.draft.take()
+    }
+
+    /// Reattach the draft, forgetting sequences that have been freed since.
+    pub(super) fn restore_draft(&mut self, mut draft: DraftModel) {
+        let block_tables = &self.scheduler.block_engine.block_tables;
+        draft
+            .progress
+            .retain(|seq_id| block_tables.contains_key(&seq_id));
+        self.draft = Some(draft);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draft_progress_keeps_only_verified_draft_kv() {
+        let mut progress = DraftProgress::default();
+        assert_eq!(progress.pending_from(7, 10), 0);
+
+        // Anchor at 10, drafts at 11..=13 (the last one never written).
+        progress.commit(7, 10, 4, 1);
+        assert_eq!(progress.pending_from(7, 13), 12);
+        progress.commit(7, 13, 4, 4);
+        assert_eq!(progress.pending_from(7, 19), 17);
+        // A sequence cut short never reads past its last token.
+        assert_eq!(progress.pending_from(7, 15), 14);
+
+        progress.retain(|id| id != 7);
+        assert_eq!(progress.pending_from(7, 19), 0);
+    }
+
+    #[test]
+    fn catch_up_is_split_into_prefill_chunks() {
+        assert_eq!(chunk_ranges(0, 5, 2), vec![0..2, 2..4, 4..5]);
+        assert_eq!(chunk_ranges(9, 10, 8192), vec![9..10]);
+        assert!(chunk_ranges(3, 3, 4).is_empty());
+    }
+
+    #[test]
+    fn stats_summary_counts_anchor_and_continuation() {
+        let stats = SpeculationStats::new("Test", 2);
+        assert_eq!(stats.update(4, 2), 1);
+        assert_eq!(stats.update(4, 4), 2);
+        assert_eq!(
+            stats.summary(),
+            "Test Stats: proposed=8, accepted=6, acceptance_rate=75.00%, avg_tokens/step=5.00"
+        );
+    }
+
+    #[test]
+    fn lookup_proposes_what_followed_the_longest_recent_match() {
+        // Trailing `7 8` last occurred at 4..6.
+        let tokens = [3, 8, 5, 1, 7, 8, 9, 4, 6, 7, 8];
+        assert_eq!(lookup_continuation(&tokens, 3, 3), vec![9, 4, 6]);
+        assert_eq!(lookup_continuation(&tokens, 3, 1), vec![9, 4, 6]);
+        assert_eq!(
+            lookup_continuation(&[1, 2, 3, 1, 2, 3], 4, 3),
+            vec![1, 2, 3]
+        );
+        // Proposals stop at the end of the sequence.
+        assert_eq!(lookup_continuation(&[5, 6, 5], 4, 2), vec![6, 5]);
+        assert!(lookup_continuation(&[1, 2, 3, 4], 4, 3).is_empty());
+        assert!(lookup_continuation(&[1], 4, 3).is_empty());
+    }
+
+    #[test]
+    fn lookup_stats_count_only_the_continuation_as_extra() {
+        let stats = SpeculationStats::new("Test", 1);
+        stats.update(3, 0);
+        stats.update(3, 3);
+        assert_eq!(
+            stats.summary(),
+            "Test Stats: proposed=6, accepted=3, acceptance_rate=50.00%, avg_tokens/step=2.50"
+        );
+    }
+}
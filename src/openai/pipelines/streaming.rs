@@ -5,7 +5,7 @@ use tokio::sync::mpsc::Sender;
 use super::{
     BufferedFinalizeResult, ChatCompletionChunk, ChatCompletionUsageResponse, Choice, ChoiceData,
     DefaultPipeline, LLMEngine, Logprobs, ParserState, Sequence, SequenceGroup, StreamEmission,
-    StreamResult, StreamToolParser,
+    StreamResult, StreamToolParser, WrapperLogprobs,
 };
 use crate::openai::ToolChoiceKind;
 use crate::openai::{streaming::ChatResponse, utils::get_created_time_secs};
@@ -93,6 +93,7 @@ impl LLMEngine {
         &self,
         request_id: String,
         created: u64,
+        index: usize,
         role: Option<String>,
         content: Option<String>,
         reasoning_content: Option<String>,
@@ -110,7 +111,8 @@ impl LLMEngine {
                 tool_calls,
             },
             finish_reason,
-            index: 0,
+            index,
+            logprobs: None,
         };
         choices.push(choice);
 
@@ -120,7 +122,7 @@ impl LLMEngine {
             created,
             model: pipeline.name().to_string(),
             object: "chat.completion.chunk",
-            system_fingerprint: None,
+            system_fingerprint: Some(self.system_fingerprint().to_string()),
             usage,
         }
     }
@@ -147,9 +149,11 @@ impl LLMEngine {
             return true;
         }
 
+        let index = group.choice_index(seq.deref().get_id());
         let chunk = self.get_stream_response(
             group.request_id.clone(),
             group.arrival_time,
+            index,
             Some("assistant".to_string()),
             None,
             None,
@@ -360,6 +364,7 @@ impl LLMEngine {
             content,
             reasoning_content,
             tool_calls: None,
+            logprobs: None,
         }
     }
 
@@ -599,6 +604,7 @@ impl LLMEngine {
             content,
             reasoning_content: None,
             tool_calls,
+            logprobs: None,
         }
     }
 
@@ -615,10 +621,15 @@ impl LLMEngine {
             && emission.tool_calls.is_none()
             && (emission.content.is_some() || emission.reasoning_content.is_some());
         let (pipeline, _) = self.get_pipeline(rank).unwrap();
+        let index = group.choice_index(seq.deref().get_id());
         let has_payload = emission.tool_calls.is_some()
             || emission.content.is_some()
             || emission.reasoning_content.is_some()
+            || emission.logprobs.is_some()
             || finish_reason.is_some();
+        let logprobs = emission.logprobs.map(|logprobs| WrapperLogprobs {
+            content: vec![logprobs],
+        });
 
         if has_payload && !self.maybe_send_stream_role_start(sender, group, seq, pipeline) {
             warn!(
@@ -637,6 +648,7 @@ impl LLMEngine {
                 let reasoning_chunk = self.get_stream_response(
                     group.request_id.clone(),
                     get_created_time_secs(),
+                    index,
                     None,
                     None,
                     Some(reasoning),
@@ -662,6 +674,7 @@ impl LLMEngine {
                 let content_chunk = self.get_stream_response(
                     group.request_id.clone(),
                     get_created_time_secs(),
+                    index,
                     None,
                     Some(content),
                     None,
@@ -683,9 +696,10 @@ impl LLMEngine {
             // OpenAI streaming spec: tool calls require TWO separate chunks:
             //   1) delta.tool_calls=[...], finish_reason=null
             //   2) delta={}, finish_reason="tool_calls"
-            let tool_chunk = self.get_stream_response(
+            let mut tool_chunk = self.get_stream_response(
                 group.request_id.clone(),
                 get_created_time_secs(),
+                index,
                 None,
                 None,
                 None,
@@ -700,6 +714,7 @@ impl LLMEngine {
                 None,
                 pipeline,
             );
+            tool_chunk.choices[0].logprobs = logprobs;
             tracing::info!("Sending tool call delta chunk: {:?}", tool_chunk);
             if sender.try_send(ChatResponse::Chunk(tool_chunk)).is_err() {
                 warn!(
@@ -713,6 +728,7 @@ impl LLMEngine {
             let finish_chunk = self.get_stream_response(
                 group.request_id.clone(),
                 get_created_time_secs(),
+                index,
                 None,
                 None,
                 None,
@@ -730,9 +746,10 @@ impl LLMEngine {
                 seq.deref_mut().set_finish_reason("abort".to_string());
             }
         } else {
-            let chunk = self.get_stream_response(
+            let mut chunk = self.get_stream_response(
                 group.request_id.clone(),
                 group.arrival_time,
+                index,
                 None,
                 emission.content,
                 emission.reasoning_content,
@@ -741,6 +758,7 @@ impl LLMEngine {
                 None,
                 pipeline,
             );
+            chunk.choices[0].logprobs = logprobs;
 
             let ret = sender.try_send(ChatResponse::Chunk(chunk));
             if ret.is_err() {
@@ -116,6 +116,7 @@ impl ThreadedRunner {
                         break;
                     }
                 }
+                e.release_speculative_slots(&scheduled);
             } else {
                 let results = {
                     let default_pipeline = e.get_mut_pipeline(0usize).unwrap().0.as_mut();
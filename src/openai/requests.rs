@@ -1,5 +1,6 @@
 use std::collections::{HashMap, HashSet};
 
+use super::sampling_params::{EarlyStoppingCondition, Priority};
 use serde::{Deserialize, Serialize};
 
 pub const EMPTY_TOOL_RESULT_ACK: &str = "Tool executed successfully with no textual output.";
@@ -216,6 +217,26 @@ pub struct StreamOptions {
     pub include_usage: bool,
 }
 
+/// OpenAI `response_format`: free text, any JSON object, or JSON matching a schema.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    Text,
+    JsonObject,
+    JsonSchema { json_schema: JsonSchemaFormat },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonSchemaFormat {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub schema: Option<serde_json::Value>,
+    #[serde(default)]
+    pub strict: Option<bool>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatCompletionRequest {
     pub model: Option<String>,
@@ -246,6 +267,12 @@ pub struct ChatCompletionRequest {
     pub best_of: Option<usize>, //None
     #[serde(default)]
     pub use_beam_search: Option<bool>, //false
+    /// Exponent of the output length that beam scores are divided by.
+    #[serde(default)]
+    pub length_penalty: Option<f32>, //1.0
+    /// When beam search stops: `true`, `false` or `"never"`.
+    #[serde(default)]
+    pub early_stopping: Option<EarlyStoppingCondition>, //false
     #[serde(default)]
     pub ignore_eos: Option<bool>, //false
     #[serde(default)]
@@ -263,6 +290,22 @@ pub struct ChatCompletionRequest {
     pub tools: Option<Vec<crate::tools::Tool>>,
     #[serde(default)]
     pub tool_choice: Option<crate::tools::ToolChoice>,
+    /// Seed for reproducible sampling.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Constrain the output to JSON, optionally matching a schema.
+    #[serde(default)]
+    pub response_format: Option<ResponseFormat>,
+    /// Seconds allowed from arrival before the request finishes with `timeout`.
+    #[serde(default)]
+    pub timeout: Option<f64>,
+    /// Id to run the request under instead of a generated one, so it can be
+    /// cancelled through `DELETE /v1/requests/{request_id}` before it returns.
+    #[serde(default)]
+    pub request_id: Option<String>,
+    /// Scheduling class: `interactive`, `normal` (default) or `batch`.
+    #[serde(default)]
+    pub priority: Option<Priority>,
 }
 
 impl Default for ChatCompletionRequest {
@@ -286,6 +329,8 @@ impl Default for ChatCompletionRequest {
             top_k: None,
             best_of: None,
             use_beam_search: None,
+            length_penalty: None,
+            early_stopping: None,
             ignore_eos: None,
             skip_special_tokens: None,
             stop_token_ids: None,
@@ -294,10 +339,113 @@ impl Default for ChatCompletionRequest {
             reasoning_effort: None,
             tools: None,
             tool_choice: None,
+            seed: None,
+            response_format: None,
+            timeout: None,
+            request_id: None,
+            priority: None,
         }
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CompletionPrompt {
+    String(String),
+    MultiString(Vec<String>),
+    Tokens(Vec<u32>),
+    MultiTokens(Vec<Vec<u32>>),
+}
+
+/// A single prompt of a text-completion request, either raw text that still
+/// needs tokenization or token ids that are fed to the engine untouched.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompletionPromptItem {
+    Text(String),
+    Tokens(Vec<u32>),
+}
+
+impl CompletionPrompt {
+    pub fn into_items(self) -> Vec<CompletionPromptItem> {
+        match self {
+            CompletionPrompt::String(s) => vec![CompletionPromptItem::Text(s)],
+            CompletionPrompt::MultiString(v) => {
+                v.into_iter().map(CompletionPromptItem::Text).collect()
+            }
+            CompletionPrompt::Tokens(t) => vec![CompletionPromptItem::Tokens(t)],
+            CompletionPrompt::MultiTokens(v) => {
+                v.into_iter().map(CompletionPromptItem::Tokens).collect()
+            }
+        }
+    }
+}
+
+/// Legacy OpenAI text-completion request (`/v1/completions`). The prompt is
+/// passed to the model as-is, without any chat template.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompletionRequest {
+    pub model: Option<String>,
+    pub prompt: CompletionPrompt,
+    /// Text that follows the completion; used for fill-in-the-middle prompts.
+    #[serde(default)]
+    pub suffix: Option<String>,
+    pub max_tokens: Option<usize>,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub min_p: Option<f32>,
+    pub top_k: Option<isize>,
+    #[serde(default)]
+    pub n: Option<usize>,
+    #[serde(default)]
+    pub best_of: Option<usize>,
+    #[serde(default)]
+    pub use_beam_search: Option<bool>,
+    #[serde(default)]
+    pub length_penalty: Option<f32>,
+    #[serde(default)]
+    pub early_stopping: Option<EarlyStoppingCondition>,
+    #[serde(default)]
+    pub stream: Option<bool>,
+    #[serde(default)]
+    pub stream_options: Option<StreamOptions>,
+    /// Number of most likely tokens to report per position (0 reports only the sampled token).
+    #[serde(default)]
+    pub logprobs: Option<usize>,
+    /// Prepend the prompt text to the returned completion.
+    #[serde(default)]
+    pub echo: Option<bool>,
+    #[serde(default)]
+    pub stop: Option<StopTokens>,
+    #[serde(default)]
+    pub presence_penalty: Option<f32>,
+    #[serde(default)]
+    pub frequency_penalty: Option<f32>,
+    pub repeat_last_n: Option<usize>,
+    #[serde(default)]
+    pub logit_bias: Option<HashMap<String, f32>>,
+    #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default)]
+    pub ignore_eos: Option<bool>,
+    #[serde(default)]
+    pub skip_special_tokens: Option<bool>,
+    #[serde(default)]
+    pub stop_token_ids: Option<Vec<usize>>,
+    /// Seed for reproducible sampling.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Seconds allowed from arrival before the request finishes with `timeout`.
+    #[serde(default)]
+    pub timeout: Option<f64>,
+    /// Id to run the request under instead of a generated one, so it can be
+    /// cancelled through `DELETE /v1/requests/{request_id}` before it returns.
+    #[serde(default)]
+    pub request_id: Option<String>,
+    /// Scheduling class: `interactive`, `normal` (default) or `batch`.
+    #[serde(default)]
+    pub priority: Option<Priority>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum EncodingFormat {
@@ -315,12 +463,17 @@ pub enum EmbeddingInput {
 }
 
 impl EmbeddingInput {
-    pub fn into_vec(self) -> Vec<String> {
+    /// One item per text to embed; token arrays are embedded as given.
+    pub fn into_items(self) -> Vec<CompletionPromptItem> {
         match self {
-            EmbeddingInput::String(s) => vec![s],
-            EmbeddingInput::MultiString(s) => s,
-            EmbeddingInput::Tokens(_) => unimplemented!("Token input not supported yet"),
-            EmbeddingInput::MultiTokens(_) => unimplemented!("Token input not supported yet"),
+            EmbeddingInput::String(s) => vec![CompletionPromptItem::Text(s)],
+            EmbeddingInput::MultiString(v) => {
+                v.into_iter().map(CompletionPromptItem::Text).collect()
+            }
+            EmbeddingInput::Tokens(t) => vec![CompletionPromptItem::Tokens(t)],
+            EmbeddingInput::MultiTokens(v) => {
+                v.into_iter().map(CompletionPromptItem::Tokens).collect()
+            }
         }
     }
 }
@@ -348,6 +501,38 @@ pub struct EmbeddingRequest {
     pub embedding_type: EmbeddingType,
 }
 
+/// Request body of `/tokenize`: either a raw `prompt`, or chat `messages`
+/// rendered through the chat template first.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenizeRequest {
+    pub model: Option<String>,
+    #[serde(default)]
+    pub prompt: Option<String>,
+    #[serde(default)]
+    pub messages: Option<Messages>,
+    #[serde(default)]
+    pub tools: Option<Vec<crate::tools::Tool>>,
+    #[serde(default)]
+    pub tool_choice: Option<crate::tools::ToolChoice>,
+    #[serde(default, alias = "enable_thinking")]
+    pub thinking: Option<bool>,
+    #[serde(default, alias = "reasoning")]
+    pub reasoning_effort: Option<String>,
+    #[serde(default)]
+    pub add_special_tokens: Option<bool>, //true
+    /// Also report how many leading tokens are already in the prefix cache.
+    #[serde(default)]
+    pub return_prefix_cache_match: Option<bool>, //false
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DetokenizeRequest {
+    pub model: Option<String>,
+    pub tokens: Vec<u32>,
+    #[serde(default)]
+    pub skip_special_tokens: Option<bool>, //false
+}
+
 impl Default for EncodingFormat {
     fn default() -> Self {
         Self::Float
@@ -357,9 +542,73 @@ impl Default for EncodingFormat {
 #[cfg(test)]
 mod tests {
     use super::{
-        validate_openai_tool_messages, ChatCompletionRequest, ChatMessage, MessageContentType,
+        validate_openai_tool_messages, ChatCompletionRequest, ChatMessage, CompletionPromptItem,
+        CompletionRequest, EmbeddingRequest, MessageContentType, Messages, TokenizeRequest,
     };
 
+    #[test]
+    fn completion_request_accepts_all_prompt_shapes() {
+        let parse = |prompt: &str| -> Vec<CompletionPromptItem> {
+            let request: CompletionRequest =
+                serde_json::from_str(&format!(r#"{{"prompt": {prompt}, "logprobs": 2}}"#))
+                    .expect("request should deserialize");
+            assert_eq!(request.logprobs, Some(2));
+            request.prompt.into_items()
+        };
+
+        assert_eq!(
+            parse(r#""def main(""#),
+            vec![CompletionPromptItem::Text("def main(".to_string())]
+        );
+        assert_eq!(
+            parse(r#"["a", "b"]"#),
+            vec![
+                CompletionPromptItem::Text("a".to_string()),
+                CompletionPromptItem::Text("b".to_string())
+            ]
+        );
+        assert_eq!(
+            parse("[1, 2, 3]"),
+            vec![CompletionPromptItem::Tokens(vec![1, 2, 3])]
+        );
+        assert_eq!(
+            parse("[[1, 2], [3]]"),
+            vec![
+                CompletionPromptItem::Tokens(vec![1, 2]),
+                CompletionPromptItem::Tokens(vec![3])
+            ]
+        );
+    }
+
+    #[test]
+    fn embedding_request_accepts_token_input() {
+        let parse = |input: &str| -> Vec<CompletionPromptItem> {
+            let request: EmbeddingRequest =
+                serde_json::from_str(&format!(r#"{{"input": {input}}}"#))
+                    .expect("request should deserialize");
+            request.input.into_items()
+        };
+
+        assert_eq!(
+            parse(r#"["a", "b"]"#),
+            vec![
+                CompletionPromptItem::Text("a".to_string()),
+                CompletionPromptItem::Text("b".to_string())
+            ]
+        );
+        assert_eq!(
+            parse("[5, 6]"),
+            vec![CompletionPromptItem::Tokens(vec![5, 6])]
+        );
+        assert_eq!(
+            parse("[[5], [6, 7]]"),
+            vec![
+                CompletionPromptItem::Tokens(vec![5]),
+                CompletionPromptItem::Tokens(vec![6, 7])
+            ]
+        );
+    }
+
     #[test]
     fn chat_completion_request_reads_stream_options() {
         let request: ChatCompletionRequest = serde_json::from_str(
@@ -421,4 +670,21 @@ mod tests {
         let err = validate_openai_tool_messages(&messages).unwrap_err();
         assert!(err.contains("no preceding assistant tool_calls"));
     }
+
+    #[test]
+    fn tokenize_request_accepts_prompt_or_chat_messages() {
+        let request: TokenizeRequest =
+            serde_json::from_str(r#"{"prompt": "hello", "add_special_tokens": false}"#).unwrap();
+        assert_eq!(request.prompt.as_deref(), Some("hello"));
+        assert_eq!(request.add_special_tokens, Some(false));
+        assert!(request.messages.is_none());
+
+        let request: TokenizeRequest = serde_json::from_str(
+            r#"{"messages": [{"role": "user", "content": "hi"}], "enable_thinking": false, "return_prefix_cache_match": true}"#,
+        )
+        .unwrap();
+        assert!(matches!(request.messages, Some(Messages::Chat(ref m)) if m.len() == 1));
+        assert_eq!(request.thinking, Some(false));
+        assert_eq!(request.return_prefix_cache_match, Some(true));
+    }
 }
@@ -1,3 +1,5 @@
+use super::anthropic_api::MessagesResponse;
+use super::responses_api::ResponseObject;
 use super::streaming::Streamer;
 use crate::openai::sampling_params::Logprobs;
 use axum::extract::Json;
@@ -5,26 +7,129 @@ use axum::http::{self, StatusCode};
 use axum::response::{sse::KeepAliveStream, IntoResponse, Sse};
 use derive_more::{Display, Error};
 use serde::{Deserialize, Serialize};
-#[derive(Debug, Display, Error, Serialize)]
+use std::collections::HashMap;
+/// Category of an API error, deciding its HTTP status and OpenAI `type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    InvalidRequest,
+    /// No valid API key was presented.
+    Unauthenticated,
+    /// The API key is valid but not allowed to use the route.
+    PermissionDenied,
+    NotFound,
+    /// The prompt (plus requested tokens) does not fit the model context.
+    ContextLengthExceeded,
+    RateLimited,
+    Internal,
+    /// The server cannot take the request right now, e.g. the KV cache is exhausted.
+    Unavailable,
+}
+
+impl ErrorKind {
+    pub fn status(self) -> StatusCode {
+        match self {
+            ErrorKind::InvalidRequest => StatusCode::BAD_REQUEST,
+            ErrorKind::Unauthenticated => StatusCode::UNAUTHORIZED,
+            ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+            ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            ErrorKind::ContextLengthExceeded => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorKind::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            ErrorKind::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorKind::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    pub fn error_type(self) -> &'static str {
+        match self {
+            ErrorKind::InvalidRequest | ErrorKind::ContextLengthExceeded => "invalid_request_error",
+            ErrorKind::Unauthenticated => "authentication_error",
+            ErrorKind::PermissionDenied => "permission_error",
+            ErrorKind::NotFound => "not_found_error",
+            ErrorKind::RateLimited => "rate_limit_error",
+            ErrorKind::Internal => "server_error",
+            ErrorKind::Unavailable => "service_unavailable_error",
+        }
+    }
+
+    fn default_code(self) -> Option<&'static str> {
+        match self {
+            ErrorKind::ContextLengthExceeded => Some("context_length_exceeded"),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Display, Error)]
 #[display(fmt = "Error: {data}")]
 pub struct APIError {
     data: String,
+    kind: Option<ErrorKind>,
+    param: Option<String>,
+    code: Option<String>,
 }
 
 impl APIError {
     pub fn new(data: String) -> Self {
-        Self { data }
+        Self {
+            data,
+            kind: None,
+            param: None,
+            code: None,
+        }
     }
 
     pub fn new_str(data: &str) -> Self {
-        Self {
-            data: data.to_string(),
-        }
+        Self::new(data.to_string())
     }
 
     pub fn from<T: ToString>(value: T) -> Self {
         Self::new(value.to_string())
     }
+
+    /// Override the kind implied by the responder variant carrying this error.
+    pub fn with_kind(mut self, kind: ErrorKind) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+
+    /// Name the request field that caused the error.
+    pub fn with_param(mut self, param: impl Into<String>) -> Self {
+        self.param = Some(param.into());
+        self
+    }
+
+    pub fn with_code(mut self, code: impl Into<String>) -> Self {
+        self.code = Some(code.into());
+        self
+    }
+
+    pub fn message(&self) -> &str {
+        &self.data
+    }
+
+    pub fn kind(&self) -> Option<ErrorKind> {
+        self.kind
+    }
+
+    /// The OpenAI error object, `{"error": {"message", "type", "param", "code"}}`.
+    pub fn to_json(&self, default_kind: ErrorKind) -> serde_json::Value {
+        let kind = self.kind.unwrap_or(default_kind);
+        serde_json::json!({
+            "error": {
+                "message": self.data,
+                "type": kind.error_type(),
+                "param": self.param,
+                "code": self.code.as_deref().or(kind.default_code()),
+            }
+        })
+    }
+
+    fn to_response(&self, default_kind: ErrorKind) -> axum::response::Response {
+        let status = self.kind.unwrap_or(default_kind).status();
+        let mut r = Json(self.to_json(default_kind)).into_response();
+        *r.status_mut() = status;
+        r
+    }
 }
 
 #[macro_export]
@@ -94,6 +199,7 @@ pub struct ChatCompletionResponse {
     pub created: u64,
     pub model: String,
     pub object: &'static str,
+    pub system_fingerprint: Option<String>,
     pub usage: ChatCompletionUsageResponse,
 }
 
@@ -113,6 +219,9 @@ pub struct Choice {
     pub delta: ChoiceData,
     pub finish_reason: Option<String>,
     pub index: usize,
+    /// The chunk's token, when the request asked for logprobs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<WrapperLogprobs>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -127,33 +236,85 @@ pub struct ChatCompletionChunk {
     pub usage: Option<ChatCompletionUsageResponse>,
 }
 
-trait ErrorToResponse: Serialize {
-    fn to_response(&self, code: StatusCode) -> axum::response::Response {
-        let mut r = Json(self).into_response();
-        *r.status_mut() = code;
-        r
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CompletionLogprobs {
+    pub tokens: Vec<String>,
+    pub token_logprobs: Vec<f32>,
+    pub top_logprobs: Vec<HashMap<String, f32>>,
+    pub text_offset: Vec<usize>,
+}
+
+impl CompletionLogprobs {
+    /// Convert per-token chat logprobs into the legacy completion layout,
+    /// keeping at most `top_n` alternatives per position. `offset` is the
+    /// character offset of the first generated token (non-zero when echoing).
+    pub fn from_logprobs(logprobs: &[Logprobs], top_n: usize, offset: usize) -> Self {
+        let mut result = Self::default();
+        let mut text_offset = offset;
+        for logprob in logprobs {
+            result.tokens.push(logprob.bytes.clone());
+            result.token_logprobs.push(logprob.logprob);
+            result.top_logprobs.push(
+                logprob
+                    .top_logprobs
+                    .iter()
+                    .take(top_n)
+                    .map(|top| (top.bytes.clone(), top.logprob))
+                    .collect(),
+            );
+            result.text_offset.push(text_offset);
+            text_offset += logprob.bytes.chars().count();
+        }
+        result
     }
 }
 
-#[derive(Serialize)]
-struct JsonError {
-    message: String,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompletionChoice {
+    pub text: String,
+    pub index: usize,
+    pub logprobs: Option<CompletionLogprobs>,
+    pub finish_reason: Option<String>,
 }
 
-impl JsonError {
-    fn new(message: String) -> Self {
-        Self { message }
-    }
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompletionResponse {
+    pub id: String,
+    pub choices: Vec<CompletionChoice>,
+    pub created: u64,
+    pub model: String,
+    pub object: &'static str,
+    pub system_fingerprint: Option<String>,
+    pub usage: ChatCompletionUsageResponse,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompletionChunk {
+    pub id: String,
+    pub choices: Vec<CompletionChoice>,
+    pub created: u64,
+    pub model: String,
+    pub object: &'static str,
+    pub system_fingerprint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<ChatCompletionUsageResponse>,
 }
-impl ErrorToResponse for JsonError {}
 
 pub enum ChatResponder {
     Streamer(Sse<KeepAliveStream<Streamer>>),
     Completion(ChatCompletionResponse),
+    TextCompletion(CompletionResponse),
     Embedding(EmbeddingResponse),
     ModelError(APIError),
     InternalError(APIError),
     ValidationError(APIError),
+    NotFound(APIError),
+    Unauthorized(APIError),
+    Forbidden(APIError),
+    /// Refused by admission control; the client may retry after the given seconds.
+    TooManyRequests(APIError, u64),
+    Response(ResponseObject),
+    Message(MessagesResponse),
 }
 
 impl IntoResponse for ChatResponder {
@@ -161,16 +322,29 @@ impl IntoResponse for ChatResponder {
         match self {
             ChatResponder::Streamer(s) => s.into_response(),
             ChatResponder::Completion(s) => Json(s).into_response(),
+            ChatResponder::TextCompletion(s) => Json(s).into_response(),
             ChatResponder::Embedding(s) => Json(s).into_response(),
-            ChatResponder::InternalError(e) => {
-                JsonError::new(e.to_string()).to_response(http::StatusCode::INTERNAL_SERVER_ERROR)
+            ChatResponder::InternalError(e) => e.to_response(ErrorKind::Internal),
+            ChatResponder::ValidationError(e) => e.to_response(ErrorKind::InvalidRequest),
+            ChatResponder::ModelError(e) => e.to_response(ErrorKind::Internal),
+            ChatResponder::NotFound(e) => e.to_response(ErrorKind::NotFound),
+            ChatResponder::Unauthorized(e) => {
+                let mut r = e.to_response(ErrorKind::Unauthenticated);
+                r.headers_mut().insert(
+                    http::header::WWW_AUTHENTICATE,
+                    http::HeaderValue::from_static("Bearer"),
+                );
+                r
             }
-            ChatResponder::ValidationError(e) => {
-                JsonError::new(e.to_string()).to_response(http::StatusCode::UNPROCESSABLE_ENTITY)
-            }
-            ChatResponder::ModelError(msg) => {
-                JsonError::new(msg.to_string()).to_response(http::StatusCode::INTERNAL_SERVER_ERROR)
+            ChatResponder::Forbidden(e) => e.to_response(ErrorKind::PermissionDenied),
+            ChatResponder::TooManyRequests(e, retry_after) => {
+                let mut r = e.to_response(ErrorKind::RateLimited);
+                r.headers_mut()
+                    .insert(http::header::RETRY_AFTER, retry_after.into());
+                r
             }
+            ChatResponder::Response(s) => Json(s).into_response(),
+            ChatResponder::Message(s) => Json(s).into_response(),
         }
     }
 }
@@ -195,6 +369,23 @@ pub struct EmbeddingUsage {
     pub total_tokens: usize,
 }
 
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenizeResponse {
+    pub count: usize,
+    pub max_model_len: usize,
+    pub tokens: Vec<u32>,
+    /// The rendered chat template, for `messages` input.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefix_cache_match_tokens: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DetokenizeResponse {
+    pub prompt: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EmbeddingResponse {
     pub object: &'static str,
@@ -203,9 +394,101 @@ pub struct EmbeddingResponse {
     pub usage: EmbeddingUsage,
 }
 
+impl EmbeddingResponse {
+    /// Combine the responses to each input of a request, indexing the
+    /// embeddings in input order and summing their usage.
+    pub fn concat(parts: Vec<EmbeddingResponse>) -> Option<Self> {
+        let mut parts = parts.into_iter();
+        let mut response = parts.next()?;
+        for mut part in parts {
+            response.data.append(&mut part.data);
+            response.usage.prompt_tokens += part.usage.prompt_tokens;
+            response.usage.total_tokens += part.usage.total_tokens;
+        }
+        for (index, item) in response.data.iter_mut().enumerate() {
+            item.index = index;
+        }
+        Some(response)
+    }
+}
+
+/// Progress of CUDA graph capture at engine start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CudaGraphStatus {
+    Pending,
+    Captured,
+    Disabled,
+    Unsupported,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RanksStatus {
+    pub num_shards: usize,
+    pub multi_process: bool,
+    /// Ranks served by pipelines in this process.
+    pub local_ranks: Vec<usize>,
+    /// Daemon processes paired through the heartbeat, in multi-process mode.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub heartbeat_peers: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seconds_since_heartbeat: Option<f64>,
+    pub heartbeat_failures: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KvCacheStatus {
+    pub block_size: usize,
+    pub num_gpu_blocks: usize,
+    pub num_free_gpu_blocks: usize,
+    pub capacity_tokens: usize,
+    pub free_tokens: usize,
+    pub usage: f64,
+    pub prefix_cache_blocks: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueStatus {
+    pub running: usize,
+    pub waiting: usize,
+    pub swapped: usize,
+    /// Requests received but not yet handed to the scheduler.
+    pub pending: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EngineLoopStatus {
+    pub running: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seconds_since_last_step: Option<f64>,
+    /// How long queued work has gone without a scheduling step.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seconds_stalled: Option<f64>,
+}
+
+/// Detailed engine state for the `/status` endpoint.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EngineStatus {
+    pub ready: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub not_ready_reasons: Vec<String>,
+    pub model: String,
+    pub cuda_graph: CudaGraphStatus,
+    pub engine_loop: EngineLoopStatus,
+    pub ranks: RanksStatus,
+    pub kv_cache: KvCacheStatus,
+    pub queue: QueueStatus,
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{ChatCompletionUsageResponse, CompletionTokensDetails, PromptTokensDetails};
+    use super::{
+        APIError, ChatCompletionUsageResponse, ChatResponder, CompletionTokensDetails,
+        EmbeddingData, EmbeddingOutput, EmbeddingResponse, EmbeddingUsage, ErrorKind,
+        PromptTokensDetails,
+    };
+    use axum::http::StatusCode;
+    use axum::response::IntoResponse;
 
     fn usage(
         prompt_tokens_details: Option<PromptTokensDetails>,
@@ -264,4 +547,88 @@ mod tests {
             Some(32)
         );
     }
+
+    #[test]
+    fn embedding_responses_concat_in_input_order() {
+        let part = |value: f32, tokens: usize| EmbeddingResponse {
+            object: "list",
+            data: vec![EmbeddingData {
+                object: "embedding",
+                embedding: EmbeddingOutput::Vector(vec![value]),
+                index: 0,
+            }],
+            model: "model".to_string(),
+            usage: EmbeddingUsage {
+                prompt_tokens: tokens,
+                total_tokens: tokens,
+            },
+        };
+        let response = EmbeddingResponse::concat(vec![part(1.0, 3), part(2.0, 5)]).unwrap();
+        let indices = response
+            .data
+            .iter()
+            .map(|item| item.index)
+            .collect::<Vec<_>>();
+        assert_eq!(indices, vec![0, 1]);
+        assert!(matches!(
+            &response.data[1].embedding,
+            EmbeddingOutput::Vector(v) if v == &vec![2.0]
+        ));
+        assert_eq!(response.usage.prompt_tokens, 8);
+        assert_eq!(response.usage.total_tokens, 8);
+        assert!(EmbeddingResponse::concat(Vec::new()).is_none());
+    }
+
+    #[test]
+    fn api_errors_render_openai_error_objects() {
+        let error = APIError::new_str("bad effort").with_param("reasoning_effort");
+        assert_eq!(
+            error.to_json(ErrorKind::InvalidRequest),
+            serde_json::json!({
+                "error": {
+                    "message": "bad effort",
+                    "type": "invalid_request_error",
+                    "param": "reasoning_effort",
+                    "code": null,
+                }
+            })
+        );
+
+        let error = APIError::new_str("too long").with_kind(ErrorKind::ContextLengthExceeded);
+        let body = error.to_json(ErrorKind::InvalidRequest);
+        assert_eq!(body["error"]["code"], "context_length_exceeded");
+        assert_eq!(body["error"]["type"], "invalid_request_error");
+    }
+
+    #[test]
+    fn responder_status_follows_error_kind() {
+        let status = |responder: ChatResponder| responder.into_response().status();
+        assert_eq!(
+            status(ChatResponder::ValidationError(APIError::new_str("x"))),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(ChatResponder::ValidationError(
+                APIError::new_str("x").with_kind(ErrorKind::ContextLengthExceeded)
+            )),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        assert_eq!(
+            status(ChatResponder::ValidationError(
+                APIError::new_str("x").with_kind(ErrorKind::Unavailable)
+            )),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            status(ChatResponder::NotFound(APIError::new_str("x"))),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status(ChatResponder::ModelError(APIError::new_str("x"))),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        let response = ChatResponder::TooManyRequests(APIError::new_str("x"), 3).into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["retry-after"], "3");
+    }
 }
@@ -0,0 +1,1143 @@
+//! OpenAI Responses API (`/v1/responses`). Input items are mapped onto chat
+//! messages and served through the chat completion path. Stored responses keep
+//! their conversation, so a follow-up turn naming `previous_response_id` only
+//! sends its new items and renders the same prompt prefix as before.
+
+use std::collections::{HashMap, VecDeque};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use super::requests::{
+    ChatCompletionRequest, ChatMessage, ImageUrlContent, MessageContent, MessageContentType,
+    Messages, StreamOptions,
+};
+use super::responses::{
+    APIError, ChatChoiceData, ChatCompletionChunk, ChatCompletionUsageResponse,
+};
+use crate::tools::{
+    Function, Tool, ToolCall, ToolChoice, ToolChoiceFunction, ToolChoiceMode, ToolChoiceType,
+};
+
+/// Responses kept for `previous_response_id` before the oldest is dropped.
+const DEFAULT_STORE_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ResponseInput {
+    Text(String),
+    Items(Vec<ResponseInputItem>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ResponseInputItem {
+    Typed(TypedInputItem),
+    /// A message without a `type` field.
+    Message {
+        role: String,
+        content: ResponseMessageContent,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TypedInputItem {
+    Message {
+        role: String,
+        content: ResponseMessageContent,
+    },
+    FunctionCall {
+        call_id: String,
+        name: String,
+        arguments: String,
+    },
+    FunctionCallOutput {
+        call_id: String,
+        output: ResponseMessageContent,
+    },
+    Reasoning {
+        #[serde(default)]
+        summary: Vec<ResponseText>,
+        #[serde(default)]
+        content: Vec<ResponseText>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ResponseMessageContent {
+    Text(String),
+    Parts(Vec<ResponseContentPart>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseContentPart {
+    InputText {
+        text: String,
+    },
+    OutputText {
+        text: String,
+    },
+    Refusal {
+        refusal: String,
+    },
+    InputImage {
+        #[serde(default)]
+        image_url: Option<String>,
+        #[serde(default)]
+        detail: Option<String>,
+    },
+}
+
+/// Typed text of reasoning items (`summary_text`, `reasoning_text`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseText {
+    #[serde(rename = "type")]
+    pub text_type: String,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseFunctionTool {
+    #[serde(rename = "type")]
+    pub tool_type: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ResponseToolChoice {
+    Mode(ToolChoiceMode),
+    Function {
+        #[serde(rename = "type")]
+        choice_type: ToolChoiceType,
+        name: String,
+    },
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResponseReasoning {
+    #[serde(default)]
+    pub effort: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+}
+
+/// Request body of `/v1/responses`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponsesRequest {
+    pub model: Option<String>,
+    pub input: ResponseInput,
+    /// System message for this turn only; it is not carried over to follow-ups.
+    #[serde(default)]
+    pub instructions: Option<String>,
+    #[serde(default)]
+    pub previous_response_id: Option<String>,
+    #[serde(default)]
+    pub tools: Option<Vec<ResponseFunctionTool>>,
+    #[serde(default)]
+    pub tool_choice: Option<ResponseToolChoice>,
+    #[serde(default)]
+    pub reasoning: Option<ResponseReasoning>,
+    #[serde(default)]
+    pub max_output_tokens: Option<usize>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub top_p: Option<f32>,
+    #[serde(default)]
+    pub stream: Option<bool>,
+    /// Whether to keep the response for `previous_response_id` (default true).
+    #[serde(default)]
+    pub store: Option<bool>,
+    #[serde(default)]
+    pub metadata: Option<Value>,
+    #[serde(default)]
+    pub user: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseOutputContent {
+    OutputText {
+        text: String,
+        annotations: Vec<Value>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseOutputItem {
+    Reasoning {
+        id: String,
+        summary: Vec<ResponseText>,
+        content: Vec<ResponseText>,
+    },
+    Message {
+        id: String,
+        role: &'static str,
+        status: &'static str,
+        content: Vec<ResponseOutputContent>,
+    },
+    FunctionCall {
+        id: String,
+        call_id: String,
+        name: String,
+        arguments: String,
+        status: &'static str,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InputTokensDetails {
+    pub cached_tokens: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OutputTokensDetails {
+    pub reasoning_tokens: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ResponseUsage {
+    pub input_tokens: usize,
+    pub input_tokens_details: InputTokensDetails,
+    pub output_tokens: usize,
+    pub output_tokens_details: OutputTokensDetails,
+    pub total_tokens: usize,
+}
+
+impl From<&ChatCompletionUsageResponse> for ResponseUsage {
+    fn from(usage: &ChatCompletionUsageResponse) -> Self {
+        Self {
+            input_tokens: usage.prompt_tokens,
+            input_tokens_details: InputTokensDetails {
+                cached_tokens: usage
+                    .prompt_tokens_details
+                    .as_ref()
+                    .map_or(0, |details| details.cached_tokens),
+            },
+            output_tokens: usage.completion_tokens,
+            output_tokens_details: OutputTokensDetails {
+                reasoning_tokens: usage
+                    .completion_tokens_details
+                    .as_ref()
+                    .map_or(0, |details| details.reasoning_tokens),
+            },
+            total_tokens: usage.total_tokens,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IncompleteDetails {
+    pub reason: &'static str,
+}
+
+/// A `response` object, as returned by `/v1/responses`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ResponseObject {
+    pub id: String,
+    pub object: &'static str,
+    pub created_at: u64,
+    pub status: &'static str,
+    pub model: String,
+    pub output: Vec<ResponseOutputItem>,
+    pub error: Option<Value>,
+    pub incomplete_details: Option<IncompleteDetails>,
+    pub instructions: Option<String>,
+    pub previous_response_id: Option<String>,
+    pub max_output_tokens: Option<usize>,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub reasoning: ResponseReasoning,
+    pub tools: Vec<ResponseFunctionTool>,
+    pub tool_choice: Value,
+    pub parallel_tool_calls: bool,
+    pub store: bool,
+    pub metadata: Value,
+    pub usage: Option<ResponseUsage>,
+}
+
+impl ResponseObject {
+    /// An in-progress response for `request`, with no output yet.
+    pub fn new(id: String, created_at: u64, model: String, request: &ResponsesRequest) -> Self {
+        Self {
+            id,
+            object: "response",
+            created_at,
+            status: "in_progress",
+            model,
+            output: Vec::new(),
+            error: None,
+            incomplete_details: None,
+            instructions: request.instructions.clone(),
+            previous_response_id: request.previous_response_id.clone(),
+            max_output_tokens: request.max_output_tokens,
+            temperature: request.temperature,
+            top_p: request.top_p,
+            reasoning: request.reasoning.clone().unwrap_or_default(),
+            tools: request.tools.clone().unwrap_or_default(),
+            tool_choice: request
+                .tool_choice
+                .as_ref()
+                .and_then(|choice| serde_json::to_value(choice).ok())
+                .unwrap_or(json!("auto")),
+            parallel_tool_calls: true,
+            store: request.store.unwrap_or(true),
+            metadata: request.metadata.clone().unwrap_or(json!({})),
+            usage: None,
+        }
+    }
+
+    /// Complete the response with its output items.
+    pub fn finish(
+        &mut self,
+        output: Vec<ResponseOutputItem>,
+        finish_reason: Option<&str>,
+        usage: Option<&ChatCompletionUsageResponse>,
+    ) {
+        self.output = output;
+        if finish_reason == Some("length") {
+            self.status = "incomplete";
+            self.incomplete_details = Some(IncompleteDetails {
+                reason: "max_output_tokens",
+            });
+        } else {
+            self.status = "completed";
+        }
+        self.usage = usage.map(ResponseUsage::from);
+    }
+}
+
+pub fn new_response_id() -> String {
+    format!("resp_{}", Uuid::new_v4().simple())
+}
+
+fn new_item_id(prefix: &str) -> String {
+    format!("{prefix}_{}", Uuid::new_v4().simple())
+}
+
+fn content_text(content: ResponseMessageContent) -> String {
+    match content {
+        ResponseMessageContent::Text(text) => text,
+        ResponseMessageContent::Parts(parts) => parts
+            .into_iter()
+            .filter_map(|part| match part {
+                ResponseContentPart::InputText { text }
+                | ResponseContentPart::OutputText { text } => Some(text),
+                ResponseContentPart::Refusal { refusal } => Some(refusal),
+                ResponseContentPart::InputImage { .. } => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+fn message_content(content: ResponseMessageContent) -> Result<MessageContentType, APIError> {
+    let parts = match content {
+        ResponseMessageContent::Text(text) => return Ok(MessageContentType::PureText(text)),
+        ResponseMessageContent::Parts(parts) => parts,
+    };
+    parts
+        .into_iter()
+        .map(|part| match part {
+            ResponseContentPart::InputText { text } | ResponseContentPart::OutputText { text } => {
+                Ok(MessageContent::Text { text })
+            }
+            ResponseContentPart::Refusal { refusal } => Ok(MessageContent::Text { text: refusal }),
+            ResponseContentPart::InputImage { image_url, detail } => image_url
+                .map(|url| MessageContent::ImageUrl {
+                    image_url: ImageUrlContent::Object { url, detail },
+                })
+                .ok_or(APIError::new_str(
+                    "`input_image` items must provide an `image_url`.",
+                )),
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(MessageContentType::Multi)
+}
+
+fn chat_message(role: &str, content: Option<MessageContentType>) -> ChatMessage {
+    ChatMessage {
+        role: role.to_string(),
+        content,
+        tool_calls: None,
+        tool_call_id: None,
+        reasoning_content: None,
+    }
+}
+
+/// Convert request input into chat messages. Function calls following an
+/// assistant message join it, and reasoning items attach to the assistant
+/// message that follows them.
+pub fn input_messages(input: ResponseInput) -> Result<Vec<ChatMessage>, APIError> {
+    let items = match input {
+        ResponseInput::Text(text) => {
+            return Ok(vec![chat_message(
+                "user",
+                Some(MessageContentType::PureText(text)),
+            )])
+        }
+        ResponseInput::Items(items) => items,
+    };
+    let mut messages: Vec<ChatMessage> = Vec::new();
+    let mut reasoning: Option<String> = None;
+    for item in items {
+        let item = match item {
+            ResponseInputItem::Typed(item) => item,
+            ResponseInputItem::Message { role, content } => {
+                TypedInputItem::Message { role, content }
+            }
+        };
+        match item {
+            TypedInputItem::Message { role, content } => {
+                let role = match role.as_str() {
+                    "developer" => "system",
+                    "user" | "assistant" | "system" => role.as_str(),
+                    other => {
+                        return Err(APIError::new(format!(
+                            "Unsupported message role '{other}'."
+                        )))
+                    }
+                };
+                let mut message = chat_message(role, Some(message_content(content)?));
+                if role == "assistant" {
+                    message.reasoning_content = reasoning.take();
+                }
+                messages.push(message);
+            }
+            TypedInputItem::FunctionCall {
+                call_id,
+                name,
+                arguments,
+            } => {
+                let call = ToolCall::new(call_id, name, arguments);
+                match messages.last_mut() {
+                    Some(last) if last.role == "assistant" && reasoning.is_none() => {
+                        last.tool_calls.get_or_insert_with(Vec::new).push(call);
+                    }
+                    _ => {
+                        let mut message = chat_message("assistant", None);
+                        message.reasoning_content = reasoning.take();
+                        message.tool_calls = Some(vec![call]);
+                        messages.push(message);
+                    }
+                }
+            }
+            TypedInputItem::FunctionCallOutput { call_id, output } => {
+                let mut message = chat_message(
+                    "tool",
+                    Some(MessageContentType::PureText(content_text(output))),
+                );
+                message.tool_call_id = Some(call_id);
+                messages.push(message);
+            }
+            TypedInputItem::Reasoning { summary, content } => {
+                let texts = if content.is_empty() { summary } else { content };
+                let text = texts
+                    .into_iter()
+                    .map(|text| text.text)
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                reasoning = Some(match reasoning {
+                    Some(previous) => format!("{previous}\n{text}"),
+                    None => text,
+                });
+            }
+        }
+    }
+    Ok(messages)
+}
+
+fn chat_tools(tools: &[ResponseFunctionTool]) -> Result<Vec<Tool>, APIError> {
+    tools
+        .iter()
+        .map(|tool| {
+            if tool.tool_type != "function" {
+                return Err(APIError::new(format!(
+                    "Unsupported tool type '{}'; only function tools are supported.",
+                    tool.tool_type
+                )));
+            }
+            let name = tool
+                .name
+                .clone()
+                .ok_or(APIError::new_str("Function tools must have a `name`."))?;
+            Ok(Tool {
+                tool_type: "function".to_string(),
+                function: Function {
+                    name,
+                    description: tool.description.clone(),
+                    parameters: tool
+                        .parameters
+                        .clone()
+                        .unwrap_or(json!({"type": "object", "properties": {}})),
+                    strict: tool.strict,
+                },
+            })
+        })
+        .collect()
+}
+
+fn reasoning_effort(reasoning: Option<&ResponseReasoning>) -> Option<String> {
+    let effort = reasoning?.effort.as_deref()?;
+    Some(match effort {
+        "minimal" => "low".to_string(),
+        other => other.to_string(),
+    })
+}
+
+/// Build the chat request for a turn: this turn's instructions, the stored
+/// conversation and the new input messages.
+pub fn chat_request(
+    request: &ResponsesRequest,
+    history: &[ChatMessage],
+    input: &[ChatMessage],
+) -> Result<ChatCompletionRequest, APIError> {
+    let mut messages = Vec::with_capacity(history.len() + input.len() + 1);
+    if let Some(instructions) = &request.instructions {
+        messages.push(chat_message(
+            "system",
+            Some(MessageContentType::PureText(instructions.clone())),
+        ));
+    }
+    messages.extend_from_slice(history);
+    messages.extend_from_slice(input);
+    let tools = chat_tools(request.tools.as_deref().unwrap_or_default())?;
+    let tool_choice = request.tool_choice.as_ref().map(|choice| match choice {
+        ResponseToolChoice::Mode(mode) => ToolChoice::Mode(mode.clone()),
+        ResponseToolChoice::Function { choice_type, name } => ToolChoice::Function {
+            choice_type: choice_type.clone(),
+            function: ToolChoiceFunction { name: name.clone() },
+        },
+    });
+    Ok(ChatCompletionRequest {
+        model: request.model.clone(),
+        messages: Messages::Chat(messages),
+        temperature: request.temperature,
+        top_p: request.top_p,
+        max_tokens: request.max_output_tokens,
+        stream: request.stream,
+        stream_options: Some(StreamOptions {
+            include_usage: true,
+        }),
+        user: request.user.clone(),
+        reasoning_effort: reasoning_effort(request.reasoning.as_ref()),
+        tools: (!tools.is_empty()).then_some(tools),
+        tool_choice,
+        ..Default::default()
+    })
+}
+
+/// Output items of a finished chat choice.
+pub fn output_items(message: &ChatChoiceData) -> Vec<ResponseOutputItem> {
+    let mut items = Vec::new();
+    if let Some(reasoning) = message.reasoning_content.as_ref().filter(|r| !r.is_empty()) {
+        items.push(ResponseOutputItem::Reasoning {
+            id: new_item_id("rs"),
+            summary: Vec::new(),
+            content: vec![ResponseText {
+                text_type: "reasoning_text".to_string(),
+                text: reasoning.clone(),
+            }],
+        });
+    }
+    if let Some(content) = message.content.as_ref().filter(|c| !c.is_empty()) {
+        items.push(ResponseOutputItem::Message {
+            id: new_item_id("msg"),
+            role: "assistant",
+            status: "completed",
+            content: vec![ResponseOutputContent::OutputText {
+                text: content.clone(),
+                annotations: Vec::new(),
+            }],
+        });
+    }
+    for call in message.tool_calls.iter().flatten() {
+        items.push(function_call_item(call));
+    }
+    items
+}
+
+fn function_call_item(call: &ToolCall) -> ResponseOutputItem {
+    ResponseOutputItem::FunctionCall {
+        id: new_item_id("fc"),
+        call_id: call.id.clone(),
+        name: call.function.name.clone(),
+        arguments: call.function.arguments.clone().unwrap_or_default(),
+        status: "completed",
+    }
+}
+
+/// The assistant turn of `output`, for the stored conversation.
+pub fn assistant_message(output: &[ResponseOutputItem]) -> Option<ChatMessage> {
+    let mut message = chat_message("assistant", None);
+    let mut text = String::new();
+    for item in output {
+        match item {
+            ResponseOutputItem::Reasoning { content, .. } => {
+                let reasoning = content.iter().map(|c| c.text.as_str()).collect::<String>();
+                message.reasoning_content = Some(reasoning);
+            }
+            ResponseOutputItem::Message { content, .. } => {
+                for ResponseOutputContent::OutputText { text: part, .. } in content {
+                    text.push_str(part);
+                }
+            }
+            ResponseOutputItem::FunctionCall {
+                call_id,
+                name,
+                arguments,
+                ..
+            } => message
+                .tool_calls
+                .get_or_insert_with(Vec::new)
+                .push(ToolCall::new(
+                    call_id.clone(),
+                    name.clone(),
+                    arguments.clone(),
+                )),
+        }
+    }
+    if text.is_empty() && message.tool_calls.is_none() && message.reasoning_content.is_none() {
+        return None;
+    }
+    message.content = Some(MessageContentType::PureText(text));
+    Some(message)
+}
+
+/// A finished response and the conversation that led to it, instructions
+/// excluded.
+#[derive(Debug, Clone)]
+pub struct StoredResponse {
+    pub response: ResponseObject,
+    pub messages: Vec<ChatMessage>,
+    /// Name of the API key that created the response; `None` when the
+    /// server runs without `--api-keys`.
+    pub owner: Option<String>,
+}
+
+/// In-process store of responses, evicting the oldest beyond its capacity.
+pub struct ResponseStore {
+    capacity: usize,
+    inner: Mutex<(HashMap<String, StoredResponse>, VecDeque<String>)>,
+}
+
+impl Default for ResponseStore {
+    fn default() -> Self {
+        Self::new(DEFAULT_STORE_CAPACITY)
+    }
+}
+
+impl ResponseStore {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new((HashMap::new(), VecDeque::new())),
+        }
+    }
+
+    pub fn insert(&self, stored: StoredResponse) {
+        let mut inner = self.inner.lock();
+        let (responses, order) = &mut *inner;
+        let id = stored.response.id.clone();
+        if responses.insert(id.clone(), stored).is_none() {
+            order.push_back(id);
+        }
+        while responses.len() > self.capacity {
+            let Some(oldest) = order.pop_front() else {
+                break;
+            };
+            responses.remove(&oldest);
+        }
+    }
+
+    /// The response with `id`, if `owner` created it. Another key's response
+    /// is reported as missing.
+    pub fn get(&self, id: &str, owner: Option<&str>) -> Option<StoredResponse> {
+        self.inner
+            .lock()
+            .0
+            .get(id)
+            .filter(|stored| stored.owner.as_deref() == owner)
+            .cloned()
+    }
+
+    pub fn remove(&self, id: &str, owner: Option<&str>) -> bool {
+        let mut inner = self.inner.lock();
+        let (responses, order) = &mut *inner;
+        if responses
+            .get(id)
+            .is_none_or(|stored| stored.owner.as_deref() != owner)
+        {
+            return false;
+        }
+        order.retain(|stored| stored != id);
+        responses.remove(id).is_some()
+    }
+}
+
+/// A server-sent event of a streamed response.
+pub struct ResponseStreamEvent {
+    pub event: &'static str,
+    pub data: Value,
+}
+
+enum OpenItem {
+    Reasoning,
+    Message,
+}
+
+/// Turns chat completion chunks into Responses streaming events while
+/// collecting the output items of the final response.
+pub struct ResponseStreamState {
+    pub response: ResponseObject,
+    output: Vec<ResponseOutputItem>,
+    open: Option<OpenItem>,
+    sequence_number: usize,
+    finish_reason: Option<String>,
+    usage: Option<ChatCompletionUsageResponse>,
+}
+
+impl ResponseStreamState {
+    pub fn new(response: ResponseObject) -> Self {
+        Self {
+            response,
+            output: Vec::new(),
+            open: None,
+            sequence_number: 0,
+            finish_reason: None,
+            usage: None,
+        }
+    }
+
+    fn event(&mut self, event: &'static str, mut data: Value) -> ResponseStreamEvent {
+        data["type"] = json!(event);
+        data["sequence_number"] = json!(self.sequence_number);
+        self.sequence_number += 1;
+        ResponseStreamEvent { event, data }
+    }
+
+    /// `response.created` and `response.in_progress`.
+    pub fn start(&mut self) -> Vec<ResponseStreamEvent> {
+        let response = json!(self.response);
+        vec![
+            self.event("response.created", json!({ "response": response })),
+            self.event("response.in_progress", json!({ "response": response })),
+        ]
+    }
+
+    fn item_id(&self) -> String {
+        match self.output.last() {
+            Some(
+                ResponseOutputItem::Reasoning { id, .. }
+                | ResponseOutputItem::Message { id, .. }
+                | ResponseOutputItem::FunctionCall { id, .. },
+            ) => id.clone(),
+            None => String::new(),
+        }
+    }
+
+    fn close_item(&mut self, events: &mut Vec<ResponseStreamEvent>) {
+        let Some(open) = self.open.take() else {
+            return;
+        };
+        let output_index = self.output.len() - 1;
+        let item_id = self.item_id();
+        match (open, self.output.last()) {
+            (OpenItem::Reasoning, Some(ResponseOutputItem::Reasoning { content, .. })) => {
+                let text = content[0].text.clone();
+                events.push(self.event(
+                    "response.reasoning_text.done",
+                    json!({ "item_id": item_id, "output_index": output_index, "content_index": 0, "text": text }),
+                ));
+                events.push(self.event(
+                    "response.content_part.done",
+                    json!({ "item_id": item_id, "output_index": output_index, "content_index": 0, "part": { "type": "reasoning_text", "text": text } }),
+                ));
+            }
+            (OpenItem::Message, Some(ResponseOutputItem::Message { content, .. })) => {
+                let ResponseOutputContent::OutputText { text, .. } = &content[0];
+                let text = text.clone();
+                events.push(self.event(
+                    "response.output_text.done",
+                    json!({ "item_id": item_id, "output_index": output_index, "content_index": 0, "text": text }),
+                ));
+                events.push(self.event(
+                    "response.content_part.done",
+                    json!({ "item_id": item_id, "output_index": output_index, "content_index": 0, "part": { "type": "output_text", "text": text, "annotations": [] } }),
+                ));
+            }
+            _ => {}
+        }
+        let item = json!(self.output[output_index]);
+        events.push(self.event(
+            "response.output_item.done",
+            json!({ "output_index": output_index, "item": item }),
+        ));
+    }
+
+    fn open_item(&mut self, kind: OpenItem, events: &mut Vec<ResponseStreamEvent>) {
+        self.close_item(events);
+        let (item, part) = match kind {
+            OpenItem::Reasoning => (
+                ResponseOutputItem::Reasoning {
+                    id: new_item_id("rs"),
+                    summary: Vec::new(),
+                    content: vec![ResponseText {
+                        text_type: "reasoning_text".to_string(),
+                        text: String::new(),
+                    }],
+                },
+                json!({ "type": "reasoning_text", "text": "" }),
+            ),
+            OpenItem::Message => (
+                ResponseOutputItem::Message {
+                    id: new_item_id("msg"),
+                    role: "assistant",
+                    status: "completed",
+                    content: vec![ResponseOutputContent::OutputText {
+                        text: String::new(),
+                        annotations: Vec::new(),
+                    }],
+                },
+                json!({ "type": "output_text", "text": "", "annotations": [] }),
+            ),
+        };
+        let mut added = json!(item);
+        if let OpenItem::Message = kind {
+            added["status"] = json!("in_progress");
+            added["content"] = json!([]);
+        }
+        self.output.push(item);
+        self.open = Some(kind);
+        let output_index = self.output.len() - 1;
+        let item_id = self.item_id();
+        events.push(self.event(
+            "response.output_item.added",
+            json!({ "output_index": output_index, "item": added }),
+        ));
+        events.push(self.event(
+            "response.content_part.added",
+            json!({ "item_id": item_id, "output_index": output_index, "content_index": 0, "part": part }),
+        ));
+    }
+
+    fn push_delta(&mut self, kind: OpenItem, delta: &str, events: &mut Vec<ResponseStreamEvent>) {
+        let is_open = matches!(
+            (&self.open, &kind),
+            (Some(OpenItem::Reasoning), OpenItem::Reasoning)
+                | (Some(OpenItem::Message), OpenItem::Message)
+        );
+        if !is_open {
+            self.open_item(kind, events);
+        }
+        let event = match self.output.last_mut() {
+            Some(ResponseOutputItem::Reasoning { content, .. }) => {
+                content[0].text.push_str(delta);
+                "response.reasoning_text.delta"
+            }
+            Some(ResponseOutputItem::Message { content, .. }) => {
+                let ResponseOutputContent::OutputText { text, .. } = &mut content[0];
+                text.push_str(delta);
+                "response.output_text.delta"
+            }
+            _ => return,
+        };
+        let output_index = self.output.len() - 1;
+        let item_id = self.item_id();
+        events.push(self.event(
+            event,
+            json!({ "item_id": item_id, "output_index": output_index, "content_index": 0, "delta": delta }),
+        ));
+    }
+
+    /// Events for one chat completion chunk.
+    pub fn on_chunk(&mut self, chunk: ChatCompletionChunk) -> Vec<ResponseStreamEvent> {
+        let mut events = Vec::new();
+        if let Some(usage) = chunk.usage {
+            self.usage = Some(usage);
+        }
+        for choice in chunk.choices {
+            if let Some(reasoning) = choice.delta.reasoning_content.filter(|r| !r.is_empty()) {
+                self.push_delta(OpenItem::Reasoning, &reasoning, &mut events);
+            }
+            if let Some(content) = choice.delta.content.filter(|c| !c.is_empty()) {
+                self.push_delta(OpenItem::Message, &content, &mut events);
+            }
+            for call in choice.delta.tool_calls.iter().flatten() {
+                self.close_item(&mut events);
+                let item = function_call_item(call);
+                let ResponseOutputItem::FunctionCall { id, arguments, .. } = &item else {
+                    unreachable!();
+                };
+                let (item_id, arguments) = (id.clone(), arguments.clone());
+                let mut added = json!(item);
+                added["status"] = json!("in_progress");
+                added["arguments"] = json!("");
+                let output_index = self.output.len();
+                self.output.push(item);
+                events.push(self.event(
+                    "response.output_item.added",
+                    json!({ "output_index": output_index, "item": added }),
+                ));
+                events.push(self.event(
+                    "response.function_call_arguments.delta",
+                    json!({ "item_id": item_id, "output_index": output_index, "delta": arguments }),
+                ));
+                events.push(self.event(
+                    "response.function_call_arguments.done",
+                    json!({ "item_id": item_id, "output_index": output_index, "arguments": arguments }),
+                ));
+                let item = json!(self.output[output_index]);
+                events.push(self.event(
+                    "response.output_item.done",
+                    json!({ "output_index": output_index, "item": item }),
+                ));
+            }
+            if choice.finish_reason.is_some() {
+                self.finish_reason = choice.finish_reason;
+            }
+        }
+        events
+    }
+
+    /// Close the open item and emit the final `response.completed` (or
+    /// `response.incomplete`) event.
+    pub fn finish(&mut self) -> Vec<ResponseStreamEvent> {
+        let mut events = Vec::new();
+        self.close_item(&mut events);
+        let output = std::mem::take(&mut self.output);
+        self.response
+            .finish(output, self.finish_reason.as_deref(), self.usage.as_ref());
+        let event = if self.response.status == "incomplete" {
+            "response.incomplete"
+        } else {
+            "response.completed"
+        };
+        let response = json!(self.response);
+        events.push(self.event(event, json!({ "response": response })));
+        events
+    }
+
+    /// An `error` event, for failures after the stream has started.
+    pub fn error(&mut self, message: String) -> ResponseStreamEvent {
+        self.event(
+            "error",
+            json!({ "code": "server_error", "message": message }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::openai::responses::{Choice, ChoiceData};
+
+    fn request(body: Value) -> ResponsesRequest {
+        serde_json::from_value(body).unwrap()
+    }
+
+    #[test]
+    fn input_items_map_to_chat_messages() {
+        let request = request(json!({
+            "input": [
+                {"role": "developer", "content": "Be brief."},
+                {"type": "message", "role": "user", "content": [{"type": "input_text", "text": "Weather?"}]},
+                {"type": "reasoning", "id": "rs_1", "summary": [{"type": "summary_text", "text": "Need a tool."}]},
+                {"type": "function_call", "call_id": "call_1", "name": "weather", "arguments": "{}"},
+                {"type": "function_call", "call_id": "call_2", "name": "time", "arguments": "{}"},
+                {"type": "function_call_output", "call_id": "call_1", "output": "Sunny"}
+            ]
+        }));
+        let messages = input_messages(request.input).unwrap();
+        let roles = messages.iter().map(|m| m.role.as_str()).collect::<Vec<_>>();
+        assert_eq!(roles, vec!["system", "user", "assistant", "tool"]);
+        assert_eq!(
+            messages[2].reasoning_content.as_deref(),
+            Some("Need a tool.")
+        );
+        let calls = messages[2].tool_calls.as_ref().unwrap();
+        assert_eq!(
+            calls.iter().map(|c| c.id.as_str()).collect::<Vec<_>>(),
+            vec!["call_1", "call_2"]
+        );
+        assert_eq!(messages[3].tool_call_id.as_deref(), Some("call_1"));
+    }
+
+    #[test]
+    fn chat_request_prepends_instructions_to_history() {
+        let request = request(json!({
+            "input": "And tomorrow?",
+            "instructions": "Answer in French.",
+            "reasoning": {"effort": "minimal"},
+            "tools": [{"type": "function", "name": "weather", "parameters": {"type": "object"}}],
+            "tool_choice": {"type": "function", "name": "weather"},
+            "max_output_tokens": 64
+        }));
+        let history = vec![
+            chat_message("user", Some(MessageContentType::PureText("Today?".into()))),
+            chat_message(
+                "assistant",
+                Some(MessageContentType::PureText("Sunny.".into())),
+            ),
+        ];
+        let input = input_messages(request.input.clone()).unwrap();
+        let chat = chat_request(&request, &history, &input).unwrap();
+        let Messages::Chat(messages) = &chat.messages else {
+            panic!("expected chat messages");
+        };
+        let roles = messages.iter().map(|m| m.role.as_str()).collect::<Vec<_>>();
+        assert_eq!(roles, vec!["system", "user", "assistant", "user"]);
+        assert_eq!(chat.max_tokens, Some(64));
+        assert_eq!(chat.reasoning_effort.as_deref(), Some("low"));
+        assert_eq!(chat.tools.unwrap()[0].function.name, "weather");
+        assert!(matches!(
+            chat.tool_choice,
+            Some(ToolChoice::Function { .. })
+        ));
+
+        let request = self::request(json!({
+            "input": "hi",
+            "tools": [{"type": "web_search"}]
+        }));
+        assert!(chat_request(&request, &[], &[]).is_err());
+    }
+
+    #[test]
+    fn output_items_round_trip_to_assistant_message() {
+        let message = ChatChoiceData {
+            content: Some("Calling.".to_string()),
+            reasoning_content: Some("Think.".to_string()),
+            role: "assistant".to_string(),
+            tool_calls: Some(vec![ToolCall::new(
+                "call_1",
+                "weather",
+                "{\"city\":\"Paris\"}",
+            )]),
+        };
+        let items = output_items(&message);
+        let types = items
+            .iter()
+            .map(|item| json!(item)["type"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(types, vec!["reasoning", "message", "function_call"]);
+        let assistant = assistant_message(&items).unwrap();
+        assert_eq!(assistant.reasoning_content.as_deref(), Some("Think."));
+        assert_eq!(assistant.tool_calls.unwrap()[0].id, "call_1");
+        assert!(assistant_message(&[]).is_none());
+    }
+
+    #[test]
+    fn store_evicts_oldest_response() {
+        let store = ResponseStore::new(2);
+        let request = request(json!({"input": "hi"}));
+        for id in ["resp_1", "resp_2", "resp_3"] {
+            store.insert(StoredResponse {
+                response: ResponseObject::new(id.to_string(), 0, "m".to_string(), &request),
+                messages: Vec::new(),
+                owner: None,
+            });
+        }
+        assert!(store.get("resp_1", None).is_none());
+        assert!(store.get("resp_3", None).is_some());
+        assert!(store.remove("resp_2", None));
+        assert!(!store.remove("resp_2", None));
+    }
+
+    #[test]
+    fn store_hides_responses_from_other_keys() {
+        let store = ResponseStore::new(4);
+        let request = request(json!({"input": "hi"}));
+        store.insert(StoredResponse {
+            response: ResponseObject::new("resp_1".to_string(), 0, "m".to_string(), &request),
+            messages: Vec::new(),
+            owner: Some("alice".to_string()),
+        });
+        assert!(store.get("resp_1", Some("bob")).is_none());
+        assert!(store.get("resp_1", None).is_none());
+        assert!(!store.remove("resp_1", Some("bob")));
+        assert!(store.get("resp_1", Some("alice")).is_some());
+        assert!(store.remove("resp_1", Some("alice")));
+    }
+
+    fn chunk(
+        reasoning: Option<&str>,
+        content: Option<&str>,
+        finish_reason: Option<&str>,
+    ) -> ChatCompletionChunk {
+        ChatCompletionChunk {
+            id: "cmpl-1".to_string(),
+            choices: vec![Choice {
+                delta: ChoiceData {
+                    content: content.map(str::to_string),
+                    reasoning_content: reasoning.map(str::to_string),
+                    role: None,
+                    tool_calls: None,
+                },
+                finish_reason: finish_reason.map(str::to_string),
+                index: 0,
+                logprobs: None,
+            }],
+            created: 0,
+            model: "m".to_string(),
+            object: "chat.completion.chunk",
+            system_fingerprint: None,
+            usage: None,
+        }
+    }
+
+    #[test]
+    fn stream_events_open_and_close_items_in_order() {
+        let request = request(json!({"input": "hi", "stream": true}));
+        let response = ResponseObject::new("resp_1".to_string(), 0, "m".to_string(), &request);
+        let mut state = ResponseStreamState::new(response);
+        let mut events = state.start();
+        events.extend(state.on_chunk(chunk(Some("Hm"), None, None)));
+        events.extend(state.on_chunk(chunk(None, Some("Hel"), None)));
+        events.extend(state.on_chunk(chunk(None, Some("lo"), Some("length"))));
+        events.extend(state.finish());
+        let names = events.iter().map(|e| e.event).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "response.created",
+                "response.in_progress",
+                "response.output_item.added",
+                "response.content_part.added",
+                "response.reasoning_text.delta",
+                "response.reasoning_text.done",
+                "response.content_part.done",
+                "response.output_item.done",
+                "response.output_item.added",
+                "response.content_part.added",
+                "response.output_text.delta",
+                "response.output_text.delta",
+                "response.output_text.done",
+                "response.content_part.done",
+                "response.output_item.done",
+                "response.incomplete",
+            ]
+        );
+        let sequence = events
+            .iter()
+            .map(|e| e.data["sequence_number"].as_u64().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(sequence, (0..events.len() as u64).collect::<Vec<_>>());
+        let last = &events.last().unwrap().data["response"];
+        assert_eq!(last["output"][1]["content"][0]["text"], "Hello");
+        assert_eq!(last["incomplete_details"]["reason"], "max_output_tokens");
+    }
+}
@@ -1,5 +1,12 @@
-use super::{requests::StopTokens, responses::APIError};
-use serde::{Deserialize, Serialize};
+use super::{
+    requests::{ResponseFormat, StopTokens},
+    responses::APIError,
+    structured_output::{GuidedConstraint, ToolCallEnvelope},
+    ToolChoiceKind,
+};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::time::Duration;
 
 const SAMPLING_EPS: f32 = 1e-5;
 
@@ -19,7 +26,7 @@ pub struct Logprobs {
     pub top_logprobs: Vec<TopLogprob>,
 }
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum EarlyStoppingCondition {
     ///True
     BestOfCompleteCandidates,
@@ -29,6 +36,63 @@ pub enum EarlyStoppingCondition {
     CanonicalNoBetterCandidates,
 }
 
+impl Serialize for EarlyStoppingCondition {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::BestOfCompleteCandidates => serializer.serialize_bool(true),
+            Self::UnlikelyBetterCandidates => serializer.serialize_bool(false),
+            Self::CanonicalNoBetterCandidates => serializer.serialize_str("never"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for EarlyStoppingCondition {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Flag(bool),
+            Name(String),
+        }
+        match Repr::deserialize(deserializer)? {
+            Repr::Flag(true) => Ok(Self::BestOfCompleteCandidates),
+            Repr::Flag(false) => Ok(Self::UnlikelyBetterCandidates),
+            Repr::Name(name) if name == "never" => Ok(Self::CanonicalNoBetterCandidates),
+            Repr::Name(name) => Err(D::Error::custom(format!(
+                "early_stopping must be true, false or \"never\", got \"{name}\""
+            ))),
+        }
+    }
+}
+
+/// Scheduling class of a request. Higher classes are admitted, swapped in
+/// and kept running ahead of lower ones.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Batch,
+    #[default]
+    Normal,
+    Interactive,
+}
+
+impl std::str::FromStr for Priority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "batch" => Ok(Self::Batch),
+            "normal" => Ok(Self::Normal),
+            "interactive" => Ok(Self::Interactive),
+            _ => Err(format!(
+                "unknown priority `{s}`, expected interactive, normal or batch"
+            )),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum SamplingType {
     BEAM,
@@ -92,6 +156,28 @@ pub struct SamplingParams {
     /// Thinking flag for reasoning models
     //  default = False
     pub thinking: Option<bool>,
+    /// Seed for a dedicated sampling RNG stream, making sampled output reproducible
+    /// regardless of other requests in the batch.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Additive bias applied to the logits of the given token ids before sampling.
+    #[serde(default)]
+    pub logit_bias: Option<HashMap<u32, f32>>,
+    /// Grammar the output must follow (`response_format` json_object / json_schema).
+    #[serde(default)]
+    pub guided_decoding: Option<GuidedConstraint>,
+    /// Time allowed from arrival; past it the request finishes with `timeout`.
+    #[serde(default)]
+    pub timeout: Option<Duration>,
+    /// Scheduling class of the request.
+    #[serde(default)]
+    pub priority: Priority,
+    /// End user the request is accounted to for fair scheduling.
+    #[serde(default)]
+    pub user: Option<String>,
+    /// Name of the API key the request came in with, for per-key usage.
+    #[serde(default)]
+    pub api_key: Option<String>,
     #[serde(skip)]
     pub mcp_mode: Option<bool>,
 }
@@ -148,6 +234,13 @@ impl SamplingParams {
             prompt_logprobs,
             skip_special_tokens,
             thinking,
+            seed: None,
+            logit_bias: None,
+            guided_decoding: None,
+            timeout: None,
+            priority: Priority::default(),
+            user: None,
+            api_key: None,
             mcp_mode: None,
         };
 
@@ -161,6 +254,113 @@ impl SamplingParams {
         Ok(this)
     }
 
+    /// Validate an OpenAI `logit_bias` map (token id strings to bias in [-100, 100])
+    /// against the tokenizer vocabulary and store it on these params.
+    pub fn set_logit_bias(
+        &mut self,
+        logit_bias: Option<&HashMap<String, f32>>,
+        vocab_size: usize,
+    ) -> Result<(), APIError> {
+        let Some(logit_bias) = logit_bias.filter(|bias| !bias.is_empty()) else {
+            self.logit_bias = None;
+            return Ok(());
+        };
+        let mut parsed = HashMap::with_capacity(logit_bias.len());
+        for (token, bias) in logit_bias {
+            let token_id = token.trim().parse::<u32>().map_err(|_| {
+                APIError::new(format!(
+                    "logit_bias keys must be token ids, got '{}'",
+                    token
+                ))
+            })?;
+            if token_id as usize >= vocab_size {
+                return Err(APIError::new(format!(
+                    "logit_bias token id {} is out of range for vocabulary size {}",
+                    token_id, vocab_size
+                )));
+            }
+            if !(-100.0..=100.0).contains(bias) {
+                return Err(APIError::new(format!(
+                    "logit_bias values must be in [-100, 100], got {} for token {}",
+                    bias, token_id
+                )));
+            }
+            parsed.insert(token_id, *bias);
+        }
+        self.logit_bias = Some(parsed);
+        Ok(())
+    }
+
+    /// Translate an OpenAI `response_format` into a decoding constraint,
+    /// rejecting schemas that cannot be compiled.
+    pub fn set_response_format(&mut self, format: Option<&ResponseFormat>) -> Result<(), APIError> {
+        let constraint = match format {
+            None | Some(ResponseFormat::Text) => None,
+            Some(ResponseFormat::JsonObject) => Some(GuidedConstraint::JsonObject),
+            Some(ResponseFormat::JsonSchema { json_schema }) => Some(GuidedConstraint::JsonSchema(
+                json_schema
+                    .schema
+                    .clone()
+                    .unwrap_or(serde_json::json!({"type": "object"})),
+            )),
+        };
+        if let Some(constraint) = &constraint {
+            constraint.compile(None).map_err(|e| {
+                APIError::new(format!("Invalid response_format json_schema: {}", e))
+            })?;
+        }
+        self.guided_decoding = constraint;
+        Ok(())
+    }
+
+    /// Validate a request `timeout` in seconds and store it on these params.
+    pub fn set_timeout(&mut self, timeout: Option<f64>) -> Result<(), APIError> {
+        self.timeout = match timeout {
+            None => None,
+            Some(secs) if secs.is_finite() && secs > 0.0 => Some(Duration::from_secs_f64(secs)),
+            Some(secs) => {
+                return Err(APIError::new(format!(
+                    "timeout must be a positive number of seconds, got {}",
+                    secs
+                )))
+            }
+        };
+        Ok(())
+    }
+
+    /// Force a well-formed call to one of `tools`, laid out in `envelope`,
+    /// when the tool choice is `required` or a named function. Takes
+    /// precedence over `response_format`. Without an envelope the model's
+    /// format cannot be constrained, and the choice is left to the prompt.
+    pub fn set_tool_choice(
+        &mut self,
+        tools: &[crate::tools::Tool],
+        choice: &ToolChoiceKind,
+        envelope: Option<&ToolCallEnvelope>,
+    ) -> Result<(), APIError> {
+        if !matches!(
+            choice,
+            ToolChoiceKind::Required | ToolChoiceKind::Function(_)
+        ) {
+            return Ok(());
+        }
+        let constraint = GuidedConstraint::ToolCall(
+            tools
+                .iter()
+                .map(|tool| (tool.function.name.clone(), tool.function.parameters.clone()))
+                .collect(),
+        );
+        let Some(envelope) = envelope else {
+            self.guided_decoding = None;
+            return Ok(());
+        };
+        constraint
+            .compile(Some(envelope))
+            .map_err(|e| APIError::new(format!("Invalid tool parameters schema: {}", e)))?;
+        self.guided_decoding = Some(constraint);
+        Ok(())
+    }
+
     fn verify_args(&self) -> Result<(), APIError> {
         if self.n < 1 {
             return Err(APIError::new(format!(
@@ -229,13 +429,21 @@ impl SamplingParams {
 
     fn verify_non_beam_search(&self) -> Result<(), APIError> {
         if self.early_stopping != EarlyStoppingCondition::UnlikelyBetterCandidates {
-            return Err(APIError::new_str("early_stopping is not effective and must be UnlikelyBetterCandidates when not using beam search."));
+            return Err(APIError::new_str(
+                "early_stopping is not effective and must be false when not using beam search.",
+            ));
         }
         if self.length_penalty < 1.0f32 - SAMPLING_EPS
             || self.length_penalty > 1.0f32 + SAMPLING_EPS
         {
             return Err(APIError::new_str("length_penalty is not effective and must be the default value of 1.0 when not using beam search."));
         }
+        if self.best_of > 1 && self.temperature.is_some_and(|t| t <= SAMPLING_EPS) {
+            return Err(APIError::new(format!(
+                "best_of must be 1 when using greedy sampling, got {}",
+                self.best_of
+            )));
+        }
         Ok(())
     }
 }
@@ -256,3 +464,204 @@ pub struct GenerationConfig {
     pub frequency_penalty: Option<f32>,
     pub presence_penalty: Option<f32>,
 }
+
+impl GenerationConfig {
+    /// The defaults that apply to a request: beam search is deterministic, so
+    /// it keeps only the penalties.
+    pub fn for_request(&self, use_beam_search: bool) -> Self {
+        if !use_beam_search {
+            return self.clone();
+        }
+        Self {
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            min_p: None,
+            ..self.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EarlyStoppingCondition, SamplingParams};
+    use crate::openai::requests::ResponseFormat;
+    use crate::openai::structured_output::{GuidedConstraint, ToolCallEnvelope};
+    use crate::openai::ToolChoiceKind;
+    use std::collections::HashMap;
+
+    fn params() -> SamplingParams {
+        SamplingParams::new(
+            1,
+            None,
+            0.0,
+            0.0,
+            None,
+            None,
+            None,
+            None,
+            None,
+            false,
+            1.0,
+            EarlyStoppingCondition::UnlikelyBetterCandidates,
+            None,
+            Vec::new(),
+            false,
+            16,
+            None,
+            None,
+            true,
+            None,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn logit_bias_is_parsed_into_token_ids() {
+        let mut params = params();
+        let bias = HashMap::from([("5".to_string(), -100.0), ("7".to_string(), 2.5)]);
+        params.set_logit_bias(Some(&bias), 10).unwrap();
+
+        let parsed = params.logit_bias.unwrap();
+        assert_eq!(parsed.get(&5), Some(&-100.0));
+        assert_eq!(parsed.get(&7), Some(&2.5));
+    }
+
+    #[test]
+    fn logit_bias_rejects_out_of_vocab_and_malformed_entries() {
+        let mut params = params();
+        let out_of_vocab = HashMap::from([("10".to_string(), 1.0)]);
+        assert!(params.set_logit_bias(Some(&out_of_vocab), 10).is_err());
+
+        let not_a_token = HashMap::from([("hello".to_string(), 1.0)]);
+        assert!(params.set_logit_bias(Some(&not_a_token), 10).is_err());
+
+        let too_large = HashMap::from([("1".to_string(), 101.0)]);
+        assert!(params.set_logit_bias(Some(&too_large), 10).is_err());
+    }
+
+    #[test]
+    fn timeout_must_be_positive() {
+        let mut params = params();
+        params.set_timeout(Some(1.5)).unwrap();
+        assert_eq!(params.timeout, Some(std::time::Duration::from_millis(1500)));
+        assert!(params.set_timeout(Some(0.0)).is_err());
+        assert!(params.set_timeout(Some(f64::NAN)).is_err());
+        params.set_timeout(None).unwrap();
+        assert!(params.timeout.is_none());
+    }
+
+    #[test]
+    fn response_format_sets_guided_constraint() {
+        let mut params = params();
+        let format: ResponseFormat = serde_json::from_str(
+            r#"{"type": "json_schema", "json_schema": {"name": "person",
+                "schema": {"type": "object", "properties": {"name": {"type": "string"}}}}}"#,
+        )
+        .unwrap();
+        params.set_response_format(Some(&format)).unwrap();
+        assert!(matches!(
+            params.guided_decoding,
+            Some(GuidedConstraint::JsonSchema(_))
+        ));
+
+        let invalid: ResponseFormat = serde_json::from_str(
+            r#"{"type": "json_schema", "json_schema": {"name": "bad", "schema": {"type": "tuple"}}}"#,
+        )
+        .unwrap();
+        assert!(params.set_response_format(Some(&invalid)).is_err());
+
+        params
+            .set_response_format(Some(&ResponseFormat::Text))
+            .unwrap();
+        assert!(params.guided_decoding.is_none());
+    }
+
+    #[test]
+    fn forced_tool_choice_sets_tool_call_constraint() {
+        let mut params = params();
+        let tools: Vec<crate::tools::Tool> = serde_json::from_value(serde_json::json!([{
+            "type": "function",
+            "function": {
+                "name": "get_weather",
+                "parameters": {"type": "object", "properties": {"city": {"type": "string"}}}
+            }
+        }]))
+        .unwrap();
+
+        let envelope = ToolCallEnvelope::default();
+        params
+            .set_tool_choice(&tools, &ToolChoiceKind::Auto, None)
+            .unwrap();
+        assert!(params.guided_decoding.is_none());
+
+        // Formats without an envelope are left unconstrained.
+        params
+            .set_tool_choice(&tools, &ToolChoiceKind::Required, None)
+            .unwrap();
+        assert!(params.guided_decoding.is_none());
+
+        params
+            .set_tool_choice(&tools, &ToolChoiceKind::Required, Some(&envelope))
+            .unwrap();
+        assert!(matches!(
+            params.guided_decoding,
+            Some(GuidedConstraint::ToolCall(ref calls)) if calls[0].0 == "get_weather"
+        ));
+    }
+
+    #[test]
+    fn best_of_requires_random_sampling() {
+        let with = |n: usize, best_of: usize, temperature: f32| {
+            SamplingParams::new(
+                n,
+                Some(best_of),
+                0.0,
+                0.0,
+                None,
+                Some(temperature),
+                None,
+                None,
+                None,
+                false,
+                1.0,
+                EarlyStoppingCondition::UnlikelyBetterCandidates,
+                None,
+                Vec::new(),
+                false,
+                16,
+                None,
+                None,
+                true,
+                None,
+            )
+        };
+        assert!(with(2, 4, 0.8).is_ok());
+        assert!(with(1, 1, 0.0).is_ok());
+        assert!(with(2, 2, 0.0).is_err());
+        assert!(with(3, 2, 0.8).is_err());
+    }
+
+    #[test]
+    fn early_stopping_uses_openai_values() {
+        let parse =
+            |value: serde_json::Value| serde_json::from_value::<EarlyStoppingCondition>(value);
+        assert_eq!(
+            parse(serde_json::json!(true)).unwrap(),
+            EarlyStoppingCondition::BestOfCompleteCandidates
+        );
+        assert_eq!(
+            parse(serde_json::json!(false)).unwrap(),
+            EarlyStoppingCondition::UnlikelyBetterCandidates
+        );
+        assert_eq!(
+            parse(serde_json::json!("never")).unwrap(),
+            EarlyStoppingCondition::CanonicalNoBetterCandidates
+        );
+        assert!(parse(serde_json::json!("always")).is_err());
+        assert_eq!(
+            serde_json::to_value(EarlyStoppingCondition::CanonicalNoBetterCandidates).unwrap(),
+            serde_json::json!("never")
+        );
+    }
+}
@@ -1,4 +1,6 @@
-use super::responses::{ChatCompletionChunk, EmbeddingResponse};
+use super::responses::{
+    APIError, ChatCompletionChunk, CompletionChunk, EmbeddingResponse, ErrorKind,
+};
 use crate::openai::logger::ChatCompletionLogger;
 use axum::response::sse::Event;
 use futures::Stream;
@@ -17,11 +19,14 @@ pub enum StreamingStatus {
     Stopped,
 }
 pub enum ChatResponse {
-    InternalError(String),
-    ValidationError(String),
-    ModelError(String),
+    InternalError(APIError),
+    ValidationError(APIError),
+    ModelError(APIError),
     Chunk(ChatCompletionChunk),
+    TextChunk(CompletionChunk),
     Embedding(EmbeddingResponse),
+    /// An SSE event with an explicit event name, for APIs that type their events.
+    NamedEvent(&'static str, serde_json::Value),
     Done, //finish flag
 }
 
@@ -31,6 +36,24 @@ pub struct Streamer {
     pub logger: Option<Arc<ChatCompletionLogger>>,
 }
 
+impl Streamer {
+    /// Emit a failure as an `error` event carrying the OpenAI error object.
+    fn error_event(
+        &self,
+        e: APIError,
+        default_kind: ErrorKind,
+    ) -> Poll<Option<Result<Event, axum::Error>>> {
+        if let Some(logger) = &self.logger {
+            logger.log_error(e.message());
+        }
+        Poll::Ready(Some(
+            Event::default()
+                .event("error")
+                .json_data(e.to_json(default_kind)),
+        ))
+    }
+}
+
 impl Stream for Streamer {
     type Item = Result<Event, axum::Error>;
 
@@ -40,24 +63,9 @@ impl Stream for Streamer {
         }
         match self.rx.poll_recv(cx) {
             Poll::Ready(Some(resp)) => match resp {
-                ChatResponse::InternalError(e) => {
-                    if let Some(logger) = &self.logger {
-                        logger.log_error(&e);
-                    }
-                    Poll::Ready(Some(Ok(Event::default().data(e))))
-                }
-                ChatResponse::ValidationError(e) => {
-                    if let Some(logger) = &self.logger {
-                        logger.log_error(&e);
-                    }
-                    Poll::Ready(Some(Ok(Event::default().data(e))))
-                }
-                ChatResponse::ModelError(e) => {
-                    if let Some(logger) = &self.logger {
-                        logger.log_error(&e);
-                    }
-                    Poll::Ready(Some(Ok(Event::default().data(e))))
-                }
+                ChatResponse::InternalError(e) => self.error_event(e, ErrorKind::Internal),
+                ChatResponse::ValidationError(e) => self.error_event(e, ErrorKind::InvalidRequest),
+                ChatResponse::ModelError(e) => self.error_event(e, ErrorKind::Internal),
                 ChatResponse::Chunk(response) => {
                     if self.status != StreamingStatus::Started {
                         self.status = StreamingStatus::Started;
@@ -84,12 +92,29 @@ impl Stream for Streamer {
                     }
                     Poll::Ready(Some(Event::default().json_data(response)))
                 }
+                ChatResponse::TextChunk(response) => {
+                    if self.status != StreamingStatus::Started {
+                        self.status = StreamingStatus::Started;
+                    }
+                    if let Some(logger) = &self.logger {
+                        for choice in &response.choices {
+                            logger.log_stream_token(&choice.text);
+                        }
+                    }
+                    Poll::Ready(Some(Event::default().json_data(response)))
+                }
                 ChatResponse::Embedding(response) => {
                     if self.status != StreamingStatus::Started {
                         self.status = StreamingStatus::Started;
                     }
                     Poll::Ready(Some(Event::default().json_data(response)))
                 }
+                ChatResponse::NamedEvent(event, data) => {
+                    if self.status != StreamingStatus::Started {
+                        self.status = StreamingStatus::Started;
+                    }
+                    Poll::Ready(Some(Event::default().event(event).json_data(data)))
+                }
                 ChatResponse::Done => {
                     self.status = StreamingStatus::Stopped;
                     Poll::Ready(Some(Ok(Event::default().data("[DONE]"))))
@@ -0,0 +1,651 @@
+//! Character-level pushdown automaton that accepts exactly the JSON texts
+//! matching a compiled [`Grammar`].
+//!
+//! The matcher tracks a small set of alternative parse stacks so `anyOf`
+//! schemas can be followed without backtracking. Every state is hashable,
+//! which lets callers cache the token mask computed for it.
+
+use super::schema::{Grammar, ObjectSchema, Schema, SchemaRef};
+use std::sync::Arc;
+
+/// Longest run of insignificant whitespace allowed between JSON tokens, so a
+/// constrained model cannot stall by emitting blank space forever.
+const MAX_WHITESPACE_RUN: u8 = 16;
+
+fn is_whitespace(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n' | '\r')
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Escape {
+    None,
+    Backslash,
+    Unicode(u8),
+}
+
+impl Escape {
+    /// Advance through one character of a string body. The closing quote is
+    /// handled by the caller.
+    fn step(self, c: char) -> Option<Self> {
+        match self {
+            Escape::None => match c {
+                '\\' => Some(Escape::Backslash),
+                c if (c as u32) < 0x20 => None,
+                _ => Some(Escape::None),
+            },
+            Escape::Backslash => match c {
+                '"' | '\\' | '/' | 'b' | 'f' | 'n' | 'r' | 't' => Some(Escape::None),
+                'u' => Some(Escape::Unicode(4)),
+                _ => None,
+            },
+            Escape::Unicode(remaining) => c.is_ascii_hexdigit().then_some(if remaining == 1 {
+                Escape::None
+            } else {
+                Escape::Unicode(remaining - 1)
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum NumberState {
+    Start,
+    Minus,
+    Zero,
+    Integer,
+    Dot,
+    Fraction,
+    Exponent,
+    ExponentSign,
+    ExponentDigits,
+}
+
+impl NumberState {
+    fn step(self, c: char, integer: bool) -> Option<Self> {
+        use NumberState::*;
+        match (self, c) {
+            (Start, '-') => Some(Minus),
+            (Start | Minus, '0') => Some(Zero),
+            (Start | Minus, '1'..='9') => Some(Integer),
+            (Integer, '0'..='9') => Some(Integer),
+            (Zero | Integer, '.') if !integer => Some(Dot),
+            (Dot | Fraction, '0'..='9') => Some(Fraction),
+            (Zero | Integer | Fraction, 'e' | 'E') if !integer => Some(Exponent),
+            (Exponent, '+' | '-') => Some(ExponentSign),
+            (Exponent | ExponentSign | ExponentDigits, '0'..='9') => Some(ExponentDigits),
+            _ => None,
+        }
+    }
+
+    fn is_terminal(self) -> bool {
+        matches!(
+            self,
+            NumberState::Zero
+                | NumberState::Integer
+                | NumberState::Fraction
+                | NumberState::ExponentDigits
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum ObjectState {
+    /// After `{`: a key or `}`.
+    Open,
+    /// After `,`: a key.
+    KeyStart,
+    InKey {
+        key: String,
+        escape: Escape,
+    },
+    Colon {
+        key: String,
+    },
+    AfterValue,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ArrayState {
+    Open,
+    Next,
+    AfterValue,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Frame {
+    /// A value described by the schema is expected next.
+    Value(SchemaRef),
+    Object {
+        schema: SchemaRef,
+        seen: Vec<String>,
+        state: ObjectState,
+    },
+    Array {
+        schema: SchemaRef,
+        count: usize,
+        state: ArrayState,
+    },
+    String {
+        escape: Escape,
+    },
+    Number {
+        integer: bool,
+        state: NumberState,
+    },
+    Literal {
+        options: Arc<[String]>,
+        typed: String,
+    },
+    /// Fixed text around the JSON value (multi-token tool-call markers).
+    Text {
+        text: Arc<str>,
+        pos: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Stack {
+    frames: Vec<Frame>,
+    whitespace: u8,
+}
+
+impl Stack {
+    fn in_text(&self) -> bool {
+        matches!(
+            self.frames.last(),
+            Some(Frame::String { .. })
+                | Some(Frame::Literal { .. })
+                | Some(Frame::Text { pos: 1.., .. })
+                | Some(Frame::Object {
+                    state: ObjectState::InKey { .. },
+                    ..
+                })
+        )
+    }
+
+    fn is_accepting(&self) -> bool {
+        match self.frames.as_slice() {
+            [] => true,
+            [Frame::Number { state, .. }] => state.is_terminal(),
+            [Frame::Literal { options, typed }] => options.contains(typed),
+            _ => false,
+        }
+    }
+
+    /// True when any character other than `"`, `\` and control characters
+    /// is accepted without affecting what may follow.
+    fn in_string_body(&self, grammar: &Grammar) -> bool {
+        match self.frames.last() {
+            Some(Frame::String {
+                escape: Escape::None,
+            }) => true,
+            Some(Frame::Object {
+                schema,
+                state:
+                    ObjectState::InKey {
+                        escape: Escape::None,
+                        ..
+                    },
+                ..
+            }) => object_schema(schema, grammar).additional.is_some(),
+            _ => false,
+        }
+    }
+}
+
+fn object_schema<'a>(schema: &'a SchemaRef, grammar: &'a Grammar) -> &'a ObjectSchema {
+    match &**schema {
+        Schema::Object(object) => object,
+        _ => match &*grammar.any_alternatives[0] {
+            Schema::Object(object) => object,
+            _ => unreachable!("the first `Any` alternative is the object schema"),
+        },
+    }
+}
+
+fn required_satisfied(object: &ObjectSchema, seen: &[String]) -> bool {
+    object.required.iter().all(|key| seen.contains(key))
+}
+
+fn accepts_more_keys(object: &ObjectSchema, seen: &[String]) -> bool {
+    object.additional.is_some() || object.properties.iter().any(|(key, _)| !seen.contains(key))
+}
+
+/// Declared properties that may be written next.
+fn open_keys<'a>(
+    object: &'a ObjectSchema,
+    seen: &'a [String],
+) -> impl Iterator<Item = &'a String> + 'a {
+    let unseen = object
+        .properties
+        .iter()
+        .map(|(key, _)| key)
+        .filter(|key| !seen.contains(key));
+    unseen.take(if object.ordered { 1 } else { usize::MAX })
+}
+
+/// The set of parse states reachable after the text consumed so far.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct JsonMatcher {
+    stacks: Vec<Stack>,
+}
+
+impl JsonMatcher {
+    pub fn new(grammar: &Grammar) -> Self {
+        let text = |text: &str| Frame::Text {
+            text: Arc::from(text),
+            pos: 0,
+        };
+        let mut frames = Vec::new();
+        if !grammar.suffix.is_empty() {
+            frames.push(text(&grammar.suffix));
+        }
+        frames.push(Frame::Value(grammar.root.clone()));
+        if !grammar.prefix.is_empty() {
+            frames.push(text(&grammar.prefix));
+        }
+        Self {
+            stacks: vec![Stack {
+                frames,
+                whitespace: 0,
+            }],
+        }
+    }
+
+    /// Returns the state after consuming `c`, or `None` if `c` cannot
+    /// continue a valid document.
+    pub fn advance(&self, grammar: &Grammar, c: char) -> Option<Self> {
+        let mut stacks = Vec::new();
+        for stack in &self.stacks {
+            let mut stack = stack.clone();
+            if !stack.in_text() {
+                if is_whitespace(c) {
+                    if stack.whitespace >= MAX_WHITESPACE_RUN {
+                        continue;
+                    }
+                    stack.whitespace += 1;
+                } else {
+                    stack.whitespace = 0;
+                }
+            }
+            let mut next = Vec::new();
+            dispatch(stack, c, grammar, &mut next);
+            for stack in next {
+                if !stacks.contains(&stack) {
+                    stacks.push(stack);
+                }
+            }
+        }
+        (!stacks.is_empty()).then_some(Self { stacks })
+    }
+
+    pub fn advance_str(&self, grammar: &Grammar, text: &str) -> Option<Self> {
+        let mut state = self.clone();
+        for c in text.chars() {
+            state = state.advance(grammar, c)?;
+        }
+        Some(state)
+    }
+
+    /// The text consumed so far is a complete document.
+    pub fn is_accepting(&self) -> bool {
+        self.stacks.iter().any(Stack::is_accepting)
+    }
+
+    /// The document is complete and nothing may follow it.
+    pub fn is_finished(&self) -> bool {
+        self.stacks.iter().all(|stack| stack.frames.is_empty())
+    }
+
+    /// Every alternative is inside a string body, so any plain text is accepted.
+    pub fn in_string_body(&self, grammar: &Grammar) -> bool {
+        self.stacks
+            .iter()
+            .all(|stack| stack.in_string_body(grammar))
+    }
+}
+
+fn dispatch(mut stack: Stack, c: char, grammar: &Grammar, out: &mut Vec<Stack>) {
+    let Some(frame) = stack.frames.pop() else {
+        return;
+    };
+    match frame {
+        Frame::Value(schema) => start_value(stack, &schema, c, grammar, out),
+        Frame::Object {
+            schema,
+            mut seen,
+            state,
+        } => {
+            let object = object_schema(&schema, grammar);
+            let next_state = match state {
+                ObjectState::Open | ObjectState::KeyStart if is_whitespace(c) => Some(state),
+                ObjectState::Open | ObjectState::KeyStart if c == '"' => {
+                    accepts_more_keys(object, &seen).then_some(ObjectState::InKey {
+                        key: String::new(),
+                        escape: Escape::None,
+                    })
+                }
+                ObjectState::Open if c == '}' => {
+                    if required_satisfied(object, &seen) {
+                        out.push(stack);
+                    }
+                    return;
+                }
+                ObjectState::InKey {
+                    key,
+                    escape: Escape::None,
+                } if c == '"' => {
+                    let allowed = if object.property(&key).is_some() {
+                        open_keys(object, &seen).any(|name| *name == key)
+                    } else {
+                        object.additional.is_some() && !seen.contains(&key)
+                    };
+                    allowed.then(|| {
+                        seen.push(key.clone());
+                        ObjectState::Colon { key }
+                    })
+                }
+                ObjectState::InKey { mut key, escape } => {
+                    if object.additional.is_some() {
+                        escape.step(c).map(|escape| {
+                            key.push(c);
+                            ObjectState::InKey { key, escape }
+                        })
+                    } else {
+                        key.push(c);
+                        open_keys(object, &seen)
+                            .any(|name| name.starts_with(&key))
+                            .then_some(ObjectState::InKey { key, escape })
+                    }
+                }
+                ObjectState::Colon { .. } if is_whitespace(c) => Some(state),
+                ObjectState::Colon { key } if c == ':' => {
+                    let value = object
+                        .property(&key)
+                        .or(object.additional.as_ref())
+                        .cloned()
+                        .expect("object keys are validated before the colon");
+                    stack.frames.push(Frame::Object {
+                        schema,
+                        seen,
+                        state: ObjectState::AfterValue,
+                    });
+                    stack.frames.push(Frame::Value(value));
+                    out.push(stack);
+                    return;
+                }
+                ObjectState::AfterValue if is_whitespace(c) => Some(state),
+                ObjectState::AfterValue if c == ',' => {
+                    accepts_more_keys(object, &seen).then_some(ObjectState::KeyStart)
+                }
+                ObjectState::AfterValue if c == '}' => {
+                    if required_satisfied(object, &seen) {
+                        out.push(stack);
+                    }
+                    return;
+                }
+                _ => None,
+            };
+            if let Some(state) = next_state {
+                stack.frames.push(Frame::Object {
+                    schema,
+                    seen,
+                    state,
+                });
+                out.push(stack);
+            }
+        }
+        Frame::Array {
+            schema,
+            count,
+            state,
+        } => {
+            let Schema::Array {
+                items,
+                min_items,
+                max_items,
+            } = &*schema
+            else {
+                unreachable!("array frames always carry an array schema");
+            };
+            let items = items.clone();
+            let (min_items, max_items) = (*min_items, *max_items);
+            match state {
+                _ if is_whitespace(c) => {
+                    stack.frames.push(Frame::Array {
+                        schema,
+                        count,
+                        state,
+                    });
+                    out.push(stack);
+                }
+                ArrayState::Open | ArrayState::AfterValue if c == ']' => {
+                    if count >= min_items {
+                        out.push(stack);
+                    }
+                }
+                ArrayState::AfterValue => {
+                    if c == ',' && max_items.is_none_or(|max| count < max) {
+                        stack.frames.push(Frame::Array {
+                            schema,
+                            count,
+                            state: ArrayState::Next,
+                        });
+                        out.push(stack);
+                    }
+                }
+                ArrayState::Open | ArrayState::Next => {
+                    if max_items.is_none_or(|max| count < max) {
+                        stack.frames.push(Frame::Array {
+                            schema,
+                            count: count + 1,
+                            state: ArrayState::AfterValue,
+                        });
+                        start_value(stack, &items, c, grammar, out);
+                    }
+                }
+            }
+        }
+        Frame::String { escape } => {
+            if escape == Escape::None && c == '"' {
+                out.push(stack);
+            } else if let Some(escape) = escape.step(c) {
+                stack.frames.push(Frame::String { escape });
+                out.push(stack);
+            }
+        }
+        Frame::Number { integer, state } => {
+            if let Some(state) = state.step(c, integer) {
+                stack.frames.push(Frame::Number { integer, state });
+                out.push(stack);
+            } else if state.is_terminal() {
+                dispatch(stack, c, grammar, out);
+            }
+        }
+        Frame::Literal { options, typed } => {
+            let mut extended = typed.clone();
+            extended.push(c);
+            if options.iter().any(|o| o.starts_with(&extended)) {
+                let longer = options
+                    .iter()
+                    .any(|o| o.len() > extended.len() && o.starts_with(&extended));
+                if longer {
+                    stack.frames.push(Frame::Literal {
+                        options,
+                        typed: extended,
+                    });
+                }
+                out.push(stack);
+            } else if options.contains(&typed) {
+                dispatch(stack, c, grammar, out);
+            }
+        }
+        Frame::Text { text, pos } => {
+            if text[pos..].starts_with(c) {
+                let pos = pos + c.len_utf8();
+                if pos < text.len() {
+                    stack.frames.push(Frame::Text { text, pos });
+                }
+                out.push(stack);
+            } else if pos == 0 && is_whitespace(c) {
+                stack.frames.push(Frame::Text { text, pos });
+                out.push(stack);
+            }
+        }
+    }
+}
+
+fn start_value(
+    mut stack: Stack,
+    schema: &SchemaRef,
+    c: char,
+    grammar: &Grammar,
+    out: &mut Vec<Stack>,
+) {
+    if is_whitespace(c) {
+        stack.frames.push(Frame::Value(schema.clone()));
+        out.push(stack);
+        return;
+    }
+    let frame = match &**schema {
+        Schema::Any => {
+            for alternative in &grammar.any_alternatives {
+                start_value(stack.clone(), alternative, c, grammar, out);
+            }
+            return;
+        }
+        Schema::AnyOf(alternatives) => {
+            for alternative in alternatives {
+                start_value(stack.clone(), alternative, c, grammar, out);
+            }
+            return;
+        }
+        Schema::Literals(options) => {
+            stack.frames.push(Frame::Literal {
+                options: options.clone(),
+                typed: String::new(),
+            });
+            dispatch(stack, c, grammar, out);
+            return;
+        }
+        Schema::Object(_) if c == '{' => Frame::Object {
+            schema: schema.clone(),
+            seen: Vec::new(),
+            state: ObjectState::Open,
+        },
+        Schema::Array { .. } if c == '[' => Frame::Array {
+            schema: schema.clone(),
+            count: 0,
+            state: ArrayState::Open,
+        },
+        Schema::String if c == '"' => Frame::String {
+            escape: Escape::None,
+        },
+        Schema::Number { integer } => match NumberState::Start.step(c, *integer) {
+            Some(state) => Frame::Number {
+                integer: *integer,
+                state,
+            },
+            None => return,
+        },
+        _ => return,
+    };
+    stack.frames.push(frame);
+    out.push(stack);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::JsonMatcher;
+    use crate::openai::structured_output::schema::Grammar;
+    use serde_json::json;
+
+    fn accepts(grammar: &Grammar, text: &str) -> bool {
+        JsonMatcher::new(grammar)
+            .advance_str(grammar, text)
+            .is_some_and(|state| state.is_accepting())
+    }
+
+    #[test]
+    fn json_object_accepts_only_objects() {
+        let grammar = Grammar::json_object();
+        assert!(accepts(
+            &grammar,
+            r#"{"a": [1, -2.5e3, true, null], "b": {"c": "é\n"}}"#
+        ));
+        assert!(accepts(&grammar, "{}"));
+        assert!(!accepts(&grammar, "[1, 2]"));
+        assert!(!accepts(&grammar, r#"{"a": 01}"#));
+        assert!(!accepts(&grammar, r#"{"a": 1,}"#));
+        assert!(!accepts(&grammar, r#"{"a": 1"#));
+        let done = JsonMatcher::new(&grammar)
+            .advance_str(&grammar, "{}")
+            .unwrap();
+        assert!(done.is_finished());
+        assert!(done.advance(&grammar, ' ').is_none());
+    }
+
+    #[test]
+    fn schema_enforces_keys_types_and_required() {
+        let grammar = Grammar::from_json_schema(&json!({
+            "type": "object",
+            "properties": {
+                "name": {"type": "string"},
+                "age": {"type": "integer"},
+                "role": {"enum": ["admin", "user"]},
+                "tags": {"type": "array", "items": {"type": "string"}, "minItems": 1, "maxItems": 2}
+            },
+            "required": ["name", "age"],
+            "additionalProperties": false
+        }))
+        .unwrap();
+
+        assert!(accepts(&grammar, r#"{"age": 3, "name": "x"}"#));
+        assert!(accepts(
+            &grammar,
+            r#"{"name": "x", "age": 30, "role": "user", "tags": ["a", "b"]}"#
+        ));
+        assert!(!accepts(&grammar, r#"{"name": "x"}"#));
+        assert!(!accepts(&grammar, r#"{"name": "x", "age": 3.5}"#));
+        assert!(!accepts(&grammar, r#"{"name": "x", "age": 3, "extra": 1}"#));
+        assert!(!accepts(
+            &grammar,
+            r#"{"name": "x", "name": "y", "age": 3}"#
+        ));
+        assert!(!accepts(
+            &grammar,
+            r#"{"name": "x", "age": 3, "role": "guest"}"#
+        ));
+        assert!(!accepts(&grammar, r#"{"name": "x", "age": 3, "tags": []}"#));
+        assert!(!accepts(
+            &grammar,
+            r#"{"name": "x", "age": 3, "tags": ["a", "b", "c"]}"#
+        ));
+    }
+
+    #[test]
+    fn any_of_tracks_alternatives_and_top_level_scalars() {
+        let grammar = Grammar::from_json_schema(&json!({
+            "anyOf": [{"type": "number"}, {"type": "string"}, {"const": 10}]
+        }))
+        .unwrap();
+        assert!(accepts(&grammar, "10"));
+        assert!(accepts(&grammar, "105"));
+        assert!(accepts(&grammar, r#""ten""#));
+        assert!(!accepts(&grammar, "true"));
+        assert!(!accepts(&grammar, "-"));
+    }
+
+    #[test]
+    fn whitespace_runs_are_bounded() {
+        let grammar = Grammar::json_object();
+        let state = JsonMatcher::new(&grammar)
+            .advance_str(&grammar, "{")
+            .unwrap();
+        assert!(state.advance_str(&grammar, &" ".repeat(16)).is_some());
+        assert!(state.advance_str(&grammar, &" ".repeat(17)).is_none());
+        let in_string = state.advance_str(&grammar, "\"").unwrap();
+        assert!(in_string.in_string_body(&grammar));
+        assert!(in_string.advance_str(&grammar, &" ".repeat(64)).is_some());
+    }
+}
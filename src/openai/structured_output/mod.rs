@@ -0,0 +1,702 @@
+//! Structured output (`response_format`) support.
+//!
+//! A request's JSON schema is compiled into a [`Grammar`], tracked per
+//! sequence by a [`GuidedDecoder`], and turned into a mask over the
+//! tokenizer vocabulary before every sampling step, so the generated text
+//! always parses and validates against the schema.
+//!
+//! Forced tool calls (`tool_choice: "required"` or a named function) use the
+//! same machinery: the model's tool-call envelope is laid around a JSON
+//! object whose arguments follow the tool's `parameters` schema.
+
+pub mod matcher;
+pub mod schema;
+pub mod vocab;
+
+pub use matcher::JsonMatcher;
+pub use schema::Grammar;
+pub use vocab::TokenVocab;
+
+use crate::tools::stream_parser::ToolModelType;
+use schema::{GrammarBuilder, ObjectSchema, Schema, SchemaRef};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::warn;
+
+/// Upper bound on the per-sequence cache of computed token masks.
+const MASK_CACHE_CAPACITY: usize = 1024;
+
+/// Error code of a request whose output left its grammar.
+pub const GUIDED_DECODING_FAILED: &str = "guided_decoding_failed";
+
+/// Constraint carried on the sampling params of a request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum GuidedConstraint {
+    /// Any JSON object.
+    JsonObject,
+    /// A JSON Schema document.
+    JsonSchema(serde_json::Value),
+    /// A call to one of the given tools (name and `parameters` schema).
+    ToolCall(Vec<(String, serde_json::Value)>),
+}
+
+impl GuidedConstraint {
+    /// Compile the constraint; tool calls are wrapped in `envelope`, and
+    /// cannot be compiled without one.
+    pub fn compile(&self, envelope: Option<&ToolCallEnvelope>) -> Result<Grammar, String> {
+        match self {
+            GuidedConstraint::JsonObject => Ok(Grammar::json_object()),
+            GuidedConstraint::JsonSchema(schema) => Grammar::from_json_schema(schema),
+            GuidedConstraint::ToolCall(tools) => match envelope {
+                Some(envelope) => tool_call_grammar(tools, envelope),
+                None => Err("the model's tool-call format cannot be constrained".to_string()),
+            },
+        }
+    }
+}
+
+/// Start or end marker of a tool-call envelope.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EnvelopeMarker {
+    /// The marker is a single token and must be emitted as that token.
+    Token(u32),
+    Text(String),
+}
+
+/// Model-specific layout of a forced tool call.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolCallEnvelope {
+    pub start: Option<EnvelopeMarker>,
+    pub end: Option<EnvelopeMarker>,
+    /// Key holding the call arguments (`parameters` for Llama 3).
+    pub arguments_key: &'static str,
+    /// The call is wrapped in a JSON list (Mistral `[TOOL_CALLS][...]`).
+    pub list: bool,
+}
+
+impl Default for ToolCallEnvelope {
+    /// A bare `{"name": ..., "arguments": ...}` object.
+    fn default() -> Self {
+        Self {
+            start: None,
+            end: None,
+            arguments_key: "arguments",
+            list: false,
+        }
+    }
+}
+
+impl ToolCallEnvelope {
+    /// Layout read by the `parser_name` tool parser of `model_type`, given
+    /// its resolved start/end markers. `None` when that parser does not read
+    /// JSON calls (XML, pythonic or harmony formats), so a forced call cannot
+    /// be constrained.
+    pub fn for_model_type(
+        model_type: &ToolModelType,
+        parser_name: &str,
+        start: Option<EnvelopeMarker>,
+        end: Option<EnvelopeMarker>,
+    ) -> Option<Self> {
+        // Gemma 4 writes `call:NAME{...}` and GPT-OSS calls go through harmony
+        // channels, whatever parser name they map to.
+        if matches!(model_type, ToolModelType::Gemma4 | ToolModelType::GptOss) {
+            return None;
+        }
+        match parser_name {
+            "llama" => Some(Self {
+                start,
+                end,
+                arguments_key: "parameters",
+                list: false,
+            }),
+            // The closing `]` of the call list doubles as the end marker.
+            "mistral" => Some(Self {
+                start,
+                end: None,
+                arguments_key: "arguments",
+                list: true,
+            }),
+            "qwen" | "json" => Some(Self {
+                start,
+                end,
+                ..Self::default()
+            }),
+            _ => None,
+        }
+    }
+}
+
+fn tool_call_grammar(
+    tools: &[(String, serde_json::Value)],
+    envelope: &ToolCallEnvelope,
+) -> Result<Grammar, String> {
+    let builder = GrammarBuilder::new();
+    let mut calls = tools
+        .iter()
+        .map(|(name, parameters)| {
+            let is_empty = parameters.is_null()
+                || parameters
+                    .as_object()
+                    .is_some_and(|object| object.is_empty());
+            let arguments = if is_empty {
+                builder.any_object()
+            } else {
+                builder
+                    .compile(parameters)
+                    .map_err(|e| format!("tool '{}': {}", name, e))?
+            };
+            let name_literal = serde_json::to_string(name).map_err(|e| e.to_string())?;
+            let keys = vec!["name".to_string(), envelope.arguments_key.to_string()];
+            Ok(SchemaRef::new(Schema::Object(ObjectSchema {
+                properties: vec![
+                    (
+                        keys[0].clone(),
+                        SchemaRef::new(Schema::Literals(Arc::from(vec![name_literal]))),
+                    ),
+                    (keys[1].clone(), arguments),
+                ],
+                required: keys,
+                additional: None,
+                ordered: true,
+            })))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let call = match calls.len() {
+        0 => return Err("no tools to call".to_string()),
+        1 => calls.remove(0),
+        _ => SchemaRef::new(Schema::AnyOf(calls)),
+    };
+    let root = if envelope.list {
+        SchemaRef::new(Schema::Array {
+            items: call,
+            min_items: 1,
+            max_items: Some(1),
+        })
+    } else {
+        call
+    };
+    let mut grammar = builder.finish(root);
+    match &envelope.start {
+        Some(EnvelopeMarker::Token(token)) => grammar.open_token = Some(*token),
+        Some(EnvelopeMarker::Text(text)) => grammar.prefix = text.clone(),
+        None => {}
+    }
+    match &envelope.end {
+        Some(EnvelopeMarker::Token(token)) => grammar.close_token = Some(*token),
+        Some(EnvelopeMarker::Text(text)) => grammar.suffix = text.clone(),
+        None => {}
+    }
+    Ok(grammar)
+}
+
+/// Per-sequence constraint state.
+///
+/// The decoder catches up on generated tokens lazily, so it stays correct
+/// regardless of how tokens were appended (prefill, decode, preemption).
+/// Text inside a reasoning block (e.g. `<think>...</think>`) is left
+/// unconstrained; the grammar applies to the answer that follows it.
+#[derive(Clone)]
+pub struct GuidedDecoder {
+    grammar: Arc<Grammar>,
+    /// `None` once the output left the grammar; the request is then failed.
+    matcher: Option<JsonMatcher>,
+    reasoning_end: Option<String>,
+    reasoning_tail: String,
+    /// Whether any non-whitespace answer text has been produced.
+    started: bool,
+    /// Single-token envelope markers already emitted.
+    opened: bool,
+    closed: bool,
+    consumed: usize,
+    masks: HashMap<JsonMatcher, Arc<Vec<u32>>>,
+}
+
+impl GuidedDecoder {
+    pub fn new(grammar: Arc<Grammar>, reasoning_end: Option<String>) -> Self {
+        let matcher = JsonMatcher::new(&grammar);
+        Self {
+            grammar,
+            matcher: Some(matcher),
+            reasoning_end,
+            reasoning_tail: String::new(),
+            started: false,
+            opened: false,
+            closed: false,
+            consumed: 0,
+            masks: HashMap::new(),
+        }
+    }
+
+    /// Feed the generated tokens the decoder has not seen yet.
+    pub fn sync(&mut self, vocab: &TokenVocab, output_tokens: &[u32]) {
+        for &token in output_tokens.iter().skip(self.consumed) {
+            self.consume(vocab, token);
+        }
+        self.consumed = self.consumed.max(output_tokens.len());
+    }
+
+    fn consume(&mut self, vocab: &TokenVocab, token: u32) {
+        if let Some(end) = self.reasoning_end.clone() {
+            self.reasoning_tail.push_str(vocab.text(token));
+            if let Some(pos) = self.reasoning_tail.find(&end) {
+                let rest = self.reasoning_tail[pos + end.len()..].to_string();
+                self.reasoning_end = None;
+                self.reasoning_tail.clear();
+                self.feed(&rest);
+            } else {
+                let keep = self
+                    .reasoning_tail
+                    .char_indices()
+                    .rev()
+                    .nth(end.chars().count())
+                    .map_or(0, |(idx, _)| idx);
+                self.reasoning_tail.drain(..keep);
+            }
+            return;
+        }
+        if !self.started {
+            if let Some(end) = vocab.reasoning_end_for(token) {
+                self.reasoning_end = Some(end.to_string());
+                return;
+            }
+        }
+        if self.grammar.open_token == Some(token) && !self.opened {
+            self.opened = true;
+            self.started = true;
+            return;
+        }
+        if self.grammar.close_token == Some(token)
+            && self.matcher.as_ref().is_some_and(JsonMatcher::is_finished)
+        {
+            self.closed = true;
+            return;
+        }
+        if vocab.is_usable(token) {
+            self.feed(vocab.text(token));
+        }
+    }
+
+    fn feed(&mut self, text: &str) {
+        let Some(matcher) = &self.matcher else {
+            return;
+        };
+        self.started |= text.chars().any(|c| !c.is_whitespace());
+        self.matcher = matcher.advance_str(&self.grammar, text);
+        if self.matcher.is_none() {
+            warn!("Generated text left the constrained grammar");
+        }
+    }
+
+    /// Whether the output left the grammar, so it can no longer be trusted
+    /// to parse.
+    pub fn failed(&self) -> bool {
+        self.matcher.is_none()
+    }
+
+    /// Token ids allowed at the next step, or `None` when the next token is
+    /// unconstrained (inside reasoning, or after the output left the grammar).
+    pub fn allowed_tokens(
+        &mut self,
+        vocab: &TokenVocab,
+        stop_token_ids: &[u32],
+    ) -> Option<Arc<Vec<u32>>> {
+        if self.reasoning_end.is_some() {
+            return None;
+        }
+        let matcher = self.matcher.as_ref()?;
+        if let Some(open) = self.grammar.open_token.filter(|_| !self.opened) {
+            let mut allowed = vec![open];
+            if !self.started {
+                allowed.extend(vocab.reasoning_start_tokens());
+            }
+            return Some(Arc::new(allowed));
+        }
+        if matcher.is_finished() {
+            if let Some(close) = self.grammar.close_token.filter(|_| !self.closed) {
+                return Some(Arc::new(vec![close]));
+            }
+        }
+        if let Some(mask) = self.masks.get(matcher) {
+            return Some(mask.clone());
+        }
+        let mut allowed = if matcher.is_finished() {
+            Vec::new()
+        } else {
+            vocab.allowed_tokens(&self.grammar, matcher)
+        };
+        if matcher.is_accepting() || allowed.is_empty() {
+            allowed.extend_from_slice(stop_token_ids);
+        }
+        if !self.started {
+            // Before the answer starts the model may still open a reasoning block.
+            allowed.extend(vocab.reasoning_start_tokens());
+            return Some(Arc::new(allowed));
+        }
+        let mask = Arc::new(allowed);
+        if self.masks.len() >= MASK_CACHE_CAPACITY {
+            self.masks.clear();
+        }
+        self.masks.insert(matcher.clone(), mask.clone());
+        Some(mask)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EnvelopeMarker, GuidedConstraint, GuidedDecoder, TokenVocab, ToolCallEnvelope};
+    use crate::openai::structured_output::JsonMatcher;
+    use crate::tools::stream_parser::{StreamToolParser, ToolModelType};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn vocab() -> TokenVocab {
+        let texts = [
+            "{", "}", "{\"", "\"", "\":", " ", "ok", "true", "<eos>", "<think>", "</think>", "\n",
+            "hmm",
+        ];
+        TokenVocab::new(texts.iter().map(|t| t.to_string()).collect(), |id| id == 8)
+    }
+
+    fn texts(vocab: &TokenVocab, ids: &[u32]) -> Vec<String> {
+        let mut texts: Vec<String> = ids.iter().map(|&id| vocab.text(id).to_string()).collect();
+        texts.sort();
+        texts
+    }
+
+    #[test]
+    fn decoder_tracks_output_and_allows_eos_when_complete() {
+        let vocab = vocab();
+        let grammar = GuidedConstraint::JsonSchema(json!({
+            "type": "object",
+            "properties": {"ok": {"type": "boolean"}},
+            "required": ["ok"],
+            "additionalProperties": false
+        }))
+        .compile(None)
+        .unwrap();
+        let mut decoder = GuidedDecoder::new(Arc::new(grammar), None);
+
+        let first = decoder.allowed_tokens(&vocab, &[8]).unwrap();
+        assert_eq!(
+            texts(&vocab, &first),
+            vec!["\n", " ", "<think>", "{", "{\""]
+        );
+
+        // {"ok": true}
+        decoder.sync(&vocab, &[2, 6, 4, 5, 7]);
+        let closing = decoder.allowed_tokens(&vocab, &[8]).unwrap();
+        assert_eq!(texts(&vocab, &closing), vec!["\n", " ", "}"]);
+
+        decoder.sync(&vocab, &[2, 6, 4, 5, 7, 1]);
+        let done = decoder.allowed_tokens(&vocab, &[8]).unwrap();
+        assert_eq!(done.as_slice(), &[8]);
+    }
+
+    #[test]
+    fn reasoning_block_is_unconstrained() {
+        let vocab = vocab();
+        let grammar = GuidedConstraint::JsonObject.compile(None).unwrap();
+        let mut decoder = GuidedDecoder::new(Arc::new(grammar), None);
+
+        decoder.sync(&vocab, &[9, 12]);
+        assert!(decoder.allowed_tokens(&vocab, &[8]).is_none());
+
+        decoder.sync(&vocab, &[9, 12, 10, 11]);
+        let mask = decoder.allowed_tokens(&vocab, &[8]).unwrap();
+        assert_eq!(texts(&vocab, &mask), vec!["\n", " ", "<think>", "{", "{\""]);
+
+        let mut prefilled = GuidedDecoder::new(
+            Arc::new(GuidedConstraint::JsonObject.compile(None).unwrap()),
+            Some("</think>".to_string()),
+        );
+        assert!(prefilled.allowed_tokens(&vocab, &[8]).is_none());
+        prefilled.sync(&vocab, &[12, 10]);
+        assert!(prefilled.allowed_tokens(&vocab, &[8]).is_some());
+    }
+
+    /// Envelope for `model_type` with the tool parser it is served with.
+    fn envelope(model_type: ToolModelType, model_id: &str) -> Option<ToolCallEnvelope> {
+        let parser = StreamToolParser::parser_name_for_model(&model_type, model_id);
+        ToolCallEnvelope::for_model_type(&model_type, parser, None, None)
+    }
+
+    #[test]
+    fn output_outside_the_grammar_fails_the_decoder() {
+        let vocab = vocab();
+        let grammar = GuidedConstraint::JsonObject.compile(None).unwrap();
+        let mut decoder = GuidedDecoder::new(Arc::new(grammar), None);
+        decoder.sync(&vocab, &[0]);
+        assert!(!decoder.failed());
+
+        decoder.sync(&vocab, &[0, 12]);
+        assert!(decoder.failed());
+        assert!(decoder.allowed_tokens(&vocab, &[8]).is_none());
+    }
+
+    fn weather_tool() -> GuidedConstraint {
+        GuidedConstraint::ToolCall(vec![(
+            "get_weather".to_string(),
+            json!({
+                "type": "object",
+                "properties": {"city": {"type": "string"}},
+                "required": ["city"]
+            }),
+        )])
+    }
+
+    #[test]
+    fn tool_call_forces_single_token_envelope() {
+        let vocab = vocab();
+        // Token ids 8 and 10 stand in for the model's start/end marker tokens.
+        let envelope = ToolCallEnvelope::for_model_type(
+            &ToolModelType::Qwen3,
+            "qwen",
+            Some(EnvelopeMarker::Token(8)),
+            Some(EnvelopeMarker::Token(10)),
+        )
+        .unwrap();
+        let grammar = weather_tool().compile(Some(&envelope)).unwrap();
+        let mut decoder = GuidedDecoder::new(Arc::new(grammar), None);
+
+        let first = decoder.allowed_tokens(&vocab, &[]).unwrap();
+        assert_eq!(texts(&vocab, &first), vec!["<eos>", "<think>"]);
+
+        decoder.sync(&vocab, &[8]);
+        let body = decoder.allowed_tokens(&vocab, &[]).unwrap();
+        assert_eq!(texts(&vocab, &body), vec!["\n", " ", "{", "{\""]);
+
+        let matcher = JsonMatcher::new(&decoder.grammar)
+            .advance_str(
+                &decoder.grammar,
+                r#"{"name": "get_weather", "arguments": {"city": "x"}}"#,
+            )
+            .unwrap();
+        assert!(matcher.is_finished());
+        decoder.matcher = Some(matcher);
+        let close = decoder.allowed_tokens(&vocab, &[]).unwrap();
+        assert_eq!(close.as_slice(), &[10]);
+    }
+
+    #[test]
+    fn tool_call_grammar_follows_model_layout() {
+        let accepts = |envelope: &ToolCallEnvelope, text: &str| {
+            let grammar = weather_tool().compile(Some(envelope)).unwrap();
+            JsonMatcher::new(&grammar)
+                .advance_str(&grammar, text)
+                .is_some_and(|state| state.is_finished())
+        };
+        let text_markers = ToolCallEnvelope::for_model_type(
+            &ToolModelType::Qwen3,
+            "qwen",
+            Some(EnvelopeMarker::Text("<tool_call>".to_string())),
+            Some(EnvelopeMarker::Text("</tool_call>".to_string())),
+        )
+        .unwrap();
+        assert!(accepts(
+            &text_markers,
+            "<tool_call>\n{\"name\": \"get_weather\", \"arguments\": {\"city\": \"x\"}}\n</tool_call>"
+        ));
+        // Arguments must follow the name and match the parameters schema.
+        assert!(!accepts(
+            &text_markers,
+            "<tool_call>{\"arguments\": {\"city\": \"x\"}, \"name\": \"get_weather\"}</tool_call>"
+        ));
+        assert!(!accepts(
+            &text_markers,
+            "<tool_call>{\"name\": \"get_weather\", \"arguments\": {}}</tool_call>"
+        ));
+        assert!(!accepts(
+            &text_markers,
+            "<tool_call>{\"name\": \"other\", \"arguments\": {\"city\": \"x\"}}</tool_call>"
+        ));
+
+        let llama = envelope(ToolModelType::LLaMa, "llama-3.1-8b").unwrap();
+        assert!(accepts(
+            &llama,
+            r#"{"name": "get_weather", "parameters": {"city": "x"}}"#
+        ));
+        let mistral = ToolCallEnvelope::for_model_type(
+            &ToolModelType::Mistral,
+            "mistral",
+            None,
+            Some(EnvelopeMarker::Text("]".to_string())),
+        )
+        .unwrap();
+        assert!(accepts(
+            &mistral,
+            r#"[{"name": "get_weather", "arguments": {"city": "x"}}]"#
+        ));
+    }
+
+    fn parameters_object() -> ToolCallEnvelope {
+        ToolCallEnvelope {
+            arguments_key: "parameters",
+            ..ToolCallEnvelope::default()
+        }
+    }
+
+    fn call_list() -> ToolCallEnvelope {
+        ToolCallEnvelope {
+            list: true,
+            ..ToolCallEnvelope::default()
+        }
+    }
+
+    #[test]
+    fn qwen3_envelope() {
+        assert_eq!(
+            envelope(ToolModelType::Qwen3, "Qwen3-8B"),
+            Some(ToolCallEnvelope::default())
+        );
+        assert_eq!(envelope(ToolModelType::Qwen3, "Qwen3-Coder-30B"), None);
+    }
+
+    #[test]
+    fn qwen3_moe_envelope() {
+        assert_eq!(
+            envelope(ToolModelType::Qwen3MoE, "Qwen3-30B-A3B"),
+            Some(ToolCallEnvelope::default())
+        );
+    }
+
+    #[test]
+    fn qwen3_vl_envelope() {
+        assert_eq!(
+            envelope(ToolModelType::Qwen3VL, "Qwen3-VL-8B"),
+            Some(ToolCallEnvelope::default())
+        );
+    }
+
+    #[test]
+    fn qwen3_5_has_no_envelope() {
+        assert_eq!(envelope(ToolModelType::Qwen3_5, "Qwen3.5-9B"), None);
+    }
+
+    #[test]
+    fn qwen3_5_moe_has_no_envelope() {
+        assert_eq!(envelope(ToolModelType::Qwen3_5MoE, "Qwen3.5-35B-A3B"), None);
+    }
+
+    #[test]
+    fn llama_envelope() {
+        assert_eq!(
+            envelope(ToolModelType::LLaMa, "Llama-3.1-8B"),
+            Some(parameters_object())
+        );
+    }
+
+    #[test]
+    fn llama4_has_no_envelope() {
+        assert_eq!(envelope(ToolModelType::LLaMa4, "Llama-4-Scout"), None);
+    }
+
+    #[test]
+    fn phi_envelope() {
+        assert_eq!(
+            envelope(ToolModelType::Phi, "Phi-3.5-mini"),
+            Some(ToolCallEnvelope::default())
+        );
+    }
+
+    #[test]
+    fn phi4_envelope() {
+        assert_eq!(
+            envelope(ToolModelType::Phi4, "Phi-4"),
+            Some(ToolCallEnvelope::default())
+        );
+    }
+
+    #[test]
+    fn mistral_envelope() {
+        assert_eq!(
+            envelope(ToolModelType::Mistral, "Mistral-7B"),
+            Some(call_list())
+        );
+    }
+
+    #[test]
+    fn mistral3_vl_envelope() {
+        assert_eq!(
+            envelope(ToolModelType::Mistral3VL, "Mistral-Small-3.1"),
+            Some(call_list())
+        );
+    }
+
+    #[test]
+    fn glm4_has_no_envelope() {
+        assert_eq!(envelope(ToolModelType::GLM4, "GLM-4-9B"), None);
+    }
+
+    #[test]
+    fn glm4_moe_has_no_envelope() {
+        assert_eq!(envelope(ToolModelType::GLM4MoE, "GLM-4.5"), None);
+    }
+
+    #[test]
+    fn glm4_moe_lite_has_no_envelope() {
+        assert_eq!(envelope(ToolModelType::GLM4MoeLite, "GLM-4.7-Flash"), None);
+    }
+
+    #[test]
+    fn glm5_has_no_envelope() {
+        assert_eq!(envelope(ToolModelType::GLM5, "GLM-5"), None);
+    }
+
+    #[test]
+    fn yi_envelope() {
+        assert_eq!(
+            envelope(ToolModelType::Yi, "Yi-1.5-9B"),
+            Some(ToolCallEnvelope::default())
+        );
+    }
+
+    #[test]
+    fn stablelm_envelope() {
+        assert_eq!(
+            envelope(ToolModelType::StableLM, "stablelm-2-1_6b"),
+            Some(ToolCallEnvelope::default())
+        );
+    }
+
+    #[test]
+    fn deepseek_has_no_envelope() {
+        assert_eq!(envelope(ToolModelType::DeepSeek, "DeepSeek-V3"), None);
+    }
+
+    #[test]
+    fn gemma_envelope() {
+        assert_eq!(
+            envelope(ToolModelType::Gemma, "gemma-2-9b"),
+            Some(ToolCallEnvelope::default())
+        );
+    }
+
+    #[test]
+    fn gemma3_envelope() {
+        assert_eq!(
+            envelope(ToolModelType::Gemma3, "gemma-3-12b"),
+            Some(ToolCallEnvelope::default())
+        );
+    }
+
+    #[test]
+    fn gemma4_has_no_envelope() {
+        assert_eq!(envelope(ToolModelType::Gemma4, "gemma-4"), None);
+    }
+
+    #[test]
+    fn minimax_has_no_envelope() {
+        assert_eq!(envelope(ToolModelType::MiniMax, "MiniMax-M2"), None);
+    }
+
+    #[test]
+    fn gpt_oss_has_no_envelope() {
+        assert_eq!(envelope(ToolModelType::GptOss, "gpt-oss-20b"), None);
+    }
+
+    #[test]
+    fn tool_call_needs_an_envelope() {
+        assert!(weather_tool().compile(None).is_err());
+    }
+}
@@ -0,0 +1,428 @@
+//! Compilation of JSON Schema documents into the reduced form understood by
+//! the [`JsonMatcher`](super::matcher::JsonMatcher).
+//!
+//! Supported keywords: `type` (including type arrays), `properties`,
+//! `required`, `additionalProperties`, `items`, `minItems`, `maxItems`,
+//! `enum`, `const`, `anyOf`, `oneOf`, single-element `allOf` and local
+//! `$ref` pointers (`#/$defs/...`, `#/definitions/...`). Value-level
+//! keywords such as `pattern`, `format`, `minimum` or `maxLength` are
+//! accepted but not enforced.
+
+use serde_json::Value;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+/// Recursive `$ref` chains deeper than this fall back to an unconstrained value.
+const MAX_REF_DEPTH: usize = 8;
+
+#[derive(Debug)]
+pub enum Schema {
+    /// Any JSON value.
+    Any,
+    Object(ObjectSchema),
+    Array {
+        items: SchemaRef,
+        min_items: usize,
+        max_items: Option<usize>,
+    },
+    String,
+    Number {
+        integer: bool,
+    },
+    /// One of a fixed set of serialized JSON values (`enum`, `const`, booleans, null).
+    Literals(Arc<[String]>),
+    AnyOf(Vec<SchemaRef>),
+}
+
+#[derive(Debug)]
+pub struct ObjectSchema {
+    /// Property names are stored JSON-escaped (without quotes), exactly as
+    /// they must appear between the key quotes in the output.
+    pub properties: Vec<(String, SchemaRef)>,
+    pub required: Vec<String>,
+    /// Schema for keys not listed in `properties`; `None` forbids them.
+    pub additional: Option<SchemaRef>,
+    /// Properties must appear in declaration order (tool-call objects, whose
+    /// streaming parsers expect `name` before the arguments).
+    pub ordered: bool,
+}
+
+impl ObjectSchema {
+    pub fn property(&self, key: &str) -> Option<&SchemaRef> {
+        self.properties
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, schema)| schema)
+    }
+}
+
+/// Shared schema node. Equality and hashing use node identity so matcher
+/// states stay cheap to compare.
+#[derive(Debug, Clone)]
+pub struct SchemaRef(pub Arc<Schema>);
+
+impl SchemaRef {
+    pub fn new(schema: Schema) -> Self {
+        Self(Arc::new(schema))
+    }
+}
+
+impl std::ops::Deref for SchemaRef {
+    type Target = Schema;
+
+    fn deref(&self) -> &Schema {
+        &self.0
+    }
+}
+
+impl PartialEq for SchemaRef {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for SchemaRef {}
+
+impl Hash for SchemaRef {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (Arc::as_ptr(&self.0) as usize).hash(state);
+    }
+}
+
+/// A compiled constraint: the root schema plus the shared nodes used to
+/// expand unconstrained (`Any`) values.
+#[derive(Debug)]
+pub struct Grammar {
+    pub root: SchemaRef,
+    /// Literal text required before / after the JSON value (tool-call
+    /// envelope markers that span several tokens).
+    pub prefix: String,
+    pub suffix: String,
+    /// Envelope markers that are a single token and are forced by id, since
+    /// tool-call parsers match them by token id.
+    pub open_token: Option<u32>,
+    pub close_token: Option<u32>,
+    pub(crate) any_alternatives: Vec<SchemaRef>,
+}
+
+/// Compiles schema documents into nodes sharing one set of `Any` alternatives.
+pub(crate) struct GrammarBuilder {
+    any: SchemaRef,
+    any_alternatives: Vec<SchemaRef>,
+}
+
+impl GrammarBuilder {
+    pub(crate) fn new() -> Self {
+        let any = SchemaRef::new(Schema::Any);
+        let any_object = SchemaRef::new(Schema::Object(ObjectSchema {
+            properties: Vec::new(),
+            required: Vec::new(),
+            additional: Some(any.clone()),
+            ordered: false,
+        }));
+        let any_alternatives = vec![
+            any_object,
+            SchemaRef::new(Schema::Array {
+                items: any.clone(),
+                min_items: 0,
+                max_items: None,
+            }),
+            SchemaRef::new(Schema::String),
+            SchemaRef::new(Schema::Number { integer: false }),
+            literals(&["true", "false", "null"]),
+        ];
+        Self {
+            any,
+            any_alternatives,
+        }
+    }
+
+    /// Any JSON object.
+    pub(crate) fn any_object(&self) -> SchemaRef {
+        self.any_alternatives[0].clone()
+    }
+
+    /// Compile `schema`, resolving `$ref` pointers against the schema itself.
+    pub(crate) fn compile(&self, schema: &Value) -> Result<SchemaRef, String> {
+        SchemaCompiler {
+            document: schema,
+            any: &self.any,
+        }
+        .compile(schema, 0)
+    }
+
+    pub(crate) fn finish(self, root: SchemaRef) -> Grammar {
+        Grammar {
+            root,
+            prefix: String::new(),
+            suffix: String::new(),
+            open_token: None,
+            close_token: None,
+            any_alternatives: self.any_alternatives,
+        }
+    }
+}
+
+impl Grammar {
+    /// Any JSON object (`response_format: {"type": "json_object"}`).
+    pub fn json_object() -> Self {
+        let builder = GrammarBuilder::new();
+        let root = builder.any_object();
+        builder.finish(root)
+    }
+
+    /// Compile a JSON Schema document.
+    pub fn from_json_schema(schema: &Value) -> Result<Self, String> {
+        let builder = GrammarBuilder::new();
+        let root = builder.compile(schema)?;
+        Ok(builder.finish(root))
+    }
+}
+
+fn literals(values: &[&str]) -> SchemaRef {
+    SchemaRef::new(Schema::Literals(
+        values.iter().map(|v| v.to_string()).collect(),
+    ))
+}
+
+/// JSON-escape a property name without the surrounding quotes.
+pub fn escape_key(name: &str) -> String {
+    let quoted = serde_json::to_string(name).unwrap_or_default();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+struct SchemaCompiler<'a> {
+    document: &'a Value,
+    any: &'a SchemaRef,
+}
+
+impl SchemaCompiler<'_> {
+    fn compile(&self, schema: &Value, ref_depth: usize) -> Result<SchemaRef, String> {
+        let object = match schema {
+            Value::Bool(true) => return Ok(self.any.clone()),
+            Value::Bool(false) => {
+                return Err("schema `false` can never be satisfied".to_string());
+            }
+            Value::Object(object) => object,
+            other => return Err(format!("expected a schema object, got {}", other)),
+        };
+
+        if let Some(reference) = object.get("$ref") {
+            let reference = reference
+                .as_str()
+                .ok_or_else(|| "`$ref` must be a string".to_string())?;
+            if ref_depth >= MAX_REF_DEPTH {
+                return Ok(self.any.clone());
+            }
+            let target = reference
+                .strip_prefix('#')
+                .and_then(|pointer| self.document.pointer(pointer))
+                .ok_or_else(|| format!("unresolvable `$ref` '{}'", reference))?;
+            return self.compile(target, ref_depth + 1);
+        }
+
+        if let Some(value) = object.get("const") {
+            return Ok(SchemaRef::new(Schema::Literals(Arc::from(vec![
+                serde_json::to_string(value).map_err(|e| e.to_string())?,
+            ]))));
+        }
+
+        if let Some(values) = object.get("enum") {
+            let values = values
+                .as_array()
+                .filter(|values| !values.is_empty())
+                .ok_or_else(|| "`enum` must be a non-empty array".to_string())?;
+            let values = values
+                .iter()
+                .map(|value| serde_json::to_string(value).map_err(|e| e.to_string()))
+                .collect::<Result<Vec<_>, _>>()?;
+            return Ok(SchemaRef::new(Schema::Literals(Arc::from(values))));
+        }
+
+        for keyword in ["anyOf", "oneOf"] {
+            if let Some(alternatives) = object.get(keyword) {
+                let alternatives = alternatives
+                    .as_array()
+                    .filter(|alternatives| !alternatives.is_empty())
+                    .ok_or_else(|| format!("`{}` must be a non-empty array", keyword))?;
+                let alternatives = alternatives
+                    .iter()
+                    .map(|alternative| self.compile(alternative, ref_depth))
+                    .collect::<Result<Vec<_>, _>>()?;
+                return Ok(SchemaRef::new(Schema::AnyOf(alternatives)));
+            }
+        }
+
+        if let Some(all_of) = object.get("allOf") {
+            return match all_of.as_array().map(|all_of| all_of.as_slice()) {
+                Some([single]) => self.compile(single, ref_depth),
+                _ => Err("`allOf` is only supported with a single schema".to_string()),
+            };
+        }
+
+        match object.get("type") {
+            Some(Value::String(ty)) => self.compile_typed(object, ty, ref_depth),
+            Some(Value::Array(types)) => {
+                let alternatives = types
+                    .iter()
+                    .map(|ty| {
+                        let ty = ty
+                            .as_str()
+                            .ok_or_else(|| "`type` entries must be strings".to_string())?;
+                        self.compile_typed(object, ty, ref_depth)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                match alternatives.len() {
+                    0 => Err("`type` must not be empty".to_string()),
+                    1 => Ok(alternatives.into_iter().next().unwrap()),
+                    _ => Ok(SchemaRef::new(Schema::AnyOf(alternatives))),
+                }
+            }
+            Some(other) => Err(format!("invalid `type` {}", other)),
+            None if ["properties", "required", "additionalProperties"]
+                .iter()
+                .any(|keyword| object.contains_key(*keyword)) =>
+            {
+                self.compile_typed(object, "object", ref_depth)
+            }
+            None if object.contains_key("items") => self.compile_typed(object, "array", ref_depth),
+            None => Ok(self.any.clone()),
+        }
+    }
+
+    fn compile_typed(
+        &self,
+        object: &serde_json::Map<String, Value>,
+        ty: &str,
+        ref_depth: usize,
+    ) -> Result<SchemaRef, String> {
+        let schema = match ty {
+            "object" => {
+                let mut properties = Vec::new();
+                if let Some(props) = object.get("properties") {
+                    let props = props
+                        .as_object()
+                        .ok_or_else(|| "`properties` must be an object".to_string())?;
+                    for (name, schema) in props {
+                        properties.push((escape_key(name), self.compile(schema, ref_depth)?));
+                    }
+                }
+                let additional = match object.get("additionalProperties") {
+                    None | Some(Value::Bool(true)) => Some(self.any.clone()),
+                    Some(Value::Bool(false)) => None,
+                    Some(schema) => Some(self.compile(schema, ref_depth)?),
+                };
+                let mut required = Vec::new();
+                if let Some(names) = object.get("required") {
+                    let names = names
+                        .as_array()
+                        .ok_or_else(|| "`required` must be an array".to_string())?;
+                    for name in names {
+                        let name = name
+                            .as_str()
+                            .ok_or_else(|| "`required` entries must be strings".to_string())?;
+                        let key = escape_key(name);
+                        if additional.is_none() && !properties.iter().any(|(p, _)| *p == key) {
+                            return Err(format!(
+                                "required property '{}' is not allowed by the schema",
+                                name
+                            ));
+                        }
+                        if !required.contains(&key) {
+                            required.push(key);
+                        }
+                    }
+                }
+                Schema::Object(ObjectSchema {
+                    properties,
+                    required,
+                    additional,
+                    ordered: false,
+                })
+            }
+            "array" => {
+                let items = match object.get("items") {
+                    None => self.any.clone(),
+                    Some(schema) => self.compile(schema, ref_depth)?,
+                };
+                let min_items =
+                    object.get("minItems").and_then(Value::as_u64).unwrap_or(0) as usize;
+                let max_items = object
+                    .get("maxItems")
+                    .and_then(Value::as_u64)
+                    .map(|max| max as usize);
+                if max_items.is_some_and(|max| max < min_items) {
+                    return Err("`maxItems` must not be smaller than `minItems`".to_string());
+                }
+                Schema::Array {
+                    items,
+                    min_items,
+                    max_items,
+                }
+            }
+            "string" => Schema::String,
+            "integer" => Schema::Number { integer: true },
+            "number" => Schema::Number { integer: false },
+            "boolean" => return Ok(literals(&["true", "false"])),
+            "null" => return Ok(literals(&["null"])),
+            other => return Err(format!("unsupported schema type '{}'", other)),
+        };
+        Ok(SchemaRef::new(schema))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Grammar, Schema};
+    use serde_json::json;
+
+    #[test]
+    fn compiles_nested_object_schema() {
+        let grammar = Grammar::from_json_schema(&json!({
+            "type": "object",
+            "properties": {
+                "name": {"type": "string"},
+                "tags": {"type": "array", "items": {"$ref": "#/$defs/tag"}, "maxItems": 3},
+                "kind": {"enum": ["a", "b"]}
+            },
+            "required": ["name"],
+            "additionalProperties": false,
+            "$defs": {"tag": {"type": ["string", "null"]}}
+        }))
+        .expect("schema compiles");
+
+        let Schema::Object(object) = &*grammar.root else {
+            panic!("root should be an object schema");
+        };
+        assert!(object.additional.is_none());
+        assert_eq!(object.required, vec!["name".to_string()]);
+        let Some(tags) = object.property("tags") else {
+            panic!("tags property missing");
+        };
+        let Schema::Array {
+            items, max_items, ..
+        } = &**tags
+        else {
+            panic!("tags should be an array schema");
+        };
+        assert_eq!(*max_items, Some(3));
+        assert!(matches!(&**items, Schema::AnyOf(alternatives) if alternatives.len() == 2));
+        let Some(kind) = object.property("kind") else {
+            panic!("kind property missing");
+        };
+        assert!(matches!(&**kind, Schema::Literals(values) if values[..] == ["\"a\"", "\"b\""]));
+    }
+
+    #[test]
+    fn rejects_unsatisfiable_schemas() {
+        assert!(Grammar::from_json_schema(&json!({"type": "tuple"})).is_err());
+        assert!(Grammar::from_json_schema(&json!({"$ref": "#/$defs/missing"})).is_err());
+        assert!(Grammar::from_json_schema(&json!({
+            "type": "object",
+            "properties": {"a": {"type": "string"}},
+            "required": ["b"],
+            "additionalProperties": false
+        }))
+        .is_err());
+    }
+}
@@ -0,0 +1,239 @@
+//! Tokenizer vocabulary index used to turn a [`JsonMatcher`] state into the
+//! set of token ids that keep the output inside the grammar.
+
+use super::matcher::JsonMatcher;
+use super::schema::Grammar;
+use crate::tools::stream_parser::reasoning_markers;
+use rayon::prelude::*;
+use std::collections::HashSet;
+use tokenizers::Tokenizer;
+
+#[derive(Default)]
+struct TrieNode {
+    /// Sorted by character.
+    children: Vec<(char, usize)>,
+    tokens: Vec<u32>,
+}
+
+/// Prefix tree over token texts, so tokens sharing a prefix are checked
+/// against the matcher only once per shared character.
+#[derive(Default)]
+struct TokenTrie {
+    nodes: Vec<TrieNode>,
+}
+
+impl TokenTrie {
+    fn new() -> Self {
+        Self {
+            nodes: vec![TrieNode::default()],
+        }
+    }
+
+    fn insert(&mut self, text: &str, token: u32) {
+        let mut node = 0;
+        for c in text.chars() {
+            node = match self.nodes[node]
+                .children
+                .binary_search_by_key(&c, |&(child, _)| child)
+            {
+                Ok(pos) => self.nodes[node].children[pos].1,
+                Err(pos) => {
+                    let child = self.nodes.len();
+                    self.nodes.push(TrieNode::default());
+                    self.nodes[node].children.insert(pos, (c, child));
+                    child
+                }
+            };
+        }
+        self.nodes[node].tokens.push(token);
+    }
+
+    fn collect_allowed(&self, grammar: &Grammar, matcher: &JsonMatcher, allowed: &mut Vec<u32>) {
+        let mut pending = vec![(0usize, matcher.clone())];
+        while let Some((node, state)) = pending.pop() {
+            for &(c, child) in &self.nodes[node].children {
+                let Some(next) = state.advance(grammar, c) else {
+                    continue;
+                };
+                allowed.extend_from_slice(&self.nodes[child].tokens);
+                if !self.nodes[child].children.is_empty() {
+                    pending.push((child, next));
+                }
+            }
+        }
+    }
+}
+
+pub struct TokenVocab {
+    texts: Vec<String>,
+    usable: Vec<bool>,
+    /// All usable tokens.
+    trie: TokenTrie,
+    /// Usable tokens that contain `"`, `\` or control characters; every other
+    /// usable token is in `plain_tokens` and always valid inside a string.
+    string_trie: TokenTrie,
+    plain_tokens: Vec<u32>,
+    reasoning_starts: Vec<(u32, &'static str)>,
+}
+
+impl TokenVocab {
+    pub fn from_tokenizer(tokenizer: &Tokenizer) -> Self {
+        let vocab_size = tokenizer.get_vocab_size(true) as u32;
+        let special: HashSet<u32> = tokenizer
+            .get_added_tokens_decoder()
+            .iter()
+            .filter(|(_, token)| token.special)
+            .map(|(id, _)| *id)
+            .collect();
+        let texts: Vec<String> = (0..vocab_size)
+            .into_par_iter()
+            .map(|id| {
+                let mut text = tokenizer.decode(&[id], false).unwrap_or_default();
+                // SentencePiece decoders strip the leading space of a lone token.
+                if tokenizer
+                    .id_to_token(id)
+                    .is_some_and(|piece| piece.starts_with('\u{2581}'))
+                    && !text.starts_with(' ')
+                {
+                    text.insert(0, ' ');
+                }
+                text
+            })
+            .collect();
+        Self::new(texts, |id| special.contains(&id))
+    }
+
+    /// Build the index from decoded token texts (indexed by token id).
+    /// Special tokens and tokens that decode to partial UTF-8 are never
+    /// allowed inside the grammar.
+    pub fn new(texts: Vec<String>, is_special: impl Fn(u32) -> bool) -> Self {
+        let mut usable = Vec::with_capacity(texts.len());
+        let mut trie = TokenTrie::new();
+        let mut string_trie = TokenTrie::new();
+        let mut plain_tokens = Vec::new();
+        let mut reasoning_starts = Vec::new();
+        for (id, text) in texts.iter().enumerate() {
+            let id = id as u32;
+            if let Some(&(_, end)) = reasoning_markers()
+                .iter()
+                .find(|(start, _)| text.trim() == *start)
+            {
+                reasoning_starts.push((id, end));
+            }
+            let ok = !is_special(id) && !text.is_empty() && !text.contains('\u{FFFD}');
+            usable.push(ok);
+            if !ok {
+                continue;
+            }
+            trie.insert(text, id);
+            if text
+                .chars()
+                .any(|c| c == '"' || c == '\\' || (c as u32) < 0x20)
+            {
+                string_trie.insert(text, id);
+            } else {
+                plain_tokens.push(id);
+            }
+        }
+        Self {
+            texts,
+            usable,
+            trie,
+            string_trie,
+            plain_tokens,
+            reasoning_starts,
+        }
+    }
+
+    pub fn text(&self, token: u32) -> &str {
+        self.texts.get(token as usize).map_or("", String::as_str)
+    }
+
+    pub fn is_usable(&self, token: u32) -> bool {
+        self.usable.get(token as usize).copied().unwrap_or(false)
+    }
+
+    /// End marker of the reasoning block opened by `token`, if it is a
+    /// reasoning start marker such as `<think>`.
+    pub fn reasoning_end_for(&self, token: u32) -> Option<&'static str> {
+        self.reasoning_starts
+            .iter()
+            .find(|(id, _)| *id == token)
+            .map(|(_, end)| *end)
+    }
+
+    pub fn reasoning_start_tokens(&self) -> impl Iterator<Item = u32> + '_ {
+        self.reasoning_starts.iter().map(|(id, _)| *id)
+    }
+
+    /// Token ids whose full text can be consumed from `matcher`.
+    pub fn allowed_tokens(&self, grammar: &Grammar, matcher: &JsonMatcher) -> Vec<u32> {
+        let mut allowed = Vec::new();
+        if matcher.in_string_body(grammar) {
+            allowed.extend_from_slice(&self.plain_tokens);
+            self.string_trie
+                .collect_allowed(grammar, matcher, &mut allowed);
+        } else {
+            self.trie.collect_allowed(grammar, matcher, &mut allowed);
+        }
+        allowed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TokenVocab;
+    use crate::openai::structured_output::{matcher::JsonMatcher, schema::Grammar};
+    use serde_json::json;
+
+    fn vocab() -> TokenVocab {
+        let texts = [
+            "{", "}", "{\"", "\"", "\":", " ", "name", "na", "me", "age", "\",", "1", "12", "x",
+            "true", "<s>", "\u{FFFD}", ",", ":", "<think>",
+        ];
+        TokenVocab::new(texts.iter().map(|t| t.to_string()).collect(), |id| id == 15)
+    }
+
+    fn allowed(vocab: &TokenVocab, grammar: &Grammar, prefix: &str) -> Vec<String> {
+        let matcher = JsonMatcher::new(grammar)
+            .advance_str(grammar, prefix)
+            .unwrap();
+        let mut texts: Vec<String> = vocab
+            .allowed_tokens(grammar, &matcher)
+            .into_iter()
+            .map(|id| vocab.text(id).to_string())
+            .collect();
+        texts.sort();
+        texts
+    }
+
+    #[test]
+    fn masks_follow_schema_keys_and_types() {
+        let vocab = vocab();
+        let grammar = Grammar::from_json_schema(&json!({
+            "type": "object",
+            "properties": {"name": {"type": "string"}, "age": {"type": "integer"}},
+            "required": ["name"],
+            "additionalProperties": false
+        }))
+        .unwrap();
+
+        assert_eq!(allowed(&vocab, &grammar, ""), vec![" ", "{", "{\""]);
+        assert_eq!(allowed(&vocab, &grammar, "{\""), vec!["age", "na", "name"]);
+        assert_eq!(allowed(&vocab, &grammar, "{\"age\":"), vec![" ", "1", "12"]);
+        // Inside a string every plain token is allowed, plus the closing quote.
+        let in_string = allowed(&vocab, &grammar, "{\"name\": \"");
+        assert!(in_string.contains(&"\"".to_string()));
+        assert!(in_string.contains(&"\",".to_string()));
+        assert!(in_string.contains(&"true".to_string()));
+        assert!(!in_string.contains(&"<s>".to_string()));
+        assert!(!in_string.contains(&"\u{FFFD}".to_string()));
+    }
+
+    #[test]
+    fn reasoning_markers_are_indexed() {
+        let vocab = vocab();
+        assert_eq!(vocab.reasoning_end_for(19), Some("</think>"));
+        assert_eq!(vocab.reasoning_end_for(0), None);
+    }
+}
@@ -221,6 +221,19 @@ pub fn tokenizer_token_id(tokenizer: &tokenizers::Tokenizer, token: &str) -> Res
         .ok_or_else(|| candle_core::Error::Msg(format!("missing multimodal token `{token}`")))
 }
 
+/// Reject prompt token ids that are outside the tokenizer vocabulary.
+pub fn check_prompt_token_ids(tokenizer: &tokenizers::Tokenizer, token_ids: &[u32]) -> Result<()> {
+    let vocab_size = tokenizer.get_vocab_size(true);
+    if let Some(token) = token_ids.iter().find(|&&id| id as usize >= vocab_size) {
+        candle_core::bail!(
+            "Prompt token id {} is out of range for vocabulary size {}.",
+            token,
+            vocab_size
+        );
+    }
+    Ok(())
+}
+
 // ---------------------------------------------------------------------------
 // GGUF vision-tower helpers
 // ---------------------------------------------------------------------------
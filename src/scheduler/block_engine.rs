@@ -299,9 +299,10 @@ impl BlockEngine {
         prefill_chunk_size: usize,
     ) -> AllocStatus {
         let block_size = self.block_size;
-        let total_required_blocks = seq_group.get_total_logical_token_blocks();
+        // The prompt blocks are shared by every sequence of the group.
+        let seq = seq_group.get_primary_seq();
+        let total_required_blocks = seq.deref().get_logical_token_blocks();
         let num_required_blocks = if let Some(prefix_cache) = self.prefix_cache.as_mut() {
-            let seq = seq_group.get_seqs().values().nth(0).unwrap();
             let tokens = seq.deref().deref().get_token_ids();
             let (seed, seed_block) = seq
                 .deref()
@@ -347,15 +348,8 @@ impl BlockEngine {
             let required_blocks = prefill_end.div_ceil(block_size);
             required_blocks.saturating_sub(matched_blocks)
         } else {
-            seq_group
-                .get_seqs()
-                .values()
-                .map(|seq| {
-                    let prompt_len = seq.deref().deref().get_prompt_len();
-                    let prefill_end = Self::prefill_chunk_end(prompt_len, 0, prefill_chunk_size);
-                    prefill_end.div_ceil(block_size)
-                })
-                .sum()
+            let prompt_len = seq.deref().deref().get_prompt_len();
+            Self::prefill_chunk_end(prompt_len, 0, prefill_chunk_size).div_ceil(block_size)
         };
         let mut num_free_gpu_blocks = *self.gpu_allocator.get_num_free_blocks();
         if num_free_gpu_blocks < num_required_blocks {
@@ -457,21 +451,13 @@ impl BlockEngine {
         seq_group: &SequenceGroup,
         prefill_chunk_size: usize,
     ) -> usize {
+        let seq = seq_group.get_primary_seq();
         if prefill_chunk_size == 0 {
-            return seq_group.get_total_logical_token_blocks();
+            return seq.deref().get_logical_token_blocks();
         }
-        seq_group
-            .get_seqs()
-            .values()
-            .map(|seq| {
-                let prefill_end = Self::prefill_chunk_end(
-                    seq.deref().deref().get_prompt_len(),
-                    0,
-                    prefill_chunk_size,
-                );
-                prefill_end.div_ceil(self.block_size)
-            })
-            .sum()
+        let prefill_end =
+            Self::prefill_chunk_end(seq.deref().deref().get_prompt_len(), 0, prefill_chunk_size);
+        prefill_end.div_ceil(self.block_size)
     }
 
     pub fn can_append_token_to_seq(&self, seq_group: &SequenceGroup) -> bool {
@@ -479,6 +465,7 @@ impl BlockEngine {
         let blocks_required: usize = seq_group
             .get_seqs()
             .values()
+            .filter(|seq| !seq.deref().is_finished())
             .map(|seq| self.blocks_required_to_append_token_slot(seq))
             .sum();
         blocks_required <= *free_blocks
@@ -520,11 +507,7 @@ impl BlockEngine {
         seq_group: &SequenceGroup,
         prefill_chunk_size: usize,
     ) -> usize {
-        seq_group
-            .get_seqs()
-            .values()
-            .map(|seq| self.blocks_missing_for_prefill_chunk(seq, prefill_chunk_size))
-            .sum()
+        self.blocks_missing_for_prefill_chunk(seq_group.get_primary_seq(), prefill_chunk_size)
     }
 
     pub fn can_append_prefill_chunk_to_seq_group(
@@ -541,17 +524,27 @@ impl BlockEngine {
         seq_group: &SequenceGroup,
         prefill_chunk_size: usize,
     ) {
-        for seq in seq_group.get_seqs().values() {
-            let blocks_to_append = self.blocks_missing_for_prefill_chunk(seq, prefill_chunk_size);
-            if blocks_to_append == 0 {
-                continue;
-            }
+        // Only the primary sequence is prefilled; its siblings share every
+        // prompt block it gets.
+        let primary = seq_group.get_primary_seq();
+        let blocks_to_append = self.blocks_missing_for_prefill_chunk(primary, prefill_chunk_size);
+        if blocks_to_append == 0 {
+            return;
+        }
+        let blocks = (0..blocks_to_append)
+            .map(|_| self.gpu_allocator.allocate())
+            .collect::<Vec<_>>();
+        let primary_id = primary.deref().get_id();
+        for seq_id in seq_group.get_seqs().keys() {
             let table = self
                 .block_tables
-                .get_mut(&seq.deref().deref().get_id())
+                .get_mut(seq_id)
                 .expect("prefill continuation must have a block table");
-            for _ in 0..blocks_to_append {
-                table.push_back(self.gpu_allocator.allocate());
+            for block in &blocks {
+                if *seq_id != primary_id {
+                    block.deref_mut().refcount += 1;
+                }
+                table.push_back(block.clone());
             }
         }
     }
@@ -591,6 +584,21 @@ impl BlockEngine {
         self.block_tables.remove(&sequence.deref_mut().get_id());
     }
 
+    /// Make `child` share every block of `parent`, releasing the blocks it
+    /// held. The shared last block is copied on the next write to it.
+    pub fn fork_sequence(&mut self, parent: &Sequence, child: &Sequence) {
+        let table = self
+            .block_tables
+            .get(&parent.deref().get_id())
+            .unwrap()
+            .clone();
+        for block in &table {
+            block.deref_mut().refcount += 1;
+        }
+        self.free_sequence(child);
+        self.block_tables.insert(child.deref().get_id(), table);
+    }
+
     pub fn cache_sequence(&mut self, sequence: &Sequence) {
         let Some(prefix_cache) = self.prefix_cache.as_mut() else {
             return;
@@ -707,6 +715,12 @@ impl BlockEngine {
             .map_or(0, |cache| cache.cached_blocks())
     }
 
+    pub fn prefix_cache_lookup_stats(&self) -> (u64, u64) {
+        self.prefix_cache
+            .as_ref()
+            .map_or((0, 0), |cache| cache.lookup_stats())
+    }
+
     pub fn query_prefix_cache_match_tokens(&mut self, tokens: &[u32]) -> usize {
         if let Some(prefix_cache) = self.prefix_cache.as_mut() {
             let PrefixMatch { matched_blocks, .. } = prefix_cache.match_prefix(tokens);
@@ -1232,6 +1246,25 @@ impl BlockEngine {
         }
     }
 
+    /// Give back blocks reserved for speculative tokens that verification
+    /// rejected, keeping only the blocks the sequence's tokens occupy.
+    pub fn release_speculative_slots_for_seq(&mut self, sequence: &Sequence) {
+        let seq = sequence.deref();
+        let seq_id = seq.get_id();
+        let needed_blocks = seq.get_logical_token_blocks();
+        drop(seq);
+        let Some(table) = self.block_tables.get_mut(&seq_id) else {
+            return;
+        };
+        let mut released = Vec::new();
+        while table.len() > needed_blocks {
+            released.extend(table.pop_back());
+        }
+        for block in released {
+            self.release_block(block);
+        }
+    }
+
     pub fn can_swap_in_seq_group(&self, seq_group: &SequenceGroup) -> bool {
         if !self.cpu_swap_enabled() {
             return false;
@@ -1351,7 +1384,8 @@ impl BlockEngine {
 
     fn allocate_with_prefix(&mut self, seq_group: &SequenceGroup, prefill_chunk_size: usize) {
         let block_size = self.block_size;
-        let seqs: Vec<_> = seq_group.get_seqs().values().cloned().collect();
+        let mut seqs: Vec<_> = seq_group.get_seqs().values().cloned().collect();
+        seqs.sort_unstable_by_key(|seq| seq.deref().get_id());
         let mut cached_tokens = 0usize;
         let mut block_table = VecDeque::new();
 
@@ -1428,6 +1462,7 @@ impl BlockEngine {
                 }
 
                 cached_tokens = matched_blocks * block_size;
+                prefix_cache.record_lookup(tokens.len(), cached_tokens);
                 if matched_blocks > 0 {
                     tracing::info!(
                         "Prefix cache hit seq {} ({} cached tokens, {} blocks)",
@@ -1510,11 +1545,26 @@ mod tests {
         block_size: usize,
         tokens: Vec<u32>,
     ) -> (SequenceGroup, Arc<Sequence>) {
-        let seq = Arc::new(Sequence(std::sync::RwLock::new(_Sequence::new(
-            &tokens, seq_id, block_size, None,
-        ))));
+        let (group, mut seqs) = make_parallel_group(seq_id, group_id, block_size, tokens, 1);
+        (group, seqs.remove(0))
+    }
+
+    fn make_parallel_group(
+        seq_id: usize,
+        group_id: usize,
+        block_size: usize,
+        tokens: Vec<u32>,
+        n: usize,
+    ) -> (SequenceGroup, Vec<Arc<Sequence>>) {
+        let seqs = (seq_id..seq_id + n)
+            .map(|seq_id| {
+                Arc::new(Sequence(std::sync::RwLock::new(_Sequence::new(
+                    &tokens, seq_id, block_size, None,
+                ))))
+            })
+            .collect::<Vec<_>>();
         let sampling_params = SamplingParams::new(
-            1,
+            n,
             None,
             0.0,
             0.0,
@@ -1537,7 +1587,7 @@ mod tests {
         )
         .expect("sampling params");
         let group = SequenceGroup::new(
-            &[seq.clone()],
+            &seqs,
             0,
             group_id,
             "req".to_string(),
@@ -1552,7 +1602,108 @@ mod tests {
             None,
             false,
         );
-        (group, seq)
+        (group, seqs)
+    }
+
+    #[test]
+    fn parallel_sequences_share_prompt_blocks_until_first_write() {
+        let block_size = 4;
+        let mut engine = BlockEngine::new(
+            block_size,
+            8,
+            8,
+            0,
+            PrefixCacheConfig {
+                enabled: false,
+                max_cached_blocks: 0,
+            },
+            false,
+        );
+
+        let (group, seqs) = make_parallel_group(1, 1, block_size, vec![1, 2, 3, 4, 5, 6], 2);
+        let mut blocks_to_copy = HashMap::new();
+        let free_before = engine.get_num_free_blocks();
+        engine.allocate(&group, &mut blocks_to_copy);
+        assert_eq!(engine.get_num_free_blocks(), free_before - 2);
+
+        let block_ids = |engine: &BlockEngine, seq: &Arc<Sequence>| {
+            engine
+                .block_tables
+                .get(&seq.deref().get_id())
+                .unwrap()
+                .iter()
+                .map(|block| block.deref_mut().block_id)
+                .collect::<Vec<_>>()
+        };
+        let shared = block_ids(&engine, &seqs[0]);
+        assert_eq!(block_ids(&engine, &seqs[1]), shared);
+
+        for (token, seq) in seqs.iter().enumerate() {
+            seq.deref_mut().add_token(Logprobs {
+                token: 7 + token as u32,
+                logprob: 0.0,
+                bytes: String::new(),
+                top_logprobs: Vec::new(),
+            });
+        }
+        // The first writer copies the partially filled last block; the other
+        // sequence then owns the original one.
+        assert_eq!(
+            engine.append_token_slot_to_seq(&seqs[0]),
+            Some((shared[1], block_ids(&engine, &seqs[0])[1]))
+        );
+        assert_eq!(engine.append_token_slot_to_seq(&seqs[1]), None);
+        assert_eq!(block_ids(&engine, &seqs[0])[0], shared[0]);
+        assert_eq!(block_ids(&engine, &seqs[1]), shared);
+        assert_eq!(engine.get_num_free_blocks(), free_before - 3);
+    }
+
+    #[test]
+    fn forked_sequence_shares_parent_blocks_copy_on_write() {
+        let block_size = 4;
+        let mut engine = BlockEngine::new(
+            block_size,
+            8,
+            8,
+            0,
+            PrefixCacheConfig {
+                enabled: false,
+                max_cached_blocks: 0,
+            },
+            false,
+        );
+
+        let (group, seqs) = make_parallel_group(1, 1, block_size, vec![1, 2, 3, 4, 5, 6], 2);
+        let mut blocks_to_copy = HashMap::new();
+        let free_before = engine.get_num_free_blocks();
+        engine.allocate(&group, &mut blocks_to_copy);
+        let token = |token: u32| Logprobs {
+            token,
+            logprob: -1.0,
+            bytes: String::new(),
+            top_logprobs: Vec::new(),
+        };
+        for (i, seq) in seqs.iter().enumerate() {
+            seq.deref_mut().add_token(token(7 + i as u32));
+            engine.append_token_slot_to_seq(seq);
+        }
+        assert_eq!(engine.get_num_free_blocks(), free_before - 3);
+
+        // The child drops its own last block and follows the parent.
+        engine.fork_sequence(&seqs[0], &seqs[1]);
+        seqs[1].deref_mut().fork_from(&seqs[0].deref());
+        assert_eq!(engine.get_num_free_blocks(), free_before - 2);
+        assert_eq!(
+            seqs[1].deref().get_token_ids(),
+            seqs[0].deref().get_token_ids()
+        );
+        assert_eq!(seqs[1].deref().get_cumulative_logprob(), -1.0);
+
+        let parent_last = engine.block_tables[&1][1].deref_mut().block_id;
+        assert_eq!(engine.block_tables[&2][1].deref_mut().block_id, parent_last);
+        seqs[0].deref_mut().add_token(token(9));
+        let (src, _) = engine.append_token_slot_to_seq(&seqs[0]).unwrap();
+        assert_eq!(src, parent_last);
     }
 
     #[test]
@@ -1602,6 +1753,7 @@ mod tests {
         let table = engine.block_tables.get(&seq2.deref().get_id()).unwrap();
         assert_eq!(table[0].deref_mut().block_id, cached_block_ids[0]);
         assert_eq!(table[1].deref_mut().block_id, cached_block_ids[1]);
+        assert_eq!(engine.prefix_cache_lookup_stats(), (20, 8));
     }
 
     #[test]
@@ -1696,6 +1848,43 @@ mod tests {
         assert_eq!(engine.block_tables.get(&seq_id).unwrap().len(), 2);
     }
 
+    #[test]
+    fn speculative_slots_are_released_after_verification() {
+        let block_size = 4;
+        let mut engine = BlockEngine::new(
+            block_size,
+            8,
+            4,
+            0,
+            PrefixCacheConfig {
+                enabled: false,
+                max_cached_blocks: 0,
+            },
+            false,
+        );
+
+        let (group, seq) = make_group(1, 1, block_size, vec![1, 2, 3]);
+        let mut blocks_to_copy = HashMap::new();
+        engine.allocate(&group, &mut blocks_to_copy);
+        let seq_id = seq.deref().get_id();
+
+        engine.reserve_additional_token_slots_for_seq(&seq, 9);
+        assert_eq!(engine.block_tables.get(&seq_id).unwrap().len(), 3);
+        assert_eq!(engine.get_num_free_blocks(), 5);
+
+        for token in 4..=5 {
+            seq.deref_mut().add_token(Logprobs {
+                token,
+                logprob: 0.0,
+                bytes: String::new(),
+                top_logprobs: Vec::new(),
+            });
+        }
+        engine.release_speculative_slots_for_seq(&seq);
+        assert_eq!(engine.block_tables.get(&seq_id).unwrap().len(), 2);
+        assert_eq!(engine.get_num_free_blocks(), 6);
+    }
+
     #[test]
     fn allocate_for_prefill_reserves_and_extends_by_chunk() {
         let block_size = 4;
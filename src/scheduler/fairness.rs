@@ -0,0 +1,117 @@
+//! Ordering of sequence groups across priority classes and users.
+//!
+//! Groups are ranked by priority class first. Within a class, the user who
+//! has been served the fewest tokens goes first (deficit-based fair share),
+//! and arrival order breaks the remaining ties. Deficits only decide who is
+//! admitted or swapped in next; running groups keep a stable class-then-arrival
+//! order, since charging every decode step would otherwise reshuffle them and
+//! preempt a different group each step.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use super::sequence::SequenceGroup;
+use crate::openai::sampling_params::Priority;
+
+/// Sort key of a group; smaller keys are more deserving.
+pub type FairKey = (Reverse<Priority>, u64, u64);
+
+/// Sort key of a running group: priority class, then arrival.
+pub type RunningKey = (Reverse<Priority>, u64);
+
+/// Tokens served per user, among users that still have requests queued.
+#[derive(Debug, Default)]
+pub struct FairShare {
+    served: HashMap<String, u64>,
+}
+
+impl FairShare {
+    pub fn user_of(group: &SequenceGroup) -> &str {
+        group.sampling_params.user.as_deref().unwrap_or_default()
+    }
+
+    pub fn served(&self, user: &str) -> u64 {
+        self.served.get(user).copied().unwrap_or_default()
+    }
+
+    pub fn charge(&mut self, user: &str, tokens: usize) {
+        if let Some(served) = self.served.get_mut(user) {
+            *served = served.saturating_add(tokens as u64);
+        }
+    }
+
+    /// Forget users that have nothing queued and start new users at the
+    /// least-served active user, so nobody banks credit while idle.
+    pub fn sync_users<'a>(&mut self, active: impl IntoIterator<Item = &'a str>) {
+        let active = active.into_iter().collect::<Vec<_>>();
+        self.served
+            .retain(|user, _| active.contains(&user.as_str()));
+        let floor = self.served.values().copied().min().unwrap_or_default();
+        for user in active {
+            self.served.entry(user.to_string()).or_insert(floor);
+        }
+    }
+
+    pub fn key(&self, group: &SequenceGroup) -> FairKey {
+        (
+            Reverse(group.sampling_params.priority),
+            self.served(Self::user_of(group)),
+            group.arrival_time(),
+        )
+    }
+
+    pub fn running_key(group: &SequenceGroup) -> RunningKey {
+        (
+            Reverse(group.sampling_params.priority),
+            group.arrival_time(),
+        )
+    }
+}
+
+/// Parse `class=N` entries, e.g. `batch=4,normal=16`, into running-group
+/// limits per priority class.
+pub fn parse_priority_limits(entries: &[String]) -> Result<HashMap<Priority, usize>, String> {
+    let mut limits = HashMap::new();
+    for entry in entries {
+        let (class, limit) = entry
+            .split_once('=')
+            .ok_or_else(|| format!("expected `class=N`, got `{entry}`"))?;
+        let class = class.trim().parse::<Priority>()?;
+        let limit = limit
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| format!("invalid limit `{limit}` for class `{class:?}`"))?;
+        limits.insert(class, limit);
+    }
+    Ok(limits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_users_start_at_least_served_active_user() {
+        let mut share = FairShare::default();
+        share.sync_users(["a", "b"]);
+        share.charge("a", 100);
+        share.charge("b", 40);
+        share.sync_users(["a", "b", "c"]);
+        assert_eq!(share.served("c"), 40);
+
+        share.sync_users(["c"]);
+        assert_eq!(share.served("a"), 0);
+        share.sync_users(["a", "c"]);
+        assert_eq!(share.served("a"), 40);
+    }
+
+    #[test]
+    fn priority_limits_parse_class_entries() {
+        let limits =
+            parse_priority_limits(&["batch=4".to_string(), "normal = 16".to_string()]).unwrap();
+        assert_eq!(limits.get(&Priority::Batch), Some(&4));
+        assert_eq!(limits.get(&Priority::Normal), Some(&16));
+        assert!(parse_priority_limits(&["batch".to_string()]).is_err());
+        assert!(parse_priority_limits(&["urgent=1".to_string()]).is_err());
+    }
+}
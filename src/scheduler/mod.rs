@@ -9,6 +9,7 @@ pub mod block_engine;
 /// actually allocates the KV cache for the CPU and GPU. It is used by the LLMEngine to execute
 /// operations issued by the scheduler.
 pub mod cache_engine;
+pub mod fairness;
 pub mod mamba;
 pub mod prefix_cache;
 pub mod sequence;
@@ -26,8 +27,10 @@ use std::{
     time::{Duration, SystemTime},
 };
 
+use crate::openai::sampling_params::Priority;
 use crate::scheduler::{block_engine::AllocStatus, sequence::SequenceStatus};
 
+use self::fairness::FairShare;
 use self::mamba::MambaState;
 use self::{
     block_engine::BlockEngine, cache_engine::CacheConfig, prefix_cache::PrefixCacheConfig,
@@ -37,6 +40,8 @@ use self::{
 const PREFIX_CACHE_PRESSURE_EVICT_PERCENT: f32 = 0.1; // evict 10% of prefix cache when under pressure
 const FINISHED_CACHED_TOKENS_MAX: usize = 16_384;
 const SWAP_COOLING_PERIOD: Duration = Duration::from_millis(300);
+/// Stop reason of a group that waited longer than `queue_timeout` before prefill.
+pub const QUEUE_TIMEOUT: &str = "queue_timeout";
 
 fn active_sequence_limit(max_num_seqs: usize, mamba_cache_capacity: Option<usize>) -> usize {
     match mamba_cache_capacity {
@@ -46,9 +51,109 @@ fn active_sequence_limit(max_num_seqs: usize, mamba_cache_capacity: Option<usize
     .max(1)
 }
 
+fn waiting_limit_rejection(
+    config: &SchedulerConfig,
+    waiting_requests: usize,
+    waiting_tokens: usize,
+    prompt_tokens: usize,
+) -> Option<String> {
+    if config
+        .max_waiting_requests
+        .is_some_and(|limit| waiting_requests >= limit)
+    {
+        return Some(format!(
+            "Server is overloaded: {waiting_requests} requests are already waiting."
+        ));
+    }
+    // A single prompt above the token limit is still admitted into an empty queue.
+    if config
+        .max_waiting_tokens
+        .is_some_and(|limit| waiting_tokens > 0 && waiting_tokens + prompt_tokens > limit)
+    {
+        return Some(format!(
+            "Server is overloaded: {waiting_tokens} prompt tokens are already waiting."
+        ));
+    }
+    None
+}
+
 #[cfg(test)]
 mod tests {
-    use super::active_sequence_limit;
+    use super::{active_sequence_limit, waiting_limit_rejection, Scheduler, SchedulerConfig};
+    use crate::openai::models::KvCacheDtype;
+    use crate::openai::requests::{EmbeddingType, EncodingFormat};
+    use crate::openai::sampling_params::{EarlyStoppingCondition, Priority, SamplingParams};
+    use crate::scheduler::cache_engine::CacheConfig;
+    use crate::scheduler::prefix_cache::PrefixCacheConfig;
+    use crate::scheduler::sequence::{_Sequence, Sequence, SequenceGroup};
+    use candle_core::DType;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::SystemTime;
+
+    fn scheduler_config() -> SchedulerConfig {
+        SchedulerConfig {
+            max_num_seqs: 8,
+            max_num_parallel_reqs: 8,
+            max_num_batched_tokens: 8192,
+            prefix_cache: PrefixCacheConfig::default(),
+            mamba_cache_capacity: None,
+            priority_limits: HashMap::new(),
+            max_waiting_requests: Some(2),
+            max_waiting_tokens: Some(1000),
+            queue_timeout: None,
+        }
+    }
+
+    fn make_group(id: usize, priority: Priority, user: &str) -> Arc<SequenceGroup> {
+        let seq = Arc::new(Sequence(std::sync::RwLock::new(_Sequence::new(
+            &vec![1, 2, 3],
+            id,
+            4,
+            None,
+        ))));
+        let mut sampling_params = SamplingParams::new(
+            1,
+            None,
+            0.0,
+            0.0,
+            None,
+            None,
+            None,
+            None,
+            None,
+            false,
+            1.0,
+            EarlyStoppingCondition::UnlikelyBetterCandidates,
+            None,
+            vec![],
+            false,
+            16,
+            None,
+            None,
+            true,
+            None,
+        )
+        .expect("sampling params");
+        sampling_params.priority = priority;
+        sampling_params.user = Some(user.to_string());
+        Arc::new(SequenceGroup::new(
+            &[seq],
+            id as u64,
+            id,
+            format!("req-{id}"),
+            SystemTime::now(),
+            sampling_params,
+            false,
+            false,
+            EncodingFormat::Float,
+            EmbeddingType::Last,
+            Vec::new(),
+            crate::openai::ToolChoiceKind::Auto,
+            None,
+            false,
+        ))
+    }
 
     #[test]
     fn mamba_capacity_cannot_raise_user_sequence_limit() {
@@ -60,6 +165,48 @@ mod tests {
     fn active_sequence_limit_is_at_least_one() {
         assert_eq!(active_sequence_limit(0, None), 1);
     }
+
+    #[test]
+    fn waiting_limits_refuse_requests_past_either_bound() {
+        let config = scheduler_config();
+        assert!(waiting_limit_rejection(&config, 1, 100, 100).is_none());
+        assert!(waiting_limit_rejection(&config, 2, 100, 100).is_some());
+        assert!(waiting_limit_rejection(&config, 1, 900, 200).is_some());
+        assert!(waiting_limit_rejection(&config, 0, 0, 5000).is_none());
+    }
+
+    #[test]
+    fn running_groups_keep_class_then_arrival_order_regardless_of_deficit() {
+        let cache_config = CacheConfig {
+            block_size: 4,
+            num_gpu_blocks: Some(8),
+            num_cpu_blocks: Some(8),
+            fully_init: true,
+            dtype: DType::F16,
+            kvcache_dtype: KvCacheDtype::Auto,
+            kvcache_mem_gpu: 0,
+            mamba_cache_budget_bytes: 0,
+        };
+        let mut scheduler = Scheduler::new(scheduler_config(), &cache_config, false, 0);
+        let oldest = make_group(0, Priority::Normal, "heavy");
+        let newer = make_group(1, Priority::Normal, "light");
+        let urgent = make_group(2, Priority::Interactive, "heavy");
+        scheduler.fair_share.sync_users(["heavy", "light"]);
+        scheduler.fair_share.charge("heavy", 10_000);
+        scheduler.running = [newer, urgent, oldest].into_iter().collect();
+
+        // Fair share alone would put the light user's newer group first.
+        for _ in 0..2 {
+            scheduler.sort_running_by_priority();
+            let order = scheduler
+                .running
+                .iter()
+                .map(|group| *group.get_id())
+                .collect::<Vec<_>>();
+            assert_eq!(order, vec![2, 0, 1]);
+            scheduler.fair_share.charge("light", 20_000);
+        }
+    }
 }
 
 pub struct SchedulerOutput {
@@ -82,6 +229,33 @@ pub struct SchedulerConfig {
     pub max_num_batched_tokens: usize,
     pub prefix_cache: PrefixCacheConfig,
     pub mamba_cache_capacity: Option<usize>,
+    /// Most running groups each priority class may hold; unlisted classes
+    /// are only bounded by the overall limits.
+    pub priority_limits: HashMap<Priority, usize>,
+    /// Most requests allowed to wait for prefill; more are refused.
+    pub max_waiting_requests: Option<usize>,
+    /// Most prompt tokens allowed to wait for prefill; more are refused.
+    pub max_waiting_tokens: Option<usize>,
+    /// Longest a request may wait before its first prefill.
+    pub queue_timeout: Option<Duration>,
+}
+
+/// Point-in-time scheduler state and cumulative event counts, exported as metrics.
+#[derive(Clone, Debug, Default)]
+pub struct SchedulerMetrics {
+    pub num_running_seqs: usize,
+    pub num_waiting_seqs: usize,
+    pub num_swapped_seqs: usize,
+    pub num_gpu_blocks: usize,
+    pub num_free_gpu_blocks: usize,
+    pub prefix_cache_blocks: usize,
+    pub prefix_cache_queried_tokens: u64,
+    pub prefix_cache_hit_tokens: u64,
+    pub num_preemptions_recompute: u64,
+    pub num_preemptions_swap: u64,
+    pub num_preemptions_aborted: u64,
+    pub num_blocks_swapped_out: u64,
+    pub num_blocks_swapped_in: u64,
 }
 
 pub struct Scheduler {
@@ -95,6 +269,12 @@ pub struct Scheduler {
     prefill_chunk_size: usize,
     finished_cached_tokens: HashMap<usize, usize>,
     pending_runner_releases: Vec<usize>,
+    /// Groups stopped early, with their finish reason, for the engine to finish.
+    stopped_groups: Vec<(Arc<SequenceGroup>, &'static str)>,
+    /// Tokens served per user, for fair ordering within a priority class.
+    fair_share: FairShare,
+    /// Cumulative counters; the gauges are filled in by `metrics`.
+    counters: SchedulerMetrics,
 }
 
 impl Scheduler {
@@ -124,6 +304,9 @@ impl Scheduler {
             prefill_chunk_size,
             finished_cached_tokens: HashMap::new(),
             pending_runner_releases: Vec::new(),
+            stopped_groups: Vec::new(),
+            fair_share: FairShare::default(),
+            counters: SchedulerMetrics::default(),
         }
     }
 
@@ -135,6 +318,76 @@ impl Scheduler {
         std::mem::take(&mut self.pending_runner_releases)
     }
 
+    pub fn take_stopped_groups(&mut self) -> Vec<(Arc<SequenceGroup>, &'static str)> {
+        std::mem::take(&mut self.stopped_groups)
+    }
+
+    /// Cancel every group whose request id matches. Returns whether any did.
+    pub fn cancel_requests(&self, matches: impl Fn(&str) -> bool) -> bool {
+        let mut cancelled = false;
+        for group in self
+            .waiting
+            .iter()
+            .chain(self.running.iter())
+            .chain(self.swapped_out.iter())
+            .filter(|group| matches(&group.request_id))
+        {
+            group.cancel();
+            cancelled = true;
+        }
+        cancelled
+    }
+
+    /// Why a new request of `prompt_tokens` tokens must be refused, if the
+    /// requests waiting for their first prefill (including `pending_requests`
+    /// not yet handed to the scheduler) are at the configured limits.
+    pub fn admission_rejection(
+        &self,
+        pending_requests: usize,
+        pending_tokens: usize,
+        prompt_tokens: usize,
+    ) -> Option<String> {
+        let (requests, tokens) = self
+            .waiting
+            .iter()
+            .filter(|group| !group.was_scheduled())
+            .fold(
+                (pending_requests, pending_tokens),
+                |(requests, tokens), group| (requests + 1, tokens + group.get_prompt_len()),
+            );
+        waiting_limit_rejection(&self.config, requests, tokens, prompt_tokens)
+    }
+
+    /// Take groups that were cancelled, ran past their deadline or waited
+    /// too long for prefill out of the queues and release their blocks.
+    fn stop_early_finished_groups(&mut self) {
+        let now = SystemTime::now();
+        let queue_timeout = self.config.queue_timeout;
+        let stopped = self
+            .waiting
+            .iter()
+            .chain(self.running.iter())
+            .chain(self.swapped_out.iter())
+            .filter_map(|group| {
+                let reason = group.early_finish_reason(now).or_else(|| {
+                    let timeout = queue_timeout?;
+                    (!group.was_scheduled() && now >= group.created_time + timeout)
+                        .then_some(QUEUE_TIMEOUT)
+                })?;
+                Some((Arc::clone(group), reason))
+            })
+            .collect::<Vec<_>>();
+        for (group, reason) in stopped {
+            warn!("Stopping request {} early ({})", group.request_id, reason);
+            self.request_runner_release_for_group(&group);
+            self.remove_seq_group(&group);
+            if self.block_engine.has_block_table(&group) {
+                self._free(&group, false);
+            }
+            self.stopped_groups.push((group, reason));
+        }
+    }
+
     fn request_runner_release_for_group(&mut self, seq_group: &SequenceGroup) {
         for seq in seq_group.get_seqs().values() {
             let seq_id = seq.deref().get_id();
@@ -179,9 +432,12 @@ impl Scheduler {
     }
 
     pub fn schedule(&mut self) -> SchedulerOutput {
+        self.stop_early_finished_groups();
+        self.sync_fair_share_users();
         // If there are no swapped seqs (they have higher priority), add seqs that are in the
         // waiting queue to the running queue.
         if self.swapped_out.is_empty() {
+            self.sort_waiting_by_priority();
             let mut scheduled = VecDeque::new();
             let mut ignored_seq_groups = VecDeque::new();
             let mut blocks_to_copy = HashMap::new();
@@ -193,17 +449,23 @@ impl Scheduler {
                 self.config.mamba_cache_capacity,
             );
 
-            while !self.waiting.is_empty() {
+            let mut index = 0;
+            while index < self.waiting.len() {
                 if self.is_last_prefill && pre_existing_running > 0 {
                     break; // interleaved scheduling
                 }
-                let seq_group = self.waiting.front().unwrap().clone();
+                let seq_group = self.waiting[index].clone();
+                if self.at_priority_limit(&seq_group) {
+                    // Leave the slot to groups of other classes.
+                    index += 1;
+                    continue;
+                }
 
+                // The prompt is prefilled once for the whole group.
                 let group_tokens = seq_group
-                    .get_seqs()
-                    .values()
-                    .map(|seq| seq.deref().prefill_chunk_tokens(self.prefill_chunk_size))
-                    .sum::<usize>();
+                    .get_primary_seq()
+                    .deref()
+                    .prefill_chunk_tokens(self.prefill_chunk_size);
                 if group_tokens > 0
                     && num_scheduled_tokens.saturating_add(group_tokens)
                         > self.config.max_num_batched_tokens.max(1)
@@ -219,7 +481,10 @@ impl Scheduler {
                     .iter()
                     .map(|group| group.get_seqs().len())
                     .sum();
-                if total_individual_seqs + 1 > self.config.max_num_parallel_reqs.max(1) {
+                if total_individual_seqs > 0
+                    && total_individual_seqs + seq_group.get_seqs().len()
+                        > self.config.max_num_parallel_reqs.max(1)
+                {
                     break;
                 }
 
@@ -237,7 +502,7 @@ impl Scheduler {
                                 seq_group.get_prompt_len()
                             );
                             seq_group.set_status(SequenceStatus::FinishedIgnored);
-                            ignored_seq_groups.push_back(self.waiting.pop_front().unwrap());
+                            ignored_seq_groups.push_back(self.waiting.remove(index).unwrap());
                             continue;
                         }
                         AllocStatus::Ok => {
@@ -249,8 +514,11 @@ impl Scheduler {
                 }
 
                 seq_group.set_status(SequenceStatus::Running);
+                seq_group.mark_scheduled();
 
-                let seq_group = self.waiting.pop_front().unwrap();
+                let seq_group = self.waiting.remove(index).unwrap();
+                self.fair_share
+                    .charge(FairShare::user_of(&seq_group), group_tokens);
                 self.running.push_back(seq_group.clone());
                 scheduled.push_back(seq_group);
                 num_scheduled_tokens = num_scheduled_tokens.saturating_add(group_tokens);
@@ -283,8 +551,9 @@ impl Scheduler {
         // sequences, which will be put into the waiting or swapped out state depending on
         // the preemption method (recompute or swap, respectively).
 
-        // Sorts by creation time, in descending order so that earliest are latest (first come first serve).
-        self.sort_running_by_priority_fcfs();
+        // Highest class and oldest first, so preemption takes the lowest-class,
+        // newest group from the back.
+        self.sort_running_by_priority();
 
         let decode_max_seqs = if let Some(mamba_cap) = self.config.mamba_cache_capacity {
             if mamba_cap > 0 {
@@ -355,6 +624,8 @@ impl Scheduler {
                 // If we need to, append physical blocks for a new token. We do not need to if there is enough space.
                 // If we just got preempted, there is no reason to allocate
                 self._append_token_slot_to_seq_group(&seq_group, &mut blocks_to_copy);
+                self.fair_share
+                    .charge(FairShare::user_of(&seq_group), seq_group.get_seqs().len());
                 running.push_back(seq_group);
             }
         }
@@ -363,12 +634,17 @@ impl Scheduler {
         // Try to swap in the swapped out sequences and add these to the
         // running state if possible.
 
-        // Sorts by creation time, in descending order so that earliest are latest (first come first serve).
-        self.sort_swapped_out_by_priority_fcfs();
+        // Most deserving groups are swapped in first.
+        self.sort_swapped_out_by_priority();
 
         if preempted.is_empty() {
-            while !self.swapped_out.is_empty() {
-                let seq_group = self.swapped_out.front().unwrap();
+            let mut index = 0;
+            while index < self.swapped_out.len() {
+                let seq_group = &self.swapped_out[index];
+                if self.at_priority_limit(seq_group) {
+                    index += 1;
+                    continue;
+                }
                 let primary = seq_group
                     .get_seqs()
                     .values()
@@ -398,9 +674,10 @@ impl Scheduler {
                     }
                 }
 
-                let seq_group = self.swapped_out.pop_front().unwrap();
+                let seq_group = self.swapped_out.remove(index).unwrap();
                 // Swap in the blocks
                 let to_swap_in = self.block_engine.swap_in(&seq_group);
+                self.counters.num_blocks_swapped_in += to_swap_in.len() as u64;
                 blocks_to_swap_in.extend(to_swap_in);
                 swap_in_groups.push(*seq_group.get_id());
                 for seq in seq_group.get_seqs().values() {
@@ -428,6 +705,26 @@ impl Scheduler {
         !self.running.is_empty() || !self.waiting.is_empty()
     }
 
+    /// Creation time of the oldest request the scheduler still holds.
+    pub fn oldest_unfinished_time(&self) -> Option<SystemTime> {
+        self.waiting
+            .iter()
+            .chain(self.running.iter())
+            .chain(self.swapped_out.iter())
+            .map(|group| group.created_time)
+            .min()
+    }
+
+    /// Ids of the groups still waiting, running or swapped out.
+    pub fn group_ids(&self) -> HashSet<usize> {
+        self.waiting
+            .iter()
+            .chain(self.running.iter())
+            .chain(self.swapped_out.iter())
+            .map(|group| *group.get_id())
+            .collect()
+    }
+
     pub fn has_waiting_sequences(&self) -> bool {
         !self.waiting.is_empty()
     }
@@ -522,6 +819,28 @@ impl Scheduler {
         );
     }
 
+    pub fn metrics(&self) -> SchedulerMetrics {
+        let num_seqs = |groups: &VecDeque<Arc<SequenceGroup>>| {
+            groups
+                .iter()
+                .map(|group| group.get_unfinished_seqs().len())
+                .sum()
+        };
+        let (prefix_cache_queried_tokens, prefix_cache_hit_tokens) =
+            self.block_engine.prefix_cache_lookup_stats();
+        SchedulerMetrics {
+            num_running_seqs: num_seqs(&self.running),
+            num_waiting_seqs: num_seqs(&self.waiting),
+            num_swapped_seqs: num_seqs(&self.swapped_out),
+            num_gpu_blocks: self.block_engine.get_num_blocks(),
+            num_free_gpu_blocks: self.block_engine.get_num_free_blocks(),
+            prefix_cache_blocks: self.block_engine.prefix_cache_blocks(),
+            prefix_cache_queried_tokens,
+            prefix_cache_hit_tokens,
+            ..self.counters.clone()
+        }
+    }
+
     pub fn get_available_kv_tokens(&self) -> usize {
         let free_blocks = self.block_engine.get_num_free_blocks();
         free_blocks * self.block_engine.get_block_size()
@@ -550,7 +869,7 @@ impl Scheduler {
         let mut chunk_finished_info = Vec::new();
         assert!(chunk_size > 0, "Invalid prefill chunk size!");
         for (i, group) in scheduled.iter().enumerate() {
-            let seq = group.get_seqs().values().nth(0).unwrap();
+            let seq = group.get_primary_seq();
             let prompt_len = seq.deref().get_prompt_len();
             let num_cached_tokens = seq.deref().get_num_cached_tokens();
             let chunk_tokens = seq.deref().prefill_chunk_tokens(chunk_size);
@@ -563,12 +882,15 @@ impl Scheduler {
                 remove_ids.push(seq.deref().get_id());
                 //unfinished due to chunked_prefill, push back to waiting list
                 let group = group.clone();
-                let seq = group.get_seqs().values().nth(0).unwrap();
-                seq.deref_mut()
-                    .set_num_cached_tokens(num_cached_tokens + chunk_tokens);
-                if seq.deref().active_mamba_prefix_warmup_target().is_none() {
-                    seq.deref_mut().clear_mamba_prefix_warmup();
+                // Siblings share the prompt blocks, so they track the primary's progress.
+                for seq in group.get_seqs().values() {
+                    seq.deref_mut()
+                        .set_num_cached_tokens(num_cached_tokens + chunk_tokens);
+                    if seq.deref().active_mamba_prefix_warmup_target().is_none() {
+                        seq.deref_mut().clear_mamba_prefix_warmup();
+                    }
                 }
+                let seq = group.get_primary_seq();
                 group.set_status(SequenceStatus::Pending);
                 chunked_info.push((
                     seq.deref().get_id(),
@@ -604,9 +926,8 @@ impl Scheduler {
                 total
             );
         }
-        self.running.retain(|s| {
-            !remove_ids.contains(&s.get_seqs().values().nth(0).unwrap().deref().get_id())
-        });
+        self.running
+            .retain(|s| !remove_ids.contains(&s.get_primary_seq().deref().get_id()));
 
         let finished_groups: VecDeque<Arc<SequenceGroup>> = finished_indices
             .iter()
@@ -690,6 +1011,9 @@ impl Scheduler {
         blocks_to_copy: &mut HashMap<usize, Vec<usize>>,
     ) {
         for seq in seq_group.get_seqs().values() {
+            if seq.deref().is_finished() {
+                continue;
+            }
             let op = self.block_engine.append_token_slot_to_seq(seq);
             if let Some((src_block, dst_block)) = op {
                 if let std::collections::hash_map::Entry::Vacant(e) =
@@ -720,6 +1044,19 @@ impl Scheduler {
         blocks_to_swap_out: &mut HashMap<usize, usize>,
         swap_out_groups: &mut Vec<usize>,
     ) {
+        if seq_group.get_seqs().len() > 1 && !self.block_engine.cpu_swap_enabled() {
+            // Sequences of a group diverge after the shared prompt, and a
+            // recompute would only prefill the prompt again.
+            warn!(
+                "Aborting request {}: its {} sequences cannot be preempted without CPU swap space.",
+                seq_group.request_id,
+                seq_group.get_seqs().len()
+            );
+            self.counters.num_preemptions_aborted += 1;
+            self.request_runner_release_for_group(&seq_group);
+            self._abort_seq_group(&seq_group);
+            return;
+        }
         if !self.block_engine.cpu_swap_enabled()
             || (seq_group.get_seqs().len() == 1 && !self.block_engine.prefix_cache_enabled())
         {
@@ -730,6 +1067,7 @@ impl Scheduler {
     }
 
     fn _preempt_by_recompute(&mut self, seq_group: Arc<SequenceGroup>) {
+        self.counters.num_preemptions_recompute += 1;
         self.request_runner_release_for_group(&seq_group);
         seq_group.set_status(SequenceStatus::Waiting);
         self._free(&seq_group, false);
@@ -751,11 +1089,14 @@ impl Scheduler {
                 return;
             }
             // If we cannot swap it out, abort the sequence group.
+            self.counters.num_preemptions_aborted += 1;
             self.request_runner_release_for_group(&seq_group);
             self._abort_seq_group(&seq_group);
             return;
         }
         let new_to_swap = self.block_engine.swap_out(&seq_group);
+        self.counters.num_preemptions_swap += 1;
+        self.counters.num_blocks_swapped_out += new_to_swap.len() as u64;
         blocks_to_swap_out.extend(new_to_swap);
         swap_out_groups.push(*seq_group.get_id());
         let swapped_time = Some(SystemTime::now());
@@ -777,6 +1118,9 @@ impl Scheduler {
     }
 
     fn _free(&mut self, seq_group: &SequenceGroup, cache_prefix: bool) {
+        // Finished beams hold the tokens of a hypothesis, not of the KV blocks
+        // they own, so they must not seed the prefix cache.
+        let cache_prefix = cache_prefix && !seq_group.sampling_params.use_beam_search;
         for seq in seq_group.get_seqs().values() {
             if cache_prefix {
                 if matches!(seq.deref().get_status(), SequenceStatus::Finished(_)) {
@@ -787,18 +1131,50 @@ impl Scheduler {
         }
     }
 
-    fn sort_running_by_priority_fcfs(&mut self) {
+    fn sync_fair_share_users(&mut self) {
+        let users = self
+            .waiting
+            .iter()
+            .chain(self.running.iter())
+            .chain(self.swapped_out.iter())
+            .map(|group| FairShare::user_of(group))
+            .collect::<HashSet<_>>();
+        self.fair_share.sync_users(users);
+    }
+
+    /// Whether the class of `seq_group` already holds its share of running groups.
+    fn at_priority_limit(&self, seq_group: &SequenceGroup) -> bool {
+        let priority = seq_group.sampling_params.priority;
+        self.config
+            .priority_limits
+            .get(&priority)
+            .is_some_and(|&limit| {
+                self.running
+                    .iter()
+                    .filter(|group| group.sampling_params.priority == priority)
+                    .count()
+                    >= limit
+            })
+    }
+
+    fn sort_waiting_by_priority(&mut self) {
+        let fair_share = &self.fair_share;
+        self.waiting
+            .make_contiguous()
+            .sort_by_cached_key(|seq_group| fair_share.key(seq_group));
+    }
+
+    fn sort_running_by_priority(&mut self) {
         self.running
             .make_contiguous()
-            .sort_by_key(|seq_group| seq_group.arrival_time());
-        self.running.make_contiguous().reverse();
+            .sort_by_key(|seq_group| FairShare::running_key(seq_group));
     }
 
-    fn sort_swapped_out_by_priority_fcfs(&mut self) {
+    fn sort_swapped_out_by_priority(&mut self) {
+        let fair_share = &self.fair_share;
         self.swapped_out
             .make_contiguous()
-            .sort_by_key(|seq_group| seq_group.arrival_time());
-        self.swapped_out.make_contiguous().reverse();
+            .sort_by_cached_key(|seq_group| fair_share.key(seq_group));
     }
 
     fn evict_prefix_cache_under_pressure(&mut self) -> usize {
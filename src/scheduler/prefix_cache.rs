@@ -40,6 +40,9 @@ pub struct PrefixCache {
     leaf_set: HashSet<u64>,
     leaf_lru: VecDeque<(u64, u64)>,
     access_counter: u64,
+    /// Prompt tokens looked up at allocation, and how many of them were cached.
+    queried_tokens: u64,
+    hit_tokens: u64,
 }
 
 impl PrefixCache {
@@ -51,6 +54,8 @@ impl PrefixCache {
             leaf_set: HashSet::new(),
             leaf_lru: VecDeque::new(),
             access_counter: 0,
+            queried_tokens: 0,
+            hit_tokens: 0,
         }
     }
 
@@ -62,6 +67,17 @@ impl PrefixCache {
         self.entries.len()
     }
 
+    /// Count a prompt allocation that reused `hit_tokens` of its `prompt_tokens`.
+    pub fn record_lookup(&mut self, prompt_tokens: usize, hit_tokens: usize) {
+        self.queried_tokens += prompt_tokens as u64;
+        self.hit_tokens += hit_tokens as u64;
+    }
+
+    /// Total prompt tokens looked up and served from the cache.
+    pub fn lookup_stats(&self) -> (u64, u64) {
+        (self.queried_tokens, self.hit_tokens)
+    }
+
     pub fn match_prefix(&mut self, tokens: &[u32]) -> PrefixMatch {
         self.match_prefix_with_seed(tokens, None, None)
     }
@@ -1,12 +1,16 @@
 use std::{
-    collections::HashMap,
-    sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    },
 };
 
 use super::block_engine::LogicalTokenBlock;
 use crate::openai::multimodal::ImageData;
 use crate::openai::sampling_params::{Logprobs, SamplingParams};
 use crate::openai::streaming::ChatResponse;
+use crate::openai::structured_output::GuidedDecoder;
 use crate::openai::ToolChoiceKind;
 use crate::tools::stream_parser::StreamToolParser;
 use crate::tools::{Tool, ToolCall};
@@ -39,6 +43,7 @@ pub struct SequenceData {
     pub images: Option<ImageData>,
     pub mamba_prefix_hash: Option<u64>,
     pub mamba_prefix_warmup_tokens: Option<usize>,
+    pub guided_decoder: Option<GuidedDecoder>,
     swapped_time: Option<SystemTime>,
 }
 
@@ -60,6 +65,7 @@ impl SequenceData {
             images,
             mamba_prefix_hash: None,
             mamba_prefix_warmup_tokens: None,
+            guided_decoder: None,
             swapped_time: None,
         }
     }
@@ -193,6 +199,26 @@ impl _Sequence {
         }
     }
 
+    /// Replace the generated tokens, e.g. with a finished beam search hypothesis.
+    pub fn set_output_tokens(&mut self, output_tokens: Vec<Logprobs>) {
+        let prompt_token_ids = self.deref().prompt_token_ids.clone();
+        self.logical_token_blocks.clear();
+        self.append_tokens_to_blocks(&prompt_token_ids);
+        for logprobs in &output_tokens {
+            self.append_token_to_blocks(logprobs.token);
+        }
+        let mut data = self.deref_mut();
+        data.cumulative_logprob = output_tokens.iter().map(|logprobs| logprobs.logprob).sum();
+        data.output_token_ids = output_tokens;
+    }
+
+    /// Continue from `parent`'s generated tokens in place of this sequence's
+    /// own, when beam search keeps several continuations of one beam.
+    pub fn fork_from(&mut self, parent: &_Sequence) {
+        self.set_output_tokens(parent.get_output_tokens());
+        self.deref_mut().guided_decoder = parent.deref().guided_decoder.clone();
+    }
+
     #[must_use]
     /// Clones the internal logprobs.
     pub fn get_output_tokens(&self) -> Vec<Logprobs> {
@@ -338,6 +364,15 @@ impl Sequence {
 
 type SeqID = usize;
 
+/// A beam that ended during beam search, kept until the search finishes.
+#[derive(Clone, Debug)]
+pub struct BeamHypothesis {
+    pub output_tokens: Vec<Logprobs>,
+    /// Length-penalised cumulative logprob.
+    pub score: f32,
+    pub finish_reason: String,
+}
+
 /// A SequenceGroup holds the `n` (see SamplingParams) sequences generated from a single prompt.
 /// A SequenceGroup contains only sequences with the same prompt. They will always be scheduled together.
 pub struct SequenceGroup {
@@ -360,6 +395,17 @@ pub struct SequenceGroup {
     /// should be replayed through the streaming tool parser before the first
     /// real decoded token, so the parser sees the reasoning start marker.
     pub prompt_replay_token_ids: Option<Vec<u32>>,
+    /// Finished beams of a beam search request, best first.
+    pub beam_hypotheses: RwLock<Vec<BeamHypothesis>>,
+    /// Beam slots that hold no live beam. They stay unfinished so a later
+    /// step can fork into them; their sample results are ignored.
+    pub parked_beams: RwLock<HashSet<SeqID>>,
+    /// When the group last produced a token, for inter-token latency.
+    pub last_token_time: RwLock<Option<SystemTime>>,
+    /// Set by `cancel`; the scheduler stops the group on its next step.
+    cancelled: AtomicBool,
+    /// Set once the group is first admitted from the waiting queue.
+    scheduled: AtomicBool,
 }
 
 impl SequenceGroup {
@@ -401,7 +447,36 @@ impl SequenceGroup {
             include_usage,
             active_reasoning_end: None,
             prompt_replay_token_ids: None,
+            beam_hypotheses: RwLock::new(Vec::new()),
+            parked_beams: RwLock::new(HashSet::new()),
+            last_token_time: RwLock::new(None),
+            cancelled: AtomicBool::new(false),
+            scheduled: AtomicBool::new(false),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn mark_scheduled(&self) {
+        self.scheduled.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether the group has been admitted at least once; preempted groups
+    /// that return to the waiting queue stay scheduled.
+    pub fn was_scheduled(&self) -> bool {
+        self.scheduled.load(Ordering::Relaxed)
+    }
+
+    /// Finish reason for a group that must stop before it finishes on its
+    /// own: `cancelled` once cancelled, `timeout` past its deadline.
+    pub fn early_finish_reason(&self, now: SystemTime) -> Option<&'static str> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return Some("cancelled");
         }
+        let deadline = self.created_time + self.sampling_params.timeout?;
+        (now >= deadline).then_some("timeout")
     }
 
     pub fn set_status(&self, status: SequenceStatus) {
@@ -409,8 +484,12 @@ impl SequenceGroup {
         //     seq.deref_mut().deref().set_status(status.clone());
         // }
         for seq in self.seqs.values() {
-            // Lock each sequence individually and set the status
+            // Lock each sequence individually and set the status. Sequences that
+            // already finished keep their status while their siblings run on.
             if let Ok(seq_guard) = seq.0.write() {
+                if seq_guard.is_finished() {
+                    continue;
+                }
                 seq_guard.deref_mut().set_status(status.clone());
             }
         }
@@ -455,6 +534,32 @@ impl SequenceGroup {
         &self.seqs
     }
 
+    /// The sequence with the lowest id. All sequences share the prompt, which
+    /// is prefilled once through this sequence.
+    pub fn get_primary_seq(&self) -> &Arc<Sequence> {
+        self.seqs
+            .iter()
+            .min_by_key(|(seq_id, _)| *seq_id)
+            .expect("sequence group must contain at least one sequence")
+            .1
+    }
+
+    /// Unfinished sequences ordered by id; each one is a row of a decode batch.
+    pub fn get_unfinished_seqs(&self) -> Vec<Arc<Sequence>> {
+        let mut seqs = self
+            .seqs
+            .iter()
+            .filter(|(_, seq)| !seq.deref().is_finished())
+            .collect::<Vec<_>>();
+        seqs.sort_unstable_by_key(|(seq_id, _)| *seq_id);
+        seqs.into_iter().map(|(_, seq)| Arc::clone(seq)).collect()
+    }
+
+    /// Choice index of a sequence: its position among the group's sequence ids.
+    pub fn choice_index(&self, seq_id: SeqID) -> usize {
+        self.seqs.keys().filter(|id| **id < seq_id).count()
+    }
+
     pub fn arrival_time(&self) -> u64 {
         self.arrival_time
     }
@@ -35,6 +35,7 @@ pub enum ToolModelType {
     Gemma4,
     Qwen3VL,
     MiniMax,
+    GptOss,
 }
 
 /// Look up the JSON schema for a parameter from a tool's properties definition.
@@ -415,7 +416,9 @@ impl ToolConfig {
             | ToolModelType::GLM5
             | ToolModelType::Yi
             | ToolModelType::StableLM
-            | ToolModelType::DeepSeek => ToolConfig {
+            | ToolModelType::DeepSeek
+            // GPT-OSS calls are rewritten from harmony by `HarmonyChannels`.
+            | ToolModelType::GptOss => ToolConfig {
                 start_token_ids: HashSet::new(),
                 end_token_ids: HashSet::new(),
                 start_token_str: "<tool_call>".to_string(),
@@ -748,6 +751,167 @@ pub fn detect_prefilled_reasoning_end_marker(prompt: &str) -> Option<String> {
     None
 }
 
+/// Longest harmony control token (`<|constrain|>`).
+const HARMONY_MARKER_MAX_LEN: usize = 13;
+
+#[derive(Debug, Clone, PartialEq)]
+enum HarmonyState {
+    /// Between messages; plain text here means the model skipped the framing.
+    Start,
+    /// Inside a message header, collected up to `<|message|>`.
+    Header(String),
+    /// Inside an `analysis` body.
+    Analysis,
+    /// Inside a `final` body, or plain text.
+    Content,
+    /// Inside the arguments of a call to `functions.*`.
+    ToolCall,
+}
+
+/// Rewrites GPT-OSS harmony output into the markup the rest of the server
+/// already handles: the `analysis` channel becomes a `<think>` block, calls
+/// addressed `to=functions.NAME` become Qwen-style `<tool_call>` envelopes and
+/// the remaining framing tokens are dropped.
+///
+/// Text is pushed incrementally as the stream decoder produces it, so a
+/// control token split across pushes is held back until it is complete.
+#[derive(Debug, Clone)]
+pub struct HarmonyChannels {
+    state: HarmonyState,
+    pending: String,
+    at_line_start: bool,
+}
+
+impl Default for HarmonyChannels {
+    fn default() -> Self {
+        Self {
+            state: HarmonyState::Start,
+            pending: String::new(),
+            at_line_start: true,
+        }
+    }
+}
+
+impl HarmonyChannels {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed decoded text and return the rewritten text ready to emit.
+    pub fn push(&mut self, text: &str) -> String {
+        self.pending.push_str(text);
+        let mut out = String::new();
+        while !self.pending.is_empty() {
+            let Some(idx) = self.pending.find("<|") else {
+                // A trailing '<' may be the start of a control token.
+                let keep = usize::from(self.pending.ends_with('<'));
+                let text: String = self.pending.drain(..self.pending.len() - keep).collect();
+                self.text(&text, &mut out);
+                break;
+            };
+            if idx > 0 {
+                let text: String = self.pending.drain(..idx).collect();
+                self.text(&text, &mut out);
+            }
+            match self.pending.find("|>") {
+                Some(end) if end + 2 <= HARMONY_MARKER_MAX_LEN => {
+                    let marker: String = self.pending.drain(..end + 2).collect();
+                    self.marker(&marker, &mut out);
+                }
+                None if self.pending.len() < HARMONY_MARKER_MAX_LEN => break,
+                _ => {
+                    let text: String = self.pending.drain(..2).collect();
+                    self.text(&text, &mut out);
+                }
+            }
+        }
+        out
+    }
+
+    fn emit(&mut self, text: &str, out: &mut String) {
+        if !text.is_empty() {
+            out.push_str(text);
+            self.at_line_start = text.ends_with('\n');
+        }
+    }
+
+    fn text(&mut self, text: &str, out: &mut String) {
+        match &mut self.state {
+            HarmonyState::Header(header) => header.push_str(text),
+            HarmonyState::Start => {
+                self.state = HarmonyState::Content;
+                self.emit(text, out);
+            }
+            _ => self.emit(text, out),
+        }
+    }
+
+    fn marker(&mut self, marker: &str, out: &mut String) {
+        match marker {
+            "<|start|>" => {
+                self.close_body(out);
+                self.state = HarmonyState::Header(String::new());
+            }
+            "<|channel|>" | "<|constrain|>" => match &mut self.state {
+                HarmonyState::Header(header) => header.push_str(marker),
+                _ => {
+                    self.close_body(out);
+                    self.state = HarmonyState::Header(marker.to_string());
+                }
+            },
+            "<|message|>" => match std::mem::replace(&mut self.state, HarmonyState::Start) {
+                HarmonyState::Header(header) => self.open_body(&header, out),
+                state => {
+                    self.state = state;
+                }
+            },
+            "<|end|>" | "<|return|>" | "<|call|>" => {
+                self.close_body(out);
+                self.state = HarmonyState::Start;
+            }
+            _ => self.text(marker, out),
+        }
+    }
+
+    fn open_body(&mut self, header: &str, out: &mut String) {
+        // e.g. `assistant to=functions.get_weather<|channel|>commentary <|constrain|>json`
+        let header = header.replace("<|", " <|");
+        let mut channel = "";
+        let mut recipient = None;
+        for word in header.split_whitespace() {
+            if let Some(name) = word.strip_prefix("<|channel|>") {
+                channel = name;
+            } else if let Some(to) = word.strip_prefix("to=") {
+                recipient = Some(to);
+            }
+        }
+        if let Some(name) = recipient.and_then(|to| to.strip_prefix("functions.")) {
+            if !self.at_line_start {
+                self.emit("\n", out);
+            }
+            let name = serde_json::to_string(name).unwrap_or_default();
+            self.emit(
+                &format!("<tool_call>\n{{\"name\": {name}, \"arguments\": "),
+                out,
+            );
+            self.state = HarmonyState::ToolCall;
+        } else if channel == "analysis" {
+            self.emit("<think>", out);
+            self.state = HarmonyState::Analysis;
+        } else {
+            self.state = HarmonyState::Content;
+        }
+    }
+
+    fn close_body(&mut self, out: &mut String) {
+        match self.state {
+            HarmonyState::Analysis => self.emit("</think>", out),
+            HarmonyState::ToolCall => self.emit("}\n</tool_call>", out),
+            _ => {}
+        }
+    }
+}
+
 impl StreamToolParser {
     /// Create a new parser for the given model type
     pub fn new(model_type: ToolModelType, model_id: String) -> Self {
@@ -1727,7 +1891,7 @@ impl StreamToolParser {
             | ToolModelType::GLM4MoE
             | ToolModelType::GLM4MoeLite
             | ToolModelType::GLM5 => "glm47_moe",
-            ToolModelType::Yi | ToolModelType::StableLM => "qwen",
+            ToolModelType::Yi | ToolModelType::StableLM | ToolModelType::GptOss => "qwen",
             ToolModelType::DeepSeek => "deepseek",
             ToolModelType::MiniMax => "minimax_m2",
         }
@@ -4316,4 +4480,60 @@ Some markdown text here.</parameter>
         assert_eq!(args["query"], "rust programming");
         assert_eq!(args["count"], 5);
     }
+
+    fn harmony(pieces: &[&str]) -> String {
+        let mut channels = HarmonyChannels::new();
+        pieces.iter().map(|piece| channels.push(piece)).collect()
+    }
+
+    #[test]
+    fn test_harmony_analysis_becomes_reasoning_block() {
+        let text = harmony(&[
+            "<|channel|>",
+            "analysis",
+            "<|message|>",
+            "User greets.",
+            "<|end|>",
+            "<|start|>",
+            "assistant",
+            "<|channel|>",
+            "final",
+            "<|message|>",
+            "Hello!",
+            "<|return|>",
+        ]);
+        assert_eq!(text, "<think>User greets.</think>Hello!");
+    }
+
+    #[test]
+    fn test_harmony_function_call_becomes_tool_call() {
+        let text = harmony(&[
+            "<|channel|>analysis<|message|>Need weather.<|end|>",
+            "<|start|>assistant<|channel|>commentary to=functions.get_weather ",
+            "<|constrain|>json<|message|>",
+            "{\"city\":",
+            "\"Paris\"}",
+            "<|call|>",
+        ]);
+        assert_eq!(
+            text,
+            "<think>Need weather.</think>\n<tool_call>\n{\"name\": \"get_weather\", \"arguments\": {\"city\":\"Paris\"}}\n</tool_call>"
+        );
+
+        // The recipient may also precede the channel.
+        let text = harmony(&[
+            "<|start|>assistant to=functions.lookup<|channel|>commentary json<|message|>{}<|call|>",
+        ]);
+        assert_eq!(
+            text,
+            "<tool_call>\n{\"name\": \"lookup\", \"arguments\": {}}\n</tool_call>"
+        );
+    }
+
+    #[test]
+    fn test_harmony_split_markers_and_plain_text() {
+        let text = harmony(&["<", "|chan", "nel|>final<|mes", "sage|>a <|b| c, then more"]);
+        assert_eq!(text, "a <|b| c, then more");
+        assert_eq!(harmony(&["No framing <here>."]), "No framing <here>.");
+    }
 }